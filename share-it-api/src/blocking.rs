@@ -0,0 +1,20 @@
+use actix_web::web;
+use futures::future::{FutureExt, LocalBoxFuture};
+use share_it_core::blocking::{Canceled, Spawner};
+
+// ActixSpawner runs blocking work on actix's own thread pool, so the server doesn't spend a thread
+// per call like share-it-core's ThreadSpawner does.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ActixSpawner;
+
+impl Spawner for ActixSpawner {
+    fn spawn_blocking<F, T>(&self, f: F) -> LocalBoxFuture<'static, Result<T, Canceled>> where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        // web::block only runs fallible work, so this can never fail for any other reason.
+        web::block(move || Ok::<_, ()>(f()))
+            .map(|result| result.map_err(|_| Canceled))
+            .boxed_local()
+    }
+}
@@ -1,3 +1,15 @@
+use serde::Deserialize;
+
+pub mod oauth;
+pub mod blocking;
+
+// OauthResponse is the query string an OAuth provider redirects back to us with.
+#[derive(Deserialize)]
+pub struct OauthResponse {
+    pub code: String,
+    pub state: String,
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
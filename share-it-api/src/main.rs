@@ -1,172 +1,180 @@
 use std::env;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use dotenv::dotenv;
-use actix_web::{web, HttpServer, App, Responder, Error as AWError, HttpResponse, Result};
+use actix_web::{web, HttpServer, App, HttpResponse};
 use actix_web::http::header;
 use actix_session::{CookieSession, Session};
-use serde::Deserialize;
-use oauth2::basic::BasicClient;
-use oauth2::reqwest::http_client;
-use oauth2::{
-    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken,
-    RedirectUrl, Scope, TokenResponse, TokenUrl,
-};
-
-struct OauthState {
-    client: BasicClient,
-}
-
-fn index(session: Session) -> HttpResponse {
+use share_it_api::oauth::{self, OauthProviders, OauthState};
+use share_it_core::availability::{AvailabilityChecker, AvailabilityCheckerHandle};
+use share_it_core::repositories::abstractions::Query;
+use share_it_core::repositories::query::{PageRequest, UserFilter};
+use share_it_core::repositories::implementations::{MysqlTokens, MysqlUsers};
+use share_it_core::tokens::TokenCipher;
+use share_it_core::repositories::pool::MysqlPool;
+use share_it_core::repositories::sql::{Dialect, Migrator, SqlError};
+use share_it_core::soundcloud_api::HttpSoundcloudClient;
+use share_it_core::user::UserID;
+
+// How often every user's songs are checked against SoundCloud.
+const AVAILABILITY_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+async fn index(session: Session, providers: web::Data<OauthProviders>) -> HttpResponse {
     let login = session.get::<String>("login").unwrap();
-    let link = if login.is_some() { "logout" } else { "login" };
+
+    // Logged out users can only log in with SoundCloud, logged in users can link everything else.
+    let links = if login.is_some() {
+        let mut links = providers.names().iter()
+            .filter(|name| name.as_str() != "soundcloud")
+            .map(|name| format!(r#"<a href="/login/{0}">link {0}</a>"#, name))
+            .collect::<Vec<_>>();
+        links.push(r#"<a href="/logout">logout</a>"#.to_string());
+        links
+    } else {
+        vec![r#"<a href="/login/soundcloud">login</a>"#.to_string()]
+    };
 
     let html = format!(
         r#"<html>
-        <head><title>Vimeo Oauth2</title></head>
+        <head><title>Share-it</title></head>
         <body>
-            {} <a href="/{}">{}</a>
+            {} {}
         </body>
     </html>"#,
         login.unwrap_or("".to_string()),
-        link,
-        link
+        links.join(" ")
     );
 
     HttpResponse::Ok().body(html)
 }
 
-async fn login(
-    session: Session,
-    state: web::Data<OauthState>
-) -> HttpResponse {
-    // Generate the authorization URL to which we'll redirect the user.
-    let (authorize_url, csrf_state) = state.client
-        .authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new("private".to_string()))
-        .url();
-
-    session.set("csrf_state", csrf_state.secret()).unwrap();
-
+async fn logout(session: Session) -> HttpResponse {
+    session.remove("login");
+    session.remove("user_id");
     HttpResponse::Found()
-        .header(header::LOCATION, authorize_url.to_string())
+        .header(header::LOCATION, "/".to_string())
         .finish()
 }
 
-#[derive(Deserialize)]
-pub struct OauthResponse {
-    code: String,
-    state: String,
-}
+// migrate brings the database up to date before we serve anything. With --migrations-dry-run, it
+// only lists what it would apply, and the server doesn't start.
+fn migrate(pool: &MysqlPool) -> bool {
+    let dry_run = env::args().any(|arg| arg == "--migrations-dry-run");
+    let mut conn = pool.connection()
+        .unwrap_or_else(|e| panic!("Couldn't connect to the database: {}", e));
+    let migrator = Migrator::for_dialect(Dialect::Mysql);
+    let migrations = if dry_run {
+        migrator.pending(&mut conn)
+    } else {
+        migrator.migrate(&mut conn)
+    }.unwrap_or_else(|e| panic!("Failed to migrate the database: {}", e));
 
-async fn auth_listener(
-    session: Session,
-    state: web::Data<OauthState>,
-    web::Query(req): web::Query<OauthResponse>,
-) -> HttpResponse {
-    let code = AuthorizationCode::new(req.code.clone());
-    let sent_state_str = session.get::<String>("csrf_state").unwrap();
-    let should_bail = if let Some(sent_state) = sent_state_str {
-        if sent_state == req.state {
-            false
-        } else {
-            true
-        }
-    } else { true };
-    if should_bail {
-        return HttpResponse::BadRequest().body("CSRF Attack detected. States did not match during oauth validation process.");
+    let verb = if dry_run { "Would apply" } else { "Applied" };
+    for migration in &migrations {
+        println!("{} migration {} ({})", verb, migration.version, migration.name);
+    }
+    if migrations.is_empty() {
+        println!("The database is up to date.");
     }
 
-    let token_res = state
-        .client
-        .exchange_code(code)
-        .request(http_client);
-
-    if let Ok(token) = token_res {
-        let scopes = if let Some(scopes_vec) = token.scopes() {
-            scopes_vec
-                .iter()
-                .map(|comma_separated| comma_separated.split(','))
-                .flatten()
-                .collect::<Vec<_>>()
-        } else {
-            Vec::new()
-        };
+    !dry_run
+}
 
-        session.set("login", token.access_token().secret()).unwrap();
-
-        // TODO: Actually return the scopes.
-        HttpResponse::Ok().body(format!(r#"<!DOCTYPE html>
-                               <html lang="en">
-                                 <head>
-                                   <meta charset="utf-8">
-                                   <title>Vimeo Access Token</title>
-                                 </head>
-                                 <body>
-                                    Vimeo access token:
-                                    <pre>{}</pre>
-                                    <a href="/">Home</a>
-                                 </body>
-                               </html>
-                               "#, token.access_token().secret()))
-    } else {
-        HttpResponse::InternalServerError().body("We didn't get a token back from vimeo, using the code from the oauth process.")
-    }
+// spawn_availability_checker starts checking every user's songs in the background, so songs
+// deleted or made private on SoundCloud are skipped rather than played.
+fn spawn_availability_checker(pool: &MysqlPool) -> AvailabilityCheckerHandle<SqlError> {
+    let connect = || MysqlUsers::new(pool).unwrap_or_else(|e| panic!("Couldn't connect to the database: {}", e));
+    let (users, mut listing) = (connect(), connect());
+    let client_id = env::var("SOUNDCLOUD_CLIENT_ID").expect("Missing the SOUNDCLOUD_CLIENT_ID environment variable.");
+
+    // reqwest's blocking client can't be built on the server's runtime, so the checker is set up
+    // on a thread of its own.
+    thread::spawn(move || {
+        AvailabilityChecker::new(users, HttpSoundcloudClient::new(client_id))
+            .spawn(AVAILABILITY_CHECK_INTERVAL, move || every_user(&mut listing))
+    }).join().expect("Failed to start the availability checker.")
 }
 
-async fn logout(session: Session) -> HttpResponse {
-    session.remove("login");
-    HttpResponse::Found()
-        .header(header::LOCATION, "/".to_string())
-        .finish()
+// every_user lists the id of every user, a page at a time. If listing them fails part way, then
+// this run checks the users listed so far.
+fn every_user(users: &mut MysqlUsers) -> Vec<UserID> {
+    let mut user_ids = Vec::new();
+    let mut request = Some(PageRequest::first(100));
+    while let Some(page_request) = request {
+        let page = match users.query(&UserFilter::default(), &page_request) {
+            Ok(page) => page,
+            Err(e) => {
+                eprintln!("Couldn't list the users to check: {}", e);
+                break;
+            },
+        };
+        user_ids.extend(page.items.iter().map(|user| user.id()));
+        request = page.next_page(&page_request);
+    }
+    user_ids
 }
 
+// report_failed_checks logs the checks the availability checker couldn't finish, for as long as
+// the server runs.
+async fn report_failed_checks(checker: AvailabilityCheckerHandle<SqlError>) {
+    let mut ticks = actix_rt::time::interval(AVAILABILITY_CHECK_INTERVAL);
+    loop {
+        ticks.tick().await;
+        for e in checker.failures() {
+            eprintln!("Song availability check failed: {}", e);
+        }
+    }
+}
 
 #[actix_rt::main]
 async fn main() {
     dotenv().ok();
 
+    let pool = MysqlPool::from_env()
+        .unwrap_or_else(|e| panic!("Couldn't connect to the database: {}", e));
+    if !migrate(&pool) {
+        return;
+    }
+
     let addr = match std::env::var("SERVER_HOST") {
         Ok(host) => host,
         Err(_) => "0.0.0.0:8080".to_string(),
     };
 
-    HttpServer::new(|| {
-        let vimeo_client_id = ClientId::new(
-            env::var("VIMEO_CLIENT_ID").expect("Missing the VIMEO_CLIENT_ID environment variable."),
-        );
-        let vimeo_client_secret = ClientSecret::new(
-            env::var("VIMEO_CLIENT_SECRET")
-                .expect("Missing the VIMEO_CLIENT_SECRET environment variable."),
-        );
-        let auth_url = AuthUrl::new("https://api.vimeo.com/oauth/authorize".to_string())
-            .expect("Invalid authorization endpoint URL");
-        let token_url = TokenUrl::new("https://api.vimeo.com/oauth/access_token".to_string())
-            .expect("Invalid token endpoint URL");
-
-        let client = BasicClient::new(
-            vimeo_client_id,
-            Some(vimeo_client_secret),
-            auth_url,
-            Some(token_url),
-        )
-        .set_redirect_url(
-            RedirectUrl::new("http://localhost:8080/auth".to_string()).expect("Invalid redirect URL"),
-        );
-
+    let providers = OauthProviders::from_env()
+        .unwrap_or_else(|e| panic!("Invalid oauth provider config: {}", e));
+    // Generate one with e.g. `openssl rand -base64 32`.
+    let cipher = TokenCipher::from_base64(
+        &env::var("TOKEN_ENCRYPTION_KEY").expect("Missing the TOKEN_ENCRYPTION_KEY environment variable."),
+    ).unwrap_or_else(|e| panic!("Invalid TOKEN_ENCRYPTION_KEY: {}", e));
+    let users = Arc::new(Mutex::new(
+        MysqlUsers::new(&pool).unwrap_or_else(|e| panic!("Couldn't connect to the database: {}", e)),
+    ));
+    actix_rt::spawn(report_failed_checks(spawn_availability_checker(&pool)));
+
+    // Every worker gets its own state, so the token vault has to be shared explicitly.
+    let oauth_state = web::Data::new(OauthState::new(
+        providers.clone(),
+        users,
+        MysqlTokens::new(&pool).unwrap_or_else(|e| panic!("Couldn't connect to the database: {}", e)),
+        cipher,
+    ));
+
+    HttpServer::new(move || {
         App::new()
-            .data(OauthState {
-                client,
-            })
+            .data(providers.clone())
+            .app_data(oauth_state.clone())
             // TODO: Make this actual prod ready
             .wrap(CookieSession::signed(&[0; 32]).secure(false))
             .route("/", web::get().to(index))
-            .route("/login", web::get().to(login))
             .route("/logout", web::get().to(logout))
-            .route("/auth", web::get().to(auth_listener))
+            .configure(oauth::routes::<MysqlUsers, MysqlTokens>)
         })
         .bind(&addr)
         .expect("Can not bind to port 8080")
         .run()
         .await
         .unwrap();
-}
\ No newline at end of file
+}
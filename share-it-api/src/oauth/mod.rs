@@ -0,0 +1,352 @@
+use std::sync::{Arc, Mutex};
+
+use actix_web::{web, HttpResponse};
+use actix_web::http::header;
+use actix_session::Session;
+use oauth2::http::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION};
+use oauth2::http::method::Method;
+use oauth2::reqwest::http_client;
+use oauth2::{AuthorizationCode, CsrfToken, HttpRequest, Scope};
+use share_it_core::blocking::{BlockingHandler, Spawner};
+use share_it_core::error::Error;
+use share_it_core::repositories::abstractions::{Query, Repository};
+use share_it_core::repositories::query::UserFilter;
+use share_it_core::services::abstractions::AsyncHandles;
+use share_it_core::services::commands::{LinkIdentityCmd, LoginCmd};
+use share_it_core::tokens::{StoredToken, TokenCipher, TokenError, TokenGrant, TokenKey, TokenVault};
+use share_it_core::user::{User, UserID, Username};
+use share_it_core::UserHandler;
+
+use crate::blocking::ActixSpawner;
+use crate::OauthResponse;
+
+pub mod profiles;
+pub mod provider;
+
+pub use profiles::{MappedProfile, ProfileMapper};
+pub use provider::{token_grant, OauthProvider, OauthProviders, ProviderConfig, ProviderConfigError, RefreshError};
+
+pub type Tokens<T> = TokenVault<T, OauthProviders>;
+
+pub struct OauthState<R, T> where
+    R: Repository<u32, User>,
+    T: Repository<TokenKey, StoredToken>,
+{
+    providers: OauthProviders,
+    users: Arc<Mutex<R>>,
+    // Provider tokens never leave the server. They're kept here, encrypted, per user.
+    tokens: Arc<Mutex<Tokens<T>>>,
+}
+
+impl<R, T> OauthState<R, T> where
+    R: Repository<u32, User>,
+    T: Repository<TokenKey, StoredToken>,
+{
+    pub fn new(providers: OauthProviders, users: Arc<Mutex<R>>, tokens: T, cipher: TokenCipher) -> OauthState<R, T> {
+        let tokens = TokenVault::new(tokens, cipher, providers.clone());
+
+        OauthState {
+            providers,
+            users,
+            tokens: Arc::new(Mutex::new(tokens)),
+        }
+    }
+
+    pub fn tokens(&self) -> Arc<Mutex<Tokens<T>>> {
+        self.tokens.clone()
+    }
+}
+
+// routes registers the login, OAuth callback and profile sync routes for every registered provider.
+pub fn routes<R, T>(cfg: &mut web::ServiceConfig) where
+    R: Repository<u32, User> + Query<UserFilter, User, Cursor = UserID> + Send + 'static,
+    T: Repository<TokenKey, StoredToken> + Send + 'static,
+{
+    cfg.route("/login/{provider}", web::get().to(login::<R, T>))
+        .route("/auth/{provider}", web::get().to(auth_listener::<R, T>))
+        .route("/sync/{provider}", web::get().to(sync::<R, T>));
+}
+
+// Each provider gets its own CSRF state, so starting one flow doesn't clobber another.
+fn csrf_state_key(provider: &str) -> String {
+    format!("{}_csrf_state", provider)
+}
+
+pub async fn login<R, T>(
+    session: Session,
+    state: web::Data<OauthState<R, T>>,
+    provider: web::Path<String>,
+) -> HttpResponse where
+    R: Repository<u32, User> + Send + 'static,
+    T: Repository<TokenKey, StoredToken> + Send + 'static,
+{
+    let provider = match state.providers.get(&provider) {
+        Some(provider) => provider,
+        None => return HttpResponse::NotFound().body("Unknown login provider."),
+    };
+
+    // Generate the authorization URL to which we'll redirect the user.
+    let mut auth_request = provider.client().authorize_url(CsrfToken::new_random);
+    for scope in provider.scopes() {
+        auth_request = auth_request.add_scope(Scope::new(scope.clone()));
+    }
+    let (authorize_url, csrf_state) = auth_request.url();
+
+    session.set(&csrf_state_key(provider.name()), csrf_state.secret()).unwrap();
+
+    HttpResponse::Found()
+        .header(header::LOCATION, authorize_url.to_string())
+        .finish()
+}
+
+pub async fn auth_listener<R, T>(
+    session: Session,
+    state: web::Data<OauthState<R, T>>,
+    provider: web::Path<String>,
+    web::Query(req): web::Query<OauthResponse>,
+) -> HttpResponse where
+    R: Repository<u32, User> + Query<UserFilter, User, Cursor = UserID> + Send + 'static,
+    T: Repository<TokenKey, StoredToken> + Send + 'static,
+{
+    let provider = match state.providers.get(&provider) {
+        Some(provider) => provider,
+        None => return HttpResponse::NotFound().body("Unknown login provider."),
+    };
+
+    let state_key = csrf_state_key(provider.name());
+    let sent_state = session.get::<String>(&state_key).unwrap();
+    if sent_state.as_ref() != Some(&req.state) {
+        return HttpResponse::BadRequest().body("CSRF Attack detected. States did not match during oauth validation process.");
+    }
+    session.remove(&state_key);
+
+    let current_user = session.get::<UserID>("user_id").unwrap();
+    let code = AuthorizationCode::new(req.code);
+    let result = complete_login(&provider, code, current_user, &state.users, &state.tokens).await;
+
+    logged_in_response(&session, provider.name(), result)
+}
+
+// sync refreshes the logged in user from their account on the provider, using the token we kept
+// from when they logged in with it.
+pub async fn sync<R, T>(
+    session: Session,
+    state: web::Data<OauthState<R, T>>,
+    provider: web::Path<String>,
+) -> HttpResponse where
+    R: Repository<u32, User> + Query<UserFilter, User, Cursor = UserID> + Send + 'static,
+    T: Repository<TokenKey, StoredToken> + Send + 'static,
+{
+    let provider = match state.providers.get(&provider) {
+        Some(provider) => provider,
+        None => return HttpResponse::NotFound().body("Unknown login provider."),
+    };
+    let user_id = match session.get::<UserID>("user_id").unwrap() {
+        Some(user_id) => user_id,
+        None => return HttpResponse::Unauthorized().body("Log in before syncing your accounts."),
+    };
+
+    let result = sync_profile(&provider, user_id, &state.users, &state.tokens).await;
+
+    logged_in_response(&session, provider.name(), result)
+}
+
+fn logged_in_response(session: &Session, provider: &str, result: Result<LoggedIn, LoginError>) -> HttpResponse {
+    match result {
+        Ok(logged_in) => {
+            session.set("user_id", logged_in.user_id).unwrap();
+            if let Some(username) = logged_in.username {
+                session.set("login", username).unwrap();
+            }
+            HttpResponse::Found()
+                .header(header::LOCATION, "/".to_string())
+                .finish()
+        },
+        // We have no token for the provider, or one we can't use anymore, so the user has to go
+        // through the provider's login again.
+        Err(LoginError::NoToken) |
+        Err(LoginError::Tokens(TokenError::Expired)) |
+        Err(LoginError::Tokens(TokenError::Refresh(_))) => {
+            HttpResponse::Found()
+                .header(header::LOCATION, format!("/login/{}", provider))
+                .finish()
+        },
+        Err(LoginError::NotLoggedIn) => {
+            HttpResponse::Unauthorized().body(format!("Log in before linking your {} account.", provider))
+        },
+        Err(LoginError::TokenExchange(_)) => {
+            HttpResponse::InternalServerError().body(format!("We didn't get a token back from {}, using the code from the oauth process.", provider))
+        },
+        Err(LoginError::Users(Error::Conflict(_))) => {
+            HttpResponse::Conflict().body(format!("That {} account is already linked to someone else.", provider))
+        },
+        Err(LoginError::Profile(_)) => {
+            HttpResponse::BadGateway().body(format!("We couldn't fetch your profile from {}.", provider))
+        },
+        Err(_) => {
+            HttpResponse::InternalServerError().body("We couldn't log you in.")
+        },
+    }
+}
+
+#[derive(Debug)]
+enum LoginError {
+    TokenExchange(String),
+    Profile(String),
+    Users(Error),
+    Tokens(TokenError),
+    // Only a SoundCloud login creates users, so other providers need someone to link to.
+    NotLoggedIn,
+    // The user never logged in with the provider, so we have no token for it.
+    NoToken,
+    // The login's blocking work never finished, e.g. because the server is shutting down.
+    Canceled,
+}
+
+struct LoggedIn {
+    user_id: UserID,
+    // Only set when the login changed who the user is.
+    username: Option<Username>,
+}
+
+// complete_login trades the OAuth code for an access token, finds out who it belongs to, and
+// keeps the token for later.
+async fn complete_login<R, T>(
+    provider: &Arc<OauthProvider>,
+    code: AuthorizationCode,
+    current_user: Option<UserID>,
+    users: &Arc<Mutex<R>>,
+    tokens: &Arc<Mutex<Tokens<T>>>,
+) -> Result<LoggedIn, LoginError> where
+    R: Repository<u32, User> + Query<UserFilter, User, Cursor = UserID> + Send + 'static,
+    T: Repository<TokenKey, StoredToken> + Send + 'static,
+{
+    let exchanging = provider.clone();
+    let (grant, profile) = blocking(move || {
+        let token = exchanging.client()
+            .exchange_code(code)
+            .request(http_client)
+            .map_err(|e| LoginError::TokenExchange(e.to_string()))?;
+        let grant = token_grant(&token);
+
+        let profile = fetch_profile(&exchanging, &grant.access_token)?;
+        Ok((grant, profile))
+    }).await?;
+
+    let logged_in = apply_profile(profile, current_user, users).await?;
+
+    store_token(provider, logged_in.user_id, grant, tokens).await?;
+    Ok(logged_in)
+}
+
+async fn sync_profile<R, T>(
+    provider: &Arc<OauthProvider>,
+    user_id: UserID,
+    users: &Arc<Mutex<R>>,
+    tokens: &Arc<Mutex<Tokens<T>>>,
+) -> Result<LoggedIn, LoginError> where
+    R: Repository<u32, User> + Query<UserFilter, User, Cursor = UserID> + Send + 'static,
+    T: Repository<TokenKey, StoredToken> + Send + 'static,
+{
+    let profile = on_behalf_of(provider, user_id, tokens, fetch_profile).await?;
+
+    apply_profile(profile, Some(user_id), users).await
+}
+
+// on_behalf_of calls the provider as the user, off the event loop, with the access token we keep
+// for them. Every call made with a stored token goes through here, so the vault gets to refresh it
+// first if it's about to expire. Only logins call providers without it, with the token they were
+// just granted.
+async fn on_behalf_of<T, F, I>(
+    provider: &Arc<OauthProvider>,
+    user_id: UserID,
+    tokens: &Arc<Mutex<Tokens<T>>>,
+    call: F,
+) -> Result<I, LoginError> where
+    T: Repository<TokenKey, StoredToken> + Send + 'static,
+    F: FnOnce(&OauthProvider, &str) -> Result<I, LoginError> + Send + 'static,
+    I: Send + 'static,
+{
+    let provider = provider.clone();
+    let tokens = tokens.clone();
+    blocking(move || {
+        let access_token = tokens.lock().unwrap()
+            .access_token(user_id, provider.name())
+            .map_err(LoginError::Tokens)?
+            .ok_or(LoginError::NoToken)?;
+
+        call(&provider, &access_token)
+    }).await
+}
+
+// apply_profile creates or refreshes the share-it user for a SoundCloud account, and links any
+// other account to the current user.
+async fn apply_profile<R>(
+    profile: MappedProfile,
+    current_user: Option<UserID>,
+    users: &Arc<Mutex<R>>,
+) -> Result<LoggedIn, LoginError> where
+    R: Repository<u32, User> + Query<UserFilter, User, Cursor = UserID> + Send + 'static,
+{
+    let handler = BlockingHandler::with_spawner(UserHandler::new(users.clone()), ActixSpawner);
+    let (persisted, username) = match profile {
+        MappedProfile::Soundcloud(soundcloud_user) => {
+            let username = soundcloud_user.username.clone();
+            (handler.handle(LoginCmd { soundcloud_user }).await, Some(username))
+        },
+        MappedProfile::Linked(identity) => {
+            let user_id = current_user.ok_or(LoginError::NotLoggedIn)?;
+            (handler.handle(LinkIdentityCmd { user_id, identity }).await, None)
+        },
+    };
+
+    let user = persisted.map_err(|_| LoginError::Canceled)?.map_err(LoginError::Users)?;
+    Ok(LoggedIn { user_id: user.id, username })
+}
+
+async fn store_token<T>(
+    provider: &OauthProvider,
+    user_id: UserID,
+    grant: TokenGrant,
+    tokens: &Arc<Mutex<Tokens<T>>>,
+) -> Result<(), LoginError> where
+    T: Repository<TokenKey, StoredToken> + Send + 'static,
+{
+    let name = provider.name().to_string();
+    let tokens = tokens.clone();
+    blocking(move || {
+        tokens.lock().unwrap()
+            .store(user_id, &name, grant)
+            .map_err(LoginError::Tokens)
+    }).await
+}
+
+// blocking runs network and storage IO off the event loop.
+async fn blocking<F, I>(f: F) -> Result<I, LoginError> where
+    F: FnOnce() -> Result<I, LoginError> + Send + 'static,
+    I: Send + 'static,
+{
+    ActixSpawner.spawn_blocking(f).await.map_err(|_| LoginError::Canceled)?
+}
+
+fn fetch_profile(provider: &OauthProvider, access_token: &str) -> Result<MappedProfile, LoginError> {
+    let authorization = HeaderValue::from_str(&format!("{} {}", provider.token_scheme(), access_token))
+        .map_err(|e| LoginError::Profile(e.to_string()))?;
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, authorization);
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+    let response = http_client(HttpRequest {
+        url: provider.profile_url().clone(),
+        method: Method::GET,
+        headers,
+        body: Vec::new(),
+    }).map_err(|e| LoginError::Profile(e.to_string()))?;
+
+    if !response.status_code.is_success() {
+        return Err(LoginError::Profile(format!("profile request failed with {}", response.status_code)));
+    }
+
+    (provider.mapper())(&String::from_utf8_lossy(&response.body))
+        .map_err(|e| LoginError::Profile(e.to_string()))
+}
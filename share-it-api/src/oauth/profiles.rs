@@ -0,0 +1,82 @@
+use serde::Deserialize;
+use share_it_core::soundcloud_api::SoundcloudUser;
+use share_it_core::user::LinkedIdentity;
+
+// MappedProfile is what a provider's profile endpoint tells us about who just logged in.
+pub enum MappedProfile {
+    // A SoundCloud account. Share-it users are SoundCloud users, so this can log a user in, and
+    // creates them on their first visit.
+    Soundcloud(SoundcloudUser),
+    // An account on any other provider. These can only be linked to an already logged in user.
+    Linked(LinkedIdentity),
+}
+
+// ProfileMapper turns the raw body of a provider's profile endpoint into a MappedProfile.
+pub type ProfileMapper = fn(&str) -> Result<MappedProfile, serde_json::Error>;
+
+// mapper_for returns the built in profile mapper for the named provider, if we have one.
+pub fn mapper_for(provider: &str) -> Option<ProfileMapper> {
+    match provider {
+        "soundcloud" => Some(soundcloud_profile),
+        "vimeo" => Some(vimeo_profile),
+        _ => None,
+    }
+}
+
+pub fn soundcloud_profile(body: &str) -> Result<MappedProfile, serde_json::Error> {
+    Ok(MappedProfile::Soundcloud(serde_json::from_str(body)?))
+}
+
+#[derive(Deserialize)]
+struct VimeoUser {
+    // Vimeo identifies users by uri, e.g. "/users/12345".
+    uri: String,
+    name: String,
+}
+
+pub fn vimeo_profile(body: &str) -> Result<MappedProfile, serde_json::Error> {
+    let v_user: VimeoUser = serde_json::from_str(body)?;
+    let subject = v_user.uri.rsplit('/').next().unwrap_or(&v_user.uri).to_string();
+
+    Ok(MappedProfile::Linked(LinkedIdentity {
+        provider: "vimeo".to_string(),
+        subject,
+        username: v_user.name,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mapper_for, MappedProfile};
+    use share_it_core::test_tools::fixtures::USER_JSON;
+
+    #[test]
+    fn soundcloud_profile_maps_to_soundcloud_user() {
+        let mapper = mapper_for("soundcloud").unwrap();
+
+        match mapper(USER_JSON).unwrap() {
+            MappedProfile::Soundcloud(s_user) => assert_eq!(s_user.id, 3207),
+            _ => panic!("expected a soundcloud profile"),
+        }
+    }
+
+    #[test]
+    fn vimeo_profile_maps_to_linked_identity() {
+        let mapper = mapper_for("vimeo").unwrap();
+        let body = r#"{"uri": "/users/12345", "name": "Vimeo User", "link": "https://vimeo.com/user12345"}"#;
+
+        match mapper(body).unwrap() {
+            MappedProfile::Linked(identity) => {
+                assert_eq!(identity.provider, "vimeo");
+                assert_eq!(identity.subject, "12345");
+                assert_eq!(identity.username, "Vimeo User");
+            },
+            _ => panic!("expected a linked identity"),
+        }
+    }
+
+    #[test]
+    fn unknown_provider_has_no_mapper() {
+        assert!(mapper_for("myspace").is_none());
+    }
+}
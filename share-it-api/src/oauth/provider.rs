@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+
+use oauth2::basic::{BasicClient, BasicTokenResponse};
+use oauth2::reqwest::http_client;
+use oauth2::url::Url;
+use oauth2::{AuthType, AuthUrl, ClientId, ClientSecret, RedirectUrl, RefreshToken, TokenResponse, TokenUrl};
+use serde::Deserialize;
+use share_it_core::tokens::{TokenGrant, TokenRefresher};
+
+use crate::oauth::profiles::{self, ProfileMapper};
+
+// ProviderConfig describes an OAuth provider. It can be built in code, deserialized from a config
+// file, or read from the environment with `from_vars`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderConfig {
+    pub name: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub auth_url: String,
+    pub token_url: String,
+    pub redirect_url: String,
+    // Where we fetch the logged in account's profile from, once we have an access token.
+    pub profile_url: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    // How the access token is presented to the profile endpoint, e.g. "Bearer" or "OAuth".
+    #[serde(default = "default_token_scheme")]
+    pub token_scheme: String,
+    // Some providers want the client credentials in the token request body, rather than in a basic
+    // auth header.
+    #[serde(default)]
+    pub credentials_in_body: bool,
+}
+
+fn default_token_scheme() -> String {
+    "Bearer".to_string()
+}
+
+// Defaults for the providers we know about, so the environment only has to supply credentials.
+struct KnownProvider {
+    auth_url: &'static str,
+    token_url: &'static str,
+    profile_url: &'static str,
+    scopes: &'static [&'static str],
+    token_scheme: &'static str,
+    credentials_in_body: bool,
+}
+
+fn known_provider(name: &str) -> Option<KnownProvider> {
+    match name {
+        "soundcloud" => Some(KnownProvider {
+            auth_url: "https://soundcloud.com/connect",
+            token_url: "https://api.soundcloud.com/oauth2/token",
+            profile_url: "https://api.soundcloud.com/me",
+            scopes: &["non-expiring"],
+            token_scheme: "OAuth",
+            credentials_in_body: true,
+        }),
+        "vimeo" => Some(KnownProvider {
+            auth_url: "https://api.vimeo.com/oauth/authorize",
+            token_url: "https://api.vimeo.com/oauth/access_token",
+            profile_url: "https://api.vimeo.com/me",
+            scopes: &["private"],
+            token_scheme: "Bearer",
+            credentials_in_body: false,
+        }),
+        _ => None,
+    }
+}
+
+impl ProviderConfig {
+    // from_vars reads the named provider's config from variables prefixed with its upper cased
+    // name, e.g. SOUNDCLOUD_CLIENT_ID. The client id and secret are always required. Everything
+    // else falls back to our defaults for known providers, and is required for anything else:
+    //
+    // {NAME}_CLIENT_ID, {NAME}_CLIENT_SECRET, {NAME}_AUTH_URL, {NAME}_TOKEN_URL,
+    // {NAME}_PROFILE_URL, {NAME}_REDIRECT_URL, {NAME}_SCOPES (comma separated), {NAME}_TOKEN_SCHEME
+    pub fn from_vars<F>(name: &str, lookup: F) -> Result<ProviderConfig, ProviderConfigError> where
+        F: Fn(&str) -> Option<String>,
+    {
+        let prefix = name.to_uppercase();
+        let known = known_provider(name);
+        let var = |suffix: &str| lookup(&format!("{}_{}", prefix, suffix));
+        let required = |suffix: &str, default: Option<&str>| {
+            var(suffix)
+                .or_else(|| default.map(|d| d.to_string()))
+                .ok_or_else(|| ProviderConfigError::MissingVar(format!("{}_{}", prefix, suffix)))
+        };
+
+        let scopes = match var("SCOPES") {
+            Some(scopes) => scopes.split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            None => known.as_ref()
+                .map(|k| k.scopes.iter().map(|s| s.to_string()).collect())
+                .unwrap_or_default(),
+        };
+
+        Ok(ProviderConfig {
+            name: name.to_string(),
+            client_id: required("CLIENT_ID", None)?,
+            client_secret: required("CLIENT_SECRET", None)?,
+            auth_url: required("AUTH_URL", known.as_ref().map(|k| k.auth_url))?,
+            token_url: required("TOKEN_URL", known.as_ref().map(|k| k.token_url))?,
+            profile_url: required("PROFILE_URL", known.as_ref().map(|k| k.profile_url))?,
+            redirect_url: var("REDIRECT_URL")
+                .unwrap_or_else(|| format!("http://localhost:8080/auth/{}", name)),
+            scopes,
+            token_scheme: var("TOKEN_SCHEME")
+                .or_else(|| known.as_ref().map(|k| k.token_scheme.to_string()))
+                .unwrap_or_else(default_token_scheme),
+            credentials_in_body: known.as_ref().map(|k| k.credentials_in_body).unwrap_or(false),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ProviderConfigError {
+    MissingVar(String),
+    InvalidUrl(String),
+    // We were asked to configure a provider we have no profile mapper for.
+    UnknownProvider(String),
+}
+
+impl fmt::Display for ProviderConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProviderConfigError::MissingVar(var) => write!(f, "missing the {} environment variable", var),
+            ProviderConfigError::InvalidUrl(url) => write!(f, "invalid oauth url {}", url),
+            ProviderConfigError::UnknownProvider(name) => write!(f, "no profile mapper for oauth provider {}", name),
+        }
+    }
+}
+
+impl error::Error for ProviderConfigError {}
+
+// OauthProvider is a configured OAuth provider, ready to send users off to and take them back from.
+pub struct OauthProvider {
+    name: String,
+    client: BasicClient,
+    scopes: Vec<String>,
+    profile_url: Url,
+    token_scheme: String,
+    mapper: ProfileMapper,
+}
+
+impl OauthProvider {
+    pub fn new(config: ProviderConfig, mapper: ProfileMapper) -> Result<OauthProvider, ProviderConfigError> {
+        let invalid = |url: &str| ProviderConfigError::InvalidUrl(url.to_string());
+
+        let mut client = BasicClient::new(
+            ClientId::new(config.client_id),
+            Some(ClientSecret::new(config.client_secret)),
+            AuthUrl::new(config.auth_url.clone()).map_err(|_| invalid(&config.auth_url))?,
+            Some(TokenUrl::new(config.token_url.clone()).map_err(|_| invalid(&config.token_url))?),
+        )
+        .set_redirect_url(
+            RedirectUrl::new(config.redirect_url.clone()).map_err(|_| invalid(&config.redirect_url))?,
+        );
+        if config.credentials_in_body {
+            client = client.set_auth_type(AuthType::RequestBody);
+        }
+
+        Ok(OauthProvider {
+            name: config.name,
+            client,
+            scopes: config.scopes,
+            profile_url: Url::parse(&config.profile_url).map_err(|_| invalid(&config.profile_url))?,
+            token_scheme: config.token_scheme,
+            mapper,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn client(&self) -> &BasicClient {
+        &self.client
+    }
+
+    pub fn scopes(&self) -> &[String] {
+        &self.scopes
+    }
+
+    pub fn profile_url(&self) -> &Url {
+        &self.profile_url
+    }
+
+    pub fn token_scheme(&self) -> &str {
+        &self.token_scheme
+    }
+
+    pub fn mapper(&self) -> ProfileMapper {
+        self.mapper
+    }
+}
+
+// OauthProviders is the registry of every provider users can log in or link accounts with, keyed
+// by provider name. The name is also what shows up in the provider's routes.
+#[derive(Clone, Default)]
+pub struct OauthProviders {
+    providers: HashMap<String, Arc<OauthProvider>>,
+}
+
+impl OauthProviders {
+    pub fn new() -> OauthProviders {
+        OauthProviders {
+            providers: HashMap::new(),
+        }
+    }
+
+    // from_env configures the providers listed in OAUTH_PROVIDERS (comma separated, defaulting to
+    // just soundcloud) from the environment. See `ProviderConfig::from_vars` for the variables.
+    pub fn from_env() -> Result<OauthProviders, ProviderConfigError> {
+        OauthProviders::from_vars(|key| env::var(key).ok())
+    }
+
+    pub fn from_vars<F>(lookup: F) -> Result<OauthProviders, ProviderConfigError> where
+        F: Fn(&str) -> Option<String>,
+    {
+        let names = lookup("OAUTH_PROVIDERS").unwrap_or_else(|| "soundcloud".to_string());
+        let configs = names.split(',')
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+            .map(|name| ProviderConfig::from_vars(name, &lookup))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        OauthProviders::from_configs(configs)
+    }
+
+    // from_configs builds the registry from already loaded configs, using the built in profile
+    // mapper for each provider.
+    pub fn from_configs(configs: Vec<ProviderConfig>) -> Result<OauthProviders, ProviderConfigError> {
+        let mut providers = OauthProviders::new();
+        for config in configs {
+            let mapper = profiles::mapper_for(&config.name)
+                .ok_or_else(|| ProviderConfigError::UnknownProvider(config.name.clone()))?;
+            providers.register(OauthProvider::new(config, mapper)?);
+        }
+        Ok(providers)
+    }
+
+    // register adds the provider, replacing any provider already registered with the same name.
+    pub fn register(&mut self, provider: OauthProvider) {
+        self.providers.insert(provider.name().to_string(), Arc::new(provider));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<OauthProvider>> {
+        self.providers.get(name).cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.providers.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+// Registered providers are also what refreshes their tokens.
+impl TokenRefresher for OauthProviders {
+    type Error = RefreshError;
+
+    fn refresh(&self, provider: &str, refresh_token: &str) -> Result<TokenGrant, Self::Error> {
+        let provider = self.get(provider)
+            .ok_or_else(|| RefreshError::UnknownProvider(provider.to_string()))?;
+
+        let token = provider.client()
+            .exchange_refresh_token(&RefreshToken::new(refresh_token.to_string()))
+            .request(http_client)
+            .map_err(|e| RefreshError::Request(e.to_string()))?;
+
+        Ok(token_grant(&token))
+    }
+}
+
+// token_grant pulls what we keep of a token out of a provider's token response.
+pub fn token_grant(token: &BasicTokenResponse) -> TokenGrant {
+    TokenGrant {
+        access_token: token.access_token().secret().clone(),
+        refresh_token: token.refresh_token().map(|t| t.secret().clone()),
+        expires_in: token.expires_in(),
+    }
+}
+
+#[derive(Debug)]
+pub enum RefreshError {
+    UnknownProvider(String),
+    Request(String),
+}
+
+impl fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RefreshError::UnknownProvider(name) => write!(f, "can't refresh tokens for unknown provider {}", name),
+            RefreshError::Request(e) => write!(f, "refresh request failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for RefreshError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{OauthProviders, ProviderConfig, ProviderConfigError};
+    use std::collections::HashMap;
+
+    fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn known_provider_only_needs_credentials() {
+        let vars = lookup(&[("VIMEO_CLIENT_ID", "id"), ("VIMEO_CLIENT_SECRET", "secret")]);
+
+        let config = ProviderConfig::from_vars("vimeo", vars).unwrap();
+
+        assert_eq!(config.auth_url, "https://api.vimeo.com/oauth/authorize");
+        assert_eq!(config.redirect_url, "http://localhost:8080/auth/vimeo");
+        assert_eq!(config.scopes, vec!["private".to_string()]);
+        assert_eq!(config.token_scheme, "Bearer");
+    }
+
+    #[test]
+    fn env_overrides_defaults() {
+        let vars = lookup(&[
+            ("SOUNDCLOUD_CLIENT_ID", "id"),
+            ("SOUNDCLOUD_CLIENT_SECRET", "secret"),
+            ("SOUNDCLOUD_TOKEN_URL", "http://localhost:9999/token"),
+            ("SOUNDCLOUD_SCOPES", "non-expiring, *"),
+        ]);
+
+        let config = ProviderConfig::from_vars("soundcloud", vars).unwrap();
+
+        assert_eq!(config.token_url, "http://localhost:9999/token");
+        assert_eq!(config.scopes, vec!["non-expiring".to_string(), "*".to_string()]);
+        assert!(config.credentials_in_body);
+    }
+
+    #[test]
+    fn missing_credentials_are_an_error() {
+        match ProviderConfig::from_vars("soundcloud", lookup(&[("SOUNDCLOUD_CLIENT_ID", "id")])) {
+            Err(ProviderConfigError::MissingVar(var)) => assert_eq!(var, "SOUNDCLOUD_CLIENT_SECRET"),
+            _ => panic!("expected a missing variable error"),
+        }
+    }
+
+    #[test]
+    fn registry_is_built_from_listed_providers() {
+        let vars = lookup(&[
+            ("OAUTH_PROVIDERS", "soundcloud, vimeo"),
+            ("SOUNDCLOUD_CLIENT_ID", "id"),
+            ("SOUNDCLOUD_CLIENT_SECRET", "secret"),
+            ("VIMEO_CLIENT_ID", "id"),
+            ("VIMEO_CLIENT_SECRET", "secret"),
+        ]);
+
+        let providers = OauthProviders::from_vars(vars).unwrap();
+
+        assert_eq!(providers.names(), vec!["soundcloud".to_string(), "vimeo".to_string()]);
+        assert_eq!(providers.get("soundcloud").unwrap().token_scheme(), "OAuth");
+    }
+
+    #[test]
+    fn providers_without_a_mapper_are_rejected() {
+        let vars = lookup(&[
+            ("OAUTH_PROVIDERS", "myspace"),
+            ("MYSPACE_CLIENT_ID", "id"),
+            ("MYSPACE_CLIENT_SECRET", "secret"),
+            ("MYSPACE_AUTH_URL", "https://myspace.com/auth"),
+            ("MYSPACE_TOKEN_URL", "https://myspace.com/token"),
+            ("MYSPACE_PROFILE_URL", "https://myspace.com/me"),
+        ]);
+
+        match OauthProviders::from_vars(vars) {
+            Err(ProviderConfigError::UnknownProvider(name)) => assert_eq!(name, "myspace"),
+            _ => panic!("expected an unknown provider error"),
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use std::sync::{Arc, Mutex};
+
+use actix_session::CookieSession;
+use actix_web::{test, web, App};
+use actix_web::cookie::Cookie;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::{header, StatusCode};
+use share_it_api::oauth::{self, profiles, OauthProvider, OauthProviders, OauthState, ProviderConfig};
+use share_it_core::{MockTokenRepository, MockUserRepository};
+use share_it_core::repositories::abstractions::Repository;
+use share_it_core::test_tools::fixtures::USER_JSON;
+use share_it_core::tokens::TokenCipher;
+use share_it_core::test_tools::{StubResponse, StubServer};
+
+const TOKEN_JSON: &str = r#"{
+    "access_token": "test_access_token",
+    "token_type": "bearer",
+    "expires_in": 3600,
+    "refresh_token": "test_refresh_token",
+    "scope": "non-expiring"
+}"#;
+
+const VIMEO_USER_JSON: &str = r#"{
+    "uri": "/users/12345",
+    "name": "Vimeo User",
+    "link": "https://vimeo.com/user12345"
+}"#;
+
+// stub_providers plays both SoundCloud's and Vimeo's OAuth endpoints and profile apis.
+fn stub_providers() -> StubServer {
+    StubServer::builder()
+        .route("/soundcloud/oauth2/token", StubResponse::json(200, TOKEN_JSON))
+        .route("/soundcloud/me", StubResponse::json(200, USER_JSON))
+        .route("/vimeo/oauth/access_token", StubResponse::json(200, TOKEN_JSON))
+        .route("/vimeo/me", StubResponse::json(200, VIMEO_USER_JSON))
+        .start()
+}
+
+type StubState = web::Data<OauthState<MockUserRepository, MockTokenRepository>>;
+
+fn stub_state(stub: &StubServer, users: Arc<Mutex<MockUserRepository>>) -> StubState {
+    web::Data::new(OauthState::new(stub_registry(stub), users, MockTokenRepository::new(), TokenCipher::new(&[7; 32])))
+}
+
+fn stub_config(stub: &StubServer, name: &str, token_path: &str, token_scheme: &str) -> ProviderConfig {
+    ProviderConfig {
+        name: name.to_string(),
+        client_id: "test_client_id".to_string(),
+        client_secret: "test_client_secret".to_string(),
+        auth_url: format!("{}/{}/authorize", stub.url(), name),
+        token_url: format!("{}/{}/{}", stub.url(), name, token_path),
+        redirect_url: format!("http://localhost:8080/auth/{}", name),
+        profile_url: format!("{}/{}/me", stub.url(), name),
+        scopes: vec!["non-expiring".to_string()],
+        token_scheme: token_scheme.to_string(),
+        credentials_in_body: name == "soundcloud",
+    }
+}
+
+fn stub_registry(stub: &StubServer) -> OauthProviders {
+    let mut providers = OauthProviders::new();
+    providers.register(OauthProvider::new(
+        stub_config(stub, "soundcloud", "oauth2/token", "OAuth"),
+        profiles::soundcloud_profile,
+    ).unwrap());
+    providers.register(OauthProvider::new(
+        stub_config(stub, "vimeo", "oauth/access_token", "Bearer"),
+        profiles::vimeo_profile,
+    ).unwrap());
+    providers
+}
+
+fn query_param(url: &str, name: &str) -> String {
+    let query = url.splitn(2, '?').nth(1).unwrap_or("");
+    query.split('&')
+        .filter_map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) if k == name => Some(v.to_string()),
+                _ => None,
+            }
+        })
+        .next()
+        .unwrap()
+}
+
+fn location<B>(resp: &ServiceResponse<B>) -> String {
+    resp.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string()
+}
+
+fn session_cookie<B>(resp: &ServiceResponse<B>) -> Cookie<'static> {
+    resp.response().cookies().next().unwrap().into_owned()
+}
+
+#[actix_rt::test]
+async fn soundcloud_login_creates_user() {
+    let stub = stub_providers();
+    let users = Arc::new(Mutex::new(MockUserRepository::new()));
+    let state = stub_state(&stub, users.clone());
+    let mut app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap(CookieSession::signed(&[0; 32]).secure(false))
+            .configure(oauth::routes::<MockUserRepository, MockTokenRepository>)
+    ).await;
+
+    // Start the flow, and get sent off to the provider.
+    let req = test::TestRequest::get().uri("/login/soundcloud").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    assert!(location(&resp).starts_with(&format!("{}/soundcloud/authorize", stub.url())));
+    let csrf = query_param(&location(&resp), "state");
+    let cookie = session_cookie(&resp);
+
+    // Come back from the provider with a code.
+    let req = test::TestRequest::get()
+        .uri(&format!("/auth/soundcloud?code=test_code&state={}", csrf))
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    assert_eq!(location(&resp), "/");
+
+    let user = users.lock().unwrap().get(&3207).unwrap().unwrap();
+    assert_eq!(user.username(), "Johannes Wagener");
+    assert!(user.identity("soundcloud").is_some());
+
+    // The token is kept on the server, and never handed to the browser.
+    assert!(!session_cookie(&resp).value().contains("test_access_token"));
+    let access_token = state.tokens().lock().unwrap().access_token(3207, "soundcloud").unwrap();
+    assert_eq!(access_token, Some("test_access_token".to_string()));
+
+    let requests = stub.requests();
+    let token_request = requests.iter().find(|r| r.path() == "/soundcloud/oauth2/token").unwrap();
+    assert!(token_request.body.contains("code=test_code"));
+    assert!(token_request.body.contains("client_secret=test_client_secret"));
+    let me_request = requests.iter().find(|r| r.path() == "/soundcloud/me").unwrap();
+    assert_eq!(me_request.headers.get("authorization"), Some(&"OAuth test_access_token".to_string()));
+}
+
+#[actix_rt::test]
+async fn vimeo_account_is_linked_to_logged_in_user() {
+    let stub = stub_providers();
+    let users = Arc::new(Mutex::new(MockUserRepository::new()));
+    let state = stub_state(&stub, users.clone());
+    let mut app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap(CookieSession::signed(&[0; 32]).secure(false))
+            .configure(oauth::routes::<MockUserRepository, MockTokenRepository>)
+    ).await;
+
+    // Log in with SoundCloud first.
+    let req = test::TestRequest::get().uri("/login/soundcloud").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let csrf = query_param(&location(&resp), "state");
+    let req = test::TestRequest::get()
+        .uri(&format!("/auth/soundcloud?code=test_code&state={}", csrf))
+        .cookie(session_cookie(&resp))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let cookie = session_cookie(&resp);
+
+    // Then link Vimeo.
+    let req = test::TestRequest::get().uri("/login/vimeo").cookie(cookie).to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    assert!(location(&resp).starts_with(&format!("{}/vimeo/authorize", stub.url())));
+    let csrf = query_param(&location(&resp), "state");
+    let req = test::TestRequest::get()
+        .uri(&format!("/auth/vimeo?code=vimeo_code&state={}", csrf))
+        .cookie(session_cookie(&resp))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+
+    let user = users.lock().unwrap().get(&3207).unwrap().unwrap();
+    let vimeo = user.identity("vimeo").unwrap();
+    assert_eq!(vimeo.subject, "12345");
+    assert_eq!(vimeo.username, "Vimeo User");
+    // Linking doesn't touch the SoundCloud side of the user.
+    assert_eq!(user.username(), "Johannes Wagener");
+    assert!(user.identity("soundcloud").is_some());
+
+    let requests = stub.requests();
+    let me_request = requests.iter().find(|r| r.path() == "/vimeo/me").unwrap();
+    assert_eq!(me_request.headers.get("authorization"), Some(&"Bearer test_access_token".to_string()));
+}
+
+#[actix_rt::test]
+async fn linking_without_login_is_unauthorized() {
+    let stub = stub_providers();
+    let users = Arc::new(Mutex::new(MockUserRepository::new()));
+    let state = stub_state(&stub, users.clone());
+    let mut app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap(CookieSession::signed(&[0; 32]).secure(false))
+            .configure(oauth::routes::<MockUserRepository, MockTokenRepository>)
+    ).await;
+
+    let req = test::TestRequest::get().uri("/login/vimeo").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let csrf = query_param(&location(&resp), "state");
+    let req = test::TestRequest::get()
+        .uri(&format!("/auth/vimeo?code=vimeo_code&state={}", csrf))
+        .cookie(session_cookie(&resp))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert!(users.lock().unwrap().get(&3207).unwrap().is_none());
+}
+
+#[actix_rt::test]
+async fn unknown_provider_is_not_found() {
+    let stub = stub_providers();
+    let users = Arc::new(Mutex::new(MockUserRepository::new()));
+    let state = stub_state(&stub, users.clone());
+    let mut app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap(CookieSession::signed(&[0; 32]).secure(false))
+            .configure(oauth::routes::<MockUserRepository, MockTokenRepository>)
+    ).await;
+
+    let req = test::TestRequest::get().uri("/login/myspace").to_request();
+    let resp = test::call_service(&mut app, req).await;
+
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn login_rejects_mismatched_state() {
+    let stub = stub_providers();
+    let users = Arc::new(Mutex::new(MockUserRepository::new()));
+    let state = stub_state(&stub, users.clone());
+    let mut app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap(CookieSession::signed(&[0; 32]).secure(false))
+            .configure(oauth::routes::<MockUserRepository, MockTokenRepository>)
+    ).await;
+
+    let req = test::TestRequest::get().uri("/login/soundcloud").to_request();
+    let resp = test::call_service(&mut app, req).await;
+
+    let req = test::TestRequest::get()
+        .uri("/auth/soundcloud?code=test_code&state=forged")
+        .cookie(session_cookie(&resp))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(stub.requests().is_empty());
+    assert!(users.lock().unwrap().get(&3207).unwrap().is_none());
+}
+
+const EXPIRING_TOKEN_JSON: &str = r#"{
+    "access_token": "expiring_access_token",
+    "token_type": "bearer",
+    "expires_in": 30,
+    "refresh_token": "test_refresh_token"
+}"#;
+
+const REFRESHED_TOKEN_JSON: &str = r#"{
+    "access_token": "refreshed_access_token",
+    "token_type": "bearer",
+    "expires_in": 3600
+}"#;
+
+#[actix_rt::test]
+async fn sync_refreshes_expiring_token_first() {
+    let stub = StubServer::builder()
+        .route("/soundcloud/oauth2/token", StubResponse::json(200, TOKEN_JSON))
+        .route("/soundcloud/me", StubResponse::json(200, USER_JSON))
+        .route("/vimeo/oauth/access_token", StubResponse::json(200, EXPIRING_TOKEN_JSON))
+        .route("/vimeo/oauth/access_token", StubResponse::json(200, REFRESHED_TOKEN_JSON))
+        .route("/vimeo/me", StubResponse::json(200, VIMEO_USER_JSON))
+        .start();
+    let users = Arc::new(Mutex::new(MockUserRepository::new()));
+    let state = stub_state(&stub, users.clone());
+    let mut app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap(CookieSession::signed(&[0; 32]).secure(false))
+            .configure(oauth::routes::<MockUserRepository, MockTokenRepository>)
+    ).await;
+
+    // Log in with SoundCloud, then link Vimeo with a token that's about to expire.
+    let req = test::TestRequest::get().uri("/login/soundcloud").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let csrf = query_param(&location(&resp), "state");
+    let req = test::TestRequest::get()
+        .uri(&format!("/auth/soundcloud?code=test_code&state={}", csrf))
+        .cookie(session_cookie(&resp))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let req = test::TestRequest::get().uri("/login/vimeo").cookie(session_cookie(&resp)).to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let csrf = query_param(&location(&resp), "state");
+    let req = test::TestRequest::get()
+        .uri(&format!("/auth/vimeo?code=vimeo_code&state={}", csrf))
+        .cookie(session_cookie(&resp))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let cookie = session_cookie(&resp);
+
+    let req = test::TestRequest::get().uri("/sync/vimeo").cookie(cookie).to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    assert_eq!(location(&resp), "/");
+
+    let requests = stub.requests();
+    let refresh_request = requests.iter()
+        .filter(|r| r.path() == "/vimeo/oauth/access_token")
+        .nth(1)
+        .unwrap();
+    assert!(refresh_request.body.contains("grant_type=refresh_token"));
+    assert!(refresh_request.body.contains("refresh_token=test_refresh_token"));
+    let last_profile_request = requests.iter().filter(|r| r.path() == "/vimeo/me").last().unwrap();
+    assert_eq!(last_profile_request.headers.get("authorization"), Some(&"Bearer refreshed_access_token".to_string()));
+
+    let access_token = state.tokens().lock().unwrap().access_token(3207, "vimeo").unwrap();
+    assert_eq!(access_token, Some("refreshed_access_token".to_string()));
+}
+
+#[actix_rt::test]
+async fn sync_without_token_sends_user_to_login() {
+    let stub = stub_providers();
+    let users = Arc::new(Mutex::new(MockUserRepository::new()));
+    let state = stub_state(&stub, users.clone());
+    let mut app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap(CookieSession::signed(&[0; 32]).secure(false))
+            .configure(oauth::routes::<MockUserRepository, MockTokenRepository>)
+    ).await;
+
+    let req = test::TestRequest::get().uri("/login/soundcloud").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let csrf = query_param(&location(&resp), "state");
+    let req = test::TestRequest::get()
+        .uri(&format!("/auth/soundcloud?code=test_code&state={}", csrf))
+        .cookie(session_cookie(&resp))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+
+    let req = test::TestRequest::get().uri("/sync/vimeo").cookie(session_cookie(&resp)).to_request();
+    let resp = test::call_service(&mut app, req).await;
+
+    assert_eq!(resp.status(), StatusCode::FOUND);
+    assert_eq!(location(&resp), "/login/vimeo");
+}
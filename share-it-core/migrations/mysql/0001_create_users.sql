@@ -0,0 +1,17 @@
+CREATE TABLE users (
+    id INT UNSIGNED NOT NULL PRIMARY KEY,
+    username VARCHAR(255) NOT NULL,
+    avatar_url TEXT NOT NULL,
+    permalink_url TEXT NOT NULL,
+    -- A ULID, so always 26 characters.
+    active_playlist CHAR(26) NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE user_identities (
+    user_id INT UNSIGNED NOT NULL,
+    provider VARCHAR(64) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    PRIMARY KEY (user_id, provider),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,34 @@
+-- Songs are SoundCloud tracks, and are shared by every playlist they're in.
+CREATE TABLE songs (
+    id INT UNSIGNED NOT NULL PRIMARY KEY,
+    user_id INT UNSIGNED NOT NULL,
+    duration_ms INT UNSIGNED NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    title VARCHAR(255) NOT NULL,
+    sharing VARCHAR(16) NOT NULL,
+    permalink VARCHAR(255) NOT NULL,
+    permalink_url TEXT NOT NULL,
+    artwork_url TEXT NULL,
+    stream_url TEXT NOT NULL,
+    availability VARCHAR(16) NOT NULL DEFAULT 'available',
+    -- SongMetadata as JSON.
+    metadata TEXT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE playlists (
+    id CHAR(26) NOT NULL PRIMARY KEY,
+    user_id INT UNSIGNED NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    INDEX (user_id),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- The songs in a playlist, in play order.
+CREATE TABLE playlist_songs (
+    playlist_id CHAR(26) NOT NULL,
+    position INT UNSIGNED NOT NULL,
+    song_id INT UNSIGNED NOT NULL,
+    PRIMARY KEY (playlist_id, position),
+    FOREIGN KEY (playlist_id) REFERENCES playlists (id) ON DELETE CASCADE,
+    FOREIGN KEY (song_id) REFERENCES songs (id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,29 @@
+CREATE TABLE chatrooms (
+    id CHAR(26) NOT NULL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    moderator INT UNSIGNED NOT NULL,
+    waitlist_id CHAR(26) NOT NULL,
+    -- Who's playing right now, and from which of their playlists.
+    current_dj INT UNSIGNED NULL,
+    current_playlist CHAR(26) NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Who's in a room, in the order they joined.
+CREATE TABLE chatroom_users (
+    chatroom_id CHAR(26) NOT NULL,
+    position INT UNSIGNED NOT NULL,
+    user_id INT UNSIGNED NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    PRIMARY KEY (chatroom_id, position),
+    FOREIGN KEY (chatroom_id) REFERENCES chatrooms (id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- A room's waitlist, in play order.
+CREATE TABLE waitlist_djs (
+    chatroom_id CHAR(26) NOT NULL,
+    position INT UNSIGNED NOT NULL,
+    user_id INT UNSIGNED NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    PRIMARY KEY (chatroom_id, position),
+    FOREIGN KEY (chatroom_id) REFERENCES chatrooms (id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,12 @@
+-- Every song played in a room, and who played it. Songs and users can outlive a room's history
+-- and the other way around, so none of these are foreign keys.
+CREATE TABLE play_history (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    chatroom_id CHAR(26) NOT NULL,
+    user_id INT UNSIGNED NOT NULL,
+    song_id INT UNSIGNED NOT NULL,
+    -- Seconds since the unix epoch.
+    played_at BIGINT UNSIGNED NOT NULL,
+    INDEX (chatroom_id, played_at),
+    INDEX (user_id, played_at)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,26 @@
+-- The log behind event sourced chatrooms: every event that happened to each chatroom, in order,
+-- and snapshots of what it looked like along the way, both as JSON. Neither is changed once it's
+-- written.
+
+-- The sequence number of each chatroom's last event. Appending moves it on, with the row locked,
+-- so only one of two appenders racing each other gets to append.
+CREATE TABLE chatroom_streams (
+    chatroom_id CHAR(26) NOT NULL PRIMARY KEY,
+    last_sequence BIGINT UNSIGNED NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE chatroom_events (
+    chatroom_id CHAR(26) NOT NULL,
+    sequence BIGINT UNSIGNED NOT NULL,
+    -- A turn's event carries the whole DJ, playlists and all, so these can outgrow TEXT.
+    event MEDIUMTEXT NOT NULL,
+    PRIMARY KEY (chatroom_id, sequence)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- What a chatroom looked like right after the event with the same sequence number.
+CREATE TABLE chatroom_snapshots (
+    chatroom_id CHAR(26) NOT NULL,
+    sequence BIGINT UNSIGNED NOT NULL,
+    state MEDIUMTEXT NOT NULL,
+    PRIMARY KEY (chatroom_id, sequence)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
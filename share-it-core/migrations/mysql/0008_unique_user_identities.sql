@@ -0,0 +1,2 @@
+-- An account on another provider can only be linked to one user.
+CREATE UNIQUE INDEX user_identities_account ON user_identities (provider, subject);
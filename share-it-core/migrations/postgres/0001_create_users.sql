@@ -0,0 +1,17 @@
+-- Ids from SoundCloud are unsigned 32 bit integers, which only fit in a BIGINT.
+CREATE TABLE users (
+    id BIGINT NOT NULL PRIMARY KEY,
+    username VARCHAR(255) NOT NULL,
+    avatar_url TEXT NOT NULL,
+    permalink_url TEXT NOT NULL,
+    -- A ULID, so always 26 characters.
+    active_playlist CHAR(26) NULL
+);
+
+CREATE TABLE user_identities (
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider VARCHAR(64) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    PRIMARY KEY (user_id, provider)
+);
@@ -0,0 +1,32 @@
+-- Songs are SoundCloud tracks, and are shared by every playlist they're in.
+CREATE TABLE songs (
+    id BIGINT NOT NULL PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    duration_ms BIGINT NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    title VARCHAR(255) NOT NULL,
+    sharing VARCHAR(16) NOT NULL,
+    permalink VARCHAR(255) NOT NULL,
+    permalink_url TEXT NOT NULL,
+    artwork_url TEXT NULL,
+    stream_url TEXT NOT NULL,
+    availability VARCHAR(16) NOT NULL DEFAULT 'available',
+    -- SongMetadata as JSON.
+    metadata TEXT NULL
+);
+
+CREATE TABLE playlists (
+    id CHAR(26) NOT NULL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL
+);
+
+CREATE INDEX playlists_user_id ON playlists (user_id);
+
+-- The songs in a playlist, in play order.
+CREATE TABLE playlist_songs (
+    playlist_id CHAR(26) NOT NULL REFERENCES playlists (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    song_id BIGINT NOT NULL REFERENCES songs (id),
+    PRIMARY KEY (playlist_id, position)
+);
@@ -0,0 +1,27 @@
+CREATE TABLE chatrooms (
+    id CHAR(26) NOT NULL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    moderator BIGINT NOT NULL,
+    waitlist_id CHAR(26) NOT NULL,
+    -- Who's playing right now, and from which of their playlists.
+    current_dj BIGINT NULL,
+    current_playlist CHAR(26) NULL
+);
+
+-- Who's in a room, in the order they joined.
+CREATE TABLE chatroom_users (
+    chatroom_id CHAR(26) NOT NULL REFERENCES chatrooms (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    user_id BIGINT NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    PRIMARY KEY (chatroom_id, position)
+);
+
+-- A room's waitlist, in play order.
+CREATE TABLE waitlist_djs (
+    chatroom_id CHAR(26) NOT NULL REFERENCES chatrooms (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    user_id BIGINT NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    PRIMARY KEY (chatroom_id, position)
+);
@@ -0,0 +1,14 @@
+-- Every song played in a room, and who played it. Songs and users can outlive a room's history
+-- and the other way around, so none of these are foreign keys.
+CREATE TABLE play_history (
+    id BIGSERIAL PRIMARY KEY,
+    chatroom_id CHAR(26) NOT NULL,
+    user_id BIGINT NOT NULL,
+    song_id BIGINT NOT NULL,
+    -- Seconds since the unix epoch.
+    played_at BIGINT NOT NULL
+);
+
+CREATE INDEX play_history_chatroom_id ON play_history (chatroom_id, played_at);
+
+CREATE INDEX play_history_user_id ON play_history (user_id, played_at);
@@ -0,0 +1,5 @@
+-- How many times each user and chatroom has been updated, so an update made from a stale copy can
+-- be turned away instead of overwriting whatever changed in the meantime.
+ALTER TABLE users ADD COLUMN version BIGINT NOT NULL DEFAULT 0;
+
+ALTER TABLE chatrooms ADD COLUMN version BIGINT NOT NULL DEFAULT 0;
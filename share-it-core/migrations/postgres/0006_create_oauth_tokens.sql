@@ -0,0 +1,9 @@
+-- Every user's provider tokens, sealed with the token encryption key. The nonce and ciphertext are
+-- base64. Tokens are removed along with the user by the repository, so user_id isn't a foreign key.
+CREATE TABLE oauth_tokens (
+    user_id BIGINT NOT NULL,
+    provider VARCHAR(64) NOT NULL,
+    nonce VARCHAR(64) NOT NULL,
+    ciphertext TEXT NOT NULL,
+    PRIMARY KEY (user_id, provider)
+);
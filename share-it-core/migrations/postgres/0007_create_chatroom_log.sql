@@ -0,0 +1,25 @@
+-- The log behind event sourced chatrooms: every event that happened to each chatroom, in order,
+-- and snapshots of what it looked like along the way, both as JSON. Neither is changed once it's
+-- written.
+
+-- The sequence number of each chatroom's last event. Appending moves it on, with the row locked,
+-- so only one of two appenders racing each other gets to append.
+CREATE TABLE chatroom_streams (
+    chatroom_id CHAR(26) NOT NULL PRIMARY KEY,
+    last_sequence BIGINT NOT NULL
+);
+
+CREATE TABLE chatroom_events (
+    chatroom_id CHAR(26) NOT NULL,
+    sequence BIGINT NOT NULL,
+    event TEXT NOT NULL,
+    PRIMARY KEY (chatroom_id, sequence)
+);
+
+-- What a chatroom looked like right after the event with the same sequence number.
+CREATE TABLE chatroom_snapshots (
+    chatroom_id CHAR(26) NOT NULL,
+    sequence BIGINT NOT NULL,
+    state TEXT NOT NULL,
+    PRIMARY KEY (chatroom_id, sequence)
+);
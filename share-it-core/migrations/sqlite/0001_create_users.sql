@@ -0,0 +1,15 @@
+CREATE TABLE users (
+    id INTEGER NOT NULL PRIMARY KEY,
+    username TEXT NOT NULL,
+    avatar_url TEXT NOT NULL,
+    permalink_url TEXT NOT NULL,
+    active_playlist TEXT NULL
+);
+
+CREATE TABLE user_identities (
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    provider TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    username TEXT NOT NULL,
+    PRIMARY KEY (user_id, provider)
+);
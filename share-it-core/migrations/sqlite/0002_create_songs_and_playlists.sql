@@ -0,0 +1,29 @@
+CREATE TABLE songs (
+    id INTEGER NOT NULL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    username TEXT NOT NULL,
+    title TEXT NOT NULL,
+    sharing TEXT NOT NULL,
+    permalink TEXT NOT NULL,
+    permalink_url TEXT NOT NULL,
+    artwork_url TEXT NULL,
+    stream_url TEXT NOT NULL,
+    availability TEXT NOT NULL DEFAULT 'available',
+    metadata TEXT NULL
+);
+
+CREATE TABLE playlists (
+    id TEXT NOT NULL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name TEXT NOT NULL
+);
+
+CREATE INDEX playlists_user_id ON playlists (user_id);
+
+CREATE TABLE playlist_songs (
+    playlist_id TEXT NOT NULL REFERENCES playlists (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    song_id INTEGER NOT NULL REFERENCES songs (id),
+    PRIMARY KEY (playlist_id, position)
+);
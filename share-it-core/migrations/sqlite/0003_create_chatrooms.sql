@@ -0,0 +1,24 @@
+CREATE TABLE chatrooms (
+    id TEXT NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    moderator INTEGER NOT NULL,
+    waitlist_id TEXT NOT NULL,
+    current_dj INTEGER NULL,
+    current_playlist TEXT NULL
+);
+
+CREATE TABLE chatroom_users (
+    chatroom_id TEXT NOT NULL REFERENCES chatrooms (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    username TEXT NOT NULL,
+    PRIMARY KEY (chatroom_id, position)
+);
+
+CREATE TABLE waitlist_djs (
+    chatroom_id TEXT NOT NULL REFERENCES chatrooms (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    username TEXT NOT NULL,
+    PRIMARY KEY (chatroom_id, position)
+);
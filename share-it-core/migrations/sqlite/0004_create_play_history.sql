@@ -0,0 +1,11 @@
+CREATE TABLE play_history (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    chatroom_id TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    song_id INTEGER NOT NULL,
+    played_at INTEGER NOT NULL
+);
+
+CREATE INDEX play_history_chatroom_id ON play_history (chatroom_id, played_at);
+
+CREATE INDEX play_history_user_id ON play_history (user_id, played_at);
@@ -0,0 +1,7 @@
+CREATE TABLE oauth_tokens (
+    user_id INTEGER NOT NULL,
+    provider TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    ciphertext TEXT NOT NULL,
+    PRIMARY KEY (user_id, provider)
+);
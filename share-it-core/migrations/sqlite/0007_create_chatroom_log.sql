@@ -0,0 +1,18 @@
+CREATE TABLE chatroom_streams (
+    chatroom_id TEXT NOT NULL PRIMARY KEY,
+    last_sequence INTEGER NOT NULL
+);
+
+CREATE TABLE chatroom_events (
+    chatroom_id TEXT NOT NULL,
+    sequence INTEGER NOT NULL,
+    event TEXT NOT NULL,
+    PRIMARY KEY (chatroom_id, sequence)
+);
+
+CREATE TABLE chatroom_snapshots (
+    chatroom_id TEXT NOT NULL,
+    sequence INTEGER NOT NULL,
+    state TEXT NOT NULL,
+    PRIMARY KEY (chatroom_id, sequence)
+);
@@ -0,0 +1,306 @@
+use crate::repositories::abstractions::Repository;
+use crate::soundcloud_api::SoundcloudClient;
+use crate::song::Availability;
+use crate::user::{User, UserID};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+// How many failed checks a handle holds on to until they're collected. Failures past that are
+// dropped, as the next run tries again anyway.
+const FAILURE_BACKLOG: usize = 16;
+
+// AvailabilityChange records a song whose availability changed during a check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvailabilityChange {
+    pub user_id: UserID,
+    pub song_id: u32,
+    pub availability: Availability,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AvailabilityReport {
+    pub users_checked: usize,
+    pub songs_checked: usize,
+    pub changed: Vec<AvailabilityChange>,
+    // Songs SoundCloud couldn't tell us about this time around. They keep whatever availability
+    // they had before.
+    pub failed: Vec<u32>,
+}
+
+// AvailabilityChecker revalidates the songs in users' playlists against SoundCloud, and marks
+// the ones that have been deleted or made private, so the waitlist can skip them instead of
+// finding out at play time.
+pub struct AvailabilityChecker<U, S> where
+    U: Repository<u32, User>,
+    S: SoundcloudClient,
+{
+    users: U,
+    soundcloud: S,
+}
+
+impl<U, S> AvailabilityChecker<U, S> where
+    U: Repository<u32, User>,
+    S: SoundcloudClient,
+{
+    pub fn new(user_repo: U, soundcloud_client: S) -> AvailabilityChecker<U, S> {
+        AvailabilityChecker {
+            users: user_repo,
+            soundcloud: soundcloud_client,
+        }
+    }
+
+    // check_users revalidates every song of every given user, persisting the users whose songs
+    // changed. Popular tracks show up in lots of playlists, so each track is only looked up once
+    // per check.
+    //
+    // A SoundCloud failure for one track doesn't stop the check, it is recorded in the report.
+    // If there was a problem communicating with the user repository, then we return its error.
+    pub fn check_users(&mut self, user_ids: &[UserID]) -> Result<AvailabilityReport, U::Error> {
+        let mut report = AvailabilityReport::default();
+        let mut checked: HashMap<u32, Option<Availability>> = HashMap::new();
+
+        for user_id in user_ids {
+            let mut user = match self.users.get(user_id)? {
+                Some(user) => user,
+                // They've gone away since we were asked to check them, nothing to do.
+                None => continue,
+            };
+            report.users_checked += 1;
+
+            let mut user_changed = false;
+            for song_id in user.song_ids() {
+                let soundcloud = &mut self.soundcloud;
+                let availability = *checked.entry(song_id).or_insert_with(|| {
+                    soundcloud.track_availability(song_id).ok()
+                });
+                report.songs_checked += 1;
+
+                let availability = match availability {
+                    Some(availability) => availability,
+                    None => {
+                        report.failed.push(song_id);
+                        continue;
+                    }
+                };
+
+                if user.set_song_availability(song_id, availability) {
+                    user_changed = true;
+                    report.changed.push(AvailabilityChange {
+                        user_id: user.id(),
+                        song_id,
+                        availability,
+                    });
+                }
+            }
+
+            if user_changed {
+                self.users.update(&user)?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    // spawn moves the checker onto a background thread, which checks whichever users
+    // `users_to_check` hands back every `interval`, until the returned handle is stopped. Checks
+    // that fail are reported through the handle.
+    pub fn spawn<F>(mut self, interval: Duration, mut users_to_check: F) -> AvailabilityCheckerHandle<U::Error> where
+        F: FnMut() -> Vec<UserID> + Send + 'static,
+        U: Send + 'static,
+        U::Error: Send + 'static,
+        S: Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel();
+        let (failed, failures) = mpsc::sync_channel(FAILURE_BACKLOG);
+        let thread = thread::spawn(move || {
+            loop {
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => (),
+                    // Either asked to stop, or the handle was dropped.
+                    _ => return,
+                }
+
+                let user_ids = users_to_check();
+                if let Err(e) = self.check_users(&user_ids) {
+                    let _ = failed.try_send(e);
+                }
+            }
+        });
+
+        AvailabilityCheckerHandle { stop, thread, failures }
+    }
+}
+
+// AvailabilityCheckerHandle controls a checker running in the background, and collects the
+// errors from its failed checks.
+pub struct AvailabilityCheckerHandle<E> {
+    stop: mpsc::Sender<()>,
+    thread: thread::JoinHandle<()>,
+    failures: mpsc::Receiver<E>,
+}
+
+impl<E> AvailabilityCheckerHandle<E> {
+    // failures returns the errors from every check that failed since it was last called.
+    pub fn failures(&self) -> Vec<E> {
+        self.failures.try_iter().collect()
+    }
+
+    // stop signals the background checker to finish, and waits for it to do so.
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AvailabilityChecker, AvailabilityChange};
+    use crate::{MockError, MockUserRepository};
+    use crate::repositories::abstractions::Repository;
+    use crate::soundcloud_api::SoundcloudTrack;
+    use crate::song::Availability;
+    use crate::test_tools::MockSoundcloudClient;
+    use crate::test_tools::factories::{new_test_user, new_test_playlist};
+    use crate::test_tools::fixtures::TRACK_JSON;
+    use crate::user::{User, UserID};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    fn test_track(track_id: u32) -> SoundcloudTrack {
+        let mut track: SoundcloudTrack = serde_json::from_str(TRACK_JSON).unwrap();
+        track.id = track_id;
+        track
+    }
+
+    fn test_user_with_songs(user_id: u32, song_count: u32) -> User {
+        let mut user = new_test_user(user_id);
+        let playlist = new_test_playlist(user_id, song_count);
+        user.set_active_playlist(&playlist.id());
+        user.add_playlist(playlist);
+        user
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn deleted_and_private_songs_are_marked() {
+        let mut repo = MockUserRepository::new();
+        repo.insert(&test_user_with_songs(0, 3));
+
+        let mut soundcloud = MockSoundcloudClient::new();
+        soundcloud.add_track(test_track(0));
+        let mut private = test_track(2);
+        private.sharing = "private".to_string();
+        soundcloud.add_track(private);
+
+        let report = AvailabilityChecker::new(&mut repo, soundcloud).check_users(&[0]).unwrap();
+
+        assert_eq!(report.users_checked, 1);
+        assert_eq!(report.songs_checked, 3);
+        assert_eq!(report.changed, vec![
+            AvailabilityChange { user_id: 0, song_id: 1, availability: Availability::Deleted },
+            AvailabilityChange { user_id: 0, song_id: 2, availability: Availability::Private },
+        ]);
+
+        // And the marks were persisted.
+        let saved = repo.get(&0).unwrap().unwrap();
+        let playlist = saved.get_playlist(saved.active_playlist().unwrap()).unwrap();
+        let availability: Vec<Availability> = playlist.songs().map(|s| s.availability()).collect();
+        assert_eq!(availability, vec![Availability::Available, Availability::Deleted, Availability::Private]);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn restored_songs_are_marked_available() {
+        let mut repo = MockUserRepository::new();
+        let mut user = test_user_with_songs(0, 1);
+        user.set_song_availability(0, Availability::Deleted);
+        repo.insert(&user);
+
+        let mut soundcloud = MockSoundcloudClient::new();
+        soundcloud.add_track(test_track(0));
+
+        let report = AvailabilityChecker::new(&mut repo, soundcloud).check_users(&[0]).unwrap();
+
+        assert_eq!(report.changed, vec![
+            AvailabilityChange { user_id: 0, song_id: 0, availability: Availability::Available },
+        ]);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn unknown_users_are_ignored() {
+        let mut repo = MockUserRepository::new();
+
+        let report = AvailabilityChecker::new(&mut repo, MockSoundcloudClient::new()).check_users(&[42]).unwrap();
+
+        assert_eq!(report.users_checked, 0);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn background_checker_runs_until_stopped() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        let checker = AvailabilityChecker::new(MockUserRepository::new(), MockSoundcloudClient::new());
+
+        let handle = checker.spawn(Duration::from_millis(1), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Vec::new()
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while runs.load(Ordering::SeqCst) < 2 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        handle.stop();
+
+        let after_stop = runs.load(Ordering::SeqCst);
+        assert!(after_stop >= 2);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(runs.load(Ordering::SeqCst), after_stop);
+    }
+
+    // Unreachable is a user repository we can't talk to.
+    struct Unreachable;
+
+    impl Repository<UserID, User> for Unreachable {
+        type Error = MockError;
+
+        fn insert(&mut self, _: &User) -> Result<Option<UserID>, MockError> {
+            Err(MockError::Failed)
+        }
+
+        fn get(&mut self, _: &UserID) -> Result<Option<User>, MockError> {
+            Err(MockError::Failed)
+        }
+
+        fn update(&mut self, _: &User) -> Result<Option<UserID>, MockError> {
+            Err(MockError::Failed)
+        }
+
+        fn remove(&mut self, _: &UserID) -> Result<Option<UserID>, MockError> {
+            Err(MockError::Failed)
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn failed_checks_are_reported_through_the_handle() {
+        let checker = AvailabilityChecker::new(Unreachable, MockSoundcloudClient::new());
+        let handle = checker.spawn(Duration::from_millis(1), || vec![1]);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut failures = Vec::new();
+        while failures.len() < 2 && Instant::now() < deadline {
+            failures.extend(handle.failures());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        handle.stop();
+
+        assert!(failures.len() >= 2);
+        assert!(failures.iter().all(|e| matches!(e, MockError::Failed)));
+    }
+}
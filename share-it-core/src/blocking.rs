@@ -0,0 +1,285 @@
+use std::error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use futures::future::{FutureExt, LocalBoxFuture};
+use crate::repositories::abstractions::{AsyncRepository, Repository};
+use crate::services::abstractions::{AsyncHandles, Handles};
+
+// The blocking adapters let synchronous repositories and handlers be awaited from an async
+// runtime. Every call runs on a Spawner, which keeps it off the threads the runtime polls
+// futures on, and the caller awaits the result.
+
+// Canceled means blocking work never finished, because it panicked or was dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Canceled;
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "blocking work was canceled before it finished")
+    }
+}
+
+impl error::Error for Canceled {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+// Spawner runs blocking work somewhere it can't stall an async runtime. Servers should use their
+// runtime's own thread pool.
+pub trait Spawner: Clone {
+    fn spawn_blocking<F, T>(&self, f: F) -> LocalBoxFuture<'static, Result<T, Canceled>> where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static;
+}
+
+// ThreadSpawner runs every piece of blocking work on a thread of its own. It works with any
+// runtime, but a thread per call is only cheap enough for tools and tests.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadSpawner;
+
+impl Spawner for ThreadSpawner {
+    fn spawn_blocking<F, T>(&self, f: F) -> LocalBoxFuture<'static, Result<T, Canceled>> where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        thread::spawn(move || {
+            // The caller may have stopped waiting, in which case nobody wants the result.
+            let _ = sender.send(f());
+        });
+
+        // A panic drops the sender without sending anything.
+        receiver.map(|result| result.map_err(|_| Canceled)).boxed_local()
+    }
+}
+
+#[derive(Debug)]
+pub enum BlockingError<E> {
+    // The repository ran, and failed.
+    Failed(E),
+    Canceled,
+}
+
+impl<E: fmt::Display> fmt::Display for BlockingError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockingError::Failed(e) => write!(f, "{}", e),
+            BlockingError::Canceled => write!(f, "{}", Canceled),
+        }
+    }
+}
+
+impl<E: error::Error + 'static> error::Error for BlockingError<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BlockingError::Failed(e) => Some(e),
+            BlockingError::Canceled => None,
+        }
+    }
+}
+
+// Blocking is an AsyncRepository backed by a synchronous Repository. Calls take turns on the
+// repository, one at a time, and clones share it.
+pub struct Blocking<R, S = ThreadSpawner> {
+    repo: Arc<Mutex<R>>,
+    spawner: S,
+}
+
+impl<R, S: Clone> Clone for Blocking<R, S> {
+    fn clone(&self) -> Self {
+        Blocking {
+            repo: self.repo.clone(),
+            spawner: self.spawner.clone(),
+        }
+    }
+}
+
+impl<R> Blocking<R> where
+    R: Send + 'static,
+{
+    pub fn new(repo: R) -> Blocking<R> {
+        Blocking::with_spawner(repo, ThreadSpawner)
+    }
+}
+
+impl<R, S> Blocking<R, S> where
+    R: Send + 'static,
+    S: Spawner,
+{
+    pub fn with_spawner(repo: R, spawner: S) -> Blocking<R, S> {
+        Blocking {
+            repo: Arc::new(Mutex::new(repo)),
+            spawner,
+        }
+    }
+
+    // run runs f with the repository on the spawner.
+    async fn run<T, E, F>(&self, f: F) -> Result<T, BlockingError<E>> where
+        F: FnOnce(&mut R) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        let repo = self.repo.clone();
+        // A poisoned lock panics, and so comes back as Canceled.
+        let result = self.spawner.spawn_blocking(move || f(&mut *repo.lock().unwrap())).await;
+
+        match result {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => Err(BlockingError::Failed(e)),
+            Err(Canceled) => Err(BlockingError::Canceled),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<K, V, R, S> AsyncRepository<K, V> for Blocking<R, S> where
+    K: Clone + Send + 'static,
+    V: Clone + Send + 'static,
+    R: Repository<K, V> + Send + 'static,
+    S: Spawner,
+{
+    type Error = BlockingError<R::Error>;
+
+    async fn insert(&self, entity: &V) -> Result<Option<K>, Self::Error> {
+        let entity = entity.clone();
+        self.run(move |repo| repo.insert(&entity)).await
+    }
+
+    async fn get(&self, key: &K) -> Result<Option<V>, Self::Error> {
+        let key = key.clone();
+        self.run(move |repo| repo.get(&key)).await
+    }
+
+    async fn contains(&self, key: &K) -> Result<bool, Self::Error> {
+        let key = key.clone();
+        self.run(move |repo| repo.contains(&key)).await
+    }
+
+    async fn update(&self, entity: &V) -> Result<Option<K>, Self::Error> {
+        let entity = entity.clone();
+        self.run(move |repo| repo.update(&entity)).await
+    }
+
+    async fn remove(&self, key: &K) -> Result<Option<K>, Self::Error> {
+        let key = key.clone();
+        self.run(move |repo| repo.remove(&key)).await
+    }
+}
+
+// BlockingHandler is an AsyncHandles backed by a synchronous handler. Commands are handled one at
+// a time, and clones share the handler.
+pub struct BlockingHandler<H, S = ThreadSpawner> {
+    handler: Arc<Mutex<H>>,
+    spawner: S,
+}
+
+impl<H, S: Clone> Clone for BlockingHandler<H, S> {
+    fn clone(&self) -> Self {
+        BlockingHandler {
+            handler: self.handler.clone(),
+            spawner: self.spawner.clone(),
+        }
+    }
+}
+
+impl<H> BlockingHandler<H> {
+    pub fn new(handler: H) -> BlockingHandler<H> {
+        BlockingHandler::with_spawner(handler, ThreadSpawner)
+    }
+}
+
+impl<H, S> BlockingHandler<H, S> {
+    pub fn with_spawner(handler: H, spawner: S) -> BlockingHandler<H, S> {
+        BlockingHandler {
+            handler: Arc::new(Mutex::new(handler)),
+            spawner,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<T, H, S> AsyncHandles<T> for BlockingHandler<H, S> where
+    T: Send + 'static,
+    H: Handles<T> + Send + 'static,
+    H::Result: Send + 'static,
+    S: Spawner,
+{
+    // The handler's own result, unless it never got to finish.
+    type Result = Result<H::Result, Canceled>;
+
+    async fn handle(&self, cmd: T) -> Self::Result {
+        let handler = self.handler.clone();
+        self.spawner.spawn_blocking(move || handler.lock().unwrap().handle(cmd)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Blocking, BlockingError, BlockingHandler, Canceled, Spawner, ThreadSpawner};
+    use crate::MockUserRepository;
+    use crate::repositories::abstractions::{AsyncRepository, Repository};
+    use crate::services::abstractions::AsyncHandles;
+    use crate::services::commands::LoginCmd;
+    use crate::soundcloud_api::SoundcloudUser;
+    use crate::test_tools::factories::new_test_user;
+    use crate::test_tools::fixtures::USER_JSON;
+    use crate::UserHandler;
+    use futures::executor::block_on;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    #[allow(unused)]
+    fn blocking_repository_keeps_the_contract() {
+        let users = Blocking::new(MockUserRepository::new());
+        let user = new_test_user(1);
+
+        block_on(async {
+            assert_eq!(users.insert(&user).await.unwrap(), Some(1));
+            assert_eq!(users.insert(&user).await.unwrap(), None);
+            assert_eq!(users.get(&1).await.unwrap(), Some(user.clone()));
+            assert!(users.contains(&1).await.unwrap());
+
+            // Clones share the repository.
+            assert_eq!(users.clone().remove(&1).await.unwrap(), Some(1));
+            assert!(users.get(&1).await.unwrap().is_none());
+            assert_eq!(users.update(&user).await.unwrap(), None);
+        });
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn blocking_handler_handles_commands() {
+        let repo = Arc::new(Mutex::new(MockUserRepository::new()));
+        let handler = BlockingHandler::new(UserHandler::new(repo.clone()));
+        let soundcloud_user: SoundcloudUser = serde_json::from_str(USER_JSON).unwrap();
+
+        let result = block_on(handler.handle(LoginCmd { soundcloud_user })).unwrap();
+
+        assert_eq!(result.unwrap().id, 3207);
+        assert!(repo.lock().unwrap().contains(&3207).unwrap());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn panics_come_back_as_canceled() {
+        let result: Result<(), Canceled> = block_on(ThreadSpawner.spawn_blocking(|| panic!("boom")));
+        assert_eq!(result, Err(Canceled));
+
+        // A repository that panicked while locked stays unusable, rather than handing out
+        // whatever it left behind.
+        let users = Blocking::new(MockUserRepository::new());
+        let poisoned = users.repo.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoned.lock().unwrap();
+            panic!("boom");
+        }).join();
+        match block_on(users.get(&1)) {
+            Err(BlockingError::Canceled) => {},
+            other => panic!("expected canceled, got {:?}", other.map(|_| ())),
+        }
+    }
+}
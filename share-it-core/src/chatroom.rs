@@ -1,14 +1,15 @@
-use crate::waitlist::{Waitlist, DJ};
-use crate::repositories::abstractions::Repository;
+use crate::events::DomainEvent;
+use crate::waitlist::{Waitlist, WaitlistNotification, DJ};
+use crate::repositories::abstractions::{Repository, Versioned};
 use crate::user::{UserID, User, Username};
 use rusty_ulid::Ulid;
 use std::collections::VecDeque;
 use crate::Song;
 
-#[derive(Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ChatUser(pub UserID, pub Username);
 
-pub(crate) struct Chatroom<T> where
+pub struct Chatroom<T> where
     T: Repository<u32, User>,
 {
     id: Ulid,
@@ -16,26 +17,61 @@ pub(crate) struct Chatroom<T> where
     moderator: UserID,
     waitlist: Waitlist<T>,
     current_users: Vec<ChatUser>,
+    // Events for changes that haven't been published yet.
+    events: Vec<DomainEvent>,
+    // Set by the repository the chatroom was loaded from.
+    version: u64,
 }
 
 impl<T> Chatroom<T> where
     T: Repository<u32, User>,
 {
     pub fn new(user_repo: T, creating_user: UserID, chatroom_name: String) -> Chatroom<T> {
+        let id = Ulid::generate();
         Chatroom {
-            id: Ulid::generate(),
-            name: chatroom_name,
+            id,
+            name: chatroom_name.clone(),
             moderator: creating_user,
             waitlist: Waitlist::new(user_repo),
             current_users: Vec::new(),
+            events: vec![DomainEvent::ChatroomCreated { chatroom_id: id, moderator: creating_user, name: chatroom_name }],
+            version: 0,
         }
     }
 
+    // restore rebuilds a chatroom from storage.
+    pub fn restore(id: Ulid,
+                   name: String,
+                   moderator: UserID,
+                   waitlist: Waitlist<T>,
+                   current_users: Vec<ChatUser>) -> Chatroom<T> {
+        Chatroom {
+            id,
+            name,
+            moderator,
+            waitlist,
+            current_users,
+            events: Vec::new(),
+            version: 0,
+        }
+    }
+
+    pub fn id(&self) -> Ulid {
+        self.id
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
     pub fn moderator(&self) -> UserID {
         self.moderator
     }
 
     pub fn change_moderator(&mut self, new_moderator: UserID) {
+        if self.moderator != new_moderator {
+            self.events.push(DomainEvent::ModeratorChanged { chatroom_id: self.id, moderator: new_moderator });
+        }
         self.moderator = new_moderator;
     }
 
@@ -47,6 +83,7 @@ impl<T> Chatroom<T> where
             return false;
         }
 
+        self.events.push(DomainEvent::UserJoined { chatroom_id: self.id, user_id: user.0, username: user.1.clone() });
         self.current_users.push(user);
         true
     }
@@ -54,7 +91,11 @@ impl<T> Chatroom<T> where
     pub fn leave(&mut self, user_id: u32) -> bool {
         let pre_len = self.current_users.len();
         self.current_users.retain(|u| u.0 != user_id);
-        self.current_users.len() != pre_len
+        let left = self.current_users.len() != pre_len;
+        if left {
+            self.events.push(DomainEvent::UserLeft { chatroom_id: self.id, user_id });
+        }
+        left
     }
 
     pub fn join_waitlist(&mut self, user_id: u32) -> bool {
@@ -71,26 +112,111 @@ impl<T> Chatroom<T> where
 
         let dj = (dj_result[0].0, dj_result[0].1.clone());
 
-        return self.waitlist.join(dj);
+        let joined = self.waitlist.join(dj);
+        if joined {
+            self.events.push(DomainEvent::DjQueued { chatroom_id: self.id, user_id });
+        }
+        joined
     }
 
     pub fn leave_waitlist(&mut self, user_id: u32) -> bool {
         let pre_len = self.waitlist.len();
         self.waitlist.leave(user_id);
-        self.waitlist.len() != pre_len()
+        let left = self.waitlist.len() != pre_len;
+        if left {
+            self.events.push(DomainEvent::DjLeft { chatroom_id: self.id, user_id });
+        }
+        left
     }
 
     pub fn len(&self) -> usize {
         self.current_users.len()
     }
 
+    pub fn current_users(&self) -> &[ChatUser] {
+        &self.current_users
+    }
+
+    pub fn waitlist(&self) -> &Waitlist<T> {
+        &self.waitlist
+    }
+
     pub fn waitlist_djs(&self) -> &VecDeque<DJ> {
         self.waitlist.djs()
     }
 
     pub fn play_next(&mut self) -> Result<Option<Song>, T::Error>{
         // TODO: We probably need to actually hand the song over for streaming somehow here.
-        self.waitlist.play_next()
+        let was_playing = self.waitlist.current_dj().is_some() || self.waitlist.len() > 0;
+        let song = self.waitlist.play_next()?;
+
+        // Anything the waitlist skipped on the way is for its owner to hear about.
+        for notification in self.waitlist.take_notifications() {
+            self.events.push(match notification {
+                WaitlistNotification::SongUnavailable { user_id, song_id, title } => {
+                    DomainEvent::SongSkipped { chatroom_id: self.id, owner: user_id, song_id, title }
+                },
+                WaitlistNotification::NothingPlayable { user_id } => {
+                    DomainEvent::TurnSkipped { chatroom_id: self.id, user_id }
+                },
+            });
+        }
+
+        match (self.waitlist.current_dj(), self.waitlist.current_playlist()) {
+            (Some(dj), Some(playlist_id)) => {
+                self.events.push(DomainEvent::TurnStarted {
+                    chatroom_id: self.id,
+                    user_id: dj.id(),
+                    playlist_id: *playlist_id,
+                    dj: Box::new(dj.clone()),
+                });
+                if let Some(song) = &song {
+                    self.events.push(DomainEvent::SongStarted {
+                        chatroom_id: self.id,
+                        user_id: dj.id(),
+                        song_id: song.id(),
+                        title: song.title(),
+                    });
+                }
+            },
+            _ => if was_playing {
+                self.events.push(DomainEvent::PlaybackStopped { chatroom_id: self.id });
+            },
+        }
+        Ok(song)
+    }
+
+    // apply replays an event recorded for this chatroom earlier, making the change it describes
+    // again without recording it twice. Turns are put back the way they were given out, with the
+    // DJ as they were then, rather than played again, so nobody's playlist is cycled twice.
+    // Skipped songs and turns are news for their owners, and the turn after them skips them again,
+    // so those change nothing here. Neither do creating, removing and restoring chatrooms, which
+    // are up to whatever stores them.
+    pub fn apply(&mut self, event: &DomainEvent) {
+        let pending = self.events.len();
+        match event {
+            DomainEvent::ModeratorChanged { moderator, .. } => self.change_moderator(*moderator),
+            DomainEvent::UserJoined { user_id, username, .. } => { self.join(ChatUser(*user_id, username.clone())); },
+            DomainEvent::UserLeft { user_id, .. } => { self.leave(*user_id); },
+            DomainEvent::DjQueued { user_id, .. } => { self.join_waitlist(*user_id); },
+            DomainEvent::DjLeft { user_id, .. } => { self.leave_waitlist(*user_id); },
+            DomainEvent::TurnStarted { dj, playlist_id, .. } => self.waitlist.replay_turn(dj, playlist_id),
+            DomainEvent::PlaybackStopped { .. } => self.waitlist.stop(),
+            _ => {},
+        }
+        self.events.truncate(pending);
+    }
+
+    // pending_events are the events for everything that changed since take_events was last called.
+    // Repositories that store events store these, so callers should save the chatroom before
+    // taking them.
+    pub fn pending_events(&self) -> &[DomainEvent] {
+        &self.events
+    }
+
+    // take_events drains the events for everything that changed since it was last called.
+    pub fn take_events(&mut self) -> Vec<DomainEvent> {
+        std::mem::take(&mut self.events)
     }
 }
 
@@ -104,15 +230,30 @@ impl<T> Clone for Chatroom<T> where
             moderator: self.moderator.clone(),
             waitlist: self.waitlist.clone(),
             current_users: self.current_users.clone(),
+            events: self.events.clone(),
+            version: self.version,
         }
     }
 }
 
+impl<T> Versioned for Chatroom<T> where
+    T: Repository<u32, User>,
+{
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::test_tools::factories::{TestChatroomSpec, new_test_chatroom, new_test_user};
     use std::collections::VecDeque;
     use crate::chatroom::ChatUser;
+    use crate::events::DomainEvent;
 
     #[test]
     #[allow(unused)]
@@ -194,4 +335,87 @@ mod tests {
 
         assert_eq!(got, &want)
     }
+
+    #[test]
+    #[allow(unused)]
+    fn test_chatroom_records_events_until_taken() {
+        let spec = TestChatroomSpec {
+            chatroom_user_count: 4,
+            playlist_per_user: 1,
+            song_per_playlist: 2,
+            // test user 1 joined the waitlist.
+            which_joined_waitlist: vec![1],
+            moderator_user: 1,
+            which_forgot_active: None,
+        };
+        let mut chatroom = new_test_chatroom(spec);
+        let chatroom_id = chatroom.id();
+
+        // Created, four users joined and one of them queued up.
+        let events = chatroom.take_events();
+        assert_eq!(events.len(), 6);
+        assert_eq!(events[0], DomainEvent::ChatroomCreated { chatroom_id, moderator: 0, name: "test_chatroom".to_string() });
+        assert!(events.contains(&DomainEvent::DjQueued { chatroom_id, user_id: 0 }));
+        assert!(chatroom.take_events().is_empty());
+
+        let song = chatroom.play_next().unwrap().unwrap();
+        chatroom.leave_waitlist(0);
+        chatroom.leave(3);
+        // Nothing happens, so there's nothing to tell.
+        chatroom.leave(3);
+
+        let playlist_id = *chatroom.waitlist().current_playlist().unwrap();
+        let dj = Box::new(chatroom.waitlist().current_dj().unwrap().clone());
+        let want = vec![
+            DomainEvent::TurnStarted { chatroom_id, user_id: 0, playlist_id, dj },
+            DomainEvent::SongStarted { chatroom_id, user_id: 0, song_id: song.id(), title: song.title() },
+            DomainEvent::DjLeft { chatroom_id, user_id: 0 },
+            DomainEvent::UserLeft { chatroom_id, user_id: 3 },
+        ];
+        assert_eq!(chatroom.take_events(), want);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn test_chatroom_replays_its_events() {
+        let spec = TestChatroomSpec {
+            chatroom_user_count: 4,
+            playlist_per_user: 1,
+            song_per_playlist: 2,
+            // test user 1, and test user 3 joined the waitlist.
+            which_joined_waitlist: vec![1, 3],
+            moderator_user: 1,
+            which_forgot_active: None,
+        };
+        let mut chatroom = new_test_chatroom(spec);
+        chatroom.take_events();
+        let mut replayed = chatroom.clone();
+
+        chatroom.play_next().unwrap();
+        chatroom.join_waitlist(1);
+        chatroom.change_moderator(3);
+        chatroom.play_next().unwrap();
+        chatroom.leave(1);
+        for event in chatroom.take_events() {
+            replayed.apply(&event);
+        }
+
+        assert_eq!(replayed.moderator(), 3);
+        assert_eq!(replayed.current_users(), chatroom.current_users());
+        assert_eq!(replayed.waitlist_djs(), chatroom.waitlist_djs());
+        assert_eq!(replayed.waitlist().current_dj().map(|dj| dj.id()), Some(2));
+        assert_eq!(replayed.waitlist().current_playlist(), chatroom.waitlist().current_playlist());
+        // Replaying isn't a change of its own.
+        assert!(replayed.take_events().is_empty());
+
+        // Once nobody is left to play, the waitlist is emptied.
+        chatroom.leave_waitlist(1);
+        chatroom.play_next().unwrap();
+        chatroom.play_next().unwrap();
+        for event in chatroom.take_events() {
+            replayed.apply(&event);
+        }
+        assert!(replayed.waitlist_djs().is_empty());
+        assert!(replayed.waitlist().current_dj().is_none());
+    }
 }
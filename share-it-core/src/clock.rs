@@ -0,0 +1,32 @@
+use std::time::{Duration, Instant, SystemTime};
+use std::thread;
+
+// Clock abstracts over the passage of time, so anything that waits or expires (rate limiting,
+// retry backoff, caches) can be driven by a fake clock in tests instead of real sleeps.
+pub trait Clock {
+    fn now(&self) -> Instant;
+
+    // The wall clock time, for anything that has to mean something outside this process, like a
+    // stored token expiry.
+    fn system_time(&self) -> SystemTime;
+
+    fn sleep(&self, duration: Duration);
+}
+
+// SystemClock is the real wall clock. Sleeping blocks the current thread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
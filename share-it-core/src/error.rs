@@ -0,0 +1,149 @@
+use std::error;
+use std::fmt;
+use rusty_ulid::Ulid;
+use crate::repositories::abstractions::StaleVersion;
+use crate::user::UserID;
+
+// Error is what share-it-core's services fail with. Storage errors keep the repository's own error
+// as their source, while the rest are about the request itself.
+#[derive(Debug)]
+pub enum Error {
+    // A repository couldn't talk to its storage.
+    Storage(Store, Box<dyn error::Error + Send>),
+    NotFound(Entity),
+    // The command acts as a user, but nobody is logged in.
+    Unauthenticated,
+    // The user isn't allowed to do that.
+    Forbidden(String),
+    // The request doesn't make sense, whatever state we're in.
+    Validation(String),
+    // The request clashes with what's already stored, e.g. creating something twice, or changing
+    // something someone else changed first.
+    Conflict(String),
+    // Nothing was registered to handle the named command.
+    Unhandled(&'static str),
+    // Middleware handed back something other than the named command's output.
+    MismatchedOutput(&'static str),
+}
+
+// Store is the repository a storage error came from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Store {
+    Users,
+    Chatrooms,
+}
+
+// Entity is something we looked for, and didn't find.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entity {
+    User(UserID),
+    Chatroom(Ulid),
+}
+
+impl Error {
+    pub fn users<E: error::Error + Send + 'static>(e: E) -> Error {
+        Error::storage(Store::Users, e)
+    }
+
+    pub fn chatrooms<E: error::Error + Send + 'static>(e: E) -> Error {
+        Error::storage(Store::Chatrooms, e)
+    }
+
+    // storage is a conflict if the repository turned away a stale update, as trying again may
+    // well work. Anything else is the repository failing.
+    fn storage<E: error::Error + Send + 'static>(store: Store, e: E) -> Error {
+        if let Some(stale) = StaleVersion::find(&e) {
+            return Error::Conflict(format!("{} changed concurrently: {}", store, stale));
+        }
+        Error::Storage(store, Box::new(e))
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::NotFound(_))
+    }
+}
+
+impl fmt::Display for Store {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Store::Users => write!(f, "user"),
+            Store::Chatrooms => write!(f, "chatroom"),
+        }
+    }
+}
+
+impl fmt::Display for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Entity::User(id) => write!(f, "user {}", id),
+            Entity::Chatroom(id) => write!(f, "chatroom {}", id),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Storage(store, e) => write!(f, "{} repository error: {}", store, e),
+            Error::NotFound(entity) => write!(f, "{} not found", entity),
+            Error::Unauthenticated => write!(f, "log in first"),
+            Error::Forbidden(e) => write!(f, "forbidden: {}", e),
+            Error::Validation(e) => write!(f, "invalid request: {}", e),
+            Error::Conflict(e) => write!(f, "conflict: {}", e),
+            Error::Unhandled(command) => write!(f, "no handler for {}", command),
+            Error::MismatchedOutput(command) => write!(f, "middleware replaced the output of {}", command),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Storage(_, e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entity, Error, Store};
+    use crate::repositories::abstractions::StaleVersion;
+    use crate::repositories::memory::MemoryError;
+    use std::error::Error as StdError;
+
+    #[test]
+    #[allow(unused)]
+    fn storage_errors_keep_their_repository_and_source() {
+        let e = Error::chatrooms(MemoryError::Poisoned);
+
+        match &e {
+            Error::Storage(Store::Chatrooms, _) => {},
+            other => panic!("expected a chatroom storage error, got {:?}", other),
+        }
+        assert_eq!(e.source().unwrap().to_string(), MemoryError::Poisoned.to_string());
+        assert_eq!(e.to_string(), format!("chatroom repository error: {}", MemoryError::Poisoned));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn not_found_names_what_is_missing() {
+        let e = Error::NotFound(Entity::User(7));
+
+        assert!(e.is_not_found());
+        assert!(e.source().is_none());
+        assert_eq!(e.to_string(), "user 7 not found");
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn stale_updates_are_conflicts() {
+        let e = Error::users(MemoryError::Stale(StaleVersion { loaded: 1, stored: 2 }));
+
+        match &e {
+            Error::Conflict(_) => {},
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert_eq!(e.to_string(), "conflict: user changed concurrently: loaded at version 1, but version 2 has been stored since");
+    }
+}
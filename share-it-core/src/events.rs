@@ -0,0 +1,151 @@
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::{self, Receiver, Sender};
+use rusty_ulid::Ulid;
+use crate::user::{PlaylistID, User, UserID, Username};
+
+// DomainEvent is something that happened in a chatroom, or to a user, that other parts of
+// share-it may want to react to. The domain records them as they happen, and the handlers
+// publish them once the change they describe has been saved. The chatroom events are also what
+// an event sourced chatroom repository stores, so between them they describe every change to a
+// chatroom.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DomainEvent {
+    ChatroomCreated { chatroom_id: Ulid, moderator: UserID, name: String },
+    ModeratorChanged { chatroom_id: Ulid, moderator: UserID },
+    UserJoined { chatroom_id: Ulid, user_id: UserID, username: Username },
+    UserLeft { chatroom_id: Ulid, user_id: UserID },
+    DjQueued { chatroom_id: Ulid, user_id: UserID },
+    DjLeft { chatroom_id: Ulid, user_id: UserID },
+    // It's the DJ's turn, playing from the given playlist. Any DJs ahead of them in the waitlist
+    // had nothing to play and were skipped. The DJ is kept as they were when their turn started,
+    // so replaying the turn later doesn't depend on who they are by then, or whether they're
+    // still around.
+    TurnStarted { chatroom_id: Ulid, user_id: UserID, playlist_id: PlaylistID, dj: Box<User> },
+    SongStarted { chatroom_id: Ulid, user_id: UserID, song_id: u32, title: String },
+    // The owner's song can no longer be streamed from SoundCloud, so it was moved to the bottom of
+    // their playlist rather than played.
+    SongSkipped { chatroom_id: Ulid, owner: UserID, song_id: u32, title: String },
+    // The DJ had nothing to play, so their turn went to the next DJ in the waitlist.
+    TurnSkipped { chatroom_id: Ulid, user_id: UserID },
+    // Nobody in the waitlist had anything to play, so it was emptied.
+    PlaybackStopped { chatroom_id: Ulid },
+    ChatroomRemoved { chatroom_id: Ulid },
+    // The chatroom was put back the way it was after the event with the given sequence number.
+    ChatroomRestored { chatroom_id: Ulid, sequence: u64 },
+    // A SoundCloud user logged in for the first time.
+    UserRegistered { user_id: UserID, username: Username },
+}
+
+// Subscriber is told about every event published on the EventBus it subscribed to. It's called
+// on the thread that published the event, in the middle of handling a command, so anything slow
+// should hand the event off, e.g. to a channel.
+pub trait Subscriber: Send + Sync {
+    fn notify(&self, event: &DomainEvent);
+}
+
+impl<F> Subscriber for F where
+    F: Fn(&DomainEvent) + Send + Sync,
+{
+    fn notify(&self, event: &DomainEvent) {
+        self(event)
+    }
+}
+
+// EventBus hands published events to every subscriber, in the order they subscribed. Clones
+// share subscribers, so the handlers publishing events don't need to know who's listening.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<RwLock<Vec<Arc<dyn Subscriber>>>>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus::default()
+    }
+
+    pub fn subscribe<S: Subscriber + 'static>(&self, subscriber: S) {
+        // A subscriber can only poison the lock by panicking while subscribing, which leaves the
+        // list as it was.
+        let mut subscribers = self.subscribers.write().unwrap_or_else(|e| e.into_inner());
+        subscribers.push(Arc::new(subscriber));
+    }
+
+    // channel subscribes a channel, for subscribers that would rather receive events on a thread
+    // of their own. Events published after the receiver is dropped are discarded.
+    pub fn channel(&self) -> Receiver<DomainEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribe(ChannelSubscriber(Mutex::new(sender)));
+        receiver
+    }
+
+    pub fn publish(&self, events: &[DomainEvent]) {
+        if events.is_empty() {
+            return;
+        }
+
+        // Subscribers are called without holding the lock, so they can subscribe others.
+        let subscribers = self.subscribers.read().unwrap_or_else(|e| e.into_inner()).clone();
+        for event in events {
+            for subscriber in &subscribers {
+                subscriber.notify(event);
+            }
+        }
+    }
+}
+
+struct ChannelSubscriber(Mutex<Sender<DomainEvent>>);
+
+impl Subscriber for ChannelSubscriber {
+    fn notify(&self, event: &DomainEvent) {
+        if let Ok(sender) = self.0.lock() {
+            let _ = sender.send(event.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DomainEvent, EventBus};
+    use rusty_ulid::Ulid;
+    use std::sync::{Arc, Mutex};
+
+    fn joined(user_id: u32) -> DomainEvent {
+        DomainEvent::UserJoined { chatroom_id: Ulid::generate(), user_id, username: "test_username".to_string() }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn every_subscriber_gets_every_event() {
+        let bus = EventBus::new();
+        let first = Arc::new(Mutex::new(Vec::new()));
+        let second = Arc::new(Mutex::new(Vec::new()));
+        let (a, b) = (first.clone(), second.clone());
+        bus.subscribe(move |event: &DomainEvent| a.lock().unwrap().push(event.clone()));
+        bus.clone().subscribe(move |event: &DomainEvent| b.lock().unwrap().push(event.clone()));
+
+        let events = vec![joined(1), joined(2)];
+        bus.publish(&events);
+
+        assert_eq!(*first.lock().unwrap(), events);
+        assert_eq!(*second.lock().unwrap(), events);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn channels_receive_events_on_other_threads() {
+        let bus = EventBus::new();
+        let receiver = bus.channel();
+
+        let publisher = bus.clone();
+        std::thread::spawn(move || publisher.publish(&[joined(1)])).join().unwrap();
+
+        match receiver.recv().unwrap() {
+            DomainEvent::UserJoined { user_id: 1, .. } => {},
+            other => panic!("expected user 1 to join, got {:?}", other),
+        }
+
+        // Nobody listening anymore is fine.
+        drop(receiver);
+        bus.publish(&[joined(2)]);
+    }
+}
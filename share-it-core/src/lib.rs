@@ -1,8 +1,9 @@
 #[macro_use]
 extern crate serde_derive;
 
-#[macro_use]
-extern crate lazy_static;
+pub mod clock;
+pub mod error;
+pub mod events;
 
 pub mod soundcloud_api;
 pub use soundcloud_api::*;
@@ -18,6 +19,9 @@ pub use song::*;
 pub mod chatroom;
 pub mod waitlist;
 pub mod playlist;
+pub mod availability;
+pub mod tokens;
+pub mod blocking;
 
 pub mod test_tools;
 pub use test_tools::*;
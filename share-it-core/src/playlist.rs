@@ -1,9 +1,9 @@
-use crate::song::Song;
+use crate::song::{Song, Availability};
 use rusty_ulid::Ulid;
 use std::collections::VecDeque;
 
-#[derive(Debug, Clone, PartialEq)]
-pub(crate) struct Playlist {
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Playlist {
     id: Ulid,
     name: String,
     songs: VecDeque<Song>
@@ -18,10 +18,23 @@ impl Playlist {
         }
     }
 
+    // with_id rebuilds an existing playlist, e.g. when loading it from storage.
+    pub fn with_id(id: Ulid, name: String) -> Playlist {
+        Playlist {
+            id,
+            name,
+            songs: VecDeque::new(),
+        }
+    }
+
     pub fn id(&self) -> Ulid {
         self.id.clone()
     }
 
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
     pub fn add_song(&mut self, song: Song) {
         if self.contains_song(&song) { return }
         self.songs.push_back(song);
@@ -54,6 +67,40 @@ impl Playlist {
         self.songs.len()
     }
 
+    pub fn songs(&self) -> impl Iterator<Item = &Song> {
+        self.songs.iter()
+    }
+
+    // set_song_availability marks the song with the given id, returning true if its
+    // availability actually changed.
+    pub fn set_song_availability(&mut self, song_id: u32, availability: Availability) -> bool {
+        for song in self.songs.iter_mut() {
+            if song.id() == song_id {
+                if song.availability() == availability { return false }
+                song.set_availability(availability);
+                return true;
+            }
+        }
+        false
+    }
+
+    // skip_unavailable cycles unavailable songs from the top of the playlist to the bottom, until
+    // the top song is playable. Each song is looked at no more than once, so a playlist with no
+    // playable songs is left in its original order. Returns the songs that were skipped.
+    pub fn skip_unavailable(&mut self) -> Vec<Song> {
+        if !self.songs.iter().any(|s| s.is_available()) {
+            return Vec::new();
+        }
+
+        let mut skipped = Vec::new();
+        while let Some(song) = self.top_song() {
+            if song.is_available() { break }
+            self.cycle_playlist();
+            skipped.push(song);
+        }
+        skipped
+    }
+
     fn contains_song(&self, song: &Song) -> bool {
         self.songs.iter().any(|s| {
             song.id() == s.id()
@@ -64,7 +111,8 @@ impl Playlist {
 #[cfg(test)]
 mod tests {
     use super::Playlist;
-    use crate::song::Song;
+    use crate::song::{Song, Availability};
+    use crate::test_tools::factories::new_test_song;
 
     #[test]
     fn test_playlist() {
@@ -204,4 +252,42 @@ mod tests {
         playlist.remove_song(song1.id());
         assert_eq!(playlist.len(), 0);
     }
+
+    #[test]
+    fn test_skip_unavailable() {
+        let mut playlist: Playlist = Playlist::new("Test Playlist".to_string());
+        for i in 0..4 {
+            playlist.add_song(new_test_song(i, 0));
+        }
+        playlist.set_song_availability(0, Availability::Deleted);
+        playlist.set_song_availability(1, Availability::Private);
+
+        let skipped: Vec<u32> = playlist.skip_unavailable().iter().map(|s| s.id()).collect();
+
+        assert_eq!(skipped, vec![0, 1]);
+        assert_eq!(playlist.top_song().unwrap().id(), 2);
+        assert_eq!(playlist.len(), 4);
+    }
+
+    #[test]
+    fn test_skip_unavailable_with_nothing_playable() {
+        let mut playlist: Playlist = Playlist::new("Test Playlist".to_string());
+        playlist.add_song(new_test_song(0, 0));
+        playlist.add_song(new_test_song(1, 0));
+        playlist.set_song_availability(0, Availability::Deleted);
+        playlist.set_song_availability(1, Availability::Deleted);
+
+        assert!(playlist.skip_unavailable().is_empty());
+        assert_eq!(playlist.top_song().unwrap().id(), 0);
+    }
+
+    #[test]
+    fn test_set_song_availability_reports_changes() {
+        let mut playlist: Playlist = Playlist::new("Test Playlist".to_string());
+        playlist.add_song(new_test_song(0, 0));
+
+        assert!(playlist.set_song_availability(0, Availability::Deleted));
+        assert!(!playlist.set_song_availability(0, Availability::Deleted));
+        assert!(!playlist.set_song_availability(7, Availability::Deleted));
+    }
 }
@@ -1,4 +1,60 @@
-pub(crate) trait Repository<K, V> {
+use async_trait::async_trait;
+use std::error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use crate::repositories::query::{Page, PageRequest};
+
+/// Versioned is an entity that knows which version of it was loaded, so a repository can refuse to
+/// update it from a stale copy, rather than overwrite whatever changed since. Repositories set the
+/// version as they load the entity, and store the next version every time it's updated. A copy
+/// that has been used to update an entity is stale too, so load it again to change it again.
+pub trait Versioned {
+    fn version(&self) -> u64;
+
+    fn set_version(&mut self, version: u64);
+}
+
+/// StaleVersion is why a repository refused to update an entity: it was loaded at one version, but
+/// someone else has stored another since. The update can be tried again from a fresh copy.
+///
+/// Repositories return it as, or as the source of, their own errors, and [`StaleVersion::find`]
+/// digs it back out.
+///
+/// [`StaleVersion::find`]: struct.StaleVersion.html#method.find
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaleVersion {
+    // The version the entity being stored was loaded at.
+    pub loaded: u64,
+    pub stored: u64,
+}
+
+impl StaleVersion {
+    /// Returns the StaleVersion that e, or one of its sources, is.
+    pub fn find<'e>(e: &'e (dyn error::Error + 'static)) -> Option<&'e StaleVersion> {
+        let mut next = Some(e);
+        while let Some(e) = next {
+            if let Some(stale) = e.downcast_ref::<StaleVersion>() {
+                return Some(stale);
+            }
+            next = e.source();
+        }
+        None
+    }
+}
+
+impl fmt::Display for StaleVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "loaded at version {}, but version {} has been stored since", self.loaded, self.stored)
+    }
+}
+
+impl error::Error for StaleVersion {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        None
+    }
+}
+
+pub trait Repository<K, V> {
     /// An error that communicates that something went wrong when communicating with the external api, database etc.
     type Error: std::error::Error + std::fmt::Display + 'static + Send;
 
@@ -40,7 +96,13 @@ pub(crate) trait Repository<K, V> {
     /// # Failure case
     ///
     /// If we fail to communicate with the underlying storage, then an error is returned.
+    ///
+    /// If the Entity is [`Versioned`], and someone else has updated it since it was loaded, then
+    /// nothing is stored and a [`StaleVersion`] error is returned.
+    ///
     /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    /// [`Versioned`]: trait.Versioned.html
+    /// [`StaleVersion`]: struct.StaleVersion.html
     fn update(&mut self, entity: &V) -> Result<Option<K>, Self::Error>;
 
     /// Removes a Entity from the underlying storage at the given key,
@@ -55,6 +117,166 @@ pub(crate) trait Repository<K, V> {
     fn remove(&mut self, key: &K) -> Result<Option<K>, Self::Error>;
 }
 
+/// AsyncRepository is Repository for callers on an async runtime, which mustn't block waiting on
+/// storage. It takes `&self` so it can be shared between requests, which makes implementations
+/// responsible for their own synchronisation.
+///
+/// A synchronous Repository becomes an AsyncRepository with [`Blocking`].
+///
+/// [`Blocking`]: ../../blocking/struct.Blocking.html
+#[async_trait(?Send)]
+pub trait AsyncRepository<K, V> {
+    /// An error that communicates that something went wrong when communicating with the external api, database etc.
+    type Error: std::error::Error + std::fmt::Display + 'static + Send;
+
+    /// Inserts a Entity, the same as [`Repository::insert`].
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the underlying storage, then an error is returned.
+    ///
+    /// [`Repository::insert`]: trait.Repository.html#tymethod.insert
+    async fn insert(&self, entity: &V) -> Result<Option<K>, Self::Error>;
+
+    /// Returns the Entity with the supplied key, the same as [`Repository::get`].
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the underlying storage, then an error is returned.
+    ///
+    /// [`Repository::get`]: trait.Repository.html#tymethod.get
+    async fn get(&self, key: &K) -> Result<Option<V>, Self::Error>;
+
+    /// Returns `true` if the underlying storage contains an entity at the specified key,
+    /// and otherwise returns `false`.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the underlying storage, then an error is returned.
+    async fn contains(&self, key: &K) -> Result<bool, Self::Error> {
+        Ok(self.get(key).await?.is_some())
+    }
+
+    /// Updates the Entity, the same as [`Repository::update`].
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the underlying storage, then an error is returned.
+    ///
+    /// [`Repository::update`]: trait.Repository.html#tymethod.update
+    async fn update(&self, entity: &V) -> Result<Option<K>, Self::Error>;
+
+    /// Removes the Entity at the given key, the same as [`Repository::remove`].
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the underlying storage, then an error is returned.
+    ///
+    /// [`Repository::remove`]: trait.Repository.html#tymethod.remove
+    async fn remove(&self, key: &K) -> Result<Option<K>, Self::Error>;
+}
+
+/// Query lists the entities matching a filter of type F, a page at a time. It sits alongside
+/// Repository, rather than extending it, so a repository can be queried for things it doesn't
+/// store by key, e.g. a user repository for the playlists its users own.
+pub trait Query<F, V> {
+    /// The key pages are ordered on, and continue after.
+    type Cursor;
+
+    type Error: std::error::Error + std::fmt::Display + 'static + Send;
+
+    /// Returns the page of entities matching filter that page asks for. Entities are ordered on
+    /// their cursor, and [`Page::next`] is [`None`] once there are no more.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the underlying storage, then an error is returned.
+    ///
+    /// [`Page::next`]: ../query/struct.Page.html#structfield.next
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    fn query(&mut self, filter: &F, page: &PageRequest<Self::Cursor>) -> Result<Page<V, Self::Cursor>, Self::Error>;
+}
+
+// A mutable reference to a repository is a repository too. This lets a caller lend out a
+// repository it still owns, e.g. to a waitlist in tests, or to a handler for a single request.
+impl<K, V, R> Repository<K, V> for &mut R where
+    R: Repository<K, V>,
+{
+    type Error = R::Error;
+
+    fn insert(&mut self, entity: &V) -> Result<Option<K>, Self::Error> {
+        (**self).insert(entity)
+    }
+
+    fn get(&mut self, key: &K) -> Result<Option<V>, Self::Error> {
+        (**self).get(key)
+    }
+
+    fn contains(&mut self, key: &K) -> Result<bool, Self::Error> {
+        (**self).contains(key)
+    }
+
+    fn update(&mut self, entity: &V) -> Result<Option<K>, Self::Error> {
+        (**self).update(entity)
+    }
+
+    fn remove(&mut self, key: &K) -> Result<Option<K>, Self::Error> {
+        (**self).remove(key)
+    }
+}
+
+// A repository shared behind a mutex is a repository too, so one can be handed to something that
+// takes its repository by value, e.g. a handler running on another thread. A poisoned lock means
+// another thread panicked half way through using the repository, so we panic rather than carry
+// on with it.
+impl<K, V, R> Repository<K, V> for Arc<Mutex<R>> where
+    R: Repository<K, V>,
+{
+    type Error = R::Error;
+
+    fn insert(&mut self, entity: &V) -> Result<Option<K>, Self::Error> {
+        self.lock().unwrap().insert(entity)
+    }
+
+    fn get(&mut self, key: &K) -> Result<Option<V>, Self::Error> {
+        self.lock().unwrap().get(key)
+    }
+
+    fn contains(&mut self, key: &K) -> Result<bool, Self::Error> {
+        self.lock().unwrap().contains(key)
+    }
+
+    fn update(&mut self, entity: &V) -> Result<Option<K>, Self::Error> {
+        self.lock().unwrap().update(entity)
+    }
+
+    fn remove(&mut self, key: &K) -> Result<Option<K>, Self::Error> {
+        self.lock().unwrap().remove(key)
+    }
+}
+
+impl<F, V, R> Query<F, V> for &mut R where
+    R: Query<F, V>,
+{
+    type Cursor = R::Cursor;
+    type Error = R::Error;
+
+    fn query(&mut self, filter: &F, page: &PageRequest<Self::Cursor>) -> Result<Page<V, Self::Cursor>, Self::Error> {
+        (**self).query(filter, page)
+    }
+}
+
+impl<F, V, R> Query<F, V> for Arc<Mutex<R>> where
+    R: Query<F, V>,
+{
+    type Cursor = R::Cursor;
+    type Error = R::Error;
+
+    fn query(&mut self, filter: &F, page: &PageRequest<Self::Cursor>) -> Result<Page<V, Self::Cursor>, Self::Error> {
+        self.lock().unwrap().query(filter, page)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::MockUserRepository;
@@ -122,4 +344,11 @@ mod tests {
         user_repo.remove(&user_id);
         assert!(!user_repo.contains(&user_id).unwrap())
     }
+
+    mod conformance {
+        use crate::MockUserRepository;
+        use crate::test_tools::conformance::UserEntities;
+
+        crate::repository_conformance_tests!(MockUserRepository::new(), UserEntities::new());
+    }
 }
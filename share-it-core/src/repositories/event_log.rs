@@ -0,0 +1,475 @@
+use std::error;
+use std::fmt;
+use std::marker::PhantomData;
+use rusty_ulid::Ulid;
+use crate::chatroom::Chatroom;
+use crate::events::DomainEvent;
+use crate::repositories::abstractions::{Repository, StaleVersion, Versioned};
+use crate::user::User;
+
+// An event sourced chatroom is stored as the log of every event that happened to it, rather than
+// as its latest state. Its state is rebuilt by replaying the log, starting from the latest
+// snapshot so we don't have to replay everything. Nothing in the log is ever changed or removed,
+// so it doubles as an audit of how a chatroom got the way it is, and lets us rebuild it the way it
+// was at any point along the way.
+
+// Recorded is an event as it's stored in a chatroom's log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recorded {
+    // Where the event is in the log. The first event is 1, and there are no gaps.
+    pub sequence: u64,
+    pub event: DomainEvent,
+}
+
+// Snapshot is what something looked like right after the event with the given sequence number.
+#[derive(Debug, Clone)]
+pub struct Snapshot<V> {
+    pub sequence: u64,
+    pub state: V,
+}
+
+/// ChatroomLog stores the events that happened to every chatroom, in order, along with snapshots
+/// of what chatrooms looked like along the way.
+pub trait ChatroomLog<U> where
+    U: Repository<u32, User>,
+{
+    /// An error that communicates that something went wrong when communicating with the underlying storage.
+    type Error: std::error::Error + std::fmt::Display + 'static + Send;
+
+    /// Appends events to the end of the chatroom's log, as long as its last event is still the one
+    /// numbered `after`, and returns the sequence number of the last event appended. If someone
+    /// else has appended events since, then nothing is appended and [`None`] is returned.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the underlying storage, then an error is returned and none
+    /// of the events are appended.
+    ///
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    fn append(&mut self, chatroom_id: &Ulid, after: u64, events: &[DomainEvent]) -> Result<Option<u64>, Self::Error>;
+
+    /// Returns the chatroom's events with sequence numbers after `after`, oldest first.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the underlying storage, then an error is returned.
+    fn events(&mut self, chatroom_id: &Ulid, after: u64) -> Result<Vec<Recorded>, Self::Error>;
+
+    /// Returns the sequence number of the last event in the chatroom's log, or 0 if it has none.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the underlying storage, then an error is returned.
+    fn last_sequence(&mut self, chatroom_id: &Ulid) -> Result<u64, Self::Error>;
+
+    /// Stores a snapshot of a chatroom that already has events in its log.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the underlying storage, then an error is returned.
+    fn save_snapshot(&mut self, snapshot: &Snapshot<Chatroom<U>>) -> Result<(), Self::Error>;
+
+    /// Returns the chatroom's latest snapshot taken at or before the given sequence number, or its
+    /// latest snapshot of all if there's no sequence number.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the underlying storage, then an error is returned.
+    fn snapshot(&mut self, chatroom_id: &Ulid, at_or_before: Option<u64>) -> Result<Option<Snapshot<Chatroom<U>>>, Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum EventLogError<E> {
+    Log(E),
+    Stale(StaleVersion),
+}
+
+impl<E: fmt::Display> fmt::Display for EventLogError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EventLogError::Log(e) => write!(f, "chatroom log error: {}", e),
+            EventLogError::Stale(e) => write!(f, "stale update: {}", e),
+        }
+    }
+}
+
+impl<E: error::Error + 'static> error::Error for EventLogError<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            EventLogError::Log(e) => Some(e),
+            EventLogError::Stale(e) => Some(e),
+        }
+    }
+}
+
+impl<E> From<E> for EventLogError<E> {
+    fn from(e: E) -> Self {
+        EventLogError::Log(e)
+    }
+}
+
+// How many events we let pile up after a chatroom's last snapshot before taking another.
+const DEFAULT_SNAPSHOT_EVERY: u64 = 100;
+
+// EventSourcedChatrooms stores chatrooms in a ChatroomLog. What it stores for a chatroom are the
+// chatroom's pending events, so only changes made through the chatroom's own methods are kept,
+// and callers should save a chatroom before taking its events.
+//
+// A chatroom's version is the sequence number of the last event it was rebuilt from, so it's
+// stale as soon as anyone appends to its log.
+//
+// Chatrooms are snapshotted as they're inserted, and then every so many events. Removing a
+// chatroom only adds a removal to its log, so it can still be restored afterwards.
+pub struct EventSourcedChatrooms<L, U> {
+    log: L,
+    snapshot_every: u64,
+    users: PhantomData<U>,
+}
+
+impl<L, U> EventSourcedChatrooms<L, U> where
+    L: ChatroomLog<U>,
+    U: Repository<u32, User> + Clone,
+{
+    pub fn new(log: L) -> EventSourcedChatrooms<L, U> {
+        EventSourcedChatrooms::with_snapshots(log, DEFAULT_SNAPSHOT_EVERY)
+    }
+
+    // with_snapshots takes a snapshot every `every` events. Fewer events between snapshots makes
+    // chatrooms quicker to load, at the cost of storing more of them.
+    pub fn with_snapshots(log: L, every: u64) -> EventSourcedChatrooms<L, U> {
+        EventSourcedChatrooms {
+            log,
+            snapshot_every: every.max(1),
+            users: PhantomData,
+        }
+    }
+
+    // history is every event in the chatroom's log, oldest first.
+    pub fn history(&mut self, chatroom_id: &Ulid) -> Result<Vec<Recorded>, EventLogError<L::Error>> {
+        Ok(self.log.events(chatroom_id, 0)?)
+    }
+
+    // as_of rebuilds the chatroom the way it was right after the event with the given sequence
+    // number, or returns None if it didn't exist then.
+    pub fn as_of(&mut self, chatroom_id: &Ulid, sequence: u64) -> Result<Option<Chatroom<U>>, EventLogError<L::Error>> {
+        self.rebuild(chatroom_id, Some(sequence))
+    }
+
+    // restore puts the chatroom back the way it was right after the event with the given sequence
+    // number, and returns it. Restoring is recorded in the log like any other change, so it can be
+    // undone by restoring to a later point. Returns None if the chatroom didn't exist then.
+    pub fn restore(&mut self, chatroom_id: &Ulid, sequence: u64) -> Result<Option<Chatroom<U>>, EventLogError<L::Error>> {
+        let mut chatroom = match self.rebuild(chatroom_id, Some(sequence))? {
+            Some(chatroom) => chatroom,
+            None => return Ok(None),
+        };
+
+        let (last, _) = self.head(chatroom_id)?;
+        let restored = DomainEvent::ChatroomRestored { chatroom_id: *chatroom_id, sequence };
+        let restored_at = self.append(chatroom_id, last, &[restored])?;
+        chatroom.set_version(restored_at);
+        // Snapshotting straight away saves whoever loads it next from rebuilding it all over again.
+        self.save_snapshot(&chatroom, restored_at)?;
+        Ok(Some(chatroom))
+    }
+
+    // rebuild replays the chatroom's log, up to the given sequence number if there is one.
+    // Chatrooms are snapshotted as they're inserted, so without a snapshot there's nothing to
+    // replay onto.
+    fn rebuild(&mut self, chatroom_id: &Ulid, up_to: Option<u64>) -> Result<Option<Chatroom<U>>, EventLogError<L::Error>> {
+        let snapshot = match self.log.snapshot(chatroom_id, up_to)? {
+            Some(snapshot) => snapshot,
+            None => return Ok(None),
+        };
+
+        let mut chatroom = Some(snapshot.state);
+        let mut version = snapshot.sequence;
+        for recorded in self.log.events(chatroom_id, snapshot.sequence)? {
+            if matches!(up_to, Some(up_to) if recorded.sequence > up_to) {
+                break;
+            }
+            version = recorded.sequence;
+
+            match &recorded.event {
+                DomainEvent::ChatroomRemoved { .. } => chatroom = None,
+                DomainEvent::ChatroomRestored { sequence, .. } => chatroom = self.rebuild(chatroom_id, Some(*sequence))?,
+                event => if let Some(chatroom) = &mut chatroom {
+                    chatroom.apply(event);
+                },
+            }
+        }
+
+        if let Some(chatroom) = &mut chatroom {
+            chatroom.set_version(version);
+        }
+        Ok(chatroom)
+    }
+
+    // head is the sequence number of the last event in the chatroom's log, and whether the
+    // chatroom has been inserted, and not removed since.
+    fn head(&mut self, chatroom_id: &Ulid) -> Result<(u64, bool), EventLogError<L::Error>> {
+        let last = self.log.last_sequence(chatroom_id)?;
+        if last == 0 {
+            return Ok((0, false));
+        }
+
+        let removed = match self.log.events(chatroom_id, last - 1)?.first() {
+            Some(recorded) => matches!(recorded.event, DomainEvent::ChatroomRemoved { .. }),
+            None => true,
+        };
+        Ok((last, !removed))
+    }
+
+    // append appends events after the event numbered `after`, and fails as a stale update if
+    // someone else appended first.
+    fn append(&mut self, chatroom_id: &Ulid, after: u64, events: &[DomainEvent]) -> Result<u64, EventLogError<L::Error>> {
+        match self.log.append(chatroom_id, after, events)? {
+            Some(last) => Ok(last),
+            None => {
+                let stored = self.log.last_sequence(chatroom_id)?;
+                Err(EventLogError::Stale(StaleVersion { loaded: after, stored }))
+            },
+        }
+    }
+
+    fn save_snapshot(&mut self, chatroom: &Chatroom<U>, sequence: u64) -> Result<(), EventLogError<L::Error>> {
+        let mut state = chatroom.clone();
+        // They're in the log already.
+        state.take_events();
+        Ok(self.log.save_snapshot(&Snapshot { sequence, state })?)
+    }
+}
+
+impl<L, U> Repository<Ulid, Chatroom<U>> for EventSourcedChatrooms<L, U> where
+    L: ChatroomLog<U>,
+    U: Repository<u32, User> + Clone,
+{
+    type Error = EventLogError<L::Error>;
+
+    fn insert(&mut self, chatroom: &Chatroom<U>) -> Result<Option<Ulid>, Self::Error> {
+        let id = chatroom.id();
+        let (last, exists) = self.head(&id)?;
+        if exists {
+            return Ok(None);
+        }
+
+        // A chatroom that's been stored before, e.g. in another repository, has no events of its own.
+        let mut events = chatroom.pending_events().to_vec();
+        if events.is_empty() {
+            events.push(DomainEvent::ChatroomCreated { chatroom_id: id, moderator: chatroom.moderator(), name: chatroom.name() });
+        }
+        let last = match self.log.append(&id, last, &events)? {
+            Some(last) => last,
+            // Someone else inserted it first.
+            None => return Ok(None),
+        };
+        self.save_snapshot(chatroom, last)?;
+
+        Ok(Some(id))
+    }
+
+    fn get(&mut self, key: &Ulid) -> Result<Option<Chatroom<U>>, Self::Error> {
+        self.rebuild(key, None)
+    }
+
+    fn contains(&mut self, key: &Ulid) -> Result<bool, Self::Error> {
+        Ok(self.head(key)?.1)
+    }
+
+    fn update(&mut self, chatroom: &Chatroom<U>) -> Result<Option<Ulid>, Self::Error> {
+        let id = chatroom.id();
+        let (stored, exists) = self.head(&id)?;
+        if !exists {
+            return Ok(None);
+        }
+        if stored != chatroom.version() {
+            return Err(EventLogError::Stale(StaleVersion { loaded: chatroom.version(), stored }));
+        }
+        if chatroom.pending_events().is_empty() {
+            return Ok(Some(id));
+        }
+
+        let last = self.append(&id, stored, chatroom.pending_events())?;
+        let snapshotted = self.log.snapshot(&id, None)?.map_or(0, |snapshot| snapshot.sequence);
+        if last - snapshotted >= self.snapshot_every {
+            self.save_snapshot(chatroom, last)?;
+        }
+
+        Ok(Some(id))
+    }
+
+    fn remove(&mut self, key: &Ulid) -> Result<Option<Ulid>, Self::Error> {
+        // Whatever anyone else appends in the meantime is removed along with the rest.
+        loop {
+            let (last, exists) = self.head(key)?;
+            if !exists {
+                return Ok(None);
+            }
+
+            if self.log.append(key, last, &[DomainEvent::ChatroomRemoved { chatroom_id: *key }])?.is_some() {
+                return Ok(Some(*key));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChatroomLog, EventSourcedChatrooms};
+    use crate::chatroom::{Chatroom, ChatUser};
+    use crate::events::DomainEvent;
+    use crate::repositories::abstractions::{Repository, Versioned};
+    use crate::repositories::memory::{InMemoryChatroomLog, InMemoryUsers};
+    use crate::test_tools::factories::{new_test_playlist, new_test_user};
+
+    fn new_test_users() -> InMemoryUsers {
+        let mut users = InMemoryUsers::new();
+        for user_id in 1..=3 {
+            let mut user = new_test_user(user_id);
+            let playlist = new_test_playlist(user_id, 2);
+            user.set_active_playlist(&playlist.id());
+            user.add_playlist(playlist);
+            users.insert(&user).unwrap();
+        }
+        users
+    }
+
+    // save stores the chatroom's changes, the way a handler would.
+    fn save(chatrooms: &mut EventSourcedChatrooms<InMemoryChatroomLog<InMemoryUsers>, InMemoryUsers>, chatroom: &mut Chatroom<InMemoryUsers>) {
+        chatrooms.update(chatroom).unwrap().unwrap();
+        caught_up(chatrooms, chatroom);
+    }
+
+    // caught_up marks everything the chatroom recorded as stored, so it can carry on being changed
+    // without being loaded again.
+    fn caught_up(chatrooms: &mut EventSourcedChatrooms<InMemoryChatroomLog<InMemoryUsers>, InMemoryUsers>, chatroom: &mut Chatroom<InMemoryUsers>) {
+        chatroom.take_events();
+        chatroom.set_version(chatrooms.history(&chatroom.id()).unwrap().len() as u64);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn chatrooms_are_rebuilt_from_their_log() {
+        let users = new_test_users();
+        let log = InMemoryChatroomLog::new();
+        let mut chatrooms = EventSourcedChatrooms::with_snapshots(log.clone(), 3);
+        let mut chatroom = Chatroom::new(users.clone(), 1, "test_chatroom".to_string());
+        chatroom.join(ChatUser(1, "test_username".to_string()));
+        chatrooms.insert(&chatroom).unwrap();
+        caught_up(&mut chatrooms, &mut chatroom);
+
+        for user_id in 2..=3 {
+            chatroom.join(ChatUser(user_id, "test_username".to_string()));
+            chatroom.join_waitlist(user_id);
+            save(&mut chatrooms, &mut chatroom);
+        }
+        chatroom.play_next().unwrap().unwrap();
+        chatroom.change_moderator(3);
+        save(&mut chatrooms, &mut chatroom);
+
+        let got = chatrooms.get(&chatroom.id()).unwrap().unwrap();
+        assert_eq!(got.moderator(), 3);
+        assert_eq!(got.current_users(), chatroom.current_users());
+        assert_eq!(got.waitlist().id(), chatroom.waitlist().id());
+        assert_eq!(got.waitlist_djs(), chatroom.waitlist_djs());
+        assert_eq!(got.waitlist().current_dj().map(|dj| dj.id()), Some(2));
+        assert_eq!(got.waitlist().current_playlist(), chatroom.waitlist().current_playlist());
+        assert!(got.pending_events().is_empty());
+
+        // Created and joined, two more joins and queues, then a turn, a song and a new moderator.
+        assert_eq!(chatrooms.history(&chatroom.id()).unwrap().len(), 9);
+        let mut log = log;
+        let snapshots: Vec<_> = (1..=9)
+            .filter_map(|sequence| log.snapshot(&chatroom.id(), Some(sequence)).unwrap())
+            .map(|snapshot| snapshot.sequence)
+            .collect();
+        assert_eq!(snapshots, vec![2, 2, 2, 2, 6, 6, 6, 9]);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn chatrooms_can_be_seen_and_restored_as_they_were() {
+        let users = new_test_users();
+        let mut chatrooms = EventSourcedChatrooms::new(InMemoryChatroomLog::new());
+        let mut chatroom = Chatroom::new(users.clone(), 1, "test_chatroom".to_string());
+        chatroom.join(ChatUser(1, "test_username".to_string()));
+        chatroom.join(ChatUser(2, "test_username".to_string()));
+        chatrooms.insert(&chatroom).unwrap();
+        caught_up(&mut chatrooms, &mut chatroom);
+        let id = chatroom.id();
+
+        chatroom.leave(2);
+        chatroom.join_waitlist(1);
+        save(&mut chatrooms, &mut chatroom);
+
+        let before = chatrooms.as_of(&id, 3).unwrap().unwrap();
+        assert_eq!(before.len(), 2);
+        assert!(before.waitlist_djs().is_empty());
+        // Before the chatroom was inserted, there's nothing to see.
+        assert!(chatrooms.as_of(&id, 2).unwrap().is_none());
+
+        let restored = chatrooms.restore(&id, 3).unwrap().unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(chatrooms.get(&id).unwrap().unwrap().len(), 2);
+
+        // The history is all still there, and restoring can be undone.
+        let history = chatrooms.history(&id).unwrap();
+        assert_eq!(history.last().unwrap().event, DomainEvent::ChatroomRestored { chatroom_id: id, sequence: 3 });
+        chatrooms.restore(&id, 5).unwrap().unwrap();
+        let got = chatrooms.get(&id).unwrap().unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got.waitlist_djs().len(), 1);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn removed_chatrooms_can_be_restored() {
+        let mut chatrooms = EventSourcedChatrooms::new(InMemoryChatroomLog::new());
+        let chatroom = Chatroom::new(new_test_users(), 1, "test_chatroom".to_string());
+        let id = chatroom.id();
+        chatrooms.insert(&chatroom).unwrap();
+
+        chatrooms.remove(&id).unwrap().unwrap();
+        assert!(chatrooms.get(&id).unwrap().is_none());
+        assert!(chatrooms.update(&chatroom).unwrap().is_none());
+
+        chatrooms.restore(&id, 1).unwrap().unwrap();
+        assert!(chatrooms.contains(&id).unwrap());
+        assert_eq!(chatrooms.get(&id).unwrap().unwrap().name(), "test_chatroom");
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn turns_are_replayed_with_the_dj_as_they_were() {
+        let mut users = new_test_users();
+        // Snapshotting every event would hide replay behind the snapshots.
+        let mut chatrooms = EventSourcedChatrooms::with_snapshots(InMemoryChatroomLog::new(), 100);
+        let mut chatroom = Chatroom::new(users.clone(), 1, "test_chatroom".to_string());
+        chatroom.join(ChatUser(1, "test_username".to_string()));
+        chatroom.join_waitlist(1);
+        chatrooms.insert(&chatroom).unwrap();
+        caught_up(&mut chatrooms, &mut chatroom);
+        let id = chatroom.id();
+
+        chatroom.play_next().unwrap().unwrap();
+        save(&mut chatrooms, &mut chatroom);
+        let dj = chatroom.waitlist().current_dj().unwrap().clone();
+        let turn = chatrooms.history(&id).unwrap().len() as u64;
+
+        // The DJ changes, and then leaves share-it altogether.
+        let mut changed = users.get(&1).unwrap().unwrap();
+        changed.add_playlist(new_test_playlist(1, 1));
+        users.update(&changed).unwrap();
+        users.remove(&1).unwrap();
+
+        let got = chatrooms.get(&id).unwrap().unwrap();
+        assert_eq!(got.waitlist().current_dj(), Some(&dj));
+        assert_eq!(got.waitlist().current_playlist(), chatroom.waitlist().current_playlist());
+        let then = chatrooms.as_of(&id, turn).unwrap().unwrap();
+        assert_eq!(then.waitlist().current_dj(), Some(&dj));
+
+        let restored = chatrooms.restore(&id, turn).unwrap().unwrap();
+        assert_eq!(restored.waitlist().current_dj(), Some(&dj));
+        assert_eq!(restored.waitlist().current_playlist(), chatroom.waitlist().current_playlist());
+    }
+}
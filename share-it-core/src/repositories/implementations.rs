@@ -1,102 +1,194 @@
 use crate::repositories::abstractions::Repository;
-use crate::user::{User, UserID};
-lazy_static! {
-    static ref MYSQL_POOL: mysql::Pool = {
-        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        mysql::Pool::new(&database_url).unwrap()
-    };
+use crate::repositories::pool::{MysqlPool, PoolError};
+use crate::repositories::sql::{Dialect, SqlChatroomLog, SqlChatrooms, SqlConnection, SqlError, SqlRow, SqlTokens, SqlUsers, SqlValue};
+use crate::user::User;
+use mysql::Value;
+
+// MysqlUsers stores users in MySQL. The tables are created by the migrations in migrations/mysql.
+pub type MysqlUsers = SqlUsers<MysqlConnection>;
+
+impl MysqlUsers {
+    // new checks a connection out of the pool, which the repository holds on to until it's dropped.
+    pub fn new(pool: &MysqlPool) -> Result<MysqlUsers, PoolError> {
+        Ok(SqlUsers::with_connection(pool.connection()?))
+    }
+}
+
+// MysqlChatrooms stores chatrooms in MySQL, rebuilding them with the given user repository.
+pub type MysqlChatrooms<U> = SqlChatrooms<MysqlConnection, U>;
+
+impl<U> MysqlChatrooms<U> where
+    U: Repository<u32, User> + Clone,
+{
+    pub fn new(pool: &MysqlPool, users: U) -> Result<MysqlChatrooms<U>, PoolError> {
+        Ok(SqlChatrooms::with_connection(pool.connection()?, users))
+    }
+}
+
+// MysqlTokens stores users' sealed provider tokens in MySQL, for a TokenVault to keep.
+pub type MysqlTokens = SqlTokens<MysqlConnection>;
+
+impl MysqlTokens {
+    pub fn new(pool: &MysqlPool) -> Result<MysqlTokens, PoolError> {
+        Ok(SqlTokens::with_connection(pool.connection()?))
+    }
 }
 
-pub struct MysqlUsers {
+// MysqlChatroomLog keeps the events of event sourced chatrooms in MySQL.
+pub type MysqlChatroomLog<U> = SqlChatroomLog<MysqlConnection, U>;
+
+impl<U> MysqlChatroomLog<U> where
+    U: Repository<u32, User> + Clone,
+{
+    pub fn new(pool: &MysqlPool, users: U) -> Result<MysqlChatroomLog<U>, PoolError> {
+        Ok(SqlChatroomLog::with_connection(pool.connection()?, users))
+    }
+}
+
+// MysqlConnection runs the SQL repositories on a pooled MySQL connection. Every statement is
+// prepared, with its values sent separately from the SQL.
+pub struct MysqlConnection {
     conn: mysql::PooledConn,
 }
 
-impl MysqlUsers {
-    pub fn new() -> MysqlSurveyWriteRepository {
-        let pool = super::MYSQL_POOL.clone();
-        MysqlSurveyWriteRepository {
-            conn: pool.get_conn().unwrap(),
+impl MysqlConnection {
+    pub(crate) fn from_pooled(conn: mysql::PooledConn) -> MysqlConnection {
+        MysqlConnection {
+            conn,
         }
     }
+
+    // connect opens a one off connection to the database at url, without configuring a pool.
+    pub fn connect(url: &str) -> Result<MysqlConnection, SqlError> {
+        let pool = mysql::Pool::new(url).map_err(SqlError::driver)?;
+        Ok(MysqlConnection {
+            conn: pool.get_conn().map_err(SqlError::driver)?,
+        })
+    }
 }
 
-impl Repository<UserID, User> for MysqlUsers {
-    type Error = mysql::Error;
+impl SqlConnection for MysqlConnection {
+    fn dialect(&self) -> Dialect {
+        Dialect::Mysql
+    }
+
+    fn execute(&mut self, sql: &str, params: &[SqlValue]) -> Result<u64, SqlError> {
+        let result = self.conn.prep_exec(sql, to_mysql_params(params)).map_err(SqlError::driver)?;
+        Ok(result.affected_rows())
+    }
+
+    fn query(&mut self, sql: &str, params: &[SqlValue]) -> Result<Vec<SqlRow>, SqlError> {
+        let result = self.conn.prep_exec(sql, to_mysql_params(params)).map_err(SqlError::driver)?;
 
-    fn insert(&mut self, user: &User) -> Result<Option<UserID>, Self::Error> {
-        match self.conn.query(
-            format!(r"INSERT INTO users (id, username, avatar_url, permalink_url)
-            VALUES ('{}', '{}', '{}', '{}')", user.id(), user.username(), user.avatar_url(), user.permalink_url())
-        ) {
-            Ok(_) => Ok(Some(user.id())),
-            Err(e) => Err(e),
+        let mut rows = Vec::new();
+        for row in result {
+            let values = row.map_err(SqlError::driver)?.unwrap()
+                .into_iter()
+                .map(from_mysql_value)
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.push(SqlRow::new(values));
         }
+        Ok(rows)
     }
 
-    fn get(&mut self, key: &UserID) -> Result<Option<User>, Self::Error> {
-        let user: Option<User> =
-            match self.conn.query(
-                format!(r"SELECT u.id, u.username, u.avatar_url, u.permalink_url
-                FROM users AS u
-                WHERE u.id = '{}'", key)
-            ) {
-                Ok(mut qr) => {
-                    if let Some(row_result) = qr.next() {
-                        let row = row_result?;
-                        let (id, username, avatar_url, permalink_url) = mysql::from_row::<(UserID, String, String, String)>(row);
-                        Some(
-                            User::new(id, username, avatar_url, permalink_url)
-                        )
-                    } else {
-                        None
-                    }
-                },
-
-                // Underlying MySQL error type unrelated to existence of user in db.
-                Err(e) => {
-                    return Err(e);
-                }
-            };
-
-        Ok(user)
-    }
-
-    fn update(&mut self, entity: &User) -> Result<Option<UserID>, Self::Error> {
-        match self.conn.query(
-            format!("UPDATE users SET username = '{}', avatar_url = '{}', permalink_url = '{}' WHERE id = '{}'",
-            entity.username(), entity.avatar_url(), entity.permalink_url(), entity.id())
-        ) {
-            Ok(result) => {
-                if result.affected_rows() == 0 {
-                    return Ok(None);
-                }
+    fn begin(&mut self) -> Result<(), SqlError> {
+        self.conn.query("START TRANSACTION").map(|_| ()).map_err(SqlError::driver)
+    }
+
+    fn commit(&mut self) -> Result<(), SqlError> {
+        self.conn.query("COMMIT").map(|_| ()).map_err(SqlError::driver)
+    }
+
+    fn rollback(&mut self) -> Result<(), SqlError> {
+        self.conn.query("ROLLBACK").map(|_| ()).map_err(SqlError::driver)
+    }
+}
+
+fn to_mysql_params(params: &[SqlValue]) -> Vec<Value> {
+    params.iter()
+        .map(|param| match param {
+            SqlValue::Null => Value::NULL,
+            SqlValue::Int(v) => Value::Int(*v),
+            SqlValue::Text(v) => Value::Bytes(v.clone().into_bytes()),
+        })
+        .collect()
+}
+
+fn from_mysql_value(value: Value) -> Result<SqlValue, SqlError> {
+    match value {
+        Value::NULL => Ok(SqlValue::Null),
+        Value::Int(v) => Ok(SqlValue::Int(v)),
+        Value::UInt(v) if v <= i64::MAX as u64 => Ok(SqlValue::Int(v as i64)),
+        Value::Bytes(bytes) => String::from_utf8(bytes)
+            .map(SqlValue::Text)
+            .map_err(|e| SqlError::InvalidData(e.to_string())),
+        other => Err(SqlError::InvalidData(format!("unsupported mysql value {:?}", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MysqlConnection;
+    use crate::MockUserRepository;
+    use crate::repositories::event_log::EventSourcedChatrooms;
+    use crate::repositories::sql::{Dialect, Migrator, SqlChatroomLog, SqlChatrooms, SqlTokens, SqlUsers};
+    use crate::test_tools::conformance::{ChatroomEntities, TokenEntities, UserEntities};
+
+    // The conformance tests need a database they're free to write to, e.g.
+    // MYSQL_TEST_URL=mysql://root@localhost/share_it_test
+    // They pass without doing anything when it isn't set.
+    fn test_connection() -> Option<MysqlConnection> {
+        let url = std::env::var("MYSQL_TEST_URL").ok()?;
+        let mut conn = MysqlConnection::connect(&url).unwrap();
+        Migrator::for_dialect(Dialect::Mysql).migrate(&mut conn).unwrap();
+        Some(conn)
+    }
+
+    mod users {
+        use super::*;
+
+        crate::repository_conformance_tests!(
+            match test_connection() {
+                Some(conn) => SqlUsers::with_connection(conn),
+                None => return,
             },
-            Err(e) => {
-                return Err(e);
-            }
-        };
+            UserEntities::new()
+        );
+    }
 
-        // Success.  Return the PK back as is.
-        Ok(Some(entity.id()))
+    mod chatrooms {
+        use super::*;
 
-        // Update playlists here.
+        crate::repository_conformance_tests!(
+            match test_connection() {
+                Some(conn) => SqlChatrooms::with_connection(conn, MockUserRepository::new()),
+                None => return,
+            },
+            ChatroomEntities::new(MockUserRepository::new())
+        );
     }
 
-    fn remove(&mut self, key: &UserID) -> Result<Option<UserID>, Self::Error> {
-        match self.conn.query(
-            format!("DELETE FROM users WHERE id = '{}'", key),
-        ) {
-            Ok(result) => {
-                if result.affected_rows() == 0 {
-                    return Ok(None);
-                }
+    mod event_sourced_chatrooms {
+        use super::*;
+
+        crate::repository_conformance_tests!(
+            match test_connection() {
+                Some(conn) => EventSourcedChatrooms::new(SqlChatroomLog::with_connection(conn, MockUserRepository::new())),
+                None => return,
             },
-            Err(e) => {
-                return Err(e);
-            }
-        };
+            ChatroomEntities::new(MockUserRepository::new())
+        );
+    }
+
+    mod tokens {
+        use super::*;
 
-        // Success.  Return the PK back as is.
-        Ok(Some(key.clone()))
+        crate::repository_conformance_tests!(unversioned:
+            match test_connection() {
+                Some(conn) => SqlTokens::with_connection(conn),
+                None => return,
+            },
+            TokenEntities::new()
+        );
     }
-}
\ No newline at end of file
+}
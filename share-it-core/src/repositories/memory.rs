@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use rusty_ulid::Ulid;
+use crate::chatroom::Chatroom;
+use crate::events::DomainEvent;
+use crate::playlist::Playlist;
+use crate::repositories::abstractions::{Query, Repository, StaleVersion, Versioned};
+use crate::repositories::event_log::{ChatroomLog, Recorded, Snapshot};
+use crate::repositories::query::{paginate, ChatroomFilter, Page, PageRequest, PlaylistFilter, UserFilter};
+use crate::user::{PlaylistID, User, UserID};
+
+// The in-memory repositories keep everything in one map shared between every clone, so a clone
+// handed to a chatroom or a worker thread sees the same data as the original. They're enough to
+// run a single server without a database, but lose everything when it stops.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemoryError {
+    // Another thread panicked while it was holding the lock, so the data may be half written.
+    Poisoned,
+    Stale(StaleVersion),
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryError::Poisoned => write!(f, "in-memory repository lock was poisoned"),
+            MemoryError::Stale(e) => write!(f, "stale update: {}", e),
+        }
+    }
+}
+
+impl error::Error for MemoryError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            MemoryError::Poisoned => None,
+            MemoryError::Stale(e) => Some(e),
+        }
+    }
+}
+
+// MemoryStore is the map behind an in-memory repository.
+struct MemoryStore<K, V> {
+    data: Arc<RwLock<HashMap<K, V>>>,
+}
+
+impl<K, V> Clone for MemoryStore<K, V> {
+    fn clone(&self) -> Self {
+        MemoryStore {
+            data: self.data.clone(),
+        }
+    }
+}
+
+impl<K, V> MemoryStore<K, V> where
+    K: std::hash::Hash + Eq + Clone,
+    V: Clone,
+{
+    fn new() -> MemoryStore<K, V> {
+        MemoryStore {
+            data: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn insert(&self, key: K, entity: &V) -> Result<Option<K>, MemoryError> {
+        let mut data = self.data.write().map_err(|_| MemoryError::Poisoned)?;
+        if data.contains_key(&key) {
+            return Ok(None);
+        }
+        data.insert(key.clone(), entity.clone());
+        Ok(Some(key))
+    }
+
+    fn get(&self, key: &K) -> Result<Option<V>, MemoryError> {
+        let data = self.data.read().map_err(|_| MemoryError::Poisoned)?;
+        Ok(data.get(key).cloned())
+    }
+
+    fn contains(&self, key: &K) -> Result<bool, MemoryError> {
+        let data = self.data.read().map_err(|_| MemoryError::Poisoned)?;
+        Ok(data.contains_key(key))
+    }
+
+    fn update(&self, key: K, entity: &V) -> Result<Option<K>, MemoryError> where
+        V: Versioned,
+    {
+        let mut data = self.data.write().map_err(|_| MemoryError::Poisoned)?;
+        match data.get_mut(&key) {
+            Some(stored) => {
+                if stored.version() != entity.version() {
+                    return Err(MemoryError::Stale(StaleVersion { loaded: entity.version(), stored: stored.version() }));
+                }
+                *stored = entity.clone();
+                stored.set_version(entity.version() + 1);
+                Ok(Some(key))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn remove(&self, key: &K) -> Result<Option<K>, MemoryError> {
+        let mut data = self.data.write().map_err(|_| MemoryError::Poisoned)?;
+        Ok(data.remove(key).map(|_| key.clone()))
+    }
+
+    // select copies out the entities f picks, with whatever f keys them on.
+    fn select<T, F>(&self, f: F) -> Result<Vec<T>, MemoryError> where
+        F: FnMut(&V) -> Option<T>,
+    {
+        let data = self.data.read().map_err(|_| MemoryError::Poisoned)?;
+        Ok(data.values().filter_map(f).collect())
+    }
+}
+
+// InMemoryUsers stores users in memory, shared by every clone.
+#[derive(Clone)]
+pub struct InMemoryUsers {
+    store: MemoryStore<UserID, User>,
+}
+
+impl InMemoryUsers {
+    pub fn new() -> InMemoryUsers {
+        InMemoryUsers {
+            store: MemoryStore::new(),
+        }
+    }
+}
+
+impl Default for InMemoryUsers {
+    fn default() -> Self {
+        InMemoryUsers::new()
+    }
+}
+
+impl Repository<UserID, User> for InMemoryUsers {
+    type Error = MemoryError;
+
+    fn insert(&mut self, user: &User) -> Result<Option<UserID>, Self::Error> {
+        self.store.insert(user.id(), user)
+    }
+
+    fn get(&mut self, key: &UserID) -> Result<Option<User>, Self::Error> {
+        self.store.get(key)
+    }
+
+    fn contains(&mut self, key: &UserID) -> Result<bool, Self::Error> {
+        self.store.contains(key)
+    }
+
+    fn update(&mut self, user: &User) -> Result<Option<UserID>, Self::Error> {
+        self.store.update(user.id(), user)
+    }
+
+    fn remove(&mut self, key: &UserID) -> Result<Option<UserID>, Self::Error> {
+        self.store.remove(key)
+    }
+}
+
+impl Query<UserFilter, User> for InMemoryUsers {
+    type Cursor = UserID;
+    type Error = MemoryError;
+
+    fn query(&mut self, filter: &UserFilter, page: &PageRequest<UserID>) -> Result<Page<User, UserID>, MemoryError> {
+        let users = self.store.select(|user| {
+            if filter.matches(user) { Some((user.id(), user.clone())) } else { None }
+        })?;
+
+        Ok(paginate(users, page))
+    }
+}
+
+impl Query<PlaylistFilter, Playlist> for InMemoryUsers {
+    type Cursor = PlaylistID;
+    type Error = MemoryError;
+
+    fn query(&mut self, filter: &PlaylistFilter, page: &PageRequest<PlaylistID>) -> Result<Page<Playlist, PlaylistID>, MemoryError> {
+        let playlists = match self.store.get(&filter.user_id)? {
+            Some(user) => user.playlists().map(|playlist| (playlist.id(), playlist.clone())).collect(),
+            None => Vec::new(),
+        };
+
+        Ok(paginate(playlists, page))
+    }
+}
+
+// InMemoryChatrooms stores chatrooms in memory, shared by every clone. The chatrooms hold on to
+// their own user repository, which should be an InMemoryUsers too so they see users change.
+pub struct InMemoryChatrooms<U> where
+    U: Repository<u32, User> + Clone,
+{
+    store: MemoryStore<Ulid, Chatroom<U>>,
+}
+
+impl<U> Clone for InMemoryChatrooms<U> where
+    U: Repository<u32, User> + Clone,
+{
+    fn clone(&self) -> Self {
+        InMemoryChatrooms {
+            store: self.store.clone(),
+        }
+    }
+}
+
+impl<U> InMemoryChatrooms<U> where
+    U: Repository<u32, User> + Clone,
+{
+    pub fn new() -> InMemoryChatrooms<U> {
+        InMemoryChatrooms {
+            store: MemoryStore::new(),
+        }
+    }
+}
+
+impl<U> Default for InMemoryChatrooms<U> where
+    U: Repository<u32, User> + Clone,
+{
+    fn default() -> Self {
+        InMemoryChatrooms::new()
+    }
+}
+
+impl<U> Repository<Ulid, Chatroom<U>> for InMemoryChatrooms<U> where
+    U: Repository<u32, User> + Clone,
+{
+    type Error = MemoryError;
+
+    fn insert(&mut self, chatroom: &Chatroom<U>) -> Result<Option<Ulid>, Self::Error> {
+        self.store.insert(chatroom.id(), &without_events(chatroom))
+    }
+
+    fn get(&mut self, key: &Ulid) -> Result<Option<Chatroom<U>>, Self::Error> {
+        self.store.get(key)
+    }
+
+    fn contains(&mut self, key: &Ulid) -> Result<bool, Self::Error> {
+        self.store.contains(key)
+    }
+
+    fn update(&mut self, chatroom: &Chatroom<U>) -> Result<Option<Ulid>, Self::Error> {
+        self.store.update(chatroom.id(), &without_events(chatroom))
+    }
+
+    fn remove(&mut self, key: &Ulid) -> Result<Option<Ulid>, Self::Error> {
+        self.store.remove(key)
+    }
+}
+
+// without_events copies a chatroom to be stored. Its pending events are left behind, as they're
+// the caller's to publish, and would be published again by whoever loaded it next.
+fn without_events<U>(chatroom: &Chatroom<U>) -> Chatroom<U> where
+    U: Repository<u32, User> + Clone,
+{
+    let mut chatroom = chatroom.clone();
+    chatroom.take_events();
+    chatroom
+}
+
+impl<U> Query<ChatroomFilter, Chatroom<U>> for InMemoryChatrooms<U> where
+    U: Repository<u32, User> + Clone,
+{
+    type Cursor = Ulid;
+    type Error = MemoryError;
+
+    fn query(&mut self, filter: &ChatroomFilter, page: &PageRequest<Ulid>) -> Result<Page<Chatroom<U>, Ulid>, MemoryError> {
+        let chatrooms = self.store.select(|chatroom| {
+            if filter.matches(chatroom) { Some((chatroom.id(), chatroom.clone())) } else { None }
+        })?;
+
+        Ok(paginate(chatrooms, page))
+    }
+}
+
+// ChatroomStream is everything an InMemoryChatroomLog has recorded for one chatroom.
+struct ChatroomStream<U> where
+    U: Repository<u32, User>,
+{
+    events: Vec<DomainEvent>,
+    // In the order they were taken, which is also sequence order.
+    snapshots: Vec<Snapshot<Chatroom<U>>>,
+}
+
+// InMemoryChatroomLog keeps every chatroom's event log in memory, shared by every clone.
+pub struct InMemoryChatroomLog<U> where
+    U: Repository<u32, User> + Clone,
+{
+    streams: Arc<RwLock<HashMap<Ulid, ChatroomStream<U>>>>,
+}
+
+impl<U> Clone for InMemoryChatroomLog<U> where
+    U: Repository<u32, User> + Clone,
+{
+    fn clone(&self) -> Self {
+        InMemoryChatroomLog {
+            streams: self.streams.clone(),
+        }
+    }
+}
+
+impl<U> InMemoryChatroomLog<U> where
+    U: Repository<u32, User> + Clone,
+{
+    pub fn new() -> InMemoryChatroomLog<U> {
+        InMemoryChatroomLog {
+            streams: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl<U> Default for InMemoryChatroomLog<U> where
+    U: Repository<u32, User> + Clone,
+{
+    fn default() -> Self {
+        InMemoryChatroomLog::new()
+    }
+}
+
+impl<U> ChatroomLog<U> for InMemoryChatroomLog<U> where
+    U: Repository<u32, User> + Clone,
+{
+    type Error = MemoryError;
+
+    fn append(&mut self, chatroom_id: &Ulid, after: u64, events: &[DomainEvent]) -> Result<Option<u64>, Self::Error> {
+        let mut streams = self.streams.write().map_err(|_| MemoryError::Poisoned)?;
+        let stream = streams.entry(*chatroom_id).or_insert_with(|| ChatroomStream {
+            events: Vec::new(),
+            snapshots: Vec::new(),
+        });
+        if stream.events.len() as u64 != after {
+            return Ok(None);
+        }
+
+        stream.events.extend_from_slice(events);
+        Ok(Some(stream.events.len() as u64))
+    }
+
+    fn events(&mut self, chatroom_id: &Ulid, after: u64) -> Result<Vec<Recorded>, Self::Error> {
+        let streams = self.streams.read().map_err(|_| MemoryError::Poisoned)?;
+        let events = match streams.get(chatroom_id) {
+            Some(stream) => &stream.events,
+            None => return Ok(Vec::new()),
+        };
+
+        Ok(events.iter()
+            .enumerate()
+            .skip(after as usize)
+            .map(|(i, event)| Recorded { sequence: i as u64 + 1, event: event.clone() })
+            .collect())
+    }
+
+    fn last_sequence(&mut self, chatroom_id: &Ulid) -> Result<u64, Self::Error> {
+        let streams = self.streams.read().map_err(|_| MemoryError::Poisoned)?;
+        Ok(streams.get(chatroom_id).map_or(0, |stream| stream.events.len() as u64))
+    }
+
+    fn save_snapshot(&mut self, snapshot: &Snapshot<Chatroom<U>>) -> Result<(), Self::Error> {
+        let mut streams = self.streams.write().map_err(|_| MemoryError::Poisoned)?;
+        if let Some(stream) = streams.get_mut(&snapshot.state.id()) {
+            stream.snapshots.push(snapshot.clone());
+        }
+        Ok(())
+    }
+
+    fn snapshot(&mut self, chatroom_id: &Ulid, at_or_before: Option<u64>) -> Result<Option<Snapshot<Chatroom<U>>>, Self::Error> {
+        let streams = self.streams.read().map_err(|_| MemoryError::Poisoned)?;
+        let snapshots = match streams.get(chatroom_id) {
+            Some(stream) => &stream.snapshots,
+            None => return Ok(None),
+        };
+
+        Ok(snapshots.iter()
+            .rev()
+            .find(|snapshot| !matches!(at_or_before, Some(sequence) if snapshot.sequence > sequence))
+            .cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InMemoryChatrooms, InMemoryUsers};
+    use crate::chatroom::{Chatroom, ChatUser};
+    use crate::repositories::abstractions::Repository;
+    use crate::test_tools::factories::{new_test_user, new_test_playlist};
+    use std::thread;
+
+    #[test]
+    #[allow(unused)]
+    fn clones_share_users() {
+        let mut users = InMemoryUsers::new();
+        let mut clone = users.clone();
+
+        clone.insert(&new_test_user(1)).unwrap();
+        assert!(users.contains(&1).unwrap());
+
+        let mut user = users.get(&1).unwrap().unwrap();
+        user.add_playlist(new_test_playlist(1, 2));
+        users.update(&user).unwrap();
+        assert_eq!(clone.get(&1).unwrap(), users.get(&1).unwrap());
+        assert_eq!(clone.get(&1).unwrap().unwrap().playlist_count(), 1);
+
+        users.remove(&1).unwrap();
+        assert!(clone.get(&1).unwrap().is_none());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn chatrooms_see_users_change() {
+        let mut users = InMemoryUsers::new();
+        let mut chatrooms = InMemoryChatrooms::new();
+        let mut chatroom = Chatroom::new(users.clone(), 1, "test_chatroom".to_string());
+        chatroom.join(ChatUser(1, "test_username".to_string()));
+        chatroom.join_waitlist(1);
+        chatrooms.clone().insert(&chatroom).unwrap();
+
+        // The user shows up after the chatroom was stored, which a cloned map would never see.
+        let mut user = new_test_user(1);
+        let playlist = new_test_playlist(1, 2);
+        user.set_active_playlist(&playlist.id());
+        user.add_playlist(playlist);
+        users.insert(&user).unwrap();
+
+        let mut stored = chatrooms.get(&chatroom.id()).unwrap().unwrap();
+        assert!(stored.play_next().unwrap().is_some());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn users_are_shared_between_threads() {
+        let users = InMemoryUsers::new();
+
+        let workers: Vec<_> = (0..8).map(|i| {
+            let mut users = users.clone();
+            thread::spawn(move || {
+                for j in 0..25 {
+                    users.insert(&new_test_user(i * 25 + j)).unwrap();
+                }
+            })
+        }).collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let mut users = users;
+        for user_id in 0..200 {
+            assert!(users.contains(&user_id).unwrap());
+        }
+    }
+
+    mod user_conformance {
+        use crate::repositories::memory::InMemoryUsers;
+        use crate::test_tools::conformance::UserEntities;
+
+        crate::repository_conformance_tests!(InMemoryUsers::new(), UserEntities::new());
+    }
+
+    mod chatroom_conformance {
+        use crate::repositories::memory::{InMemoryChatrooms, InMemoryUsers};
+        use crate::test_tools::conformance::ChatroomEntities;
+
+        crate::repository_conformance_tests!(
+            InMemoryChatrooms::new(),
+            ChatroomEntities::new(InMemoryUsers::new())
+        );
+    }
+
+    mod event_sourced_conformance {
+        use crate::repositories::event_log::EventSourcedChatrooms;
+        use crate::repositories::memory::{InMemoryChatroomLog, InMemoryUsers};
+        use crate::test_tools::conformance::ChatroomEntities;
+
+        crate::repository_conformance_tests!(
+            EventSourcedChatrooms::new(InMemoryChatroomLog::new()),
+            ChatroomEntities::new(InMemoryUsers::new())
+        );
+    }
+}
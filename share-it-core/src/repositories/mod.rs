@@ -1,2 +1,7 @@
 pub mod abstractions;
+pub mod event_log;
 pub mod implementations;
+pub mod memory;
+pub mod pool;
+pub mod query;
+pub mod sql;
@@ -0,0 +1,347 @@
+use std::env;
+use std::error;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+use mysql::{Opts, OptsBuilder};
+use crate::repositories::implementations::MysqlConnection;
+
+// MysqlPoolConfig is everything needed to open a MySQL connection pool. Timeouts left as None
+// wait as long as the driver and server let them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MysqlPoolConfig {
+    pub url: String,
+    // Connections opened up front, and kept open while idle.
+    pub min_connections: usize,
+    pub max_connections: usize,
+    pub connect_timeout: Option<Duration>,
+    // How long to wait for a free connection once all max_connections are in use.
+    pub checkout_timeout: Option<Duration>,
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    pub tls: Option<MysqlTls>,
+}
+
+// MysqlTls encrypts connections, verifying the server against ca_cert. The client identity is
+// only needed by servers that authenticate clients by certificate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MysqlTls {
+    pub ca_cert: PathBuf,
+    // The client certificate and its key.
+    pub client_identity: Option<(PathBuf, PathBuf)>,
+}
+
+impl MysqlPoolConfig {
+    // new configures a pool for url with the driver's default size, and no timeouts.
+    pub fn new(url: &str) -> MysqlPoolConfig {
+        MysqlPoolConfig {
+            url: url.to_string(),
+            min_connections: 10,
+            max_connections: 100,
+            connect_timeout: None,
+            checkout_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            tls: None,
+        }
+    }
+
+    // from_env configures the pool from the environment. See `MysqlPoolConfig::from_vars` for the
+    // variables.
+    pub fn from_env() -> Result<MysqlPoolConfig, PoolError> {
+        MysqlPoolConfig::from_vars(|key| env::var(key).ok())
+    }
+
+    // from_vars reads DATABASE_URL, which is required, and the optional
+    // DATABASE_POOL_MIN_CONNECTIONS, DATABASE_POOL_MAX_CONNECTIONS, DATABASE_CONNECT_TIMEOUT_MS,
+    // DATABASE_CHECKOUT_TIMEOUT_MS, DATABASE_READ_TIMEOUT_MS, DATABASE_WRITE_TIMEOUT_MS,
+    // DATABASE_TLS_CA_CERT, DATABASE_TLS_CLIENT_CERT and DATABASE_TLS_CLIENT_KEY.
+    pub fn from_vars<F>(lookup: F) -> Result<MysqlPoolConfig, PoolError> where
+        F: Fn(&str) -> Option<String>,
+    {
+        let url = lookup("DATABASE_URL").ok_or(PoolError::MissingUrl)?;
+        let mut config = MysqlPoolConfig::new(&url);
+
+        let number = |key: &str| -> Result<Option<u64>, PoolError> {
+            match lookup(key) {
+                Some(value) => value.trim().parse()
+                    .map(Some)
+                    .map_err(|_| PoolError::InvalidConfig(format!("{} must be a number, not {}", key, value))),
+                None => Ok(None),
+            }
+        };
+        let millis = |key: &str| number(key).map(|ms| ms.map(Duration::from_millis));
+
+        if let Some(min) = number("DATABASE_POOL_MIN_CONNECTIONS")? {
+            config.min_connections = min as usize;
+        }
+        if let Some(max) = number("DATABASE_POOL_MAX_CONNECTIONS")? {
+            config.max_connections = max as usize;
+        }
+        config.connect_timeout = millis("DATABASE_CONNECT_TIMEOUT_MS")?;
+        config.checkout_timeout = millis("DATABASE_CHECKOUT_TIMEOUT_MS")?;
+        config.read_timeout = millis("DATABASE_READ_TIMEOUT_MS")?;
+        config.write_timeout = millis("DATABASE_WRITE_TIMEOUT_MS")?;
+
+        let client_identity = match (lookup("DATABASE_TLS_CLIENT_CERT"), lookup("DATABASE_TLS_CLIENT_KEY")) {
+            (Some(cert), Some(key)) => Some((PathBuf::from(cert), PathBuf::from(key))),
+            (None, None) => None,
+            _ => return Err(PoolError::InvalidConfig(
+                "DATABASE_TLS_CLIENT_CERT and DATABASE_TLS_CLIENT_KEY must be set together".to_string(),
+            )),
+        };
+        config.tls = match lookup("DATABASE_TLS_CA_CERT") {
+            Some(ca_cert) => Some(MysqlTls { ca_cert: PathBuf::from(ca_cert), client_identity }),
+            None if client_identity.is_some() => return Err(PoolError::InvalidConfig(
+                "a TLS client certificate needs DATABASE_TLS_CA_CERT too".to_string(),
+            )),
+            None => None,
+        };
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), PoolError> {
+        if self.max_connections == 0 {
+            return Err(PoolError::InvalidConfig("the pool needs at least one connection".to_string()));
+        }
+        if self.min_connections > self.max_connections {
+            return Err(PoolError::InvalidConfig(format!(
+                "the pool can't keep {} connections open with a maximum of {}",
+                self.min_connections, self.max_connections,
+            )));
+        }
+        if self.checkout_timeout.map(|t| t.as_millis() > u32::MAX as u128).unwrap_or(false) {
+            return Err(PoolError::InvalidConfig("the checkout timeout is too long".to_string()));
+        }
+        Ok(())
+    }
+
+    fn opts(&self) -> Result<Opts, PoolError> {
+        let opts = Opts::from_url(&self.url).map_err(|e| PoolError::InvalidConfig(e.to_string()))?;
+        let mut builder = OptsBuilder::from_opts(opts);
+        builder.tcp_connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout)
+            .write_timeout(self.write_timeout);
+
+        if let Some(tls) = &self.tls {
+            configure_tls(&mut builder, tls)?;
+        }
+
+        Ok(builder.into())
+    }
+}
+
+#[cfg(feature = "mysql-tls")]
+fn configure_tls(builder: &mut OptsBuilder, tls: &MysqlTls) -> Result<(), PoolError> {
+    builder.ssl_opts(Some((tls.ca_cert.clone(), tls.client_identity.clone())));
+    Ok(())
+}
+
+// Without the feature the driver would panic when asked for TLS, so refuse the config instead.
+#[cfg(not(feature = "mysql-tls"))]
+fn configure_tls(_: &mut OptsBuilder, _: &MysqlTls) -> Result<(), PoolError> {
+    Err(PoolError::TlsUnsupported)
+}
+
+#[derive(Debug)]
+pub enum PoolError {
+    MissingUrl,
+    InvalidConfig(String),
+    // TLS was configured, but share-it-core was built without the mysql-tls feature.
+    TlsUnsupported,
+    // The database, or the driver talking to it, failed.
+    Driver(mysql::Error),
+    // We got a connection, but it didn't answer a ping.
+    Unhealthy,
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolError::MissingUrl => write!(f, "missing the DATABASE_URL environment variable"),
+            PoolError::InvalidConfig(e) => write!(f, "invalid database config: {}", e),
+            PoolError::TlsUnsupported => write!(f, "database TLS needs share-it-core's mysql-tls feature"),
+            PoolError::Driver(e) => write!(f, "database error: {}", e),
+            PoolError::Unhealthy => write!(f, "the database didn't answer a ping"),
+        }
+    }
+}
+
+impl error::Error for PoolError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PoolError::Driver(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// MysqlPool hands out MySQL connections to the repositories. It's cheap to clone, and clones share
+// the same connections.
+#[derive(Clone)]
+pub struct MysqlPool {
+    pool: mysql::Pool,
+    checkout_timeout: Option<Duration>,
+}
+
+impl MysqlPool {
+    // new opens min_connections up front, so a bad config or unreachable database fails here
+    // rather than on the first request.
+    pub fn new(config: &MysqlPoolConfig) -> Result<MysqlPool, PoolError> {
+        config.validate()?;
+        let pool = mysql::Pool::new_manual(config.min_connections, config.max_connections, config.opts()?)
+            .map_err(PoolError::Driver)?;
+
+        Ok(MysqlPool {
+            pool,
+            checkout_timeout: config.checkout_timeout,
+        })
+    }
+
+    pub fn from_env() -> Result<MysqlPool, PoolError> {
+        MysqlPool::new(&MysqlPoolConfig::from_env()?)
+    }
+
+    // connection checks a connection out of the pool. It goes back when the MysqlConnection is
+    // dropped.
+    pub fn connection(&self) -> Result<MysqlConnection, PoolError> {
+        self.checkout().map(MysqlConnection::from_pooled)
+    }
+
+    // ping checks we can get a connection, and that the database answers on it.
+    pub fn ping(&self) -> Result<(), PoolError> {
+        let mut conn = self.checkout()?;
+        if conn.as_mut().ping() {
+            Ok(())
+        } else {
+            Err(PoolError::Unhealthy)
+        }
+    }
+
+    fn checkout(&self) -> Result<mysql::PooledConn, PoolError> {
+        match self.checkout_timeout {
+            // validate made sure this fits.
+            Some(timeout) => self.pool.try_get_conn(timeout.as_millis() as u32),
+            None => self.pool.get_conn(),
+        }.map_err(PoolError::Driver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MysqlPool, MysqlPoolConfig, MysqlTls, PoolError};
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn config_from(vars: &[(&str, &str)]) -> Result<MysqlPoolConfig, PoolError> {
+        let vars: HashMap<String, String> = vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        MysqlPoolConfig::from_vars(|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn config_reads_the_environment() {
+        let config = config_from(&[
+            ("DATABASE_URL", "mysql://root@localhost/share_it"),
+            ("DATABASE_POOL_MIN_CONNECTIONS", "2"),
+            ("DATABASE_POOL_MAX_CONNECTIONS", "8"),
+            ("DATABASE_CONNECT_TIMEOUT_MS", "500"),
+            ("DATABASE_CHECKOUT_TIMEOUT_MS", "1000"),
+            ("DATABASE_TLS_CA_CERT", "/etc/mysql/ca.pem"),
+        ]).unwrap();
+
+        assert_eq!(config.url, "mysql://root@localhost/share_it");
+        assert_eq!(config.min_connections, 2);
+        assert_eq!(config.max_connections, 8);
+        assert_eq!(config.connect_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(config.checkout_timeout, Some(Duration::from_millis(1000)));
+        assert_eq!(config.read_timeout, None);
+        assert_eq!(config.tls, Some(MysqlTls { ca_cert: PathBuf::from("/etc/mysql/ca.pem"), client_identity: None }));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn config_defaults_everything_but_the_url() {
+        let config = config_from(&[("DATABASE_URL", "mysql://localhost/share_it")]).unwrap();
+        assert_eq!(config, MysqlPoolConfig::new("mysql://localhost/share_it"));
+
+        match config_from(&[]) {
+            Err(PoolError::MissingUrl) => {},
+            other => panic!("expected a missing url, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn bad_config_is_an_error() {
+        let bad = [
+            vec![("DATABASE_POOL_MAX_CONNECTIONS", "lots")],
+            vec![("DATABASE_READ_TIMEOUT_MS", "-1")],
+            vec![("DATABASE_TLS_CLIENT_CERT", "client.pem")],
+            vec![("DATABASE_TLS_CLIENT_CERT", "client.pem"), ("DATABASE_TLS_CLIENT_KEY", "client.key")],
+        ];
+        for vars in bad.iter() {
+            let mut vars = vars.clone();
+            vars.push(("DATABASE_URL", "mysql://localhost/share_it"));
+            match config_from(&vars) {
+                Err(PoolError::InvalidConfig(_)) => {},
+                other => panic!("expected {:?} to be invalid, got {:?}", vars, other),
+            }
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn pools_refuse_impossible_sizes() {
+        let mut config = MysqlPoolConfig::new("mysql://localhost/share_it");
+        config.max_connections = 0;
+        assert!(matches!(MysqlPool::new(&config), Err(PoolError::InvalidConfig(_))));
+
+        config.min_connections = 5;
+        config.max_connections = 4;
+        assert!(matches!(MysqlPool::new(&config), Err(PoolError::InvalidConfig(_))));
+
+        config.max_connections = 5;
+        config.url = "postgres://localhost/share_it".to_string();
+        assert!(matches!(MysqlPool::new(&config), Err(PoolError::InvalidConfig(_))));
+    }
+
+    #[cfg(not(feature = "mysql-tls"))]
+    #[test]
+    #[allow(unused)]
+    fn tls_needs_the_feature() {
+        let mut config = MysqlPoolConfig::new("mysql://localhost/share_it");
+        config.min_connections = 0;
+        config.tls = Some(MysqlTls { ca_cert: PathBuf::from("ca.pem"), client_identity: None });
+
+        assert!(matches!(MysqlPool::new(&config), Err(PoolError::TlsUnsupported)));
+    }
+
+    // Like the repository conformance tests, this needs MYSQL_TEST_URL, and passes without doing
+    // anything when it isn't set.
+    #[test]
+    #[allow(unused)]
+    fn pools_ping_the_database() {
+        let url = match std::env::var("MYSQL_TEST_URL") {
+            Ok(url) => url,
+            Err(_) => return,
+        };
+        let mut config = MysqlPoolConfig::new(&url);
+        config.min_connections = 1;
+        config.max_connections = 1;
+        config.checkout_timeout = Some(Duration::from_millis(100));
+        let pool = MysqlPool::new(&config).unwrap();
+
+        pool.ping().unwrap();
+
+        // With the only connection checked out, the next checkout times out.
+        let held = pool.connection().unwrap();
+        assert!(matches!(pool.ping(), Err(PoolError::Driver(_))));
+        drop(held);
+        pool.ping().unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+use crate::chatroom::Chatroom;
+use crate::repositories::abstractions::Repository;
+use crate::user::{User, UserID};
+
+// Queries list entities matching a filter, a page at a time. Pages are keyed on a cursor, the key
+// of the last entity on the previous page, rather than an offset, so entities being added or
+// removed between pages never makes us skip or repeat any. Where the key is a ULID, ordering on it
+// is ordering by when the entity was created.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+// PageRequest asks for up to limit entities, starting after the cursor if there is one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageRequest<C> {
+    pub limit: u32,
+    pub after: Option<C>,
+    pub order: Order,
+}
+
+impl<C> PageRequest<C> {
+    // first asks for the first page, in ascending order.
+    pub fn first(limit: u32) -> PageRequest<C> {
+        PageRequest {
+            limit,
+            after: None,
+            order: Order::Ascending,
+        }
+    }
+
+    pub fn after(mut self, cursor: C) -> PageRequest<C> {
+        self.after = Some(cursor);
+        self
+    }
+
+    pub fn descending(mut self) -> PageRequest<C> {
+        self.order = Order::Descending;
+        self
+    }
+}
+
+// Page is one page of results. next is the cursor to ask for the page after it, and is None on
+// the last page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<V, C> {
+    pub items: Vec<V>,
+    pub next: Option<C>,
+}
+
+impl<V, C: Clone> Page<V, C> {
+    // next_page asks for the page after this one, the same way request asked for this one.
+    pub fn next_page(&self, request: &PageRequest<C>) -> Option<PageRequest<C>> {
+        self.next.clone().map(|cursor| PageRequest {
+            limit: request.limit,
+            after: Some(cursor),
+            order: request.order,
+        })
+    }
+}
+
+// UserFilter finds users, ordered by id.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserFilter {
+    // Only users with exactly this username.
+    pub username: Option<String>,
+    // Only the user with this account linked, given as its provider and the provider's id for it.
+    pub identity: Option<(String, String)>,
+}
+
+impl UserFilter {
+    pub fn matches(&self, user: &User) -> bool {
+        self.username.as_ref().is_none_or(|username| &user.username() == username)
+            && self.identity.as_ref().is_none_or(|(provider, subject)| {
+                user.identity(provider).is_some_and(|identity| &identity.subject == subject)
+            })
+    }
+}
+
+// PlaylistFilter finds a user's playlists, ordered by id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistFilter {
+    pub user_id: UserID,
+}
+
+// ChatroomFilter finds chatrooms, ordered by id.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChatroomFilter {
+    // Only chatrooms this user moderates.
+    pub moderator: Option<UserID>,
+    // Only chatrooms this user is in.
+    pub member: Option<UserID>,
+}
+
+impl ChatroomFilter {
+    pub fn matches<U>(&self, chatroom: &Chatroom<U>) -> bool where
+        U: Repository<u32, User>,
+    {
+        self.moderator.is_none_or(|moderator| chatroom.moderator() == moderator)
+            && self.member.is_none_or(|member| chatroom.current_users().iter().any(|u| u.0 == member))
+    }
+}
+
+// page_of turns up to limit + 1 entities, in order, into a page. The extra entity only tells us
+// there's another page.
+pub(crate) fn page_of<C: Clone, V>(mut items: Vec<(C, V)>, limit: u32) -> Page<V, C> {
+    let has_more = items.len() > limit as usize;
+    items.truncate(limit as usize);
+
+    let next = if has_more {
+        items.last().map(|(cursor, _)| cursor.clone())
+    } else {
+        None
+    };
+
+    Page {
+        items: items.into_iter().map(|(_, item)| item).collect(),
+        next,
+    }
+}
+
+// paginate pages through entities held in memory, keyed on their cursor.
+pub(crate) fn paginate<C: Ord + Clone, V>(mut items: Vec<(C, V)>, page: &PageRequest<C>) -> Page<V, C> {
+    if let Some(after) = &page.after {
+        items.retain(|(cursor, _)| match page.order {
+            Order::Ascending => cursor > after,
+            Order::Descending => cursor < after,
+        });
+    }
+
+    items.sort_by(|(a, _), (b, _)| a.cmp(b));
+    if page.order == Order::Descending {
+        items.reverse();
+    }
+    items.truncate(page.limit as usize + 1);
+
+    page_of(items, page.limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{paginate, PageRequest};
+
+    fn letters() -> Vec<(u32, char)> {
+        vec![(3, 'c'), (1, 'a'), (5, 'e'), (2, 'b'), (4, 'd')]
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn pages_cover_everything_once() {
+        let request = PageRequest::first(2);
+
+        let first = paginate(letters(), &request);
+        assert_eq!(first.items, vec!['a', 'b']);
+        assert_eq!(first.next, Some(2));
+
+        let second = paginate(letters(), &first.next_page(&request).unwrap());
+        assert_eq!(second.items, vec!['c', 'd']);
+
+        let last = paginate(letters(), &second.next_page(&request).unwrap());
+        assert_eq!(last.items, vec!['e']);
+        assert_eq!(last.next, None);
+        assert!(last.next_page(&request).is_none());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn descending_pages_go_backwards() {
+        let request = PageRequest::first(3).descending();
+
+        let first = paginate(letters(), &request);
+        assert_eq!(first.items, vec!['e', 'd', 'c']);
+
+        let last = paginate(letters(), &first.next_page(&request).unwrap());
+        assert_eq!(last.items, vec!['b', 'a']);
+        assert_eq!(last.next, None);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn exactly_full_pages_have_no_next() {
+        let page = paginate(letters(), &PageRequest::first(5));
+
+        assert_eq!(page.items.len(), 5);
+        assert_eq!(page.next, None);
+        assert!(paginate(letters(), &PageRequest::first(0)).items.is_empty());
+    }
+}
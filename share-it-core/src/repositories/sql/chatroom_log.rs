@@ -0,0 +1,251 @@
+use std::collections::VecDeque;
+use rusty_ulid::Ulid;
+use crate::chatroom::{Chatroom, ChatUser};
+use crate::events::DomainEvent;
+use crate::repositories::abstractions::Repository;
+use crate::repositories::event_log::{ChatroomLog, Recorded, Snapshot};
+use crate::repositories::sql::{SqlConnection, SqlError};
+use crate::user::{PlaylistID, User, UserID};
+use crate::waitlist::{Waitlist, DJ};
+
+// SqlChatroomLog keeps the log behind event sourced chatrooms in any SQL database we have a
+// connection for. Events and snapshots are stored as JSON. Snapshots are rebuilt with the given
+// user repository, though never loaded from it, as the DJ is kept in the snapshot as they were.
+pub struct SqlChatroomLog<C, U> {
+    conn: C,
+    users: U,
+}
+
+impl<C, U> SqlChatroomLog<C, U> where
+    C: SqlConnection,
+    U: Repository<u32, User> + Clone,
+{
+    pub fn with_connection(conn: C, users: U) -> SqlChatroomLog<C, U> {
+        SqlChatroomLog {
+            conn,
+            users,
+        }
+    }
+}
+
+// StoredChatroom is what a snapshot keeps of a chatroom.
+#[derive(Serialize, Deserialize)]
+struct StoredChatroom {
+    id: Ulid,
+    name: String,
+    moderator: UserID,
+    current_users: Vec<ChatUser>,
+    waitlist_id: Ulid,
+    current_dj: Option<User>,
+    current_playlist: Option<PlaylistID>,
+    queue: VecDeque<DJ>,
+}
+
+impl<C, U> ChatroomLog<U> for SqlChatroomLog<C, U> where
+    C: SqlConnection,
+    U: Repository<u32, User> + Clone,
+{
+    type Error = SqlError;
+
+    fn append(&mut self, chatroom_id: &Ulid, after: u64, events: &[DomainEvent]) -> Result<Option<u64>, Self::Error> {
+        self.conn.transaction(|conn| {
+            // The stream's row stays locked until we're done, so nobody can append in between.
+            let sql = format!(
+                "SELECT last_sequence FROM chatroom_streams WHERE chatroom_id = ?{}",
+                conn.dialect().for_update(),
+            );
+            let last = match conn.query(&sql, &[(*chatroom_id).into()])?.first() {
+                Some(row) => Some(row.get::<u64>(0)?),
+                None => None,
+            };
+            if last.unwrap_or(0) != after {
+                return Ok(None);
+            }
+
+            let appended = after + events.len() as u64;
+            if last.is_some() {
+                conn.execute(
+                    "UPDATE chatroom_streams SET last_sequence = ? WHERE chatroom_id = ?",
+                    &[appended.into(), (*chatroom_id).into()],
+                )?;
+            } else {
+                conn.execute(
+                    "INSERT INTO chatroom_streams (chatroom_id, last_sequence) VALUES (?, ?)",
+                    &[(*chatroom_id).into(), appended.into()],
+                )?;
+            }
+
+            for (sequence, event) in (after + 1..).zip(events) {
+                conn.execute(
+                    "INSERT INTO chatroom_events (chatroom_id, sequence, event) VALUES (?, ?, ?)",
+                    &[(*chatroom_id).into(), sequence.into(), to_json(event)?.into()],
+                )?;
+            }
+
+            Ok(Some(appended))
+        })
+    }
+
+    fn events(&mut self, chatroom_id: &Ulid, after: u64) -> Result<Vec<Recorded>, Self::Error> {
+        let rows = self.conn.query(
+            r"SELECT e.sequence, e.event
+            FROM chatroom_events AS e
+            WHERE e.chatroom_id = ? AND e.sequence > ?
+            ORDER BY e.sequence",
+            &[(*chatroom_id).into(), after.into()],
+        )?;
+
+        rows.iter()
+            .map(|row| Ok(Recorded {
+                sequence: row.get(0)?,
+                event: from_json(&row.get::<String>(1)?)?,
+            }))
+            .collect()
+    }
+
+    fn last_sequence(&mut self, chatroom_id: &Ulid) -> Result<u64, Self::Error> {
+        let rows = self.conn.query(
+            "SELECT last_sequence FROM chatroom_streams WHERE chatroom_id = ?",
+            &[(*chatroom_id).into()],
+        )?;
+
+        match rows.first() {
+            Some(row) => row.get(0),
+            None => Ok(0),
+        }
+    }
+
+    fn save_snapshot(&mut self, snapshot: &Snapshot<Chatroom<U>>) -> Result<(), Self::Error> {
+        let chatroom = &snapshot.state;
+        let waitlist = chatroom.waitlist();
+        let state = to_json(&StoredChatroom {
+            id: chatroom.id(),
+            name: chatroom.name(),
+            moderator: chatroom.moderator(),
+            current_users: chatroom.current_users().to_vec(),
+            waitlist_id: waitlist.id(),
+            current_dj: waitlist.current_dj().cloned(),
+            current_playlist: waitlist.current_playlist().copied(),
+            queue: waitlist.djs().clone(),
+        })?;
+
+        self.conn.transaction(|conn| {
+            // Chatrooms without events have nothing to snapshot. A snapshot that's already been
+            // taken is of the same state, so there's no need to take it twice.
+            if conn.query("SELECT chatroom_id FROM chatroom_streams WHERE chatroom_id = ?", &[chatroom.id().into()])?.is_empty() {
+                return Ok(());
+            }
+            let taken = conn.query(
+                "SELECT sequence FROM chatroom_snapshots WHERE chatroom_id = ? AND sequence = ?",
+                &[chatroom.id().into(), snapshot.sequence.into()],
+            )?;
+            if !taken.is_empty() {
+                return Ok(());
+            }
+
+            conn.execute(
+                "INSERT INTO chatroom_snapshots (chatroom_id, sequence, state) VALUES (?, ?, ?)",
+                &[chatroom.id().into(), snapshot.sequence.into(), state.into()],
+            )?;
+            Ok(())
+        })
+    }
+
+    fn snapshot(&mut self, chatroom_id: &Ulid, at_or_before: Option<u64>) -> Result<Option<Snapshot<Chatroom<U>>>, Self::Error> {
+        let mut sql = "SELECT s.sequence, s.state FROM chatroom_snapshots AS s WHERE s.chatroom_id = ?".to_string();
+        let mut params = vec![(*chatroom_id).into()];
+        if let Some(sequence) = at_or_before {
+            sql.push_str(" AND s.sequence <= ?");
+            params.push(sequence.into());
+        }
+        sql.push_str(" ORDER BY s.sequence DESC LIMIT 1");
+
+        let rows = self.conn.query(&sql, &params)?;
+        let row = match rows.first() {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let stored: StoredChatroom = from_json(&row.get::<String>(1)?)?;
+        let waitlist = Waitlist::restore(
+            stored.waitlist_id,
+            self.users.clone(),
+            stored.current_dj,
+            stored.current_playlist,
+            stored.queue,
+        );
+        let state = Chatroom::restore(stored.id, stored.name, stored.moderator, waitlist, stored.current_users);
+
+        Ok(Some(Snapshot { sequence: row.get(0)?, state }))
+    }
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, SqlError> {
+    serde_json::to_string(value).map_err(|e| SqlError::InvalidData(e.to_string()))
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(json: &str) -> Result<T, SqlError> {
+    serde_json::from_str(json).map_err(|e| SqlError::InvalidData(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqlChatroomLog;
+    use crate::chatroom::{Chatroom, ChatUser};
+    use crate::events::DomainEvent;
+    use crate::repositories::abstractions::Repository;
+    use crate::repositories::event_log::{ChatroomLog, Snapshot};
+    use crate::repositories::memory::InMemoryUsers;
+    use crate::repositories::sql::sqlite::SqliteConnection;
+    use crate::test_tools::factories::{new_test_playlist, new_test_user};
+
+    fn new_test_log(users: InMemoryUsers) -> SqlChatroomLog<SqliteConnection, InMemoryUsers> {
+        SqlChatroomLog::with_connection(SqliteConnection::in_memory(), users)
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn appends_after_a_stale_sequence_are_rejected() {
+        let mut log = new_test_log(InMemoryUsers::new());
+        let chatroom = Chatroom::new(InMemoryUsers::new(), 1, "test_chatroom".to_string());
+        let id = chatroom.id();
+        let restored = DomainEvent::ChatroomRestored { chatroom_id: id, sequence: 1 };
+
+        assert_eq!(log.append(&id, 0, &[restored.clone(), restored.clone()]).unwrap(), Some(2));
+        assert_eq!(log.append(&id, 1, std::slice::from_ref(&restored)).unwrap(), None);
+        assert_eq!(log.append(&id, 2, std::slice::from_ref(&restored)).unwrap(), Some(3));
+
+        assert_eq!(log.last_sequence(&id).unwrap(), 3);
+        let sequences: Vec<_> = log.events(&id, 1).unwrap().iter().map(|recorded| recorded.sequence).collect();
+        assert_eq!(sequences, vec![2, 3]);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn snapshots_keep_the_dj_as_they_were() {
+        let mut users = InMemoryUsers::new();
+        let mut user = new_test_user(1);
+        let playlist = new_test_playlist(1, 2);
+        user.set_active_playlist(&playlist.id());
+        user.add_playlist(playlist);
+        users.insert(&user).unwrap();
+
+        let mut chatroom = Chatroom::new(users.clone(), 1, "test_chatroom".to_string());
+        chatroom.join(ChatUser(1, "test_username".to_string()));
+        chatroom.join_waitlist(1);
+        chatroom.play_next().unwrap().unwrap();
+        let id = chatroom.id();
+
+        // The snapshot is rebuilt without the DJ being looked up again.
+        let mut log = new_test_log(InMemoryUsers::new());
+        log.append(&id, 0, &[]).unwrap();
+        log.save_snapshot(&Snapshot { sequence: 4, state: chatroom.clone() }).unwrap();
+
+        let got = log.snapshot(&id, None).unwrap().unwrap();
+        assert_eq!(got.sequence, 4);
+        assert_eq!(got.state.current_users(), chatroom.current_users());
+        assert_eq!(got.state.waitlist().current_dj(), Some(&user));
+        assert_eq!(got.state.waitlist().current_playlist(), chatroom.waitlist().current_playlist());
+        assert!(log.snapshot(&id, Some(3)).unwrap().is_none());
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::VecDeque;
+use rusty_ulid::Ulid;
+use crate::chatroom::{Chatroom, ChatUser};
+use crate::repositories::abstractions::{Query, Repository, StaleVersion, Versioned};
+use crate::repositories::query::{page_of, ChatroomFilter, Page, PageRequest};
+use crate::repositories::sql::{paged_query, parse_ulid, SqlConnection, SqlError};
+use crate::user::User;
+use crate::waitlist::{Waitlist, DJ};
+
+// SqlChatrooms stores chatrooms, with who's in them and their waitlist. Users themselves live in
+// the user repository, which every chatroom we load is rebuilt with.
+pub struct SqlChatrooms<C, U> {
+    conn: C,
+    users: U,
+}
+
+impl<C, U> SqlChatrooms<C, U> where
+    C: SqlConnection,
+    U: Repository<u32, User> + Clone,
+{
+    pub fn with_connection(conn: C, users: U) -> SqlChatrooms<C, U> {
+        SqlChatrooms {
+            conn,
+            users,
+        }
+    }
+}
+
+impl<C, U> Repository<Ulid, Chatroom<U>> for SqlChatrooms<C, U> where
+    C: SqlConnection,
+    U: Repository<u32, User> + Clone,
+{
+    type Error = SqlError;
+
+    fn insert(&mut self, chatroom: &Chatroom<U>) -> Result<Option<Ulid>, Self::Error> {
+        self.conn.transaction(|conn| {
+            if chatroom_exists(conn, &chatroom.id())? {
+                return Ok(None);
+            }
+
+            let waitlist = chatroom.waitlist();
+            conn.execute(
+                r"INSERT INTO chatrooms (id, name, moderator, waitlist_id, current_dj, current_playlist, version)
+                VALUES (?, ?, ?, ?, ?, ?, ?)",
+                &[
+                    chatroom.id().to_string().into(),
+                    chatroom.name().into(),
+                    chatroom.moderator().into(),
+                    waitlist.id().to_string().into(),
+                    waitlist.current_dj().map(|dj| dj.id()).into(),
+                    waitlist.current_playlist().map(|p| p.to_string()).into(),
+                    chatroom.version().into(),
+                ],
+            )?;
+            save_members(conn, chatroom)?;
+
+            Ok(Some(chatroom.id()))
+        })
+    }
+
+    fn get(&mut self, key: &Ulid) -> Result<Option<Chatroom<U>>, Self::Error> {
+        let id = key.to_string();
+        let rows = self.conn.query(
+            r"SELECT c.name, c.moderator, c.waitlist_id, c.current_dj, c.current_playlist, c.version
+            FROM chatrooms AS c
+            WHERE c.id = ?",
+            &[(&id).into()],
+        )?;
+        let row = match rows.first() {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let current_users = self.conn.query(
+            "SELECT user_id, username FROM chatroom_users WHERE chatroom_id = ? ORDER BY position",
+            &[(&id).into()],
+        )?.iter()
+            .map(|r| Ok(ChatUser(r.get(0)?, r.get(1)?)))
+            .collect::<Result<Vec<_>, SqlError>>()?;
+
+        let queue = self.conn.query(
+            "SELECT user_id, username FROM waitlist_djs WHERE chatroom_id = ? ORDER BY position",
+            &[(&id).into()],
+        )?.iter()
+            .map(|r| Ok((r.get(0)?, r.get(1)?)))
+            .collect::<Result<VecDeque<DJ>, SqlError>>()?;
+
+        // The current DJ is whatever the user repository has for them now. The waitlist keeps
+        // their user up to date as it cycles their playlist, so this is the same user it had.
+        let current_dj = match row.get::<Option<u32>>(3)? {
+            Some(user_id) => self.users.get(&user_id).map_err(|e| SqlError::Users(e.to_string()))?,
+            None => None,
+        };
+        let current_playlist = match row.get::<Option<String>>(4)? {
+            Some(playlist_id) => Some(parse_ulid(&playlist_id)?),
+            None => None,
+        };
+
+        let waitlist = Waitlist::restore(
+            parse_ulid(&row.get::<String>(2)?)?,
+            self.users.clone(),
+            current_dj,
+            current_playlist,
+            queue,
+        );
+
+        let mut chatroom = Chatroom::restore(*key, row.get(0)?, row.get(1)?, waitlist, current_users);
+        chatroom.set_version(row.get(5)?);
+        Ok(Some(chatroom))
+    }
+
+    fn update(&mut self, chatroom: &Chatroom<U>) -> Result<Option<Ulid>, Self::Error> {
+        self.conn.transaction(|conn| {
+            // The chatroom's row stays locked until we're done, so nobody can update it in between.
+            let stored = match chatroom_version(conn, &chatroom.id())? {
+                Some(version) => version,
+                None => return Ok(None),
+            };
+            if stored != chatroom.version() {
+                return Err(SqlError::Stale(StaleVersion { loaded: chatroom.version(), stored }));
+            }
+
+            let waitlist = chatroom.waitlist();
+            conn.execute(
+                r"UPDATE chatrooms SET name = ?, moderator = ?, waitlist_id = ?, current_dj = ?, current_playlist = ?, version = ?
+                WHERE id = ?",
+                &[
+                    chatroom.name().into(),
+                    chatroom.moderator().into(),
+                    waitlist.id().to_string().into(),
+                    waitlist.current_dj().map(|dj| dj.id()).into(),
+                    waitlist.current_playlist().map(|p| p.to_string()).into(),
+                    (stored + 1).into(),
+                    chatroom.id().to_string().into(),
+                ],
+            )?;
+            save_members(conn, chatroom)?;
+
+            // Success.  Return the PK back as is.
+            Ok(Some(chatroom.id()))
+        })
+    }
+
+    fn remove(&mut self, key: &Ulid) -> Result<Option<Ulid>, Self::Error> {
+        let id = key.to_string();
+        self.conn.transaction(|conn| {
+            conn.execute("DELETE FROM chatroom_users WHERE chatroom_id = ?", &[(&id).into()])?;
+            conn.execute("DELETE FROM waitlist_djs WHERE chatroom_id = ?", &[(&id).into()])?;
+
+            if conn.execute("DELETE FROM chatrooms WHERE id = ?", &[(&id).into()])? == 0 {
+                return Ok(None);
+            }
+
+            // Success.  Return the PK back as is.
+            Ok(Some(*key))
+        })
+    }
+}
+
+impl<C, U> Query<ChatroomFilter, Chatroom<U>> for SqlChatrooms<C, U> where
+    C: SqlConnection,
+    U: Repository<u32, User> + Clone,
+{
+    type Cursor = Ulid;
+    type Error = SqlError;
+
+    fn query(&mut self, filter: &ChatroomFilter, page: &PageRequest<Ulid>) -> Result<Page<Chatroom<U>, Ulid>, SqlError> {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        if let Some(moderator) = filter.moderator {
+            conditions.push("c.moderator = ?".to_string());
+            params.push(moderator.into());
+        }
+        if let Some(member) = filter.member {
+            conditions.push(
+                "EXISTS (SELECT 1 FROM chatroom_users AS cu WHERE cu.chatroom_id = c.id AND cu.user_id = ?)".to_string(),
+            );
+            params.push(member.into());
+        }
+        let sql = paged_query("SELECT c.id FROM chatrooms AS c", "c.id", conditions, &mut params, page);
+
+        let mut chatrooms = Vec::new();
+        for row in self.conn.query(&sql, &params)? {
+            let id = parse_ulid(&row.get::<String>(0)?)?;
+            // A chatroom removed since we listed it just isn't on the page.
+            if let Some(chatroom) = self.get(&id)? {
+                chatrooms.push((id, chatroom));
+            }
+        }
+
+        Ok(page_of(chatrooms, page.limit))
+    }
+}
+
+fn chatroom_exists<C: SqlConnection>(conn: &mut C, id: &Ulid) -> Result<bool, SqlError> {
+    Ok(chatroom_version(conn, id)?.is_some())
+}
+
+// chatroom_version locks the chatroom's row, and returns the version stored in it.
+fn chatroom_version<C: SqlConnection>(conn: &mut C, id: &Ulid) -> Result<Option<u64>, SqlError> {
+    let sql = format!("SELECT version FROM chatrooms WHERE id = ?{}", conn.dialect().for_update());
+    match conn.query(&sql, &[id.to_string().into()])?.first() {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
+    }
+}
+
+// save_members replaces who's stored as being in the room and on its waitlist, keeping their
+// order.
+fn save_members<C, U>(conn: &mut C, chatroom: &Chatroom<U>) -> Result<(), SqlError> where
+    C: SqlConnection,
+    U: Repository<u32, User>,
+{
+    let id = chatroom.id().to_string();
+    conn.execute("DELETE FROM chatroom_users WHERE chatroom_id = ?", &[(&id).into()])?;
+    conn.execute("DELETE FROM waitlist_djs WHERE chatroom_id = ?", &[(&id).into()])?;
+
+    for (position, ChatUser(user_id, username)) in chatroom.current_users().iter().enumerate() {
+        conn.execute(
+            "INSERT INTO chatroom_users (chatroom_id, position, user_id, username) VALUES (?, ?, ?, ?)",
+            &[(&id).into(), (position as u32).into(), (*user_id).into(), username.into()],
+        )?;
+    }
+
+    for (position, (user_id, username)) in chatroom.waitlist_djs().iter().enumerate() {
+        conn.execute(
+            "INSERT INTO waitlist_djs (chatroom_id, position, user_id, username) VALUES (?, ?, ?, ?)",
+            &[(&id).into(), (position as u32).into(), (*user_id).into(), username.into()],
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqlChatrooms;
+    use crate::MockUserRepository;
+    use crate::chatroom::{Chatroom, ChatUser};
+    use crate::repositories::abstractions::{Query, Repository};
+    use crate::repositories::query::{ChatroomFilter, PageRequest};
+    use crate::repositories::sql::sqlite::SqliteConnection;
+    use crate::test_tools::factories::{new_test_user, new_test_playlist};
+
+    // new_test_room has four users in the room, three of them on the waitlist, and the first of
+    // those playing.
+    fn new_test_room(users: &mut MockUserRepository) -> Chatroom<MockUserRepository> {
+        for user_id in 0..4 {
+            let mut user = new_test_user(user_id);
+            let playlist = new_test_playlist(user_id, 2);
+            user.set_active_playlist(&playlist.id());
+            user.add_playlist(playlist);
+            users.insert(&user).unwrap();
+        }
+
+        let mut chatroom = Chatroom::new(users.clone(), 0, "O'Brien's room".to_string());
+        for user_id in 0..4 {
+            chatroom.join(ChatUser(user_id, format!("user {}", user_id)));
+        }
+        for user_id in &[2, 0, 3] {
+            chatroom.join_waitlist(*user_id);
+        }
+        chatroom.play_next().unwrap();
+
+        chatroom
+    }
+
+    fn new_test_repo(users: &MockUserRepository) -> SqlChatrooms<SqliteConnection, MockUserRepository> {
+        SqlChatrooms::with_connection(SqliteConnection::in_memory(), users.clone())
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn chatroom_round_trips() {
+        let mut users = MockUserRepository::new();
+        let chatroom = new_test_room(&mut users);
+        let mut repo = new_test_repo(&users);
+
+        assert_eq!(repo.insert(&chatroom).unwrap(), Some(chatroom.id()));
+
+        let loaded = repo.get(&chatroom.id()).unwrap().unwrap();
+        assert_eq!(loaded.id(), chatroom.id());
+        assert_eq!(loaded.name(), "O'Brien's room");
+        assert_eq!(loaded.moderator(), 0);
+        assert_eq!(loaded.current_users(), chatroom.current_users());
+        assert_eq!(loaded.waitlist_djs(), chatroom.waitlist_djs());
+        assert_eq!(loaded.waitlist().id(), chatroom.waitlist().id());
+        assert_eq!(loaded.waitlist().current_dj().map(|u| u.id()), Some(2));
+        assert_eq!(loaded.waitlist().current_playlist(), chatroom.waitlist().current_playlist());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn loaded_chatroom_keeps_playing_where_it_left_off() {
+        let mut users = MockUserRepository::new();
+        let mut chatroom = new_test_room(&mut users);
+        let mut repo = new_test_repo(&users);
+        repo.insert(&chatroom).unwrap();
+
+        let mut loaded = repo.get(&chatroom.id()).unwrap().unwrap();
+
+        // The current DJ's turn is over, so the next DJ plays, from their active playlist.
+        loaded.play_next().unwrap();
+        assert_eq!(loaded.waitlist().current_dj().map(|u| u.id()), Some(0));
+        assert_eq!(loaded.waitlist_djs().len(), 2);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn update_replaces_members() {
+        let mut users = MockUserRepository::new();
+        let mut chatroom = new_test_room(&mut users);
+        let mut repo = new_test_repo(&users);
+        repo.insert(&chatroom).unwrap();
+
+        chatroom.leave(1);
+        chatroom.leave_waitlist(3);
+        chatroom.change_moderator(3);
+        chatroom.play_next().unwrap();
+        assert_eq!(repo.update(&chatroom).unwrap(), Some(chatroom.id()));
+
+        let loaded = repo.get(&chatroom.id()).unwrap().unwrap();
+        assert_eq!(loaded.moderator(), 3);
+        assert_eq!(loaded.current_users(), chatroom.current_users());
+        assert_eq!(loaded.waitlist_djs(), chatroom.waitlist_djs());
+        assert_eq!(loaded.waitlist().current_dj().map(|u| u.id()), Some(0));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn missing_chatrooms_are_none() {
+        let mut users = MockUserRepository::new();
+        let chatroom = new_test_room(&mut users);
+        let mut repo = new_test_repo(&users);
+
+        assert!(repo.get(&chatroom.id()).unwrap().is_none());
+        assert_eq!(repo.update(&chatroom).unwrap(), None);
+        assert_eq!(repo.remove(&chatroom.id()).unwrap(), None);
+
+        repo.insert(&chatroom).unwrap();
+        assert_eq!(repo.insert(&chatroom).unwrap(), None);
+        assert_eq!(repo.remove(&chatroom.id()).unwrap(), Some(chatroom.id()));
+        assert!(repo.get(&chatroom.id()).unwrap().is_none());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn chatrooms_are_listed_by_moderator_and_member() {
+        let users = MockUserRepository::new();
+        let mut repo = new_test_repo(&users);
+        let mut ids = Vec::new();
+        for moderator in 0..4 {
+            let mut chatroom = Chatroom::new(users.clone(), moderator, format!("room {}", moderator));
+            chatroom.join(ChatUser(moderator, "moderator".to_string()));
+            if moderator % 2 == 0 {
+                chatroom.join(ChatUser(9, "regular".to_string()));
+            }
+            repo.insert(&chatroom).unwrap();
+            ids.push(chatroom.id());
+        }
+        ids.sort();
+
+        let request = PageRequest::first(3);
+        let first = repo.query(&ChatroomFilter::default(), &request).unwrap();
+        assert_eq!(first.items.iter().map(|c| c.id()).collect::<Vec<_>>(), ids[..3].to_vec());
+        let last = repo.query(&ChatroomFilter::default(), &first.next_page(&request).unwrap()).unwrap();
+        assert_eq!(last.items.iter().map(|c| c.id()).collect::<Vec<_>>(), ids[3..].to_vec());
+        assert_eq!(last.next, None);
+
+        let moderated = repo.query(&ChatroomFilter { moderator: Some(2), member: None }, &request).unwrap();
+        assert_eq!(moderated.items.len(), 1);
+        assert_eq!(moderated.items[0].name(), "room 2");
+
+        let filter = ChatroomFilter { moderator: None, member: Some(9) };
+        let joined = repo.query(&filter, &PageRequest::first(10).descending()).unwrap();
+        let mut names: Vec<_> = joined.items.iter().map(|c| c.name()).collect();
+        names.sort();
+        assert_eq!(names, vec!["room 0", "room 2"]);
+        assert!(joined.items[0].id() > joined.items[1].id());
+    }
+
+    mod conformance {
+        use crate::MockUserRepository;
+        use crate::repositories::sql::SqliteChatrooms;
+        use crate::test_tools::conformance::ChatroomEntities;
+
+        crate::repository_conformance_tests!(
+            SqliteChatrooms::in_memory(MockUserRepository::new()),
+            ChatroomEntities::new(MockUserRepository::new())
+        );
+    }
+}
@@ -0,0 +1,415 @@
+use crate::repositories::sql::{Dialect, SqlConnection, SqlError};
+
+// Migrations build the schema the SQL repositories expect, one versioned step at a time. The SQL
+// for each dialect lives in migrations/<dialect>, and is compiled in so a deploy can't end up
+// without it. Which versions a database has had applied is recorded in its schema_migrations
+// table.
+//
+// A migration is never edited once it has shipped. Changes to the schema go in a new one, with the
+// next version number.
+
+// Migration is one versioned step of the schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Migration {
+    // statements splits the migration into the statements it's made of, as drivers run prepared
+    // statements one at a time. Comments must be on their own lines, and string literals can't
+    // contain semicolons.
+    pub fn statements(&self) -> Vec<String> {
+        let sql = self.sql.lines()
+            .filter(|line| !line.trim_start().starts_with("--"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        sql.split(';')
+            .map(|statement| statement.trim())
+            .filter(|statement| !statement.is_empty())
+            .map(|statement| statement.to_string())
+            .collect()
+    }
+}
+
+const MYSQL_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_users",
+        sql: include_str!("../../../migrations/mysql/0001_create_users.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "create_songs_and_playlists",
+        sql: include_str!("../../../migrations/mysql/0002_create_songs_and_playlists.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "create_chatrooms",
+        sql: include_str!("../../../migrations/mysql/0003_create_chatrooms.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "create_play_history",
+        sql: include_str!("../../../migrations/mysql/0004_create_play_history.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "add_versions",
+        sql: include_str!("../../../migrations/mysql/0005_add_versions.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "create_oauth_tokens",
+        sql: include_str!("../../../migrations/mysql/0006_create_oauth_tokens.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "create_chatroom_log",
+        sql: include_str!("../../../migrations/mysql/0007_create_chatroom_log.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "unique_user_identities",
+        sql: include_str!("../../../migrations/mysql/0008_unique_user_identities.sql"),
+    },
+];
+
+const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_users",
+        sql: include_str!("../../../migrations/sqlite/0001_create_users.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "create_songs_and_playlists",
+        sql: include_str!("../../../migrations/sqlite/0002_create_songs_and_playlists.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "create_chatrooms",
+        sql: include_str!("../../../migrations/sqlite/0003_create_chatrooms.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "create_play_history",
+        sql: include_str!("../../../migrations/sqlite/0004_create_play_history.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "add_versions",
+        sql: include_str!("../../../migrations/sqlite/0005_add_versions.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "create_oauth_tokens",
+        sql: include_str!("../../../migrations/sqlite/0006_create_oauth_tokens.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "create_chatroom_log",
+        sql: include_str!("../../../migrations/sqlite/0007_create_chatroom_log.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "unique_user_identities",
+        sql: include_str!("../../../migrations/sqlite/0008_unique_user_identities.sql"),
+    },
+];
+
+const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_users",
+        sql: include_str!("../../../migrations/postgres/0001_create_users.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "create_songs_and_playlists",
+        sql: include_str!("../../../migrations/postgres/0002_create_songs_and_playlists.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "create_chatrooms",
+        sql: include_str!("../../../migrations/postgres/0003_create_chatrooms.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "create_play_history",
+        sql: include_str!("../../../migrations/postgres/0004_create_play_history.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "add_versions",
+        sql: include_str!("../../../migrations/postgres/0005_add_versions.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "create_oauth_tokens",
+        sql: include_str!("../../../migrations/postgres/0006_create_oauth_tokens.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "create_chatroom_log",
+        sql: include_str!("../../../migrations/postgres/0007_create_chatroom_log.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "unique_user_identities",
+        sql: include_str!("../../../migrations/postgres/0008_unique_user_identities.sql"),
+    },
+];
+
+// Migrator brings a database up to date with a list of migrations, ordered by version.
+pub struct Migrator<'m> {
+    migrations: &'m [Migration],
+}
+
+impl<'m> Migrator<'m> {
+    pub fn new(migrations: &'m [Migration]) -> Migrator<'m> {
+        Migrator {
+            migrations,
+        }
+    }
+
+    // for_dialect has the migrations embedded for the given database.
+    pub fn for_dialect(dialect: Dialect) -> Migrator<'static> {
+        match dialect {
+            Dialect::Mysql => Migrator::new(MYSQL_MIGRATIONS),
+            Dialect::Sqlite => Migrator::new(SQLITE_MIGRATIONS),
+            Dialect::Postgres => Migrator::new(POSTGRES_MIGRATIONS),
+        }
+    }
+
+    pub fn migrations(&self) -> &'m [Migration] {
+        self.migrations
+    }
+
+    /// Lists the migrations the database hasn't had applied yet, in the order migrate would
+    /// apply them, without changing anything. This is the dry run of migrate.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to read which migrations have been applied, or the database has a migration
+    /// applied that we don't know about, then an error is returned.
+    pub fn pending<C: SqlConnection>(&self, conn: &mut C) -> Result<Vec<&'m Migration>, SqlError> {
+        let applied = if migrations_table_exists(conn)? {
+            applied_versions(conn)?
+        } else {
+            Vec::new()
+        };
+
+        // A database ahead of us was migrated by a newer build, and we can't know what it expects.
+        if let Some(version) = applied.iter().find(|v| !self.migrations.iter().any(|m| m.version == **v)) {
+            return Err(SqlError::Migration(format!("database has unknown migration {} applied", version)));
+        }
+
+        Ok(self.migrations.iter()
+            .filter(|m| !applied.contains(&m.version))
+            .collect())
+    }
+
+    /// Applies every pending migration in order, recording each one as it's applied. Returns the
+    /// migrations that were applied.
+    ///
+    /// Each migration runs in its own transaction. MySQL commits implicitly after DDL though, so
+    /// a migration that fails part way there has to be cleaned up by hand before it's retried.
+    ///
+    /// # Failure case
+    ///
+    /// If a migration fails, then we stop there with its error. The migrations before it stay
+    /// applied.
+    pub fn migrate<C: SqlConnection>(&self, conn: &mut C) -> Result<Vec<&'m Migration>, SqlError> {
+        if !migrations_table_exists(conn)? {
+            conn.execute(create_migrations_table(conn.dialect()), &[])?;
+        }
+
+        let pending = self.pending(conn)?;
+        for migration in &pending {
+            conn.transaction(|conn| {
+                for statement in migration.statements() {
+                    conn.execute(&statement, &[]).map_err(|e| {
+                        SqlError::Migration(format!("migration {} ({}) failed: {}", migration.version, migration.name, e))
+                    })?;
+                }
+                conn.execute(
+                    "INSERT INTO schema_migrations (version, name) VALUES (?, ?)",
+                    &[migration.version.into(), migration.name.into()],
+                )?;
+                Ok(())
+            })?;
+        }
+
+        Ok(pending)
+    }
+}
+
+fn create_migrations_table(dialect: Dialect) -> &'static str {
+    match dialect {
+        Dialect::Mysql => r"CREATE TABLE schema_migrations (
+            version INT UNSIGNED NOT NULL PRIMARY KEY,
+            name VARCHAR(255) NOT NULL,
+            applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4",
+        Dialect::Sqlite => r"CREATE TABLE schema_migrations (
+            version INTEGER NOT NULL PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+        Dialect::Postgres => r"CREATE TABLE schema_migrations (
+            version BIGINT NOT NULL PRIMARY KEY,
+            name VARCHAR(255) NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )",
+    }
+}
+
+fn migrations_table_exists<C: SqlConnection>(conn: &mut C) -> Result<bool, SqlError> {
+    let sql = match conn.dialect() {
+        Dialect::Mysql => {
+            "SELECT table_name FROM information_schema.tables WHERE table_schema = DATABASE() AND table_name = ?"
+        },
+        Dialect::Sqlite => "SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?",
+        Dialect::Postgres => {
+            // table_name is of type information_schema.sql_identifier, which we don't read back.
+            "SELECT table_name::text FROM information_schema.tables WHERE table_schema = current_schema() AND table_name = ?"
+        },
+    };
+    Ok(!conn.query(sql, &["schema_migrations".into()])?.is_empty())
+}
+
+fn applied_versions<C: SqlConnection>(conn: &mut C) -> Result<Vec<u32>, SqlError> {
+    conn.query("SELECT version FROM schema_migrations ORDER BY version", &[])?
+        .iter()
+        .map(|row| row.get(0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Migration, Migrator, MYSQL_MIGRATIONS, POSTGRES_MIGRATIONS, SQLITE_MIGRATIONS};
+    use crate::repositories::sql::{Dialect, SqlConnection};
+    use crate::repositories::sql::sqlite::SqliteConnection;
+
+    const TEST_MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            name: "create_things",
+            sql: "-- Things; with a semicolon in the comment.\nCREATE TABLE things (id INTEGER NOT NULL PRIMARY KEY);\n",
+        },
+        Migration {
+            version: 2,
+            name: "create_more_things",
+            sql: "CREATE TABLE more_things (id INTEGER);\nCREATE INDEX more_things_id ON more_things (id);",
+        },
+    ];
+
+    const BROKEN_MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            name: "create_things",
+            sql: "CREATE TABLE things (id INTEGER NOT NULL PRIMARY KEY);",
+        },
+        Migration {
+            version: 2,
+            name: "broken",
+            sql: "CREATE TABLE half_done (id INTEGER);\nTHIS IS NOT SQL;",
+        },
+    ];
+
+    fn versions(migrations: &[&Migration]) -> Vec<u32> {
+        migrations.iter().map(|m| m.version).collect()
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn statements_are_split() {
+        assert_eq!(TEST_MIGRATIONS[0].statements(), vec!["CREATE TABLE things (id INTEGER NOT NULL PRIMARY KEY)"]);
+        assert_eq!(TEST_MIGRATIONS[1].statements().len(), 2);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn embedded_migrations_are_in_order() {
+        for migrations in &[MYSQL_MIGRATIONS, SQLITE_MIGRATIONS, POSTGRES_MIGRATIONS] {
+            for (i, migration) in migrations.iter().enumerate() {
+                assert_eq!(migration.version, i as u32 + 1);
+                assert!(!migration.statements().is_empty());
+            }
+        }
+        assert_eq!(MYSQL_MIGRATIONS.len(), SQLITE_MIGRATIONS.len());
+        assert_eq!(POSTGRES_MIGRATIONS.len(), SQLITE_MIGRATIONS.len());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn migrate_applies_pending_migrations_once() {
+        let mut conn = SqliteConnection::empty();
+        let migrator = Migrator::new(TEST_MIGRATIONS);
+
+        assert_eq!(versions(&migrator.migrate(&mut conn).unwrap()), vec![1, 2]);
+        assert!(migrator.migrate(&mut conn).unwrap().is_empty());
+        assert!(migrator.pending(&mut conn).unwrap().is_empty());
+
+        let recorded = conn.query("SELECT version, name FROM schema_migrations ORDER BY version", &[]).unwrap();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[1].get::<String>(1).unwrap(), "create_more_things");
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn dry_run_changes_nothing() {
+        let mut conn = SqliteConnection::empty();
+        let migrator = Migrator::new(TEST_MIGRATIONS);
+
+        assert_eq!(versions(&migrator.pending(&mut conn).unwrap()), vec![1, 2]);
+        let tables = conn.query("SELECT name FROM sqlite_master WHERE type = 'table'", &[]).unwrap();
+        assert!(tables.is_empty());
+
+        Migrator::new(&TEST_MIGRATIONS[..1]).migrate(&mut conn).unwrap();
+        assert_eq!(versions(&migrator.pending(&mut conn).unwrap()), vec![2]);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn failed_migrations_are_not_recorded() {
+        let mut conn = SqliteConnection::empty();
+        let migrator = Migrator::new(BROKEN_MIGRATIONS);
+
+        assert!(migrator.migrate(&mut conn).is_err());
+        assert_eq!(versions(&migrator.pending(&mut conn).unwrap()), vec![2]);
+
+        // SQLite DDL is transactional, so the broken migration left nothing behind.
+        let half_done = conn.query("SELECT name FROM sqlite_master WHERE name = 'half_done'", &[]).unwrap();
+        assert!(half_done.is_empty());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn unknown_applied_migrations_are_an_error() {
+        let mut conn = SqliteConnection::empty();
+        Migrator::new(TEST_MIGRATIONS).migrate(&mut conn).unwrap();
+
+        let older = Migrator::new(&TEST_MIGRATIONS[..1]);
+        assert!(older.pending(&mut conn).is_err());
+        assert!(older.migrate(&mut conn).is_err());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn embedded_sqlite_migrations_apply() {
+        let mut conn = SqliteConnection::empty();
+        let migrator = Migrator::for_dialect(Dialect::Sqlite);
+
+        assert_eq!(migrator.migrate(&mut conn).unwrap().len(), SQLITE_MIGRATIONS.len());
+        for table in &["users", "user_identities", "songs", "playlists", "playlist_songs", "chatrooms",
+                       "chatroom_users", "waitlist_djs", "play_history", "oauth_tokens",
+                       "chatroom_streams", "chatroom_events", "chatroom_snapshots"] {
+            let found = conn.query("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?", &[(*table).into()]).unwrap();
+            assert_eq!(found.len(), 1, "missing table {}", table);
+        }
+    }
+}
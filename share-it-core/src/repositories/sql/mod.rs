@@ -0,0 +1,331 @@
+use std::error;
+use std::fmt;
+use rusty_ulid::Ulid;
+use crate::repositories::abstractions::StaleVersion;
+use crate::repositories::query::{Order, PageRequest};
+
+pub mod users;
+pub use users::*;
+
+pub mod chatrooms;
+pub use chatrooms::*;
+
+pub mod tokens;
+pub use tokens::*;
+
+pub mod chatroom_log;
+pub use chatroom_log::*;
+
+pub mod migrations;
+pub use migrations::{Migration, Migrator};
+
+pub mod sqlite;
+pub use sqlite::*;
+
+#[cfg(feature = "postgresql")]
+pub mod postgresql;
+#[cfg(feature = "postgresql")]
+pub use postgresql::*;
+
+// The SQL repositories are written once against SqlConnection, and every database we support
+// plugs in underneath. Statements always take their values as parameters, bound with `?`
+// placeholders, and never have values pasted into them. Connections to databases that number
+// their placeholders rewrite them before running the statement.
+
+// SqlValue is a value bound to, or read back from, a statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Int(i64),
+    Text(String),
+}
+
+impl From<u32> for SqlValue {
+    fn from(v: u32) -> Self {
+        SqlValue::Int(v as i64)
+    }
+}
+
+impl From<u64> for SqlValue {
+    fn from(v: u64) -> Self {
+        SqlValue::Int(v as i64)
+    }
+}
+
+impl From<i64> for SqlValue {
+    fn from(v: i64) -> Self {
+        SqlValue::Int(v)
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(v: String) -> Self {
+        SqlValue::Text(v)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(v: &str) -> Self {
+        SqlValue::Text(v.to_string())
+    }
+}
+
+impl From<&String> for SqlValue {
+    fn from(v: &String) -> Self {
+        SqlValue::Text(v.clone())
+    }
+}
+
+impl From<Ulid> for SqlValue {
+    fn from(v: Ulid) -> Self {
+        SqlValue::Text(v.to_string())
+    }
+}
+
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(v: Option<T>) -> Self {
+        match v {
+            Some(v) => v.into(),
+            None => SqlValue::Null,
+        }
+    }
+}
+
+// FromSqlValue converts a column back into a rust type.
+pub trait FromSqlValue: Sized {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, SqlError>;
+}
+
+impl FromSqlValue for i64 {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, SqlError> {
+        match value {
+            SqlValue::Int(v) => Ok(*v),
+            other => Err(SqlError::InvalidData(format!("expected an integer, got {:?}", other))),
+        }
+    }
+}
+
+impl FromSqlValue for u32 {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, SqlError> {
+        let v = i64::from_sql_value(value)?;
+        if v < 0 || v > u32::MAX as i64 {
+            return Err(SqlError::InvalidData(format!("{} is out of range for u32", v)));
+        }
+        Ok(v as u32)
+    }
+}
+
+impl FromSqlValue for u64 {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, SqlError> {
+        let v = i64::from_sql_value(value)?;
+        if v < 0 {
+            return Err(SqlError::InvalidData(format!("{} is out of range for u64", v)));
+        }
+        Ok(v as u64)
+    }
+}
+
+impl FromSqlValue for String {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, SqlError> {
+        match value {
+            SqlValue::Text(v) => Ok(v.clone()),
+            other => Err(SqlError::InvalidData(format!("expected text, got {:?}", other))),
+        }
+    }
+}
+
+impl<T: FromSqlValue> FromSqlValue for Option<T> {
+    fn from_sql_value(value: &SqlValue) -> Result<Self, SqlError> {
+        match value {
+            SqlValue::Null => Ok(None),
+            other => Ok(Some(T::from_sql_value(other)?)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlRow {
+    values: Vec<SqlValue>,
+}
+
+impl SqlRow {
+    pub fn new(values: Vec<SqlValue>) -> SqlRow {
+        SqlRow {
+            values,
+        }
+    }
+
+    pub fn get<T: FromSqlValue>(&self, index: usize) -> Result<T, SqlError> {
+        match self.values.get(index) {
+            Some(value) => T::from_sql_value(value),
+            None => Err(SqlError::InvalidData(format!("row has no column {}", index))),
+        }
+    }
+}
+
+// Dialect covers the few places the databases we support disagree on syntax.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    Mysql,
+    Sqlite,
+    Postgres,
+}
+
+impl Dialect {
+    // for_update locks the selected rows until the end of the transaction. SQLite locks the whole
+    // database for a write transaction, so doesn't have (or need) it.
+    pub fn for_update(&self) -> &'static str {
+        match self {
+            Dialect::Mysql | Dialect::Postgres => " FOR UPDATE",
+            Dialect::Sqlite => "",
+        }
+    }
+}
+
+/// SqlConnection is a connection to a SQL database that the SQL repositories can run on.
+pub trait SqlConnection {
+    fn dialect(&self) -> Dialect;
+
+    /// Runs a statement with its `?` placeholders bound to params, returning the number of rows
+    /// it changed.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the database, or it rejects the statement, then an error
+    /// is returned.
+    fn execute(&mut self, sql: &str, params: &[SqlValue]) -> Result<u64, SqlError>;
+
+    /// Runs a query with its `?` placeholders bound to params, returning every row.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with the database, or it rejects the query, then an error is
+    /// returned.
+    fn query(&mut self, sql: &str, params: &[SqlValue]) -> Result<Vec<SqlRow>, SqlError>;
+
+    fn begin(&mut self) -> Result<(), SqlError>;
+
+    fn commit(&mut self) -> Result<(), SqlError>;
+
+    fn rollback(&mut self) -> Result<(), SqlError>;
+
+    /// Runs f in a transaction, committing if it succeeds and rolling back if it fails.
+    ///
+    /// # Failure case
+    ///
+    /// If f fails, then its error is returned. Otherwise if we fail to begin or commit the
+    /// transaction, then that error is returned.
+    fn transaction<T, F>(&mut self, f: F) -> Result<T, SqlError> where
+        F: FnOnce(&mut Self) -> Result<T, SqlError>,
+        Self: Sized,
+    {
+        self.begin()?;
+        match f(self) {
+            Ok(result) => {
+                self.commit()?;
+                Ok(result)
+            },
+            Err(e) => {
+                // The error that made us roll back is the one worth reporting.
+                let _ = self.rollback();
+                Err(e)
+            },
+        }
+    }
+}
+
+// paged_query finishes select into the query for a page of results, ordered on cursor_column.
+// Conditions are ANDed together, and their values must already be in params. It asks for one more
+// row than the page holds, so page_of can tell whether there's another page.
+pub(crate) fn paged_query<K>(select: &str,
+                             cursor_column: &str,
+                             mut conditions: Vec<String>,
+                             params: &mut Vec<SqlValue>,
+                             page: &PageRequest<K>) -> String where
+    K: Into<SqlValue> + Clone,
+{
+    let (comparison, direction) = match page.order {
+        Order::Ascending => (">", "ASC"),
+        Order::Descending => ("<", "DESC"),
+    };
+    if let Some(after) = &page.after {
+        conditions.push(format!("{} {} ?", cursor_column, comparison));
+        params.push(after.clone().into());
+    }
+
+    let mut sql = select.to_string();
+    if !conditions.is_empty() {
+        sql.push_str(" WHERE ");
+        sql.push_str(&conditions.join(" AND "));
+    }
+    sql.push_str(&format!(" ORDER BY {} {} LIMIT ?", cursor_column, direction));
+    params.push((page.limit as i64 + 1).into());
+
+    sql
+}
+
+#[derive(Debug)]
+pub enum SqlError {
+    // The database, or the driver talking to it, failed.
+    Driver(Box<dyn error::Error + Send + Sync>),
+    // We read back something that doesn't fit the domain.
+    InvalidData(String),
+    // The user repository we rebuild chatrooms with failed.
+    Users(String),
+    // The schema couldn't be brought up to date.
+    Migration(String),
+    Stale(StaleVersion),
+}
+
+impl SqlError {
+    pub fn driver<E: error::Error + Send + Sync + 'static>(e: E) -> SqlError {
+        SqlError::Driver(Box::new(e))
+    }
+}
+
+impl fmt::Display for SqlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SqlError::Driver(e) => write!(f, "database error: {}", e),
+            SqlError::InvalidData(e) => write!(f, "invalid data in database: {}", e),
+            SqlError::Users(e) => write!(f, "user repository error: {}", e),
+            SqlError::Migration(e) => write!(f, "migration error: {}", e),
+            SqlError::Stale(e) => write!(f, "stale update: {}", e),
+        }
+    }
+}
+
+impl error::Error for SqlError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SqlError::Driver(e) => Some(e.as_ref()),
+            SqlError::Stale(e) => Some(e),
+            SqlError::InvalidData(_) | SqlError::Users(_) | SqlError::Migration(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SqlRow, SqlValue};
+
+    #[test]
+    #[allow(unused)]
+    fn row_values_convert() {
+        let row = SqlRow::new(vec![SqlValue::Int(7), SqlValue::Text("it's".to_string()), SqlValue::Null]);
+
+        assert_eq!(row.get::<u32>(0).unwrap(), 7);
+        assert_eq!(row.get::<String>(1).unwrap(), "it's");
+        assert_eq!(row.get::<Option<String>>(2).unwrap(), None);
+        assert!(row.get::<String>(0).is_err());
+        assert!(row.get::<u32>(3).is_err());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn negative_ids_do_not_fit_u32() {
+        let row = SqlRow::new(vec![SqlValue::Int(-1)]);
+
+        assert!(row.get::<u32>(0).is_err());
+    }
+}
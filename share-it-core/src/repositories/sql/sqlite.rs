@@ -0,0 +1,168 @@
+use std::path::Path;
+use std::time::Duration;
+use rusqlite::types::Value;
+use crate::repositories::abstractions::Repository;
+use crate::repositories::sql::{Dialect, Migrator, SqlChatroomLog, SqlChatrooms, SqlConnection, SqlError, SqlRow, SqlTokens, SqlUsers, SqlValue};
+use crate::user::User;
+
+// How long a connection waits for another one to finish writing before giving up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// SqliteUsers stores users in a SQLite database, for local development and tests that want real
+// persistence without a database server.
+pub type SqliteUsers = SqlUsers<SqliteConnection>;
+
+impl SqliteUsers {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteUsers, SqlError> {
+        Ok(SqlUsers::with_connection(SqliteConnection::open(path)?))
+    }
+
+    pub fn in_memory() -> SqliteUsers {
+        SqlUsers::with_connection(SqliteConnection::in_memory())
+    }
+}
+
+// SqliteChatrooms stores chatrooms in a SQLite database, rebuilding them with the given user
+// repository.
+pub type SqliteChatrooms<U> = SqlChatrooms<SqliteConnection, U>;
+
+impl<U> SqliteChatrooms<U> where
+    U: Repository<u32, User> + Clone,
+{
+    pub fn open<P: AsRef<Path>>(path: P, users: U) -> Result<SqliteChatrooms<U>, SqlError> {
+        Ok(SqlChatrooms::with_connection(SqliteConnection::open(path)?, users))
+    }
+
+    pub fn in_memory(users: U) -> SqliteChatrooms<U> {
+        SqlChatrooms::with_connection(SqliteConnection::in_memory(), users)
+    }
+}
+
+// SqliteTokens stores users' sealed provider tokens in a SQLite database.
+pub type SqliteTokens = SqlTokens<SqliteConnection>;
+
+impl SqliteTokens {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteTokens, SqlError> {
+        Ok(SqlTokens::with_connection(SqliteConnection::open(path)?))
+    }
+
+    pub fn in_memory() -> SqliteTokens {
+        SqlTokens::with_connection(SqliteConnection::in_memory())
+    }
+}
+
+// SqliteChatroomLog keeps the events of event sourced chatrooms in a SQLite database.
+pub type SqliteChatroomLog<U> = SqlChatroomLog<SqliteConnection, U>;
+
+impl<U> SqliteChatroomLog<U> where
+    U: Repository<u32, User> + Clone,
+{
+    pub fn open<P: AsRef<Path>>(path: P, users: U) -> Result<SqliteChatroomLog<U>, SqlError> {
+        Ok(SqlChatroomLog::with_connection(SqliteConnection::open(path)?, users))
+    }
+
+    pub fn in_memory(users: U) -> SqliteChatroomLog<U> {
+        SqlChatroomLog::with_connection(SqliteConnection::in_memory(), users)
+    }
+}
+
+// SqliteConnection runs the SQL repositories on SQLite. Each connection to the same file sees the
+// same data, while every in-memory connection is a database of its own.
+pub struct SqliteConnection {
+    conn: rusqlite::Connection,
+}
+
+impl SqliteConnection {
+    // open opens, or creates, the database at path and applies any pending migrations.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteConnection, SqlError> {
+        let mut conn = SqliteConnection::configure(rusqlite::Connection::open(path).map_err(SqlError::driver)?)?;
+        Migrator::for_dialect(Dialect::Sqlite).migrate(&mut conn)?;
+        Ok(conn)
+    }
+
+    // in_memory opens a fresh database with every migration applied.
+    pub fn in_memory() -> SqliteConnection {
+        let mut conn = SqliteConnection::empty();
+        Migrator::for_dialect(Dialect::Sqlite).migrate(&mut conn).unwrap();
+        conn
+    }
+
+    // empty opens a fresh in-memory database with no tables at all.
+    pub fn empty() -> SqliteConnection {
+        SqliteConnection::configure(rusqlite::Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    // configure makes SQLite behave like MySQL where the repositories rely on it. Foreign keys
+    // are off unless asked for, and without a busy timeout a second writer fails straight away.
+    fn configure(conn: rusqlite::Connection) -> Result<SqliteConnection, SqlError> {
+        conn.execute_batch("PRAGMA foreign_keys = ON").map_err(SqlError::driver)?;
+        conn.busy_timeout(BUSY_TIMEOUT).map_err(SqlError::driver)?;
+
+        Ok(SqliteConnection {
+            conn,
+        })
+    }
+
+    // raw gives a way to look at what's actually stored.
+    pub fn raw(&self) -> &rusqlite::Connection {
+        &self.conn
+    }
+}
+
+impl SqlConnection for SqliteConnection {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    fn execute(&mut self, sql: &str, params: &[SqlValue]) -> Result<u64, SqlError> {
+        let changed = self.conn.execute(sql, to_sqlite_params(params)).map_err(SqlError::driver)?;
+        Ok(changed as u64)
+    }
+
+    fn query(&mut self, sql: &str, params: &[SqlValue]) -> Result<Vec<SqlRow>, SqlError> {
+        let mut stmt = self.conn.prepare(sql).map_err(SqlError::driver)?;
+        let columns = stmt.column_count();
+        let mut result = stmt.query(to_sqlite_params(params)).map_err(SqlError::driver)?;
+
+        let mut rows = Vec::new();
+        while let Some(row) = result.next().map_err(SqlError::driver)? {
+            let mut values = Vec::with_capacity(columns);
+            for i in 0..columns {
+                values.push(from_sqlite_value(row.get(i).map_err(SqlError::driver)?)?);
+            }
+            rows.push(SqlRow::new(values));
+        }
+        Ok(rows)
+    }
+
+    fn begin(&mut self) -> Result<(), SqlError> {
+        self.conn.execute_batch("BEGIN IMMEDIATE").map_err(SqlError::driver)
+    }
+
+    fn commit(&mut self) -> Result<(), SqlError> {
+        self.conn.execute_batch("COMMIT").map_err(SqlError::driver)
+    }
+
+    fn rollback(&mut self) -> Result<(), SqlError> {
+        self.conn.execute_batch("ROLLBACK").map_err(SqlError::driver)
+    }
+}
+
+fn to_sqlite_params(params: &[SqlValue]) -> Vec<Value> {
+    params.iter()
+        .map(|param| match param {
+            SqlValue::Null => Value::Null,
+            SqlValue::Int(v) => Value::Integer(*v),
+            SqlValue::Text(v) => Value::Text(v.clone()),
+        })
+        .collect()
+}
+
+fn from_sqlite_value(value: Value) -> Result<SqlValue, SqlError> {
+    match value {
+        Value::Null => Ok(SqlValue::Null),
+        Value::Integer(v) => Ok(SqlValue::Int(v)),
+        Value::Text(v) => Ok(SqlValue::Text(v)),
+        other => Err(SqlError::InvalidData(format!("unsupported sqlite value {:?}", other))),
+    }
+}
@@ -0,0 +1,171 @@
+use crate::repositories::abstractions::Repository;
+use crate::repositories::sql::{SqlConnection, SqlError, SqlRow};
+use crate::tokens::{StoredToken, TokenKey};
+
+// SqlTokens stores users' provider tokens, just as the TokenVault sealed them. The nonce and
+// ciphertext are kept as base64 text, which every database we support can hold.
+pub struct SqlTokens<C> {
+    conn: C,
+}
+
+impl<C> SqlTokens<C> where
+    C: SqlConnection,
+{
+    pub fn with_connection(conn: C) -> SqlTokens<C> {
+        SqlTokens {
+            conn,
+        }
+    }
+}
+
+impl<C> Repository<TokenKey, StoredToken> for SqlTokens<C> where
+    C: SqlConnection,
+{
+    type Error = SqlError;
+
+    fn insert(&mut self, token: &StoredToken) -> Result<Option<TokenKey>, Self::Error> {
+        self.conn.transaction(|conn| {
+            if token_exists(conn, &token.key())? {
+                return Ok(None);
+            }
+
+            conn.execute(
+                "INSERT INTO oauth_tokens (user_id, provider, nonce, ciphertext) VALUES (?, ?, ?, ?)",
+                &[
+                    token.user_id.into(),
+                    (&token.provider).into(),
+                    base64::encode(&token.nonce).into(),
+                    base64::encode(&token.ciphertext).into(),
+                ],
+            )?;
+
+            Ok(Some(token.key()))
+        })
+    }
+
+    fn get(&mut self, key: &TokenKey) -> Result<Option<StoredToken>, Self::Error> {
+        let rows = self.conn.query(
+            r"SELECT t.user_id, t.provider, t.nonce, t.ciphertext
+            FROM oauth_tokens AS t
+            WHERE t.user_id = ? AND t.provider = ?",
+            &[key.0.into(), (&key.1).into()],
+        )?;
+
+        match rows.first() {
+            Some(row) => Ok(Some(token_from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn update(&mut self, token: &StoredToken) -> Result<Option<TokenKey>, Self::Error> {
+        self.conn.transaction(|conn| {
+            // MySQL reports zero affected rows for an update that didn't change anything, so check
+            // the token exists up front instead.
+            if !token_exists(conn, &token.key())? {
+                return Ok(None);
+            }
+
+            conn.execute(
+                "UPDATE oauth_tokens SET nonce = ?, ciphertext = ? WHERE user_id = ? AND provider = ?",
+                &[
+                    base64::encode(&token.nonce).into(),
+                    base64::encode(&token.ciphertext).into(),
+                    token.user_id.into(),
+                    (&token.provider).into(),
+                ],
+            )?;
+
+            Ok(Some(token.key()))
+        })
+    }
+
+    fn remove(&mut self, key: &TokenKey) -> Result<Option<TokenKey>, Self::Error> {
+        let removed = self.conn.execute(
+            "DELETE FROM oauth_tokens WHERE user_id = ? AND provider = ?",
+            &[key.0.into(), (&key.1).into()],
+        )?;
+        if removed == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(key.clone()))
+    }
+}
+
+// token_exists locks the token's row, if it has one.
+fn token_exists<C: SqlConnection>(conn: &mut C, key: &TokenKey) -> Result<bool, SqlError> {
+    let sql = format!(
+        "SELECT user_id FROM oauth_tokens WHERE user_id = ? AND provider = ?{}",
+        conn.dialect().for_update(),
+    );
+    Ok(!conn.query(&sql, &[key.0.into(), (&key.1).into()])?.is_empty())
+}
+
+fn token_from_row(row: &SqlRow) -> Result<StoredToken, SqlError> {
+    Ok(StoredToken {
+        user_id: row.get(0)?,
+        provider: row.get(1)?,
+        nonce: decode_base64(&row.get::<String>(2)?)?,
+        ciphertext: decode_base64(&row.get::<String>(3)?)?,
+    })
+}
+
+fn decode_base64(value: &str) -> Result<Vec<u8>, SqlError> {
+    base64::decode(value).map_err(|e| SqlError::InvalidData(format!("invalid base64: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqlTokens;
+    use crate::repositories::abstractions::Repository;
+    use crate::repositories::sql::sqlite::SqliteConnection;
+    use crate::tokens::{OauthToken, TokenCipher};
+
+    fn new_test_repo() -> SqlTokens<SqliteConnection> {
+        SqlTokens::with_connection(SqliteConnection::in_memory())
+    }
+
+    fn token(access_token: &str) -> OauthToken {
+        OauthToken {
+            access_token: access_token.to_string(),
+            refresh_token: Some("refresh".to_string()),
+            expires_at: Some(1_600_000_000),
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn sealed_tokens_round_trip() {
+        let cipher = TokenCipher::new(&[7; 32]);
+        let mut repo = new_test_repo();
+        let stored = cipher.seal(1, "vimeo", &token("access")).unwrap();
+
+        assert_eq!(repo.insert(&stored).unwrap(), Some((1, "vimeo".to_string())));
+        let loaded = repo.get(&(1, "vimeo".to_string())).unwrap().unwrap();
+        assert_eq!(loaded, stored);
+        assert_eq!(cipher.open(&loaded).unwrap(), token("access"));
+
+        // Only the ciphertext is stored, never the token itself.
+        let raw: String = repo.conn.raw()
+            .query_row("SELECT ciphertext FROM oauth_tokens", rusqlite::NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert!(!raw.contains("access"));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn tokens_are_kept_per_provider() {
+        let cipher = TokenCipher::new(&[7; 32]);
+        let mut repo = new_test_repo();
+        repo.insert(&cipher.seal(1, "vimeo", &token("vimeo")).unwrap()).unwrap();
+        repo.insert(&cipher.seal(1, "soundcloud", &token("soundcloud")).unwrap()).unwrap();
+
+        let refreshed = cipher.seal(1, "vimeo", &token("refreshed")).unwrap();
+        assert_eq!(repo.update(&refreshed).unwrap(), Some((1, "vimeo".to_string())));
+        assert_eq!(repo.remove(&(1, "soundcloud".to_string())).unwrap(), Some((1, "soundcloud".to_string())));
+
+        let vimeo = repo.get(&(1, "vimeo".to_string())).unwrap().unwrap();
+        assert_eq!(cipher.open(&vimeo).unwrap(), token("refreshed"));
+        assert!(repo.get(&(1, "soundcloud".to_string())).unwrap().is_none());
+    }
+}
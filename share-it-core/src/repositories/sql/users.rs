@@ -0,0 +1,658 @@
+use crate::repositories::abstractions::{Query, Repository, StaleVersion, Versioned};
+use crate::repositories::query::{page_of, Page, PageRequest, PlaylistFilter, UserFilter};
+use crate::repositories::sql::{paged_query, SqlConnection, SqlError, SqlRow, SqlValue};
+use crate::user::{User, UserID, LinkedIdentity, PlaylistID};
+use crate::playlist::Playlist;
+use crate::song::{Song, SongMetadata, Availability};
+
+// SqlUsers stores users, along with their playlists, the songs in them and their linked
+// identities, in any SQL database we have a connection for.
+pub struct SqlUsers<C> {
+    conn: C,
+}
+
+impl<C> SqlUsers<C> where
+    C: SqlConnection,
+{
+    pub fn with_connection(conn: C) -> SqlUsers<C> {
+        SqlUsers {
+            conn,
+        }
+    }
+}
+
+impl<C> Repository<UserID, User> for SqlUsers<C> where
+    C: SqlConnection,
+{
+    type Error = SqlError;
+
+    fn insert(&mut self, user: &User) -> Result<Option<UserID>, Self::Error> {
+        self.conn.transaction(|conn| {
+            if user_exists(conn, user.id())? {
+                return Ok(None);
+            }
+
+            conn.execute(
+                r"INSERT INTO users (id, username, avatar_url, permalink_url, active_playlist, version)
+                VALUES (?, ?, ?, ?, ?, ?)",
+                &[
+                    user.id().into(),
+                    user.username().into(),
+                    user.avatar_url().into(),
+                    user.permalink_url().into(),
+                    user.active_playlist().map(|p| p.to_string()).into(),
+                    user.version().into(),
+                ],
+            )?;
+            save_playlists(conn, user)?;
+            save_identities(conn, user)?;
+
+            Ok(Some(user.id()))
+        })
+    }
+
+    fn get(&mut self, key: &UserID) -> Result<Option<User>, Self::Error> {
+        let rows = self.conn.query(
+            r"SELECT u.id, u.username, u.avatar_url, u.permalink_url, u.active_playlist, u.version
+            FROM users AS u
+            WHERE u.id = ?",
+            &[(*key).into()],
+        )?;
+        let row = match rows.first() {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let mut user = User::new(row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?);
+        if let Some(active_playlist) = row.get::<Option<String>>(4)? {
+            user.set_active_playlist(&parse_ulid(&active_playlist)?);
+        }
+        user.set_version(row.get(5)?);
+        get_playlists(&mut self.conn, &mut user)?;
+        get_identities(&mut self.conn, &mut user)?;
+
+        Ok(Some(user))
+    }
+
+    fn update(&mut self, entity: &User) -> Result<Option<UserID>, Self::Error> {
+        self.conn.transaction(|conn| {
+            // MySQL reports zero affected rows for an update that didn't change anything, so check
+            // the user exists up front instead. Their row stays locked until we're done, so nobody
+            // can update them in between.
+            let stored = match user_version(conn, entity.id())? {
+                Some(version) => version,
+                None => return Ok(None),
+            };
+            if stored != entity.version() {
+                return Err(SqlError::Stale(StaleVersion { loaded: entity.version(), stored }));
+            }
+
+            conn.execute(
+                r"UPDATE users SET username = ?, avatar_url = ?, permalink_url = ?, active_playlist = ?, version = ?
+                WHERE id = ?",
+                &[
+                    entity.username().into(),
+                    entity.avatar_url().into(),
+                    entity.permalink_url().into(),
+                    entity.active_playlist().map(|p| p.to_string()).into(),
+                    (stored + 1).into(),
+                    entity.id().into(),
+                ],
+            )?;
+            save_playlists(conn, entity)?;
+            save_identities(conn, entity)?;
+
+            // Success.  Return the PK back as is.
+            Ok(Some(entity.id()))
+        })
+    }
+
+    fn remove(&mut self, key: &UserID) -> Result<Option<UserID>, Self::Error> {
+        self.conn.transaction(|conn| {
+            // Not every database enforces foreign keys by default, so clean up after the user
+            // ourselves rather than relying on cascading deletes.
+            conn.execute(
+                "DELETE FROM playlist_songs WHERE playlist_id IN (SELECT id FROM playlists WHERE user_id = ?)",
+                &[(*key).into()],
+            )?;
+            conn.execute("DELETE FROM playlists WHERE user_id = ?", &[(*key).into()])?;
+            conn.execute("DELETE FROM user_identities WHERE user_id = ?", &[(*key).into()])?;
+            conn.execute("DELETE FROM oauth_tokens WHERE user_id = ?", &[(*key).into()])?;
+
+            if conn.execute("DELETE FROM users WHERE id = ?", &[(*key).into()])? == 0 {
+                return Ok(None);
+            }
+
+            // Success.  Return the PK back as is.
+            Ok(Some(*key))
+        })
+    }
+}
+
+impl<C> Query<UserFilter, User> for SqlUsers<C> where
+    C: SqlConnection,
+{
+    type Cursor = UserID;
+    type Error = SqlError;
+
+    fn query(&mut self, filter: &UserFilter, page: &PageRequest<UserID>) -> Result<Page<User, UserID>, SqlError> {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        if let Some(username) = &filter.username {
+            conditions.push("u.username = ?".to_string());
+            params.push(username.into());
+        }
+        if let Some((provider, subject)) = &filter.identity {
+            conditions.push("u.id IN (SELECT i.user_id FROM user_identities AS i WHERE i.provider = ? AND i.subject = ?)".to_string());
+            params.push(provider.into());
+            params.push(subject.into());
+        }
+        let sql = paged_query("SELECT u.id FROM users AS u", "u.id", conditions, &mut params, page);
+
+        let mut users = Vec::new();
+        for row in self.conn.query(&sql, &params)? {
+            let user_id: UserID = row.get(0)?;
+            // A user removed since we listed them just isn't on the page.
+            if let Some(user) = self.get(&user_id)? {
+                users.push((user_id, user));
+            }
+        }
+
+        Ok(page_of(users, page.limit))
+    }
+}
+
+impl<C> Query<PlaylistFilter, Playlist> for SqlUsers<C> where
+    C: SqlConnection,
+{
+    type Cursor = PlaylistID;
+    type Error = SqlError;
+
+    fn query(&mut self, filter: &PlaylistFilter, page: &PageRequest<PlaylistID>) -> Result<Page<Playlist, PlaylistID>, SqlError> {
+        let mut params = vec![filter.user_id.into()];
+        let sql = paged_query(
+            "SELECT p.id, p.name FROM playlists AS p",
+            "p.id",
+            vec!["p.user_id = ?".to_string()],
+            &mut params,
+            page,
+        );
+
+        let mut playlists = Vec::new();
+        for row in self.conn.query(&sql, &params)? {
+            let mut playlist = Playlist::with_id(parse_ulid(&row.get::<String>(0)?)?, row.get(1)?);
+            get_songs(&mut self.conn, &mut playlist)?;
+            playlists.push((playlist.id(), playlist));
+        }
+
+        Ok(page_of(playlists, page.limit))
+    }
+}
+
+fn user_exists<C: SqlConnection>(conn: &mut C, user_id: UserID) -> Result<bool, SqlError> {
+    Ok(user_version(conn, user_id)?.is_some())
+}
+
+// user_version locks the user's row, and returns the version stored in it.
+fn user_version<C: SqlConnection>(conn: &mut C, user_id: UserID) -> Result<Option<u64>, SqlError> {
+    let sql = format!("SELECT version FROM users WHERE id = ?{}", conn.dialect().for_update());
+    match conn.query(&sql, &[user_id.into()])?.first() {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
+    }
+}
+
+fn get_playlists<C: SqlConnection>(conn: &mut C, user: &mut User) -> Result<(), SqlError> {
+    let playlist_rows = conn.query(
+        r"SELECT p.id, p.name
+        FROM playlists AS p
+        WHERE p.user_id = ?",
+        &[user.id().into()],
+    )?;
+
+    for playlist_row in playlist_rows {
+        let id: String = playlist_row.get(0)?;
+        let mut playlist = Playlist::with_id(parse_ulid(&id)?, playlist_row.get(1)?);
+        get_songs(conn, &mut playlist)?;
+
+        user.add_playlist(playlist);
+    }
+
+    Ok(())
+}
+
+// get_songs adds the playlist's stored songs to it, in play order.
+fn get_songs<C: SqlConnection>(conn: &mut C, playlist: &mut Playlist) -> Result<(), SqlError> {
+    let song_rows = conn.query(
+        r"SELECT s.id, s.user_id, s.duration_ms, s.username, s.title, s.sharing, s.permalink,
+        s.permalink_url, s.artwork_url, s.stream_url, s.availability, s.metadata
+        FROM playlist_songs AS ps
+        JOIN songs AS s ON s.id = ps.song_id
+        WHERE ps.playlist_id = ?
+        ORDER BY ps.position",
+        &[playlist.id().into()],
+    )?;
+    for song_row in song_rows {
+        playlist.add_song(song_from_row(&song_row)?);
+    }
+
+    Ok(())
+}
+
+fn get_identities<C: SqlConnection>(conn: &mut C, user: &mut User) -> Result<(), SqlError> {
+    let rows = conn.query(
+        r"SELECT i.provider, i.subject, i.username
+        FROM user_identities AS i
+        WHERE i.user_id = ?
+        ORDER BY i.provider",
+        &[user.id().into()],
+    )?;
+    for row in rows {
+        user.link_identity(LinkedIdentity {
+            provider: row.get(0)?,
+            subject: row.get(1)?,
+            username: row.get(2)?,
+        });
+    }
+
+    Ok(())
+}
+
+// save_playlists replaces everything stored for the user's playlists with what they have now. A
+// playlist's songs are stored with their position, so the play order survives a round trip.
+fn save_playlists<C: SqlConnection>(conn: &mut C, user: &User) -> Result<(), SqlError> {
+    conn.execute(
+        "DELETE FROM playlist_songs WHERE playlist_id IN (SELECT id FROM playlists WHERE user_id = ?)",
+        &[user.id().into()],
+    )?;
+    conn.execute("DELETE FROM playlists WHERE user_id = ?", &[user.id().into()])?;
+
+    for playlist in user.playlists() {
+        let playlist_id = playlist.id().to_string();
+        conn.execute(
+            "INSERT INTO playlists (id, user_id, name) VALUES (?, ?, ?)",
+            &[(&playlist_id).into(), user.id().into(), playlist.name().into()],
+        )?;
+
+        for (position, song) in playlist.songs().enumerate() {
+            save_song(conn, song)?;
+            conn.execute(
+                "INSERT INTO playlist_songs (playlist_id, position, song_id) VALUES (?, ?, ?)",
+                &[(&playlist_id).into(), (position as u32).into(), song.id().into()],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// save_song stores the song, or refreshes it if another playlist already has it.
+fn save_song<C: SqlConnection>(conn: &mut C, song: &Song) -> Result<(), SqlError> {
+    let metadata = match song.metadata() {
+        Some(metadata) => Some(
+            serde_json::to_string(metadata).map_err(|e| SqlError::InvalidData(e.to_string()))?
+        ),
+        None => None,
+    };
+    let values: Vec<SqlValue> = vec![
+        song.user_id().into(),
+        song.duration_ms().into(),
+        song.username().into(),
+        song.title().into(),
+        song.sharing().into(),
+        song.permalink().into(),
+        song.permalink_url().into(),
+        song.artwork_url().into(),
+        song.stream_url().into(),
+        song.availability().as_str().into(),
+        metadata.into(),
+        song.id().into(),
+    ];
+
+    // Upserts are spelled differently by every database, so do it by hand.
+    let exists = !conn.query("SELECT id FROM songs WHERE id = ?", &[song.id().into()])?.is_empty();
+    if exists {
+        conn.execute(
+            r"UPDATE songs SET user_id = ?, duration_ms = ?, username = ?, title = ?, sharing = ?,
+            permalink = ?, permalink_url = ?, artwork_url = ?, stream_url = ?, availability = ?,
+            metadata = ?
+            WHERE id = ?",
+            &values,
+        )?;
+    } else {
+        conn.execute(
+            r"INSERT INTO songs (user_id, duration_ms, username, title, sharing, permalink,
+            permalink_url, artwork_url, stream_url, availability, metadata, id)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            &values,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn save_identities<C: SqlConnection>(conn: &mut C, user: &User) -> Result<(), SqlError> {
+    conn.execute("DELETE FROM user_identities WHERE user_id = ?", &[user.id().into()])?;
+
+    for identity in user.identities() {
+        conn.execute(
+            "INSERT INTO user_identities (user_id, provider, subject, username) VALUES (?, ?, ?, ?)",
+            &[
+                user.id().into(),
+                (&identity.provider).into(),
+                (&identity.subject).into(),
+                (&identity.username).into(),
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn song_from_row(row: &SqlRow) -> Result<Song, SqlError> {
+    let mut song = Song::new(
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+        row.get(6)?,
+        row.get(7)?,
+        row.get(8)?,
+        row.get(9)?,
+    );
+
+    let availability: String = row.get(10)?;
+    song.set_availability(
+        Availability::parse(&availability)
+            .ok_or_else(|| SqlError::InvalidData(format!("unknown song availability {}", availability)))?,
+    );
+    if let Some(metadata) = row.get::<Option<String>>(11)? {
+        let metadata: SongMetadata = serde_json::from_str(&metadata)
+            .map_err(|e| SqlError::InvalidData(e.to_string()))?;
+        song.set_metadata(metadata);
+    }
+
+    Ok(song)
+}
+
+pub(crate) fn parse_ulid(s: &str) -> Result<PlaylistID, SqlError> {
+    s.parse().map_err(|_| SqlError::InvalidData(format!("invalid ulid {}", s)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqlUsers;
+    use crate::repositories::abstractions::{Query, Repository, Versioned};
+    use crate::repositories::query::{PageRequest, PlaylistFilter, UserFilter};
+    use crate::repositories::sql::sqlite::SqliteConnection;
+    use crate::test_tools::factories::{new_test_user, new_test_playlist, new_test_song};
+    use crate::playlist::Playlist;
+    use crate::test_tools::fixtures::{TRACK_JSON, USER_JSON};
+    use crate::soundcloud_api::{SoundcloudTrack, SoundcloudUser};
+    use crate::song::{Song, Availability};
+    use crate::user::{User, LinkedIdentity};
+
+    const HOSTILE_USERNAMES: &[&str] = &[
+        "O'Brien",
+        "Robert'); DROP TABLE users;--",
+        "' OR '1'='1",
+        "\\'; DELETE FROM users WHERE '1'='1",
+        "\"double\" `backtick` ?placeholder?",
+        "üñíçødé 🎧",
+    ];
+
+    fn new_test_repo() -> SqlUsers<SqliteConnection> {
+        SqlUsers::with_connection(SqliteConnection::in_memory())
+    }
+
+    fn count_users(repo: &SqlUsers<SqliteConnection>) -> i64 {
+        repo.conn.raw().query_row("SELECT COUNT(*) FROM users", rusqlite::NO_PARAMS, |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn user_round_trips_with_playlists() {
+        let mut repo = new_test_repo();
+        let s_user: SoundcloudUser = serde_json::from_str(USER_JSON).unwrap();
+        let mut user = User::from(s_user);
+        user.link_identity(LinkedIdentity {
+            provider: "vimeo".to_string(),
+            subject: "12345".to_string(),
+            username: "vimeo user".to_string(),
+        });
+
+        let mut playlist = new_test_playlist(user.id(), 4);
+        let s_track: SoundcloudTrack = serde_json::from_str(TRACK_JSON).unwrap();
+        playlist.add_song(Song::from(s_track));
+        // Play order isn't insertion or id order anymore.
+        playlist.cycle_playlist();
+        playlist.set_song_availability(2, Availability::Deleted);
+        let playlist_id = playlist.id();
+        user.add_playlist(playlist);
+        user.add_playlist(new_test_playlist(user.id(), 0));
+        user.set_active_playlist(&playlist_id);
+
+        assert_eq!(repo.insert(&user).unwrap(), Some(user.id()));
+
+        assert_eq!(repo.get(&user.id()).unwrap(), Some(user));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn update_replaces_playlists() {
+        let mut repo = new_test_repo();
+        let mut user = new_test_user(1);
+        let old_playlist = new_test_playlist(1, 3);
+        let old_playlist_id = old_playlist.id();
+        user.add_playlist(old_playlist);
+        repo.insert(&user).unwrap();
+
+        user.remove_playlist(&old_playlist_id);
+        let mut new_playlist = new_test_playlist(1, 2);
+        new_playlist.add_song(new_test_song(9, 1));
+        user.add_playlist(new_playlist);
+
+        assert_eq!(repo.update(&user).unwrap(), Some(1));
+        // Updating moved the stored user on to the next version.
+        user.set_version(1);
+        assert_eq!(repo.get(&1).unwrap(), Some(user));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn update_without_changes_still_finds_user() {
+        let mut repo = new_test_repo();
+        let user = new_test_user(1);
+        repo.insert(&user).unwrap();
+
+        assert_eq!(repo.update(&user).unwrap(), Some(1));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn missing_users_are_none() {
+        let mut repo = new_test_repo();
+        let user = new_test_user(1);
+
+        assert_eq!(repo.get(&1).unwrap(), None);
+        assert_eq!(repo.update(&user).unwrap(), None);
+        assert_eq!(repo.remove(&1).unwrap(), None);
+
+        repo.insert(&user).unwrap();
+        assert_eq!(repo.insert(&user).unwrap(), None);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn remove_takes_playlists_with_it() {
+        let mut repo = new_test_repo();
+        let mut user = new_test_user(1);
+        user.add_playlist(new_test_playlist(1, 2));
+        repo.insert(&user).unwrap();
+
+        assert_eq!(repo.remove(&1).unwrap(), Some(1));
+
+        let playlists: i64 = repo.conn.raw()
+            .query_row("SELECT COUNT(*) FROM playlists", rusqlite::NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(playlists, 0);
+        // Songs are shared between users, so they stay.
+        let songs: i64 = repo.conn.raw()
+            .query_row("SELECT COUNT(*) FROM songs", rusqlite::NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(songs, 2);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn hostile_usernames_are_stored_verbatim() {
+        let mut repo = new_test_repo();
+        let bystander = new_test_user(0);
+        repo.insert(&bystander).unwrap();
+
+        for (i, username) in HOSTILE_USERNAMES.iter().enumerate() {
+            let user_id = i as u32 + 1;
+            let user = User::new(user_id, username.to_string(), "avatar'url".to_string(), "permalink'url".to_string());
+
+            assert_eq!(repo.insert(&user).unwrap(), Some(user_id));
+            assert_eq!(repo.get(&user_id).unwrap().unwrap().username(), username.to_string());
+        }
+
+        // Nothing got dropped or deleted along the way.
+        assert_eq!(count_users(&repo), HOSTILE_USERNAMES.len() as i64 + 1);
+        assert_eq!(repo.get(&0).unwrap(), Some(bystander));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn hostile_usernames_survive_updates() {
+        let mut repo = new_test_repo();
+        let mut user = new_test_user(1);
+        repo.insert(&user).unwrap();
+        repo.insert(&new_test_user(2)).unwrap();
+
+        for username in HOSTILE_USERNAMES {
+            user = User::new(1, username.to_string(), user.avatar_url(), user.permalink_url());
+            user.set_version(repo.get(&1).unwrap().unwrap().version());
+
+            assert_eq!(repo.update(&user).unwrap(), Some(1));
+            assert_eq!(repo.get(&1).unwrap().unwrap().username(), username.to_string());
+        }
+
+        // The other user wasn't touched.
+        assert_eq!(repo.get(&2).unwrap().unwrap().username(), "test_username");
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn hostile_playlist_and_song_names_round_trip() {
+        let mut repo = new_test_repo();
+        let mut user = new_test_user(1);
+        let mut playlist = crate::playlist::Playlist::new("Robert'); DROP TABLE playlists;--".to_string());
+        let song = Song::new(
+            1, 1, 1000,
+            "O'Brien".to_string(),
+            "Don't Stop Me Now'; --".to_string(),
+            "public".to_string(),
+            "dont-stop".to_string(),
+            "https://soundcloud.com/o'brien/dont-stop".to_string(),
+            None,
+            "https://api.soundcloud.com/tracks/1".to_string(),
+        );
+        playlist.add_song(song);
+        user.add_playlist(playlist);
+
+        repo.insert(&user).unwrap();
+
+        assert_eq!(repo.get(&1).unwrap(), Some(user));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn users_are_found_by_username_a_page_at_a_time() {
+        let mut repo = new_test_repo();
+        for user_id in 0..5 {
+            let mut user = new_test_user(user_id);
+            if user_id == 3 {
+                user = User::new(3, "O'Brien".to_string(), "avatar".to_string(), "permalink".to_string());
+            }
+            repo.insert(&user).unwrap();
+        }
+
+        let request = PageRequest::first(2);
+        let first = repo.query(&UserFilter::default(), &request).unwrap();
+        assert_eq!(first.items.iter().map(|u| u.id()).collect::<Vec<_>>(), vec![0, 1]);
+        let second = repo.query(&UserFilter::default(), &first.next_page(&request).unwrap()).unwrap();
+        assert_eq!(second.items.iter().map(|u| u.id()).collect::<Vec<_>>(), vec![2, 3]);
+        let last = repo.query(&UserFilter::default(), &second.next_page(&request).unwrap()).unwrap();
+        assert_eq!(last.items.iter().map(|u| u.id()).collect::<Vec<_>>(), vec![4]);
+        assert_eq!(last.next, None);
+
+        let filter = UserFilter { username: Some("O'Brien".to_string()), ..UserFilter::default() };
+        let found = repo.query(&filter, &PageRequest::first(10)).unwrap();
+        assert_eq!(found.items.len(), 1);
+        assert_eq!(found.items[0].id(), 3);
+
+        let descending = repo.query(&UserFilter::default(), &PageRequest::first(2).descending()).unwrap();
+        assert_eq!(descending.items.iter().map(|u| u.id()).collect::<Vec<_>>(), vec![4, 3]);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn identities_belong_to_one_user() {
+        let mut repo = new_test_repo();
+        let identity = LinkedIdentity {
+            provider: "vimeo".to_string(),
+            subject: "12345".to_string(),
+            username: "vimeo user".to_string(),
+        };
+        let mut user = new_test_user(1);
+        user.link_identity(identity.clone());
+        repo.insert(&user).unwrap();
+        repo.insert(&new_test_user(2)).unwrap();
+
+        let filter = UserFilter { identity: Some(("vimeo".to_string(), "12345".to_string())), ..UserFilter::default() };
+        let found = repo.query(&filter, &PageRequest::first(10)).unwrap();
+        assert_eq!(found.items.iter().map(|u| u.id()).collect::<Vec<_>>(), vec![1]);
+
+        // The database won't have the same account linked twice either.
+        let mut other = repo.get(&2).unwrap().unwrap();
+        other.link_identity(identity);
+        assert!(repo.update(&other).is_err());
+        assert!(repo.get(&2).unwrap().unwrap().identity("vimeo").is_none());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn playlists_are_paged_in_id_order() {
+        let mut repo = new_test_repo();
+        let mut user = new_test_user(1);
+        for _ in 0..3 {
+            user.add_playlist(new_test_playlist(1, 2));
+        }
+        repo.insert(&user).unwrap();
+        repo.insert(&new_test_user(2)).unwrap();
+
+        let mut want: Vec<Playlist> = user.playlists().cloned().collect();
+        want.sort_by_key(|p| p.id());
+
+        let request = PageRequest::first(2);
+        let filter = PlaylistFilter { user_id: 1 };
+        let first = repo.query(&filter, &request).unwrap();
+        assert_eq!(first.items, want[..2].to_vec());
+        let last = repo.query(&filter, &first.next_page(&request).unwrap()).unwrap();
+        assert_eq!(last.items, want[2..].to_vec());
+        assert_eq!(last.next, None);
+
+        assert!(repo.query(&PlaylistFilter { user_id: 2 }, &request).unwrap().items.is_empty());
+    }
+
+    mod conformance {
+        use crate::repositories::sql::SqliteUsers;
+        use crate::test_tools::conformance::UserEntities;
+
+        crate::repository_conformance_tests!(SqliteUsers::in_memory(), UserEntities::new());
+    }
+}
@@ -1,5 +1,29 @@
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+
 pub trait Handles<T> {
     type Result;
 
     fn handle(&mut self, cmd: T) -> Self::Result;
 }
+
+// A handler shared behind a mutex handles the same commands, so one handler can be registered on
+// a CommandBus for each command it handles. Like shared repositories, a poisoned lock panics.
+impl<T, H> Handles<T> for Arc<Mutex<H>> where
+    H: Handles<T>,
+{
+    type Result = H::Result;
+
+    fn handle(&mut self, cmd: T) -> Self::Result {
+        self.lock().unwrap().handle(cmd)
+    }
+}
+
+// AsyncHandles is Handles for callers on an async runtime. A synchronous handler becomes one with
+// BlockingHandler, which runs it where it can't stall the runtime.
+#[async_trait(?Send)]
+pub trait AsyncHandles<T> {
+    type Result;
+
+    async fn handle(&self, cmd: T) -> Self::Result;
+}
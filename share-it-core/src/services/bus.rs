@@ -0,0 +1,259 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use crate::error::Error;
+use crate::services::abstractions::Handles;
+use crate::user::UserID;
+
+// Command is anything the CommandBus can dispatch. Commands are cloned for every attempt at
+// handling them, so that middleware can retry.
+pub trait Command: Clone + 'static {
+    // What the command's handler returns when it succeeds.
+    type Output: 'static;
+
+    // name is how the command shows up in logs and timings.
+    fn name(&self) -> &'static str;
+
+    // actor is the user the command acts as, if it acts as anyone. The authorization middleware
+    // only lets callers act as themselves.
+    fn actor(&self) -> Option<UserID>;
+}
+
+// Context is who is dispatching a command, as far as the caller has been able to tell.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Context {
+    // The logged in user, if there is one.
+    pub user_id: Option<UserID>,
+}
+
+impl Context {
+    pub fn anonymous() -> Context {
+        Context::default()
+    }
+
+    pub fn user(user_id: UserID) -> Context {
+        Context {
+            user_id: Some(user_id),
+        }
+    }
+}
+
+// Dispatch is what middleware gets to see of the command it's running around.
+#[derive(Debug)]
+pub struct Dispatch<'a> {
+    pub name: &'static str,
+    pub actor: Option<UserID>,
+    pub context: &'a Context,
+}
+
+// Output is a command's output on its way through the middleware, which doesn't know its type.
+pub type Output = Box<dyn Any>;
+
+// Middleware runs around every command the bus dispatches. It may call `next.run` to carry on
+// towards the handler, as many times as it likes, or return without calling it at all.
+pub trait Middleware {
+    fn handle(&self, dispatch: &Dispatch, next: &mut Next) -> Result<Output, Error>;
+}
+
+// Next is the rest of the middleware chain, ending in the command's handler.
+pub struct Next<'a> {
+    chain: &'a [Box<dyn Middleware>],
+    handler: &'a mut dyn FnMut() -> Result<Output, Error>,
+}
+
+impl<'a> Next<'a> {
+    pub fn run(&mut self, dispatch: &Dispatch) -> Result<Output, Error> {
+        match self.chain.split_first() {
+            Some((middleware, rest)) => {
+                let mut next = Next {
+                    chain: rest,
+                    handler: &mut *self.handler,
+                };
+                middleware.handle(dispatch, &mut next)
+            },
+            None => (self.handler)(),
+        }
+    }
+}
+
+type BoxedHandler<C> = Box<dyn FnMut(C) -> Result<<C as Command>::Output, Error>>;
+
+// CommandBus routes commands to the handler registered for them, through every middleware. The
+// first middleware added is the outermost.
+pub struct CommandBus {
+    // Each value is the BoxedHandler for the command type it's keyed by.
+    handlers: HashMap<TypeId, Box<dyn Any>>,
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl CommandBus {
+    pub fn new() -> CommandBus {
+        CommandBus {
+            handlers: HashMap::new(),
+            middleware: Vec::new(),
+        }
+    }
+
+    // register makes handler the one that handles C, replacing any handler registered for it
+    // before. A handler that handles several commands has to be shared to be registered for each,
+    // e.g. behind an `Arc<Mutex<_>>`.
+    pub fn register<C, H>(&mut self, mut handler: H) where
+        C: Command,
+        H: Handles<C, Result = Result<C::Output, Error>> + 'static,
+    {
+        let handler: BoxedHandler<C> = Box::new(move |cmd| handler.handle(cmd));
+        self.handlers.insert(TypeId::of::<C>(), Box::new(handler));
+    }
+
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    pub fn dispatch<C: Command>(&mut self, cmd: C, context: &Context) -> Result<C::Output, Error> {
+        let handler = self.handlers.get_mut(&TypeId::of::<C>())
+            .and_then(|handler| handler.downcast_mut::<BoxedHandler<C>>())
+            .ok_or_else(|| Error::Unhandled(cmd.name()))?;
+
+        let dispatch = Dispatch {
+            name: cmd.name(),
+            actor: cmd.actor(),
+            context,
+        };
+        let mut run = || handler(cmd.clone()).map(|output| Box::new(output) as Output);
+        let mut next = Next {
+            chain: &self.middleware,
+            handler: &mut run,
+        };
+
+        let output = next.run(&dispatch)?;
+        // Well behaved middleware only ever returns what next gave it, but nothing stops it making
+        // up an output of its own.
+        output.downcast::<C::Output>()
+            .map(|output| *output)
+            .map_err(|_| Error::MismatchedOutput(dispatch.name))
+    }
+}
+
+impl Default for CommandBus {
+    fn default() -> Self {
+        CommandBus::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, CommandBus, Context, Dispatch, Middleware, Next, Output};
+    use crate::error::Error;
+    use crate::services::abstractions::Handles;
+    use crate::repositories::abstractions::Repository;
+    use crate::repositories::memory::{InMemoryChatrooms, InMemoryUsers};
+    use crate::services::ChatroomHandler;
+    use crate::services::commands::{CreateChatroomCmd, JoinChatroomCmd};
+    use crate::test_tools::factories::new_test_user;
+    use crate::user::UserID;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone)]
+    struct Echo(u32);
+
+    impl Command for Echo {
+        type Output = u32;
+
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+
+        fn actor(&self) -> Option<UserID> {
+            None
+        }
+    }
+
+    struct EchoHandler;
+
+    impl Handles<Echo> for EchoHandler {
+        type Result = Result<u32, Error>;
+
+        fn handle(&mut self, cmd: Echo) -> Self::Result {
+            Ok(cmd.0)
+        }
+    }
+
+    // Trace records the order middleware runs in.
+    struct Trace(&'static str, Rc<RefCell<Vec<String>>>);
+
+    impl Middleware for Trace {
+        fn handle(&self, dispatch: &Dispatch, next: &mut Next) -> Result<Output, Error> {
+            self.1.borrow_mut().push(format!("{} before {}", self.0, dispatch.name));
+            let result = next.run(dispatch);
+            self.1.borrow_mut().push(format!("{} after {}", self.0, dispatch.name));
+            result
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn commands_go_through_middleware_in_order() {
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let mut bus = CommandBus::new();
+        bus.register(EchoHandler);
+        bus.add_middleware(Trace("outer", trace.clone()));
+        bus.add_middleware(Trace("inner", trace.clone()));
+
+        assert_eq!(bus.dispatch(Echo(7), &Context::anonymous()).unwrap(), 7);
+
+        let want = vec!["outer before echo", "inner before echo", "inner after echo", "outer after echo"];
+        assert_eq!(*trace.borrow(), want);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn commands_without_a_handler_are_an_error() {
+        let mut bus = CommandBus::new();
+
+        match bus.dispatch(Echo(7), &Context::anonymous()) {
+            Err(Error::Unhandled("echo")) => {},
+            other => panic!("expected echo to be unhandled, got {:?}", other),
+        }
+    }
+
+    // Impostor answers every command with a string, whatever the command's output is.
+    struct Impostor;
+
+    impl Middleware for Impostor {
+        fn handle(&self, _dispatch: &Dispatch, _next: &mut Next) -> Result<Output, Error> {
+            Ok(Box::new("not a number".to_string()))
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn mismatched_outputs_are_an_error() {
+        let mut bus = CommandBus::new();
+        bus.register(EchoHandler);
+        bus.add_middleware(Impostor);
+
+        match bus.dispatch(Echo(7), &Context::anonymous()) {
+            Err(Error::MismatchedOutput("echo")) => {},
+            other => panic!("expected a mismatched output, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn one_handler_can_handle_several_commands() {
+        let mut users = InMemoryUsers::new();
+        users.insert(&new_test_user(1)).unwrap();
+        let handler = Arc::new(Mutex::new(ChatroomHandler::new(InMemoryChatrooms::new(), users)));
+        let mut bus = CommandBus::new();
+        bus.register::<CreateChatroomCmd, _>(handler.clone());
+        bus.register::<JoinChatroomCmd, _>(handler);
+
+        let context = Context::user(1);
+        let chatroom = bus.dispatch(CreateChatroomCmd { creating_user: 1, chatroom_name: "room".to_string() }, &context).unwrap();
+        let chatroom_id = chatroom.id.parse().unwrap();
+        let chatroom = bus.dispatch(JoinChatroomCmd { chatroom_id, user_id: 1 }, &context).unwrap();
+
+        assert_eq!(chatroom.users.len(), 1);
+    }
+}
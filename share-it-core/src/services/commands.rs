@@ -1,43 +1,175 @@
-use crate::user::UserID;
+use crate::user::{UserID, LinkedIdentity};
+use crate::soundcloud_api::SoundcloudUser;
+use crate::services::bus::Command;
+use crate::services::views::{ChatroomView, NowPlayingView, UserView, WaitlistView};
 use rusty_ulid::Ulid;
 
+#[derive(Clone)]
 pub struct CreateChatroomCmd {
     pub creating_user: UserID,
     pub chatroom_name: String,
 }
 
+#[derive(Clone)]
 pub struct JoinChatroomCmd {
     pub chatroom_id: Ulid,
     pub user_id: u32,
 }
 
+#[derive(Clone)]
 pub struct LeaveChatroomCmd {
     pub chatroom_id: Ulid,
     pub user_id: u32,
 }
 
+// LoginCmd logs in the SoundCloud user that just completed the OAuth flow, creating their
+// share-it user on first login.
+#[derive(Clone)]
 pub struct LoginCmd {
-    // TODO: Fill in necessary info to log in a user.
+    pub soundcloud_user: SoundcloudUser,
 }
 
+// LinkIdentityCmd links another provider's account (Vimeo etc.) to a logged in user.
+#[derive(Clone)]
+pub struct LinkIdentityCmd {
+    pub user_id: UserID,
+    pub identity: LinkedIdentity,
+}
+
+#[derive(Clone)]
 pub struct JoinWaitlistCmd {
     pub chatroom_id: Ulid,
     pub user_id: u32,
 }
 
+#[derive(Clone)]
 pub struct LeaveWaitlistCmd {
     pub chatroom_id: Ulid,
     pub user_id: u32,
 }
 
+#[derive(Clone)]
 pub struct ListWaistlistDJs {
     pub chatroom_id: Ulid,
 }
 
+// PlayNextCmd moves the chatroom on to the next song. Only its moderator can.
+#[derive(Clone)]
 pub struct PlayNextCmd {
-    // TODO: Fill in necessary info to play the next song, if you are the moderator.
+    pub chatroom_id: Ulid,
+    pub user_id: UserID,
 }
 
 pub struct UploadSongCmd {
     // TODO: Fill in necessary info to upload a song.
-}
\ No newline at end of file
+}
+
+impl Command for CreateChatroomCmd {
+    type Output = ChatroomView;
+
+    fn name(&self) -> &'static str {
+        "create_chatroom"
+    }
+
+    fn actor(&self) -> Option<UserID> {
+        Some(self.creating_user)
+    }
+}
+
+impl Command for JoinChatroomCmd {
+    type Output = ChatroomView;
+
+    fn name(&self) -> &'static str {
+        "join_chatroom"
+    }
+
+    fn actor(&self) -> Option<UserID> {
+        Some(self.user_id)
+    }
+}
+
+impl Command for LeaveChatroomCmd {
+    type Output = ChatroomView;
+
+    fn name(&self) -> &'static str {
+        "leave_chatroom"
+    }
+
+    fn actor(&self) -> Option<UserID> {
+        Some(self.user_id)
+    }
+}
+
+impl Command for LoginCmd {
+    type Output = UserView;
+
+    fn name(&self) -> &'static str {
+        "login"
+    }
+
+    // Logging in is how a user gets to act as themselves, so it doesn't act as anyone yet.
+    fn actor(&self) -> Option<UserID> {
+        None
+    }
+}
+
+impl Command for LinkIdentityCmd {
+    type Output = UserView;
+
+    fn name(&self) -> &'static str {
+        "link_identity"
+    }
+
+    fn actor(&self) -> Option<UserID> {
+        Some(self.user_id)
+    }
+}
+
+impl Command for JoinWaitlistCmd {
+    type Output = WaitlistView;
+
+    fn name(&self) -> &'static str {
+        "join_waitlist"
+    }
+
+    fn actor(&self) -> Option<UserID> {
+        Some(self.user_id)
+    }
+}
+
+impl Command for LeaveWaitlistCmd {
+    type Output = WaitlistView;
+
+    fn name(&self) -> &'static str {
+        "leave_waitlist"
+    }
+
+    fn actor(&self) -> Option<UserID> {
+        Some(self.user_id)
+    }
+}
+
+impl Command for PlayNextCmd {
+    type Output = NowPlayingView;
+
+    fn name(&self) -> &'static str {
+        "play_next"
+    }
+
+    fn actor(&self) -> Option<UserID> {
+        Some(self.user_id)
+    }
+}
+
+impl Command for ListWaistlistDJs {
+    type Output = WaitlistView;
+
+    fn name(&self) -> &'static str {
+        "list_waitlist_djs"
+    }
+
+    // Anyone can look at a waitlist.
+    fn actor(&self) -> Option<UserID> {
+        None
+    }
+}
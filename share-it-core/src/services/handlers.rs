@@ -1,32 +1,68 @@
-use crate::repositories::abstractions::Repository;
-use crate::user::User;
+use crate::error::{Entity, Error};
+use crate::events::{DomainEvent, EventBus};
+use crate::repositories::abstractions::{Query, Repository};
+use crate::repositories::query::{PageRequest, UserFilter};
+use crate::soundcloud_api::SoundcloudUser;
+use crate::user::{User, UserID};
 use crate::chatroom::{Chatroom, ChatUser};
 use rusty_ulid::Ulid;
 use crate::services::abstractions::Handles;
-use crate::services::commands::{CreateChatroomCmd, JoinChatroomCmd, LeaveChatroomCmd, JoinWaitlistCmd, LeaveWaitlistCmd, ListWaistlistDJs};
-use std::collections::VecDeque;
-use crate::waitlist::DJ;
+use crate::services::commands::{CreateChatroomCmd, JoinChatroomCmd, LeaveChatroomCmd, JoinWaitlistCmd, LeaveWaitlistCmd, ListWaistlistDJs, PlayNextCmd, LoginCmd, LinkIdentityCmd};
+use crate::services::views::{ChatroomView, NowPlayingView, SongView, UserView, WaitlistView};
 
+// Handlers only return views, never domain types.
 
-// PACKAGE TODOS: Handlers should only return serialized types.
-
-// ChatroomHandler is a Handler that handles all chatroom related commands.
+// ChatroomHandler is a Handler that handles all chatroom related commands. The events for every
+// change it saves are published on its EventBus.
 pub struct ChatroomHandler<T, U> where
     T: Repository<Ulid, Chatroom<U>>,
     U: Repository<u32, User> + Clone,
 {
     chatrooms: T,
     users: U,
+    events: EventBus,
 }
 
 impl<T, U> ChatroomHandler<T, U> where
     T: Repository<Ulid, Chatroom<U>>,
     U: Repository<u32, User> + Clone,
 {
+    // new creates a handler whose events nobody is subscribed to.
     pub fn new(chatroom_repo: T, user_repo: U) -> ChatroomHandler<T, U> {
+        ChatroomHandler::with_events(chatroom_repo, user_repo, EventBus::new())
+    }
+
+    pub fn with_events(chatroom_repo: T, user_repo: U, events: EventBus) -> ChatroomHandler<T, U> {
         ChatroomHandler {
             chatrooms: chatroom_repo,
             users: user_repo,
+            events,
+        }
+    }
+
+    fn chatroom(&mut self, chatroom_id: &Ulid) -> Result<Chatroom<U>, Error> {
+        self.chatrooms.get(chatroom_id)
+            .map_err(Error::chatrooms)?
+            .ok_or(Error::NotFound(Entity::Chatroom(*chatroom_id)))
+    }
+
+    fn user(&mut self, user_id: &UserID) -> Result<User, Error> {
+        self.users.get(user_id)
+            .map_err(Error::users)?
+            .ok_or(Error::NotFound(Entity::User(*user_id)))
+    }
+
+    // save persists a chatroom we just loaded, then publishes what changed. It disappearing in
+    // between means someone removed it, and it changing in between is a conflict, which the
+    // RetryOnConflict middleware retries from the top. The events are only taken once it's saved,
+    // as some repositories store them.
+    fn save(&mut self, chatroom: &mut Chatroom<U>) -> Result<(), Error> {
+        match self.chatrooms.update(chatroom).map_err(Error::chatrooms)? {
+            Some(_) => {
+                self.events.publish(&chatroom.take_events());
+                Ok(())
+            },
+            None => Err(Error::NotFound(Entity::Chatroom(chatroom.id()))),
         }
     }
 }
@@ -35,11 +71,22 @@ impl<T, U> Handles<CreateChatroomCmd> for ChatroomHandler<T, U> where
     T: Repository<Ulid, Chatroom<U>>,
     U: Repository<u32, User> + Clone,
 {
-    type Result = Result<Option<Ulid>, T::Error>;
+    type Result = Result<ChatroomView, Error>;
 
     fn handle(&mut self, cmd: CreateChatroomCmd) -> Self::Result {
+        if cmd.chatroom_name.trim().is_empty() {
+            return Err(Error::Validation("chatrooms need a name".to_string()));
+        }
+        // Only existing users can moderate.
+        self.user(&cmd.creating_user)?;
+
         let mut new_chatroom = Chatroom::new(self.users.clone(), cmd.creating_user, cmd.chatroom_name);
         self.chatrooms.insert(&new_chatroom)
+            .map_err(Error::chatrooms)?
+            .ok_or_else(|| Error::Conflict(format!("chatroom {} already exists", new_chatroom.id())))?;
+        self.events.publish(&new_chatroom.take_events());
+
+        Ok(ChatroomView::from(&new_chatroom))
     }
 }
 
@@ -47,34 +94,18 @@ impl<T, U> Handles<JoinChatroomCmd> for ChatroomHandler<T, U> where
     T: Repository<Ulid, Chatroom<U>>,
     U: Repository<u32, User> + Clone,
 {
-    type Result = Result<Option<()>, T::Error>;
+    type Result = Result<ChatroomView, Error>;
 
     fn handle(&mut self, cmd: JoinChatroomCmd) -> Self::Result {
-        let maybe_chatroom = self.chatrooms.get(&cmd.chatroom_id)?;
-        if let None = maybe_chatroom {
-            return Ok(None);
-        }
-        let mut chatroom = maybe_chatroom.unwrap();
-
-        // TODO: This will return a U::Error, so we need an error tree. After establishing, remove unwrap.
-        let maybe_requesting_user = self.users.get(&cmd.user_id).unwrap();
-        if let None = maybe_requesting_user {
-            return Ok(None)
-        }
-        let User{ id, username, ..} = maybe_requesting_user.unwrap();
-
-        let joined = chatroom.join(ChatUser(id, username));
-        if !joined {
-            // No work was necessary, so no need to persist.
-            return Ok(None)
-        }
+        let mut chatroom = self.chatroom(&cmd.chatroom_id)?;
+        let user = self.user(&cmd.user_id)?;
 
-        let result = self.chatrooms.update(&chatroom)?;
-        if result.is_none() {
-            return Ok(None);
+        let joined = chatroom.join(ChatUser(user.id(), user.username()));
+        if joined {
+            self.save(&mut chatroom)?;
         }
 
-        Ok(Some(()))
+        Ok(ChatroomView::from(&chatroom))
     }
 }
 
@@ -82,28 +113,18 @@ impl<T, U> Handles<LeaveChatroomCmd> for ChatroomHandler<T, U> where
     T: Repository<Ulid, Chatroom<U>>,
     U: Repository<u32, User> + Clone,
 {
-    type Result = Result<Option<()>, T::Error>;
+    type Result = Result<ChatroomView, Error>;
 
     fn handle(&mut self, cmd: LeaveChatroomCmd) -> Self::Result {
-        let maybe_chatroom = self.chatrooms.get(&cmd.chatroom_id)?;
-        if let None = maybe_chatroom {
-            return Ok(None);
-        }
-        let mut chatroom = maybe_chatroom.unwrap();
+        let mut chatroom = self.chatroom(&cmd.chatroom_id)?;
 
         let left = chatroom.leave(cmd.user_id);
 
-        if !left {
-            // No work was necessary, so no need to persist.
-            return Ok(None)
-        }
-
-        let result = self.chatrooms.update(&chatroom)?;
-        if result.is_none() {
-            return Ok(None);
+        if left {
+            self.save(&mut chatroom)?;
         }
 
-        Ok(Some(()))
+        Ok(ChatroomView::from(&chatroom))
     }
 }
 
@@ -111,33 +132,22 @@ impl<T, U> Handles<JoinWaitlistCmd> for ChatroomHandler<T, U> where
     T: Repository<Ulid, Chatroom<U>>,
     U: Repository<u32, User> + Clone,
 {
-    type Result = Result<Option<()>, T::Error>;
+    type Result = Result<WaitlistView, Error>;
 
     fn handle(&mut self, cmd: JoinWaitlistCmd) -> Self::Result {
-        let maybe_chatroom = self.chatrooms.get(&cmd.chatroom_id)?;
-        if let None = maybe_chatroom {
-            return Ok(None);
-        }
-        let mut chatroom = maybe_chatroom.unwrap();
+        let mut chatroom = self.chatroom(&cmd.chatroom_id)?;
+        let user = self.user(&cmd.user_id)?;
 
-        // TODO: This will return a U::Error, so we need an error tree. After establishing, remove unwrap.
-        let maybe_requesting_user = self.users.get(&cmd.user_id).unwrap();
-        if let None = maybe_requesting_user {
-            return Ok(None)
+        if !chatroom.current_users().iter().any(|u| u.0 == user.id()) {
+            return Err(Error::Forbidden("only users in the chatroom can join its waitlist".to_string()));
         }
-        let User{id, ..} = maybe_requesting_user.unwrap();
 
-        let joined = chatroom.join_waitlist(id);
-        if !joined {
-            return Ok(None)
+        let joined = chatroom.join_waitlist(user.id());
+        if joined {
+            self.save(&mut chatroom)?;
         }
 
-        let result = self.chatrooms.update(&chatroom)?;
-        if result.is_none() {
-            return Ok(None);
-        }
-
-        Ok(Some(()))
+        Ok(WaitlistView::from(chatroom.waitlist()))
     }
 }
 
@@ -145,49 +155,548 @@ impl<T, U> Handles<LeaveWaitlistCmd> for ChatroomHandler<T, U> where
     T: Repository<Ulid, Chatroom<U>>,
     U: Repository<u32, User> + Clone,
 {
-    type Result = Result<Option<()>, T::Error>;
+    type Result = Result<WaitlistView, Error>;
 
     fn handle(&mut self, cmd: LeaveWaitlistCmd) -> Self::Result {
-        let maybe_chatroom = self.chatrooms.get(&cmd.chatroom_id)?;
-        if let None = maybe_chatroom {
-            return Ok(None);
+        let mut chatroom = self.chatroom(&cmd.chatroom_id)?;
+        let user = self.user(&cmd.user_id)?;
+
+        let left = chatroom.leave_waitlist(user.id());
+        if left {
+            self.save(&mut chatroom)?;
         }
-        let mut chatroom = maybe_chatroom.unwrap();
 
-        // TODO: This will return a U::Error, so we need an error tree. After establishing, remove unwrap.
-        let maybe_requesting_user = self.users.get(&cmd.user_id).unwrap();
-        if let None = maybe_requesting_user {
-            return Ok(None)
+        Ok(WaitlistView::from(chatroom.waitlist()))
+    }
+}
+
+impl<T, U> Handles<ListWaistlistDJs> for ChatroomHandler<T, U> where
+    T: Repository<Ulid, Chatroom<U>>,
+    U: Repository<u32, User> + Clone,
+{
+    type Result = Result<WaitlistView, Error>;
+
+    fn handle(&mut self, cmd: ListWaistlistDJs) -> Self::Result {
+        let chatroom = self.chatroom(&cmd.chatroom_id)?;
+
+        Ok(WaitlistView::from(chatroom.waitlist()))
+    }
+}
+
+impl<T, U> Handles<PlayNextCmd> for ChatroomHandler<T, U> where
+    T: Repository<Ulid, Chatroom<U>>,
+    U: Repository<u32, User> + Clone,
+{
+    type Result = Result<NowPlayingView, Error>;
+
+    // Songs and turns skipped on the way are published with the rest, so their owners hear about
+    // them.
+    fn handle(&mut self, cmd: PlayNextCmd) -> Self::Result {
+        let mut chatroom = self.chatroom(&cmd.chatroom_id)?;
+        if cmd.user_id != chatroom.moderator() {
+            return Err(Error::Forbidden("only the moderator can play the next song".to_string()));
         }
-        let User{id, ..} = maybe_requesting_user.unwrap();
 
-        let left = chatroom.leave_waitlist(id);
-        if !left {
-            return Ok(None)
+        let song = chatroom.play_next().map_err(Error::users)?;
+        self.save(&mut chatroom)?;
+
+        Ok(NowPlayingView {
+            song: song.as_ref().map(SongView::from),
+            waitlist: WaitlistView::from(chatroom.waitlist()),
+        })
+    }
+}
+
+// LOGIN_RETRIES is how many more times a login that conflicted is tried. Logins are retried here
+// rather than left to the RetryOnConflict middleware, as the API handles them outside the
+// CommandBus, and two first logins racing each other only takes a double clicked button.
+const LOGIN_RETRIES: u32 = 3;
+
+// UserHandler is a Handler that handles all user account related commands.
+pub struct UserHandler<U> where
+    U: Repository<u32, User>,
+{
+    users: U,
+    events: EventBus,
+}
+
+impl<U> UserHandler<U> where
+    U: Repository<u32, User>,
+{
+    // new creates a handler whose events nobody is subscribed to.
+    pub fn new(user_repo: U) -> UserHandler<U> {
+        UserHandler::with_events(user_repo, EventBus::new())
+    }
+
+    pub fn with_events(user_repo: U, events: EventBus) -> UserHandler<U> {
+        UserHandler {
+            users: user_repo,
+            events,
         }
+    }
 
-        let result = self.chatrooms.update(&chatroom)?;
-        if result.is_none() {
-            return Ok(None);
+    // login upserts the user. A returning user gets their SoundCloud profile refreshed, while a
+    // first time user is created from it. Someone else creating them first is a conflict, which
+    // makes this a returning login when it's tried again.
+    fn login(&mut self, soundcloud_user: SoundcloudUser) -> Result<UserView, Error> {
+        let user_id = soundcloud_user.id;
+        let maybe_user = self.users.get(&user_id).map_err(Error::users)?;
+        if let Some(mut user) = maybe_user {
+            user.refresh_profile(soundcloud_user);
+            self.users.update(&user)
+                .map_err(Error::users)?
+                .ok_or(Error::NotFound(Entity::User(user_id)))?;
+            return Ok(UserView::from(&user));
         }
 
-        Ok(Some(()))
+        let user = User::from(soundcloud_user);
+        // Someone else logging in as the same user got there first.
+        self.users.insert(&user)
+            .map_err(Error::users)?
+            .ok_or_else(|| Error::Conflict(format!("user {} logged in twice at once", user_id)))?;
+        self.events.publish(&[DomainEvent::UserRegistered { user_id, username: user.username() }]);
+        Ok(UserView::from(&user))
     }
 }
 
-impl<T, U> Handles<ListWaistlistDJs> for ChatroomHandler<T, U> where
-    T: Repository<Ulid, Chatroom<U>>,
-    U: Repository<u32, User> + Clone,
+impl<U> Handles<LoginCmd> for UserHandler<U> where
+    U: Repository<u32, User>,
 {
-    type Result = Result<Option<VecDeque<DJ>>, T::Error>;
+    type Result = Result<UserView, Error>;
+
+    fn handle(&mut self, cmd: LoginCmd) -> Self::Result {
+        let mut retries = 0;
+        loop {
+            match self.login(cmd.soundcloud_user.clone()) {
+                Err(Error::Conflict(_)) if retries < LOGIN_RETRIES => retries += 1,
+                result => return result,
+            }
+        }
+    }
+}
 
-    fn handle(&mut self, cmd: ListWaistlistDJs) -> Self::Result {
-        let maybe_chatroom = self.chatrooms.get(&cmd.chatroom_id)?;
-        if let None = maybe_chatroom {
-            return Ok(None);
+impl<U> Handles<LinkIdentityCmd> for UserHandler<U> where
+    U: Repository<u32, User> + Query<UserFilter, User, Cursor = UserID>,
+{
+    type Result = Result<UserView, Error>;
+
+    // An account can only be linked to one user. Linking it to the user it's already linked to
+    // just refreshes it.
+    fn handle(&mut self, cmd: LinkIdentityCmd) -> Self::Result {
+        let mut user = self.users.get(&cmd.user_id)
+            .map_err(Error::users)?
+            .ok_or(Error::NotFound(Entity::User(cmd.user_id)))?;
+
+        let filter = UserFilter {
+            identity: Some((cmd.identity.provider.clone(), cmd.identity.subject.clone())),
+            ..UserFilter::default()
+        };
+        let owners = self.users.query(&filter, &PageRequest::first(1)).map_err(Error::users)?;
+        if owners.items.iter().any(|owner| owner.id() != cmd.user_id) {
+            return Err(Error::Conflict(format!(
+                "{} account {} is linked to another user", cmd.identity.provider, cmd.identity.subject,
+            )));
         }
-        let chatroom = maybe_chatroom.unwrap();
 
-        Ok(Some(chatroom.waitlist_djs().clone()))
+        user.link_identity(cmd.identity);
+        self.users.update(&user)
+            .map_err(Error::users)?
+            .ok_or(Error::NotFound(Entity::User(cmd.user_id)))?;
+        Ok(UserView::from(&user))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChatroomHandler, UserHandler};
+    use crate::MockUserRepository;
+    use crate::repositories::abstractions::Repository;
+    use crate::services::abstractions::Handles;
+    use crate::services::commands::{LoginCmd, LinkIdentityCmd};
+    use crate::soundcloud_api::SoundcloudUser;
+    use crate::test_tools::factories::new_test_playlist;
+    use crate::test_tools::fixtures::USER_JSON;
+    use crate::user::{User, LinkedIdentity};
+    use crate::error::{Entity, Error};
+    use crate::repositories::memory::{InMemoryChatrooms, InMemoryUsers};
+    use crate::services::commands::{CreateChatroomCmd, JoinChatroomCmd, JoinWaitlistCmd, ListWaistlistDJs, PlayNextCmd};
+    use crate::song::Availability;
+    use crate::test_tools::factories::new_test_user;
+    use crate::events::{DomainEvent, EventBus};
+    use crate::chatroom::{Chatroom, ChatUser};
+    use crate::repositories::memory::MemoryError;
+    use crate::services::bus::{CommandBus, Context};
+    use crate::services::middleware::RetryOnConflict;
+    use crate::soundcloud_api::RetryPolicy;
+    use crate::test_tools::MockClock;
+    use rusty_ulid::Ulid;
+    use crate::blocking::BlockingHandler;
+    use crate::services::abstractions::AsyncHandles;
+    use futures::executor::block_on;
+    use std::sync::{Arc, Barrier, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn vimeo_identity() -> LinkedIdentity {
+        LinkedIdentity {
+            provider: "vimeo".to_string(),
+            subject: "12345".to_string(),
+            username: "vimeo user".to_string(),
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn first_login_creates_user() {
+        let mut repo = MockUserRepository::new();
+        let s_user: SoundcloudUser = serde_json::from_str(USER_JSON).unwrap();
+
+        let result = UserHandler::new(&mut repo).handle(LoginCmd { soundcloud_user: s_user }).unwrap();
+
+        assert_eq!(result.id, 3207);
+        assert_eq!(repo.get(&3207).unwrap().unwrap().username(), "Johannes Wagener");
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn returning_login_refreshes_profile_and_keeps_playlists() {
+        let mut repo = MockUserRepository::new();
+        let mut s_user: SoundcloudUser = serde_json::from_str(USER_JSON).unwrap();
+        let mut existing = User::from(s_user.clone());
+        existing.add_playlist(new_test_playlist(3207, 2));
+        repo.insert(&existing);
+
+        s_user.username = "Johannes".to_string();
+        let result = UserHandler::new(&mut repo).handle(LoginCmd { soundcloud_user: s_user }).unwrap();
+
+        assert_eq!(result.id, 3207);
+        let user = repo.get(&3207).unwrap().unwrap();
+        assert_eq!(user.username(), "Johannes");
+        assert_eq!(user.playlist_count(), 1);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn linking_identity_keeps_soundcloud_identity() {
+        let mut repo = MockUserRepository::new();
+        let s_user: SoundcloudUser = serde_json::from_str(USER_JSON).unwrap();
+        UserHandler::new(&mut repo).handle(LoginCmd { soundcloud_user: s_user }).unwrap();
+
+        let result = UserHandler::new(&mut repo)
+            .handle(LinkIdentityCmd { user_id: 3207, identity: vimeo_identity() })
+            .unwrap();
+
+        assert_eq!(result.id, 3207);
+        let user = repo.get(&3207).unwrap().unwrap();
+        assert!(user.identity("soundcloud").is_some());
+        assert_eq!(user.identity("vimeo"), Some(&vimeo_identity()));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn linking_identity_to_missing_user_is_not_found() {
+        let mut repo = MockUserRepository::new();
+
+        let result = UserHandler::new(&mut repo)
+            .handle(LinkIdentityCmd { user_id: 1, identity: vimeo_identity() });
+
+        match result {
+            Err(Error::NotFound(Entity::User(1))) => {},
+            other => panic!("expected user 1 to be missing, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn identities_linked_to_another_user_are_a_conflict() {
+        let mut repo = MockUserRepository::new();
+        repo.insert(&new_test_user(1));
+        repo.insert(&new_test_user(2));
+        UserHandler::new(&mut repo).handle(LinkIdentityCmd { user_id: 1, identity: vimeo_identity() }).unwrap();
+
+        match UserHandler::new(&mut repo).handle(LinkIdentityCmd { user_id: 2, identity: vimeo_identity() }) {
+            Err(Error::Conflict(_)) => {},
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert!(repo.get(&2).unwrap().unwrap().identity("vimeo").is_none());
+        // Linking it again to the user it's linked to is fine.
+        UserHandler::new(&mut repo).handle(LinkIdentityCmd { user_id: 1, identity: vimeo_identity() }).unwrap();
+    }
+
+    fn new_test_handler() -> ChatroomHandler<InMemoryChatrooms<InMemoryUsers>, InMemoryUsers> {
+        let mut users = InMemoryUsers::new();
+        users.insert(&new_test_user(1)).unwrap();
+        users.insert(&new_test_user(2)).unwrap();
+        ChatroomHandler::new(InMemoryChatrooms::new(), users)
+    }
+
+    fn create(handler: &mut ChatroomHandler<InMemoryChatrooms<InMemoryUsers>, InMemoryUsers>) -> rusty_ulid::Ulid {
+        let chatroom = handler.handle(CreateChatroomCmd { creating_user: 1, chatroom_name: "room".to_string() }).unwrap();
+        chatroom.id.parse().unwrap()
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn joining_a_chatroom_then_its_waitlist() {
+        let mut handler = new_test_handler();
+        let chatroom_id = create(&mut handler);
+
+        let chatroom = handler.handle(JoinChatroomCmd { chatroom_id, user_id: 2 }).unwrap();
+        assert_eq!(chatroom.users.len(), 1);
+        // Joining twice changes nothing.
+        assert_eq!(handler.handle(JoinChatroomCmd { chatroom_id, user_id: 2 }).unwrap(), chatroom);
+
+        let waitlist = handler.handle(JoinWaitlistCmd { chatroom_id, user_id: 2 }).unwrap();
+        assert_eq!(waitlist.djs.len(), 1);
+        assert_eq!(waitlist.djs[0].id, 2);
+        assert_eq!(handler.handle(ListWaistlistDJs { chatroom_id }).unwrap(), waitlist);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn missing_chatrooms_and_users_are_not_found() {
+        let mut handler = new_test_handler();
+        let chatroom_id = create(&mut handler);
+        let missing_id = rusty_ulid::Ulid::generate();
+
+        match handler.handle(JoinChatroomCmd { chatroom_id: missing_id, user_id: 2 }) {
+            Err(Error::NotFound(Entity::Chatroom(id))) => assert_eq!(id, missing_id),
+            other => panic!("expected the chatroom to be missing, got {:?}", other),
+        }
+        match handler.handle(JoinChatroomCmd { chatroom_id, user_id: 3 }) {
+            Err(Error::NotFound(Entity::User(3))) => {},
+            other => panic!("expected user 3 to be missing, got {:?}", other),
+        }
+        assert!(handler.handle(ListWaistlistDJs { chatroom_id: missing_id }).unwrap_err().is_not_found());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn only_chatroom_users_can_join_the_waitlist() {
+        let mut handler = new_test_handler();
+        let chatroom_id = create(&mut handler);
+
+        match handler.handle(JoinWaitlistCmd { chatroom_id, user_id: 2 }) {
+            Err(Error::Forbidden(_)) => {},
+            other => panic!("expected joining to be forbidden, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn chatrooms_need_a_name() {
+        let mut handler = new_test_handler();
+
+        match handler.handle(CreateChatroomCmd { creating_user: 1, chatroom_name: "  ".to_string() }) {
+            Err(Error::Validation(_)) => {},
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn saved_changes_are_published() {
+        let mut users = InMemoryUsers::new();
+        users.insert(&new_test_user(1)).unwrap();
+        let events = EventBus::new();
+        let received = events.channel();
+        let mut handler = ChatroomHandler::with_events(InMemoryChatrooms::new(), users, events);
+
+        let chatroom_id = create(&mut handler);
+        handler.handle(JoinChatroomCmd { chatroom_id, user_id: 1 }).unwrap();
+        handler.handle(JoinWaitlistCmd { chatroom_id, user_id: 1 }).unwrap();
+        // Joining again changes nothing, so publishes nothing.
+        handler.handle(JoinChatroomCmd { chatroom_id, user_id: 1 }).unwrap();
+
+        let want = vec![
+            DomainEvent::ChatroomCreated { chatroom_id, moderator: 1, name: "room".to_string() },
+            DomainEvent::UserJoined { chatroom_id, user_id: 1, username: "test_username".to_string() },
+            DomainEvent::DjQueued { chatroom_id, user_id: 1 },
+        ];
+        assert_eq!(received.try_iter().collect::<Vec<_>>(), want);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn skipped_songs_and_turns_are_published() {
+        let mut users = InMemoryUsers::new();
+        // User 1's first song is gone from SoundCloud, and user 2 has nothing to play.
+        let mut user = new_test_user(1);
+        let playlist = new_test_playlist(1, 2);
+        user.set_active_playlist(&playlist.id());
+        user.add_playlist(playlist);
+        user.set_song_availability(0, Availability::Deleted);
+        users.insert(&user).unwrap();
+        let mut user = new_test_user(2);
+        let playlist = new_test_playlist(2, 0);
+        user.set_active_playlist(&playlist.id());
+        user.add_playlist(playlist);
+        users.insert(&user).unwrap();
+        let events = EventBus::new();
+        let mut handler = ChatroomHandler::with_events(InMemoryChatrooms::new(), users, events.clone());
+
+        let chatroom_id = create(&mut handler);
+        for user_id in [2, 1] {
+            handler.handle(JoinChatroomCmd { chatroom_id, user_id }).unwrap();
+            handler.handle(JoinWaitlistCmd { chatroom_id, user_id }).unwrap();
+        }
+        let received = events.channel();
+
+        let playing = handler.handle(PlayNextCmd { chatroom_id, user_id: 1 }).unwrap();
+        assert_eq!(playing.song.unwrap().id, 1);
+        assert_eq!(playing.waitlist.current_dj, Some(1));
+
+        let skipped: Vec<_> = received.try_iter()
+            .filter(|event| matches!(event, DomainEvent::TurnSkipped { .. } | DomainEvent::SongSkipped { .. }))
+            .collect();
+        let want = vec![
+            DomainEvent::TurnSkipped { chatroom_id, user_id: 2 },
+            DomainEvent::SongSkipped { chatroom_id, owner: 1, song_id: 0, title: "test song".to_string() },
+        ];
+        assert_eq!(skipped, want);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn only_the_moderator_can_play_next() {
+        let mut handler = new_test_handler();
+        let chatroom_id = create(&mut handler);
+
+        match handler.handle(PlayNextCmd { chatroom_id, user_id: 2 }) {
+            Err(Error::Forbidden(_)) => {},
+            other => panic!("expected playing next to be forbidden, got {:?}", other),
+        }
+    }
+
+    // Interfering makes user 1 join every chatroom behind our back, just before our first update.
+    struct Interfering {
+        chatrooms: InMemoryChatrooms<InMemoryUsers>,
+        interfered: bool,
+    }
+
+    impl Repository<Ulid, Chatroom<InMemoryUsers>> for Interfering {
+        type Error = MemoryError;
+
+        fn insert(&mut self, chatroom: &Chatroom<InMemoryUsers>) -> Result<Option<Ulid>, MemoryError> {
+            self.chatrooms.insert(chatroom)
+        }
+
+        fn get(&mut self, key: &Ulid) -> Result<Option<Chatroom<InMemoryUsers>>, MemoryError> {
+            self.chatrooms.get(key)
+        }
+
+        fn update(&mut self, chatroom: &Chatroom<InMemoryUsers>) -> Result<Option<Ulid>, MemoryError> {
+            if !self.interfered {
+                self.interfered = true;
+                let mut theirs = self.chatrooms.get(&chatroom.id())?.unwrap();
+                theirs.join(ChatUser(1, "test_username".to_string()));
+                self.chatrooms.update(&theirs)?;
+            }
+            self.chatrooms.update(chatroom)
+        }
+
+        fn remove(&mut self, key: &Ulid) -> Result<Option<Ulid>, MemoryError> {
+            self.chatrooms.remove(key)
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn conflicting_changes_are_retried_by_the_bus() {
+        let mut users = InMemoryUsers::new();
+        users.insert(&new_test_user(1)).unwrap();
+        users.insert(&new_test_user(2)).unwrap();
+        let chatrooms = Interfering { chatrooms: InMemoryChatrooms::new(), interfered: false };
+        let handler = Arc::new(Mutex::new(ChatroomHandler::new(chatrooms, users)));
+        let clock = MockClock::new();
+        let mut bus = CommandBus::new();
+        bus.register::<CreateChatroomCmd, _>(handler.clone());
+        bus.register::<JoinChatroomCmd, _>(handler.clone());
+        bus.add_middleware(RetryOnConflict::with_clock(RetryPolicy::default(), clock.clone()));
+
+        let context = Context::anonymous();
+        let chatroom = bus.dispatch(CreateChatroomCmd { creating_user: 1, chatroom_name: "room".to_string() }, &context).unwrap();
+        let chatroom_id = chatroom.id.parse().unwrap();
+        let chatroom = bus.dispatch(JoinChatroomCmd { chatroom_id, user_id: 2 }, &context).unwrap();
+
+        // The handler reloaded the chatroom on the retry, so neither join was lost.
+        assert!(handler.lock().unwrap().chatrooms.interfered);
+        assert_eq!(clock.sleeps().len(), 1);
+        let mut ids: Vec<_> = chatroom.users.iter().map(|u| u.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn first_logins_are_published() {
+        let mut repo = MockUserRepository::new();
+        let events = EventBus::new();
+        let received = events.channel();
+        let s_user: SoundcloudUser = serde_json::from_str(USER_JSON).unwrap();
+
+        UserHandler::with_events(&mut repo, events.clone()).handle(LoginCmd { soundcloud_user: s_user.clone() }).unwrap();
+        UserHandler::with_events(&mut repo, events).handle(LoginCmd { soundcloud_user: s_user }).unwrap();
+
+        let want = vec![DomainEvent::UserRegistered { user_id: 3207, username: "Johannes Wagener".to_string() }];
+        assert_eq!(received.try_iter().collect::<Vec<_>>(), want);
+    }
+
+    // Racing holds back the first two lookups until both have been made, so two first logins
+    // both find nobody there, and both try to create the user.
+    #[derive(Clone)]
+    struct Racing {
+        users: InMemoryUsers,
+        lookups: Arc<AtomicUsize>,
+        both_looked: Arc<Barrier>,
+    }
+
+    impl Repository<u32, User> for Racing {
+        type Error = MemoryError;
+
+        fn insert(&mut self, user: &User) -> Result<Option<u32>, MemoryError> {
+            self.users.insert(user)
+        }
+
+        fn get(&mut self, key: &u32) -> Result<Option<User>, MemoryError> {
+            let user = self.users.get(key);
+            if self.lookups.fetch_add(1, Ordering::SeqCst) < 2 {
+                self.both_looked.wait();
+            }
+            user
+        }
+
+        fn update(&mut self, user: &User) -> Result<Option<u32>, MemoryError> {
+            self.users.update(user)
+        }
+
+        fn remove(&mut self, key: &u32) -> Result<Option<u32>, MemoryError> {
+            self.users.remove(key)
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn racing_first_logins_both_log_in() {
+        let users = Racing {
+            users: InMemoryUsers::new(),
+            lookups: Arc::new(AtomicUsize::new(0)),
+            both_looked: Arc::new(Barrier::new(2)),
+        };
+        let events = EventBus::new();
+        let received = events.channel();
+        let s_user: SoundcloudUser = serde_json::from_str(USER_JSON).unwrap();
+
+        // Like the API, each login gets a handler of its own, sharing the repository.
+        let first = BlockingHandler::new(UserHandler::with_events(users.clone(), events.clone()));
+        let second = BlockingHandler::new(UserHandler::with_events(users.clone(), events));
+        let (first, second) = block_on(futures::future::join(
+            first.handle(LoginCmd { soundcloud_user: s_user.clone() }),
+            second.handle(LoginCmd { soundcloud_user: s_user }),
+        ));
+
+        assert_eq!(first.unwrap().unwrap().id, 3207);
+        assert_eq!(second.unwrap().unwrap().id, 3207);
+        // Only the one that got there first registered them.
+        assert_eq!(received.try_iter().count(), 1);
     }
 }
@@ -0,0 +1,290 @@
+use std::time::Duration;
+use crate::clock::{Clock, SystemClock};
+use crate::error::Error;
+use crate::services::bus::{Dispatch, Middleware, Next, Output};
+use crate::soundcloud_api::RetryPolicy;
+
+// Authenticate refuses commands that act as a user when nobody is logged in.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Authenticate;
+
+impl Middleware for Authenticate {
+    fn handle(&self, dispatch: &Dispatch, next: &mut Next) -> Result<Output, Error> {
+        if dispatch.actor.is_some() && dispatch.context.user_id.is_none() {
+            return Err(Error::Unauthenticated);
+        }
+        next.run(dispatch)
+    }
+}
+
+// Authorize only lets logged in users act as themselves. It lets anonymous callers through, so
+// it's meant to run after Authenticate.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Authorize;
+
+impl Middleware for Authorize {
+    fn handle(&self, dispatch: &Dispatch, next: &mut Next) -> Result<Output, Error> {
+        if let (Some(actor), Some(user_id)) = (dispatch.actor, dispatch.context.user_id) {
+            if actor != user_id {
+                return Err(Error::Forbidden(format!("user {} can't {} as user {}", user_id, dispatch.name, actor)));
+            }
+        }
+        next.run(dispatch)
+    }
+}
+
+// Logging writes a line for every command, saying how it went.
+pub struct Logging<F> where
+    F: Fn(&str),
+{
+    log: F,
+}
+
+impl<F> Logging<F> where
+    F: Fn(&str),
+{
+    pub fn new(log: F) -> Logging<F> {
+        Logging {
+            log,
+        }
+    }
+}
+
+impl Logging<fn(&str)> {
+    pub fn stderr() -> Logging<fn(&str)> {
+        Logging::new(|line| eprintln!("{}", line))
+    }
+}
+
+impl<F> Middleware for Logging<F> where
+    F: Fn(&str),
+{
+    fn handle(&self, dispatch: &Dispatch, next: &mut Next) -> Result<Output, Error> {
+        let caller = match dispatch.context.user_id {
+            Some(user_id) => format!("user {}", user_id),
+            None => "anonymous".to_string(),
+        };
+
+        let result = next.run(dispatch);
+        match &result {
+            Ok(_) => (self.log)(&format!("{} by {}: ok", dispatch.name, caller)),
+            Err(e) => (self.log)(&format!("{} by {}: {}", dispatch.name, caller, e)),
+        }
+        result
+    }
+}
+
+// Timing reports how long every command took, whether it succeeded or not.
+pub struct Timing<F, C = SystemClock> where
+    F: Fn(&'static str, Duration),
+    C: Clock,
+{
+    report: F,
+    clock: C,
+}
+
+impl<F> Timing<F> where
+    F: Fn(&'static str, Duration),
+{
+    pub fn new(report: F) -> Timing<F> {
+        Timing::with_clock(report, SystemClock)
+    }
+}
+
+impl<F, C> Timing<F, C> where
+    F: Fn(&'static str, Duration),
+    C: Clock,
+{
+    pub fn with_clock(report: F, clock: C) -> Timing<F, C> {
+        Timing {
+            report,
+            clock,
+        }
+    }
+}
+
+impl<F, C> Middleware for Timing<F, C> where
+    F: Fn(&'static str, Duration),
+    C: Clock,
+{
+    fn handle(&self, dispatch: &Dispatch, next: &mut Next) -> Result<Output, Error> {
+        let start = self.clock.now();
+        let result = next.run(dispatch);
+        (self.report)(dispatch.name, self.clock.now() - start);
+        result
+    }
+}
+
+// RetryOnConflict runs a command again when it fails with a conflict, e.g. because someone else
+// changed the same chatroom in the meantime, backing off between attempts like we do with
+// SoundCloud. Any other error is returned straight away.
+pub struct RetryOnConflict<C = SystemClock> where
+    C: Clock,
+{
+    policy: RetryPolicy,
+    clock: C,
+}
+
+impl RetryOnConflict {
+    pub fn new(policy: RetryPolicy) -> RetryOnConflict {
+        RetryOnConflict::with_clock(policy, SystemClock)
+    }
+}
+
+impl<C> RetryOnConflict<C> where
+    C: Clock,
+{
+    pub fn with_clock(policy: RetryPolicy, clock: C) -> RetryOnConflict<C> {
+        RetryOnConflict {
+            policy,
+            clock,
+        }
+    }
+}
+
+impl<C> Middleware for RetryOnConflict<C> where
+    C: Clock,
+{
+    fn handle(&self, dispatch: &Dispatch, next: &mut Next) -> Result<Output, Error> {
+        let mut attempt = 0;
+        loop {
+            match next.run(dispatch) {
+                Err(Error::Conflict(_)) if attempt < self.policy.max_retries => {
+                    self.clock.sleep(self.policy.backoff(attempt));
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Authenticate, Authorize, Logging, RetryOnConflict, Timing};
+    use crate::error::Error;
+    use crate::services::abstractions::Handles;
+    use crate::services::bus::{Command, CommandBus, Context};
+    use crate::soundcloud_api::RetryPolicy;
+    use crate::test_tools::MockClock;
+    use crate::user::UserID;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    struct Rename {
+        user_id: UserID,
+    }
+
+    impl Command for Rename {
+        type Output = ();
+
+        fn name(&self) -> &'static str {
+            "rename"
+        }
+
+        fn actor(&self) -> Option<UserID> {
+            Some(self.user_id)
+        }
+    }
+
+    // Flaky fails with a conflict until it has been called `conflicts` times, taking a second
+    // each time.
+    struct Flaky {
+        conflicts: u32,
+        calls: Rc<RefCell<u32>>,
+        clock: MockClock,
+    }
+
+    impl Handles<Rename> for Flaky {
+        type Result = Result<(), Error>;
+
+        fn handle(&mut self, _: Rename) -> Self::Result {
+            self.clock.advance(Duration::from_secs(1));
+            *self.calls.borrow_mut() += 1;
+            if *self.calls.borrow() <= self.conflicts {
+                return Err(Error::Conflict("renamed concurrently".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    fn new_test_bus(conflicts: u32, clock: &MockClock) -> (CommandBus, Rc<RefCell<u32>>) {
+        let calls = Rc::new(RefCell::new(0));
+        let mut bus = CommandBus::new();
+        bus.register(Flaky { conflicts, calls: calls.clone(), clock: clock.clone() });
+        (bus, calls)
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn users_can_only_act_as_themselves() {
+        let (mut bus, calls) = new_test_bus(0, &MockClock::new());
+        bus.add_middleware(Authenticate);
+        bus.add_middleware(Authorize);
+
+        match bus.dispatch(Rename { user_id: 1 }, &Context::anonymous()) {
+            Err(Error::Unauthenticated) => {},
+            other => panic!("expected the command to need a login, got {:?}", other),
+        }
+        match bus.dispatch(Rename { user_id: 1 }, &Context::user(2)) {
+            Err(Error::Forbidden(_)) => {},
+            other => panic!("expected the command to be forbidden, got {:?}", other),
+        }
+        assert_eq!(*calls.borrow(), 0);
+
+        bus.dispatch(Rename { user_id: 1 }, &Context::user(1)).unwrap();
+        assert_eq!(*calls.borrow(), 1);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn conflicts_are_retried_with_backoff() {
+        let clock = MockClock::new();
+        let (mut bus, calls) = new_test_bus(2, &clock);
+        let policy = RetryPolicy::new(2, Duration::from_millis(100), Duration::from_secs(1));
+        bus.add_middleware(RetryOnConflict::with_clock(policy, clock.clone()));
+
+        bus.dispatch(Rename { user_id: 1 }, &Context::user(1)).unwrap();
+
+        assert_eq!(*calls.borrow(), 3);
+        assert_eq!(clock.sleeps().len(), 2);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn retries_are_bounded() {
+        let clock = MockClock::new();
+        let (mut bus, calls) = new_test_bus(5, &clock);
+        bus.add_middleware(RetryOnConflict::with_clock(RetryPolicy::new(2, Duration::from_millis(1), Duration::from_millis(1)), clock.clone()));
+
+        match bus.dispatch(Rename { user_id: 1 }, &Context::user(1)) {
+            Err(Error::Conflict(_)) => {},
+            other => panic!("expected to give up on the conflict, got {:?}", other),
+        }
+        assert_eq!(*calls.borrow(), 3);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn commands_are_logged_and_timed() {
+        let clock = MockClock::new();
+        let (mut bus, _) = new_test_bus(1, &clock);
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let timings = Rc::new(RefCell::new(Vec::new()));
+        let (log, report) = (lines.clone(), timings.clone());
+        bus.add_middleware(Logging::new(move |line| log.borrow_mut().push(line.to_string())));
+        bus.add_middleware(Timing::with_clock(move |name, took| report.borrow_mut().push((name, took)), clock.clone()));
+
+        bus.dispatch(Rename { user_id: 1 }, &Context::user(1));
+        bus.dispatch(Rename { user_id: 1 }, &Context::anonymous()).unwrap();
+
+        let want = vec![
+            "rename by user 1: conflict: renamed concurrently".to_string(),
+            "rename by anonymous: ok".to_string(),
+        ];
+        assert_eq!(*lines.borrow(), want);
+        assert_eq!(*timings.borrow(), vec![("rename", Duration::from_secs(1)), ("rename", Duration::from_secs(1))]);
+    }
+}
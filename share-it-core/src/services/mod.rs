@@ -1,3 +1,8 @@
 mod handlers;
+pub use handlers::{ChatroomHandler, UserHandler};
+
 pub mod commands;
 pub mod abstractions;
+pub mod views;
+pub mod bus;
+pub mod middleware;
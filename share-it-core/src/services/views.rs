@@ -0,0 +1,251 @@
+use crate::chatroom::{Chatroom, ChatUser};
+use crate::playlist::Playlist;
+use crate::repositories::abstractions::Repository;
+use crate::song::Song;
+use crate::user::{LinkedIdentity, User, UserID, Username};
+use crate::waitlist::{Waitlist, DJ};
+
+// Views are what handlers hand back to callers. They're plain data with a stable JSON shape, so
+// the API can send them as they are, and the domain types are free to change underneath them.
+// Ulids are sent as their canonical string.
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserView {
+    pub id: UserID,
+    pub username: Username,
+    pub avatar_url: String,
+    pub permalink_url: String,
+    pub active_playlist: Option<String>,
+    // Ordered by id, which is oldest first.
+    pub playlists: Vec<PlaylistView>,
+    pub identities: Vec<IdentityView>,
+}
+
+// IdentityView leaves out the provider's id for the account, which callers have no use for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdentityView {
+    pub provider: String,
+    pub username: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistView {
+    pub id: String,
+    pub name: String,
+    // In play order.
+    pub songs: Vec<SongView>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SongView {
+    pub id: u32,
+    pub title: String,
+    // The SoundCloud user who uploaded the song.
+    pub username: String,
+    pub duration_ms: u32,
+    pub permalink_url: String,
+    pub artwork_url: Option<String>,
+    pub stream_url: String,
+    // One of available, deleted or private.
+    pub availability: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatroomView {
+    pub id: String,
+    pub name: String,
+    pub moderator: UserID,
+    pub users: Vec<ChatUserView>,
+    pub waitlist: WaitlistView,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatUserView {
+    pub id: UserID,
+    pub username: Username,
+}
+
+// NowPlayingView is what a chatroom moved on to. No song means the waitlist ran dry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NowPlayingView {
+    pub song: Option<SongView>,
+    pub waitlist: WaitlistView,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaitlistView {
+    // The DJ whose song is playing, if any.
+    pub current_dj: Option<UserID>,
+    // In turn order, starting with the current DJ.
+    pub djs: Vec<ChatUserView>,
+}
+
+impl From<&User> for UserView {
+    fn from(user: &User) -> Self {
+        let mut playlists: Vec<&Playlist> = user.playlists().collect();
+        playlists.sort_by_key(|playlist| playlist.id());
+
+        UserView {
+            id: user.id(),
+            username: user.username(),
+            avatar_url: user.avatar_url(),
+            permalink_url: user.permalink_url(),
+            active_playlist: user.active_playlist().map(|id| id.to_string()),
+            playlists: playlists.into_iter().map(PlaylistView::from).collect(),
+            identities: user.identities().iter().map(IdentityView::from).collect(),
+        }
+    }
+}
+
+impl From<&LinkedIdentity> for IdentityView {
+    fn from(identity: &LinkedIdentity) -> Self {
+        IdentityView {
+            provider: identity.provider.clone(),
+            username: identity.username.clone(),
+        }
+    }
+}
+
+impl From<&Playlist> for PlaylistView {
+    fn from(playlist: &Playlist) -> Self {
+        PlaylistView {
+            id: playlist.id().to_string(),
+            name: playlist.name(),
+            songs: playlist.songs().map(SongView::from).collect(),
+        }
+    }
+}
+
+impl From<&Song> for SongView {
+    fn from(song: &Song) -> Self {
+        SongView {
+            id: song.id(),
+            title: song.title(),
+            username: song.username(),
+            duration_ms: song.duration_ms(),
+            permalink_url: song.permalink_url(),
+            artwork_url: song.artwork_url(),
+            stream_url: song.stream_url(),
+            availability: song.availability().as_str().to_string(),
+        }
+    }
+}
+
+impl<T> From<&Chatroom<T>> for ChatroomView where
+    T: Repository<u32, User>,
+{
+    fn from(chatroom: &Chatroom<T>) -> Self {
+        ChatroomView {
+            id: chatroom.id().to_string(),
+            name: chatroom.name(),
+            moderator: chatroom.moderator(),
+            users: chatroom.current_users().iter().map(ChatUserView::from).collect(),
+            waitlist: WaitlistView::from(chatroom.waitlist()),
+        }
+    }
+}
+
+impl From<&ChatUser> for ChatUserView {
+    fn from(user: &ChatUser) -> Self {
+        ChatUserView {
+            id: user.0,
+            username: user.1.clone(),
+        }
+    }
+}
+
+impl From<&DJ> for ChatUserView {
+    fn from(dj: &DJ) -> Self {
+        ChatUserView {
+            id: dj.0,
+            username: dj.1.clone(),
+        }
+    }
+}
+
+impl<T> From<&Waitlist<T>> for WaitlistView where
+    T: Repository<u32, User>,
+{
+    fn from(waitlist: &Waitlist<T>) -> Self {
+        WaitlistView {
+            current_dj: waitlist.current_dj().map(|dj| dj.id()),
+            djs: waitlist.djs().iter().map(ChatUserView::from).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChatroomView, SongView, UserView};
+    use crate::chatroom::{Chatroom, ChatUser};
+    use crate::song::Availability;
+    use crate::test_tools::factories::{new_test_playlist, new_test_song, new_test_user};
+    use crate::MockUserRepository;
+    use rusty_ulid::Ulid;
+    use serde_json::json;
+
+    #[test]
+    #[allow(unused)]
+    fn songs_serialize_to_a_stable_shape() {
+        let mut song = new_test_song(1, 2);
+        song.set_availability(Availability::Private);
+
+        let want = json!({
+            "id": 1,
+            "title": song.title(),
+            "username": song.username(),
+            "duration_ms": song.duration_ms(),
+            "permalink_url": song.permalink_url(),
+            "artwork_url": song.artwork_url(),
+            "stream_url": song.stream_url(),
+            "availability": "private",
+        });
+        assert_eq!(serde_json::to_value(SongView::from(&song)).unwrap(), want);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn chatrooms_serialize_to_a_stable_shape() {
+        let mut chatroom = Chatroom::new(MockUserRepository::new(), 1, "room".to_string());
+        chatroom.join(ChatUser(1, "moderator".to_string()));
+        chatroom.join(ChatUser(2, "dj".to_string()));
+        chatroom.join_waitlist(2);
+
+        let view = ChatroomView::from(&chatroom);
+        let want = json!({
+            "id": chatroom.id().to_string(),
+            "name": "room",
+            "moderator": 1,
+            "users": [
+                { "id": 1, "username": "moderator" },
+                { "id": 2, "username": "dj" },
+            ],
+            "waitlist": {
+                "current_dj": null,
+                "djs": [{ "id": 2, "username": "dj" }],
+            },
+        });
+        assert_eq!(serde_json::to_value(&view).unwrap(), want);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn users_list_playlists_oldest_first() {
+        let mut user = new_test_user(1);
+        let playlists = vec![new_test_playlist(1, 2), new_test_playlist(1, 1), new_test_playlist(1, 3)];
+        let mut ids: Vec<Ulid> = playlists.iter().map(|p| p.id()).collect();
+        ids.sort();
+        for playlist in playlists {
+            user.add_playlist(playlist);
+        }
+
+        let view = UserView::from(&user);
+
+        let got: Vec<String> = view.playlists.iter().map(|p| p.id.clone()).collect();
+        let want: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        assert_eq!(got, want);
+
+        let json = serde_json::to_string(&view).unwrap();
+        assert_eq!(serde_json::from_str::<UserView>(&json).unwrap(), view);
+    }
+}
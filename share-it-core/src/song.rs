@@ -1,6 +1,6 @@
 use crate::SoundcloudTrack;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Song {
     id: u32,
     user_id: u32,
@@ -13,6 +13,104 @@ pub struct Song {
     permalink_url: String,
     artwork_url: Option<String>,
     stream_url: String,
+    metadata: Option<SongMetadata>,
+    availability: Availability,
+}
+
+// Availability is whether a song can still be streamed from SoundCloud. Tracks get deleted or
+// made private after users add them to playlists, so this is revalidated in the background.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Availability {
+    Available,
+    // SoundCloud no longer has the track.
+    Deleted,
+    // The track still exists, but we aren't allowed to stream it.
+    Private,
+}
+
+impl Availability {
+    pub fn is_available(&self) -> bool {
+        *self == Availability::Available
+    }
+
+    // as_str is how availability is stored, e.g. in a database column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Availability::Available => "available",
+            Availability::Deleted => "deleted",
+            Availability::Private => "private",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Availability> {
+        match s {
+            "available" => Some(Availability::Available),
+            "deleted" => Some(Availability::Deleted),
+            "private" => Some(Availability::Private),
+            _ => None,
+        }
+    }
+}
+
+impl From<&SoundcloudTrack> for Availability {
+    fn from(s_track: &SoundcloudTrack) -> Self {
+        if s_track.sharing != "public" || s_track.streamable == Some(false) {
+            Availability::Private
+        } else {
+            Availability::Available
+        }
+    }
+}
+
+// SongMetadata holds the descriptive parts of a SoundCloud track that aren't needed for playback,
+// but are useful for room rules, search and recommendations. Every field is optional because
+// SoundCloud frequently leaves them null.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct SongMetadata {
+    pub created_at: Option<String>,
+    pub genre: Option<String>,
+    pub tag_list: Option<String>,
+    pub bpm: Option<f32>,
+    pub key_signature: Option<String>,
+    pub license: Option<String>,
+    pub waveform_url: Option<String>,
+    pub streamable: Option<bool>,
+    pub playback_count: Option<u32>,
+    pub download_count: Option<u32>,
+    pub favoritings_count: Option<u32>,
+    pub comment_count: Option<u32>,
+}
+
+impl SongMetadata {
+    // tags splits the raw SoundCloud tag_list into individual tags. SoundCloud separates tags with
+    // spaces, and wraps multi-word tags in double quotes.
+    pub fn tags(&self) -> Vec<String> {
+        let tag_list = match &self.tag_list {
+            Some(tag_list) => tag_list,
+            None => return Vec::new(),
+        };
+
+        let mut tags = Vec::new();
+        let mut current = String::new();
+        let mut in_quotes = false;
+        for c in tag_list.chars() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                ' ' if !in_quotes => {
+                    if !current.is_empty() {
+                        tags.push(current.clone());
+                        current.clear();
+                    }
+                },
+                _ => current.push(c),
+            }
+        }
+        if !current.is_empty() {
+            tags.push(current);
+        }
+
+        tags
+    }
 }
 
 impl Song {
@@ -36,17 +134,92 @@ impl Song {
             permalink: permalink,
             permalink_url: permalink_url,
             artwork_url: artwork_url,
-            stream_url: stream_url
+            stream_url: stream_url,
+            metadata: None,
+            availability: Availability::Available,
         }
     }
 
     pub fn id(&self) -> u32 {
         self.id
     }
+
+    // user_id is the SoundCloud id of the user who uploaded the song.
+    pub fn user_id(&self) -> u32 {
+        self.user_id
+    }
+
+    pub fn duration_ms(&self) -> u32 {
+        self.duration_ms
+    }
+
+    pub fn username(&self) -> String {
+        self.username.clone()
+    }
+
+    pub fn sharing(&self) -> String {
+        self.sharing.clone()
+    }
+
+    pub fn permalink(&self) -> String {
+        self.permalink.clone()
+    }
+
+    pub fn permalink_url(&self) -> String {
+        self.permalink_url.clone()
+    }
+
+    pub fn artwork_url(&self) -> Option<String> {
+        self.artwork_url.clone()
+    }
+
+    pub fn stream_url(&self) -> String {
+        self.stream_url.clone()
+    }
+
+    pub fn metadata(&self) -> Option<&SongMetadata> {
+        self.metadata.as_ref()
+    }
+
+    pub fn set_metadata(&mut self, metadata: SongMetadata) {
+        self.metadata = Some(metadata);
+    }
+
+    pub fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    pub fn availability(&self) -> Availability {
+        self.availability
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.availability.is_available()
+    }
+
+    pub fn set_availability(&mut self, availability: Availability) {
+        self.availability = availability;
+    }
 }
 
 impl From<SoundcloudTrack> for Song {
     fn from(s_track: SoundcloudTrack) -> Self {
+        let availability = Availability::from(&s_track);
+        let metadata = SongMetadata {
+            created_at: s_track.created_at,
+            genre: s_track.genre,
+            tag_list: s_track.tag_list,
+            bpm: s_track.bpm,
+            key_signature: s_track.key_signature,
+            license: s_track.license,
+            waveform_url: s_track.waveform_url,
+            streamable: s_track.streamable,
+            playback_count: s_track.playback_count,
+            download_count: s_track.download_count,
+            favoritings_count: s_track.favoritings_count,
+            comment_count: s_track.comment_count,
+        };
+
         Song {
             id: s_track.id,
             user_id: s_track.user_id,
@@ -58,6 +231,8 @@ impl From<SoundcloudTrack> for Song {
             stream_url: s_track.stream_url,
             permalink_url: s_track.permalink_url,
             permalink: s_track.permalink,
+            metadata: Some(metadata),
+            availability,
         }
     }
 }
@@ -65,8 +240,9 @@ impl From<SoundcloudTrack> for Song {
 
 #[cfg(test)]
 mod tests {
-    use super::Song;
+    use super::{Song, SongMetadata, Availability};
     use crate::SoundcloudTrack;
+    use crate::test_tools::fixtures::TRACK_JSON;
 
     #[test]
     fn mapping_from_soundcloud_track_works() {
@@ -127,6 +303,61 @@ mod tests {
         let s = Song::from(s_track.clone());
 
         assert_eq!(s.id, 13158665);
+
+        let metadata = s.metadata().unwrap();
+        assert_eq!(metadata.license, Some("all-rights-reserved".to_string()));
+        assert_eq!(metadata.waveform_url, Some("https://w1.sndcdn.com/fxguEjG4ax6B_m.png".to_string()));
+        assert_eq!(metadata.streamable, Some(true));
+        assert_eq!(metadata.genre, None);
+        assert_eq!(metadata.bpm, None);
+    }
+
+    #[test]
+    fn private_tracks_map_to_unavailable_songs() {
+        let mut s_track: SoundcloudTrack = serde_json::from_str(TRACK_JSON).unwrap();
+        assert!(Song::from(s_track.clone()).is_available());
+
+        s_track.sharing = "private".to_string();
+        assert_eq!(Song::from(s_track.clone()).availability(), Availability::Private);
+
+        s_track.sharing = "public".to_string();
+        s_track.streamable = Some(false);
+        assert_eq!(Song::from(s_track).availability(), Availability::Private);
+    }
+
+    #[test]
+    fn metadata_tags_are_split() {
+        let metadata = SongMetadata {
+            tag_list: Some(r#"house "deep house" soundcloud:source=iphone-record"#.to_string()),
+            ..SongMetadata::default()
+        };
+
+        let want = vec![
+            "house".to_string(),
+            "deep house".to_string(),
+            "soundcloud:source=iphone-record".to_string(),
+        ];
+        assert_eq!(metadata.tags(), want);
+        assert!(SongMetadata::default().tags().is_empty());
+    }
+
+    #[test]
+    fn availability_round_trips_through_str() {
+        for availability in &[Availability::Available, Availability::Deleted, Availability::Private] {
+            assert_eq!(Availability::parse(availability.as_str()), Some(*availability));
+        }
+        assert_eq!(Availability::parse("gone"), None);
+    }
+
+    #[test]
+    fn metadata_round_trips_through_json() {
+        let s_track: SoundcloudTrack = serde_json::from_str(TRACK_JSON).unwrap();
+        let song = Song::from(s_track);
+        let metadata = song.metadata().unwrap();
+
+        let json = serde_json::to_string(metadata).unwrap();
+        let restored: SongMetadata = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(&restored, metadata);
     }
-    
 }
@@ -0,0 +1,404 @@
+use crate::clock::{Clock, SystemClock};
+use crate::song::Availability;
+use crate::soundcloud_api::client::SoundcloudClient;
+use crate::soundcloud_api::responses::{SoundcloudTrack, SoundcloudUser, SoundcloudPlaylist, SoundcloudResource};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+// CacheConfig bounds how much, and for how long, we remember SoundCloud lookups.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    // The most entries kept per cache before the least recently used is evicted.
+    pub capacity: usize,
+    // How long a track we found stays fresh.
+    pub ttl: Duration,
+    // How long we remember that a track didn't exist. Kept shorter than `ttl`, since a missing
+    // track is more likely to show up later than a found one is to change.
+    pub negative_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            capacity: 10_000,
+            ttl: Duration::from_secs(60 * 60),
+            negative_ttl: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+struct Entry<V> {
+    value: V,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+// LruCache is a size bounded map whose entries also expire. Recency is tracked with a
+// monotonically increasing tick, indexed in a BTreeMap so the least recently used entry is
+// always the first one.
+pub struct LruCache<K, V> where
+    K: Eq + Hash + Clone,
+{
+    capacity: usize,
+    entries: HashMap<K, Entry<V>>,
+    recency: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K, V> LruCache<K, V> where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    pub fn new(capacity: usize) -> LruCache<K, V> {
+        LruCache {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    // get returns the value if present and not yet expired, marking it as recently used.
+    // Expired entries are dropped on the way.
+    pub fn get(&mut self, key: &K, now: Instant) -> Option<V> {
+        let expired = match self.entries.get(key) {
+            Some(entry) => entry.expires_at <= now,
+            None => return None,
+        };
+        if expired {
+            self.remove(key);
+            return None;
+        }
+
+        let tick = self.next_tick();
+        let entry = self.entries.get_mut(key).unwrap();
+        self.recency.remove(&entry.last_used);
+        self.recency.insert(tick, key.clone());
+        entry.last_used = tick;
+        Some(entry.value.clone())
+    }
+
+    pub fn insert(&mut self, key: K, value: V, expires_at: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            self.evict_least_recently_used();
+        }
+
+        let tick = self.next_tick();
+        self.recency.insert(tick, key.clone());
+        self.entries.insert(key, Entry { value, expires_at, last_used: tick });
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self.recency.keys().next().cloned();
+        if let Some(tick) = oldest {
+            let key = self.recency.remove(&tick).unwrap();
+            self.entries.remove(&key);
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+// CachingSoundcloudClient sits in front of another SoundcloudClient and remembers track lookups,
+// both by track id and by permalink url. Tracks SoundCloud says don't exist are remembered too
+// (for `negative_ttl`), so repeatedly adding a dead link doesn't keep hitting the api.
+//
+// Only tracks are cached. Users, playlists and non track permalinks pass straight through, though
+// tracks found through search are used to warm the cache.
+pub struct CachingSoundcloudClient<S, C = SystemClock> where
+    S: SoundcloudClient,
+    C: Clock,
+{
+    inner: S,
+    clock: C,
+    config: CacheConfig,
+    tracks: LruCache<u32, Option<SoundcloudTrack>>,
+    permalinks: LruCache<String, Option<u32>>,
+    stats: CacheStats,
+}
+
+impl<S> CachingSoundcloudClient<S, SystemClock> where
+    S: SoundcloudClient,
+{
+    pub fn new(inner: S, config: CacheConfig) -> CachingSoundcloudClient<S, SystemClock> {
+        CachingSoundcloudClient::with_clock(inner, config, SystemClock)
+    }
+}
+
+impl<S, C> CachingSoundcloudClient<S, C> where
+    S: SoundcloudClient,
+    C: Clock,
+{
+    pub fn with_clock(inner: S, config: CacheConfig, clock: C) -> CachingSoundcloudClient<S, C> {
+        CachingSoundcloudClient {
+            inner,
+            clock,
+            tracks: LruCache::new(config.capacity),
+            permalinks: LruCache::new(config.capacity),
+            config,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    // invalidate_track forgets everything we know about a track, forcing the next lookup through
+    // to SoundCloud.
+    pub fn invalidate_track(&mut self, track_id: u32) {
+        self.tracks.remove(&track_id);
+    }
+
+    fn cache_track(&mut self, track_id: u32, track: Option<SoundcloudTrack>) {
+        let expires_at = self.expiry(track.is_some());
+        if let Some(t) = &track {
+            self.permalinks.insert(t.permalink_url.clone(), Some(t.id), expires_at);
+        }
+        self.tracks.insert(track_id, track, expires_at);
+    }
+
+    fn expiry(&self, found: bool) -> Instant {
+        let ttl = if found { self.config.ttl } else { self.config.negative_ttl };
+        self.clock.now() + ttl
+    }
+}
+
+impl<S, C> SoundcloudClient for CachingSoundcloudClient<S, C> where
+    S: SoundcloudClient,
+    C: Clock,
+{
+    type Error = S::Error;
+
+    fn resolve(&mut self, permalink_url: &str) -> Result<Option<SoundcloudResource>, Self::Error> {
+        let now = self.clock.now();
+        match self.permalinks.get(&permalink_url.to_string(), now) {
+            Some(None) => {
+                self.stats.hits += 1;
+                return Ok(None);
+            },
+            Some(Some(track_id)) => {
+                // The permalink is only useful if the track it points at is still cached.
+                if let Some(Some(track)) = self.tracks.get(&track_id, now) {
+                    self.stats.hits += 1;
+                    return Ok(Some(SoundcloudResource::Track(track)));
+                }
+            },
+            None => (),
+        }
+        self.stats.misses += 1;
+
+        let resource = self.inner.resolve(permalink_url)?;
+        match &resource {
+            Some(SoundcloudResource::Track(track)) => {
+                let track = track.clone();
+                let expires_at = self.expiry(true);
+                self.permalinks.insert(permalink_url.to_string(), Some(track.id), expires_at);
+                self.cache_track(track.id, Some(track));
+            },
+            None => {
+                let expires_at = self.expiry(false);
+                self.permalinks.insert(permalink_url.to_string(), None, expires_at);
+            },
+            _ => (),
+        }
+
+        Ok(resource)
+    }
+
+    fn track(&mut self, track_id: u32) -> Result<Option<SoundcloudTrack>, Self::Error> {
+        if let Some(cached) = self.tracks.get(&track_id, self.clock.now()) {
+            self.stats.hits += 1;
+            return Ok(cached);
+        }
+        self.stats.misses += 1;
+
+        let track = self.inner.track(track_id)?;
+        self.cache_track(track_id, track.clone());
+        Ok(track)
+    }
+
+    fn user(&mut self, user_id: u32) -> Result<Option<SoundcloudUser>, Self::Error> {
+        self.inner.user(user_id)
+    }
+
+    fn playlist(&mut self, playlist_id: u32) -> Result<Option<SoundcloudPlaylist>, Self::Error> {
+        self.inner.playlist(playlist_id)
+    }
+
+    fn search_tracks(&mut self, query: &str) -> Result<Vec<SoundcloudTrack>, Self::Error> {
+        let tracks = self.inner.search_tracks(query)?;
+        for track in &tracks {
+            self.cache_track(track.id, Some(track.clone()));
+        }
+        Ok(tracks)
+    }
+
+    fn me(&mut self, access_token: &str) -> Result<SoundcloudUser, Self::Error> {
+        self.inner.me(access_token)
+    }
+
+    fn track_availability(&mut self, track_id: u32) -> Result<Availability, Self::Error> {
+        // Availability checks exist to catch tracks changing underneath us, so they always go
+        // through to SoundCloud, and whatever is cached for the track is stale from here on.
+        self.invalidate_track(track_id);
+        self.inner.track_availability(track_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheConfig, CacheStats, CachingSoundcloudClient, LruCache};
+    use crate::soundcloud_api::{SoundcloudClient, SoundcloudResource};
+    use crate::test_tools::{MockClock, MockSoundcloudClient};
+    use crate::clock::Clock;
+    use std::time::Duration;
+
+    const TRACK_ID: u32 = 13158665;
+    const TRACK_PERMALINK: &str = "https://soundcloud.com/user2835985/munching-at-tiannas-house";
+
+    fn test_config() -> CacheConfig {
+        CacheConfig {
+            capacity: 10,
+            ttl: Duration::from_secs(60),
+            negative_ttl: Duration::from_secs(10),
+        }
+    }
+
+    #[test]
+    fn lru_cache_evicts_least_recently_used() {
+        let clock = MockClock::new();
+        let expires = clock.now() + Duration::from_secs(60);
+        let mut cache = LruCache::new(2);
+
+        cache.insert(1, "one", expires);
+        cache.insert(2, "two", expires);
+        // Touch 1, so 2 becomes the least recently used.
+        cache.get(&1, clock.now());
+        cache.insert(3, "three", expires);
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&1, clock.now()), Some("one"));
+        assert_eq!(cache.get(&2, clock.now()), None);
+        assert_eq!(cache.get(&3, clock.now()), Some("three"));
+    }
+
+    #[test]
+    fn lru_cache_expires_entries() {
+        let clock = MockClock::new();
+        let mut cache = LruCache::new(2);
+
+        cache.insert(1, "one", clock.now() + Duration::from_secs(5));
+        clock.advance(Duration::from_secs(5));
+
+        assert_eq!(cache.get(&1, clock.now()), None);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn repeat_track_lookups_are_hits() {
+        let mut client = CachingSoundcloudClient::with_clock(MockSoundcloudClient::with_fixtures(), test_config(), MockClock::new());
+
+        assert!(client.track(TRACK_ID).unwrap().is_some());
+        assert!(client.track(TRACK_ID).unwrap().is_some());
+        assert!(client.track(TRACK_ID).unwrap().is_some());
+
+        assert_eq!(client.stats(), CacheStats { hits: 2, misses: 1 });
+    }
+
+    #[test]
+    fn tracks_expire_after_ttl() {
+        let clock = MockClock::new();
+        let mut client = CachingSoundcloudClient::with_clock(MockSoundcloudClient::with_fixtures(), test_config(), clock.clone());
+
+        client.track(TRACK_ID).unwrap();
+        clock.advance(Duration::from_secs(61));
+        client.track(TRACK_ID).unwrap();
+
+        assert_eq!(client.stats(), CacheStats { hits: 0, misses: 2 });
+    }
+
+    #[test]
+    fn missing_tracks_are_negatively_cached() {
+        let clock = MockClock::new();
+        let mut client = CachingSoundcloudClient::with_clock(MockSoundcloudClient::new(), test_config(), clock.clone());
+
+        assert!(client.track(1).unwrap().is_none());
+        assert!(client.track(1).unwrap().is_none());
+        assert_eq!(client.stats(), CacheStats { hits: 1, misses: 1 });
+
+        // Negative entries live for negative_ttl, not ttl.
+        clock.advance(Duration::from_secs(11));
+        assert!(client.track(1).unwrap().is_none());
+        assert_eq!(client.stats(), CacheStats { hits: 1, misses: 2 });
+    }
+
+    #[test]
+    fn permalink_and_id_share_cached_tracks() {
+        let mut client = CachingSoundcloudClient::with_clock(MockSoundcloudClient::with_fixtures(), test_config(), MockClock::new());
+
+        match client.resolve(TRACK_PERMALINK).unwrap() {
+            Some(SoundcloudResource::Track(t)) => assert_eq!(t.id, TRACK_ID),
+            _ => panic!("expected permalink to resolve to a track"),
+        }
+        // Both of these are served from what the resolve cached.
+        assert!(client.track(TRACK_ID).unwrap().is_some());
+        assert!(client.resolve(TRACK_PERMALINK).unwrap().is_some());
+
+        assert_eq!(client.stats(), CacheStats { hits: 2, misses: 1 });
+    }
+
+    #[test]
+    fn unknown_permalinks_are_negatively_cached() {
+        let mut client = CachingSoundcloudClient::with_clock(MockSoundcloudClient::new(), test_config(), MockClock::new());
+
+        assert!(client.resolve("https://soundcloud.com/nobody/nothing").unwrap().is_none());
+        assert!(client.resolve("https://soundcloud.com/nobody/nothing").unwrap().is_none());
+
+        assert_eq!(client.stats(), CacheStats { hits: 1, misses: 1 });
+    }
+
+    #[test]
+    fn hit_ratio() {
+        assert_eq!(CacheStats::default().hit_ratio(), 0.0);
+        assert_eq!(CacheStats { hits: 3, misses: 1 }.hit_ratio(), 0.75);
+    }
+}
@@ -0,0 +1,267 @@
+use crate::soundcloud_api::responses::{SoundcloudTrack, SoundcloudUser, SoundcloudPlaylist, SoundcloudResource};
+use crate::soundcloud_api::error::SoundcloudError;
+use crate::soundcloud_api::rate_limit::TokenBucket;
+use crate::soundcloud_api::retry::RetryPolicy;
+use crate::clock::{Clock, SystemClock};
+use crate::song::Availability;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{AUTHORIZATION, RETRY_AFTER};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+pub const SOUNDCLOUD_API_URL: &str = "https://api.soundcloud.com";
+
+pub trait SoundcloudClient {
+    /// An error that communicates that something went wrong when talking to SoundCloud.
+    type Error: std::error::Error + std::fmt::Display + 'static + Send;
+
+    /// Resolves a soundcloud.com permalink url (for a track, user or playlist) to the api resource
+    /// it points at.
+    ///
+    /// If SoundCloud doesn't know about the permalink, then [`None`] is returned.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with SoundCloud, then an error is returned.
+    ///
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    fn resolve(&mut self, permalink_url: &str) -> Result<Option<SoundcloudResource>, Self::Error>;
+
+    /// Fetches the track with the supplied id, returning [`None`] if it doesn't exist.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with SoundCloud, then an error is returned.
+    ///
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    fn track(&mut self, track_id: u32) -> Result<Option<SoundcloudTrack>, Self::Error>;
+
+    /// Fetches the user with the supplied id, returning [`None`] if it doesn't exist.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with SoundCloud, then an error is returned.
+    ///
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    fn user(&mut self, user_id: u32) -> Result<Option<SoundcloudUser>, Self::Error>;
+
+    /// Fetches the playlist with the supplied id, returning [`None`] if it doesn't exist.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with SoundCloud, then an error is returned.
+    ///
+    /// [`None`]: https://doc.rust-lang.org/std/option/enum.Option.html#variant.None
+    fn playlist(&mut self, playlist_id: u32) -> Result<Option<SoundcloudPlaylist>, Self::Error>;
+
+    /// Searches SoundCloud for tracks matching the query. No matches is an empty list.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with SoundCloud, then an error is returned.
+    fn search_tracks(&mut self, query: &str) -> Result<Vec<SoundcloudTrack>, Self::Error>;
+
+    /// Fetches the SoundCloud user that the supplied OAuth access token was issued to.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with SoundCloud, or it rejects the token, then an error is returned.
+    fn me(&mut self, access_token: &str) -> Result<SoundcloudUser, Self::Error>;
+
+    /// Checks whether the track with the supplied id can still be streamed. A track that no
+    /// longer exists is `Availability::Deleted`.
+    ///
+    /// # Failure case
+    ///
+    /// If we fail to communicate with SoundCloud, then an error is returned.
+    fn track_availability(&mut self, track_id: u32) -> Result<Availability, Self::Error> {
+        match self.track(track_id)? {
+            Some(track) => Ok(Availability::from(&track)),
+            None => Ok(Availability::Deleted),
+        }
+    }
+}
+
+// SoundcloudClientConfig tunes how hard we are willing to lean on SoundCloud.
+#[derive(Debug, Clone)]
+pub struct SoundcloudClientConfig {
+    // How many requests we may fire back to back before being paced.
+    pub burst: u32,
+    // Sustained requests per second once the burst is used up. Must be positive.
+    pub requests_per_second: f64,
+    pub retry: RetryPolicy,
+}
+
+impl Default for SoundcloudClientConfig {
+    fn default() -> Self {
+        SoundcloudClientConfig {
+            burst: 10,
+            requests_per_second: 5.0,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+// HttpSoundcloudClient talks to the real SoundCloud api over HTTP. The base url can be swapped out
+// so we can point it at a local stub server in tests.
+//
+// Every request first takes a token from the rate limiter. Rate limited and transient server
+// failures are retried with jittered exponential backoff, honouring any Retry-After SoundCloud
+// sends back, as long as it's within the retry policy's `max_delay`. All waiting happens on the
+// supplied clock.
+pub struct HttpSoundcloudClient<C = SystemClock> where
+    C: Clock,
+{
+    http: Client,
+    base_url: String,
+    client_id: String,
+    clock: C,
+    limiter: TokenBucket,
+    retry: RetryPolicy,
+}
+
+impl HttpSoundcloudClient<SystemClock> {
+    pub fn new(client_id: String) -> HttpSoundcloudClient<SystemClock> {
+        HttpSoundcloudClient::with_base_url(SOUNDCLOUD_API_URL.to_string(), client_id)
+    }
+
+    pub fn with_base_url(base_url: String, client_id: String) -> HttpSoundcloudClient<SystemClock> {
+        HttpSoundcloudClient::with_config(base_url, client_id, SoundcloudClientConfig::default(), SystemClock)
+    }
+}
+
+impl<C> HttpSoundcloudClient<C> where
+    C: Clock,
+{
+    pub fn with_config(base_url: String,
+                       client_id: String,
+                       config: SoundcloudClientConfig,
+                       clock: C) -> HttpSoundcloudClient<C> {
+        let limiter = TokenBucket::new(config.burst, config.requests_per_second, clock.now());
+        HttpSoundcloudClient {
+            http: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client_id,
+            clock,
+            limiter,
+            retry: config.retry,
+        }
+    }
+
+    // get performs a GET against the given api path, and deserializes the body. A 404 is not an
+    // error, it just means SoundCloud doesn't have what we asked for.
+    fn get<R>(&mut self, path: &str, query: &[(&str, &str)]) -> Result<Option<R>, SoundcloudError> where
+        R: DeserializeOwned,
+    {
+        match self.send(path, query, None) {
+            Ok(response) => Ok(Some(parse_body(response)?)),
+            Err(SoundcloudError::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // send performs the request, retrying for as long as the failure is retryable and the retry
+    // policy allows.
+    fn send(&mut self, path: &str, query: &[(&str, &str)], access_token: Option<&str>) -> Result<Response, SoundcloudError> {
+        let mut attempt = 0;
+        loop {
+            self.limiter.acquire(&self.clock);
+
+            let err = match self.send_once(path, query, access_token) {
+                Ok(response) => return Ok(response),
+                Err(e) => e,
+            };
+
+            if !err.is_retryable() || attempt >= self.retry.max_retries {
+                return Err(err);
+            }
+
+            let delay = match err.retry_after() {
+                // Waiting that long would stall our caller, so we leave it up to them instead.
+                Some(retry_after) if retry_after > self.retry.max_delay => return Err(err),
+                Some(retry_after) => retry_after,
+                None => self.retry.backoff(attempt),
+            };
+            self.clock.sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    // send_once performs a single request. Requests made on behalf of a user carry their OAuth
+    // access token, everything else identifies us with our client id.
+    fn send_once(&self, path: &str, query: &[(&str, &str)], access_token: Option<&str>) -> Result<Response, SoundcloudError> {
+        let mut request = self.http.get(&format!("{}{}", self.base_url, path));
+        request = match access_token {
+            Some(token) => request.header(AUTHORIZATION, format!("OAuth {}", token)),
+            None => request.query(&[("client_id", self.client_id.as_str())]),
+        };
+        let response = request.query(query).send()?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        Err(SoundcloudError::from_status(status.as_u16(), retry_after(&response)))
+    }
+}
+
+// retry_after reads the Retry-After header. SoundCloud sends it as a number of seconds; the
+// HTTP-date form isn't used by them, so we don't try to parse it.
+fn retry_after(response: &Response) -> Option<Duration> {
+    response.headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+fn parse_body<R>(response: Response) -> Result<R, SoundcloudError> where
+    R: DeserializeOwned,
+{
+    let body = response.text()?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+impl<C> SoundcloudClient for HttpSoundcloudClient<C> where
+    C: Clock,
+{
+    type Error = SoundcloudError;
+
+    fn resolve(&mut self, permalink_url: &str) -> Result<Option<SoundcloudResource>, Self::Error> {
+        // SoundCloud answers /resolve with a redirect to the resource itself, which reqwest follows.
+        self.get("/resolve", &[("url", permalink_url)])
+    }
+
+    fn track(&mut self, track_id: u32) -> Result<Option<SoundcloudTrack>, Self::Error> {
+        self.get(&format!("/tracks/{}", track_id), &[])
+    }
+
+    fn user(&mut self, user_id: u32) -> Result<Option<SoundcloudUser>, Self::Error> {
+        self.get(&format!("/users/{}", user_id), &[])
+    }
+
+    fn playlist(&mut self, playlist_id: u32) -> Result<Option<SoundcloudPlaylist>, Self::Error> {
+        self.get(&format!("/playlists/{}", playlist_id), &[])
+    }
+
+    fn search_tracks(&mut self, query: &str) -> Result<Vec<SoundcloudTrack>, Self::Error> {
+        let tracks: Option<Vec<SoundcloudTrack>> = self.get("/tracks", &[("q", query)])?;
+        Ok(tracks.unwrap_or_default())
+    }
+
+    fn me(&mut self, access_token: &str) -> Result<SoundcloudUser, Self::Error> {
+        let response = self.send("/me", &[], Some(access_token))?;
+        parse_body(response)
+    }
+
+    fn track_availability(&mut self, track_id: u32) -> Result<Availability, Self::Error> {
+        // SoundCloud answers with a 403 for tracks that have been made private.
+        match self.track(track_id) {
+            Ok(Some(track)) => Ok(Availability::from(&track)),
+            Ok(None) => Ok(Availability::Deleted),
+            Err(SoundcloudError::Forbidden) => Ok(Availability::Private),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::error;
+use std::time::Duration;
+
+// SoundcloudError is everything that can go wrong talking to the SoundCloud api. Clients turn
+// `NotFound` into `Ok(None)` at the trait boundary, so callers mostly see the other variants.
+#[derive(Debug)]
+pub enum SoundcloudError {
+    // We never got a usable response back (connection refused, timeout, bad url etc.).
+    Http(reqwest::Error),
+    // 404. The resource doesn't exist, or has been deleted.
+    NotFound,
+    // 401. Our credentials were missing, invalid or expired.
+    Unauthorized,
+    // 403. The resource exists, but we aren't allowed to see it (private, geo blocked etc.).
+    Forbidden,
+    // 429. We are being rate limited, and kept being rate limited after retrying.
+    RateLimited { retry_after: Option<Duration> },
+    // 5xx. SoundCloud is having trouble, and kept having trouble after retrying.
+    Server(u16),
+    // SoundCloud answered with a status code we don't know how to handle.
+    Status(u16),
+    // SoundCloud answered, but the body wasn't the shape we expected.
+    Deserialize(serde_json::Error),
+}
+
+impl SoundcloudError {
+    // from_status classifies an unsuccessful response status into an error.
+    pub fn from_status(status: u16, retry_after: Option<Duration>) -> SoundcloudError {
+        match status {
+            401 => SoundcloudError::Unauthorized,
+            403 => SoundcloudError::Forbidden,
+            404 => SoundcloudError::NotFound,
+            429 => SoundcloudError::RateLimited { retry_after },
+            500..=599 => SoundcloudError::Server(status),
+            _ => SoundcloudError::Status(status),
+        }
+    }
+
+    // is_retryable returns true for failures that are likely to go away on their own, so the
+    // request is worth trying again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SoundcloudError::Http(e) => e.is_timeout() || e.is_request(),
+            SoundcloudError::RateLimited { .. } => true,
+            SoundcloudError::Server(status) => *status != 501,
+            _ => false,
+        }
+    }
+
+    // retry_after is how long SoundCloud asked us to wait before trying again, if it told us.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            SoundcloudError::RateLimited { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SoundcloudError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SoundcloudError::Http(e) => write!(f, "failed to reach soundcloud: {}", e),
+            SoundcloudError::NotFound => write!(f, "soundcloud resource not found"),
+            SoundcloudError::Unauthorized => write!(f, "soundcloud rejected our credentials"),
+            SoundcloudError::Forbidden => write!(f, "soundcloud resource is forbidden"),
+            SoundcloudError::RateLimited { retry_after: Some(after) } => write!(f, "rate limited by soundcloud, retry after {:?}", after),
+            SoundcloudError::RateLimited { retry_after: None } => write!(f, "rate limited by soundcloud"),
+            SoundcloudError::Server(status) => write!(f, "soundcloud server error {}", status),
+            SoundcloudError::Status(status) => write!(f, "soundcloud responded with unexpected status {}", status),
+            SoundcloudError::Deserialize(e) => write!(f, "failed to deserialize soundcloud response: {}", e),
+        }
+    }
+}
+
+impl error::Error for SoundcloudError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SoundcloudError::Http(e) => Some(e),
+            SoundcloudError::Deserialize(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for SoundcloudError {
+    fn from(e: reqwest::Error) -> Self {
+        SoundcloudError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for SoundcloudError {
+    fn from(e: serde_json::Error) -> Self {
+        SoundcloudError::Deserialize(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SoundcloudError;
+    use std::time::Duration;
+
+    #[test]
+    fn statuses_are_classified() {
+        assert!(matches!(SoundcloudError::from_status(401, None), SoundcloudError::Unauthorized));
+        assert!(matches!(SoundcloudError::from_status(403, None), SoundcloudError::Forbidden));
+        assert!(matches!(SoundcloudError::from_status(404, None), SoundcloudError::NotFound));
+        assert!(matches!(SoundcloudError::from_status(503, None), SoundcloudError::Server(503)));
+        assert!(matches!(SoundcloudError::from_status(418, None), SoundcloudError::Status(418)));
+
+        let limited = SoundcloudError::from_status(429, Some(Duration::from_secs(3)));
+        assert_eq!(limited.retry_after(), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn only_transient_errors_are_retryable() {
+        assert!(SoundcloudError::from_status(429, None).is_retryable());
+        assert!(SoundcloudError::from_status(503, None).is_retryable());
+        assert!(!SoundcloudError::from_status(403, None).is_retryable());
+        assert!(!SoundcloudError::from_status(404, None).is_retryable());
+        assert!(!SoundcloudError::from_status(418, None).is_retryable());
+    }
+}
@@ -1,2 +1,17 @@
 pub mod responses;
 pub use responses::*;
+
+pub mod error;
+pub use error::*;
+
+pub mod client;
+pub use client::*;
+
+pub mod rate_limit;
+pub use rate_limit::*;
+
+pub mod retry;
+pub use retry::*;
+
+pub mod cache;
+pub use cache::*;
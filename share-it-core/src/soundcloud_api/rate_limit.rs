@@ -0,0 +1,109 @@
+use crate::clock::Clock;
+use std::time::{Duration, Instant};
+
+// TokenBucket is a classic token bucket rate limiter. It holds up to `capacity` tokens, refills at
+// `refill_per_sec` tokens a second, and every outbound request costs one token. Bursts up to the
+// capacity go straight through, after which callers are paced to the refill rate.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    // new panics unless refill_per_sec is a positive, finite rate, as a bucket that never refills
+    // would leave callers waiting forever.
+    pub fn new(capacity: u32, refill_per_sec: f64, now: Instant) -> TokenBucket {
+        assert!(
+            refill_per_sec > 0.0 && refill_per_sec.is_finite(),
+            "token buckets must refill at a positive rate, got {} tokens a second", refill_per_sec,
+        );
+        TokenBucket {
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            refill_per_sec,
+            last_refill: now,
+        }
+    }
+
+    // acquire takes a token from the bucket, sleeping on the supplied clock until one is
+    // available if the bucket is currently empty.
+    pub fn acquire<C: Clock>(&mut self, clock: &C) {
+        self.refill(clock.now());
+        if self.tokens < 1.0 {
+            let wait = (1.0 - self.tokens) / self.refill_per_sec;
+            clock.sleep(Duration::from_secs_f64(wait));
+            self.refill(clock.now());
+        }
+        self.tokens = (self.tokens - 1.0).max(0.0);
+    }
+
+    pub fn available(&self) -> u32 {
+        self.tokens as u32
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now <= self.last_refill {
+            return;
+        }
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenBucket;
+    use crate::clock::Clock;
+    use crate::test_tools::MockClock;
+    use std::time::Duration;
+
+    #[test]
+    fn bursts_up_to_capacity_without_waiting() {
+        let clock = MockClock::new();
+        let mut bucket = TokenBucket::new(3, 1.0, clock.now());
+
+        bucket.acquire(&clock);
+        bucket.acquire(&clock);
+        bucket.acquire(&clock);
+
+        assert!(clock.sleeps().is_empty());
+        assert_eq!(bucket.available(), 0);
+    }
+
+    #[test]
+    fn waits_for_refill_once_empty() {
+        let clock = MockClock::new();
+        let mut bucket = TokenBucket::new(1, 2.0, clock.now());
+
+        bucket.acquire(&clock);
+        bucket.acquire(&clock);
+
+        // Two tokens a second means the second request waits half a second.
+        assert_eq!(clock.sleeps(), vec![Duration::from_millis(500)]);
+    }
+
+    #[test]
+    fn refills_while_idle() {
+        let clock = MockClock::new();
+        let mut bucket = TokenBucket::new(2, 1.0, clock.now());
+
+        bucket.acquire(&clock);
+        bucket.acquire(&clock);
+        clock.advance(Duration::from_secs(10));
+        bucket.acquire(&clock);
+
+        assert!(clock.sleeps().is_empty());
+        // Refill never goes past capacity.
+        assert_eq!(bucket.available(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "token buckets must refill at a positive rate")]
+    fn buckets_that_never_refill_are_rejected() {
+        TokenBucket::new(3, 0.0, MockClock::new().now());
+    }
+}
@@ -20,12 +20,66 @@ pub struct SoundcloudTrack {
     pub artwork_url: Option<String>,
     pub stream_url: String,
     pub user: SoundcloudUser,
+    // Everything below is optional metadata. SoundCloud regularly sends these back as null (or
+    // leaves them out entirely), so they must never fail deserialization.
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub genre: Option<String>,
+    #[serde(default)]
+    pub tag_list: Option<String>,
+    #[serde(default)]
+    pub bpm: Option<f32>,
+    #[serde(default)]
+    pub key_signature: Option<String>,
+    #[serde(default)]
+    pub license: Option<String>,
+    #[serde(default)]
+    pub waveform_url: Option<String>,
+    #[serde(default)]
+    pub streamable: Option<bool>,
+    #[serde(default)]
+    pub playback_count: Option<u32>,
+    #[serde(default)]
+    pub download_count: Option<u32>,
+    #[serde(default)]
+    pub favoritings_count: Option<u32>,
+    #[serde(default)]
+    pub comment_count: Option<u32>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SoundcloudPlaylist {
+    pub id: u32,
+    pub user_id: u32,
+    #[serde(rename = "duration")]
+    pub duration_ms: u32,
+    pub sharing: String,
+    pub title: String,
+    pub permalink: String,
+    pub permalink_url: String,
+    pub artwork_url: Option<String>,
+    pub user: SoundcloudUser,
+    #[serde(default)]
+    pub track_count: Option<u32>,
+    #[serde(default)]
+    pub tracks: Vec<SoundcloudTrack>,
+}
+
+// SoundcloudResource is what the /resolve endpoint hands back. A permalink url can point at a
+// track, a user or a playlist, and SoundCloud tells us which through the `kind` field.
+#[derive(Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SoundcloudResource {
+    Track(SoundcloudTrack),
+    User(SoundcloudUser),
+    Playlist(SoundcloudPlaylist),
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SoundcloudUser;
-    use super::SoundcloudTrack;
+    use super::{SoundcloudUser, SoundcloudTrack, SoundcloudPlaylist, SoundcloudResource};
+    use crate::test_tools::fixtures::{PLAYLIST_JSON, TRACK_JSON};
 
     #[test]
     fn mapping_from_user_api_response_works() {
@@ -119,5 +173,72 @@ mod tests {
         let t: SoundcloudTrack = serde_json::from_str(mock_api_response).unwrap();
 
         assert_eq!(t.id, 13158665);
+        assert_eq!(t.created_at, Some("2011/04/06 15:37:43 +0000".to_string()));
+        assert_eq!(t.tag_list, Some("soundcloud:source=iphone-record".to_string()));
+        assert_eq!(t.license, Some("all-rights-reserved".to_string()));
+        assert_eq!(t.waveform_url, Some("https://w1.sndcdn.com/fxguEjG4ax6B_m.png".to_string()));
+        assert_eq!(t.streamable, Some(true));
+        assert_eq!(t.playback_count, Some(0));
+        assert_eq!(t.genre, None);
+        assert_eq!(t.bpm, None);
+        assert_eq!(t.key_signature, None);
+    }
+
+    #[test]
+    fn mapping_from_track_api_response_without_metadata_works() {
+        let mock_api_response = r#"
+        {
+            "id": 13158665,
+            "user_id": 3699101,
+            "duration": 18109,
+            "sharing": "public",
+            "permalink": "munching-at-tiannas-house",
+            "title": "Munching at Tiannas house",
+            "permalink_url": "https://soundcloud.com/user2835985/munching-at-tiannas-house",
+            "artwork_url": null,
+            "user": {
+              "id": 3699101,
+              "permalink": "user2835985",
+              "username": "user2835985",
+              "uri": "https://api.soundcloud.com/users/3699101",
+              "permalink_url": "https://soundcloud.com/user2835985",
+              "avatar_url": "https://a1.sndcdn.com/images/default_avatar_large.png?142a848"
+            },
+            "stream_url": "https://api.soundcloud.com/tracks/13158665/stream",
+            "playback_count": null,
+            "streamable": null
+          }"#;
+
+        let t: SoundcloudTrack = serde_json::from_str(mock_api_response).unwrap();
+
+        assert_eq!(t.id, 13158665);
+        assert_eq!(t.created_at, None);
+        assert_eq!(t.playback_count, None);
+        assert_eq!(t.streamable, None);
+    }
+
+    #[test]
+    fn mapping_from_playlist_api_response_works() {
+        let p: SoundcloudPlaylist = serde_json::from_str(PLAYLIST_JSON).unwrap();
+
+        assert_eq!(p.id, 405726);
+        assert_eq!(p.track_count, Some(1));
+        assert_eq!(p.tracks.len(), 1);
+        assert_eq!(p.tracks[0].id, 13158665);
+    }
+
+    #[test]
+    fn mapping_resource_by_kind_works() {
+        let r: SoundcloudResource = serde_json::from_str(TRACK_JSON).unwrap();
+        match r {
+            SoundcloudResource::Track(t) => assert_eq!(t.id, 13158665),
+            _ => panic!("expected a track resource"),
+        }
+
+        let r: SoundcloudResource = serde_json::from_str(PLAYLIST_JSON).unwrap();
+        match r {
+            SoundcloudResource::Playlist(p) => assert_eq!(p.id, 405726),
+            _ => panic!("expected a playlist resource"),
+        }
     }
 }
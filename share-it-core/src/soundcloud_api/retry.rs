@@ -0,0 +1,76 @@
+use rand::Rng;
+use std::time::Duration;
+
+// RetryPolicy decides how often, and how long to wait between, retries of a failed SoundCloud
+// call. Delays grow exponentially from `base_delay`, are capped at `max_delay`, and are jittered
+// so that many clients backing off at once don't all retry in lock step.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, base_delay: Duration, max_delay: Duration) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            base_delay,
+            max_delay,
+        }
+    }
+
+    // no_retries gives up on the first failure.
+    pub fn no_retries() -> RetryPolicy {
+        RetryPolicy::new(0, Duration::from_millis(0), Duration::from_millis(0))
+    }
+
+    // backoff returns how long to wait before the given retry attempt (zero based). The result is
+    // picked at random between half and all of the capped exponential delay.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self.ceiling(attempt);
+        let half = ceiling / 2;
+        let jitter = rand::thread_rng().gen_range(0.0, 1.0);
+        half + half.mul_f64(jitter)
+    }
+
+    fn ceiling(&self, attempt: u32) -> Duration {
+        // Past 2^16 the cap has long since kicked in, so avoid overflowing the multiplier.
+        let multiplier = 2u32.saturating_pow(attempt.min(16));
+        self.base_delay.checked_mul(multiplier)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(3, Duration::from_millis(500), Duration::from_secs(30))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_grows_exponentially_with_jitter() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(100), Duration::from_secs(60));
+
+        for attempt in 0..5 {
+            let ceiling = Duration::from_millis(100 * 2u64.pow(attempt));
+            let delay = policy.backoff(attempt);
+            assert!(delay >= ceiling / 2, "attempt {} waited {:?}", attempt, delay);
+            assert!(delay <= ceiling, "attempt {} waited {:?}", attempt, delay);
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = RetryPolicy::new(50, Duration::from_secs(1), Duration::from_secs(8));
+
+        assert!(policy.backoff(40) <= Duration::from_secs(8));
+        assert!(policy.backoff(40) >= Duration::from_secs(4));
+    }
+}
@@ -0,0 +1,399 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::chatroom::{Chatroom, ChatUser};
+use crate::playlist::Playlist;
+use crate::repositories::abstractions::{Repository, StaleVersion, Versioned};
+use crate::song::Song;
+use crate::tokens::{OauthToken, StoredToken, TokenCipher, TokenKey};
+use crate::user::{LinkedIdentity, User, UserID};
+use rusty_ulid::Ulid;
+
+// The conformance suite checks a repository keeps the contract documented on Repository: insert
+// returns None for a key already in use, update and remove return None for a missing key, update
+// rejects stale versions, and so on. Every backend runs the same suite, so they all behave exactly
+// alike.
+//
+// The easiest way to run it is with repository_conformance_tests!, e.g.
+//
+//     mod conformance {
+//         use super::*;
+//         repository_conformance_tests!(SqliteUsers::in_memory(), UserEntities::new());
+//     }
+//
+// The suite is safe to run against a shared database. Every entity it makes has a key nothing
+// else uses, and it only touches the entities it made.
+
+// Entities makes the entities the suite stores, and knows how to tell them apart.
+pub trait Entities<K, V> {
+    // entity makes a new entity, with a key not handed out before.
+    fn entity(&mut self) -> V;
+
+    fn key(&self, entity: &V) -> K;
+
+    // modify changes everything about the entity the repository stores, other than its key.
+    fn modify(&mut self, entity: &mut V);
+
+    // assert_same panics unless got is what the repository should have returned for want.
+    fn assert_same(&self, want: &V, got: &V);
+}
+
+pub fn insert_returns_the_key<K, V, R, E>(repo: &mut R, entities: &mut E) where
+    K: PartialEq + Debug,
+    R: Repository<K, V>,
+    E: Entities<K, V>,
+{
+    let entity = entities.entity();
+
+    assert_eq!(repo.insert(&entity).unwrap(), Some(entities.key(&entity)));
+    entities.assert_same(&entity, &repo.get(&entities.key(&entity)).unwrap().expect("inserted entity is missing"));
+}
+
+pub fn insert_returns_none_for_duplicates<K, V, R, E>(repo: &mut R, entities: &mut E) where
+    K: PartialEq + Debug,
+    R: Repository<K, V>,
+    E: Entities<K, V>,
+{
+    let entity = entities.entity();
+    repo.insert(&entity).unwrap();
+
+    let mut duplicate = repo.get(&entities.key(&entity)).unwrap().unwrap();
+    entities.modify(&mut duplicate);
+    assert_eq!(repo.insert(&duplicate).unwrap(), None);
+
+    // The duplicate must not have overwritten anything either.
+    entities.assert_same(&entity, &repo.get(&entities.key(&entity)).unwrap().unwrap());
+}
+
+pub fn get_returns_none_when_missing<K, V, R, E>(repo: &mut R, entities: &mut E) where
+    K: PartialEq + Debug,
+    R: Repository<K, V>,
+    E: Entities<K, V>,
+{
+    let entity = entities.entity();
+
+    assert!(repo.get(&entities.key(&entity)).unwrap().is_none());
+    assert!(!repo.contains(&entities.key(&entity)).unwrap());
+}
+
+pub fn update_replaces_the_entity<K, V, R, E>(repo: &mut R, entities: &mut E) where
+    K: PartialEq + Debug,
+    R: Repository<K, V>,
+    E: Entities<K, V>,
+{
+    let entity = entities.entity();
+    repo.insert(&entity).unwrap();
+
+    let mut entity = repo.get(&entities.key(&entity)).unwrap().unwrap();
+    entities.modify(&mut entity);
+    assert_eq!(repo.update(&entity).unwrap(), Some(entities.key(&entity)));
+    let stored = repo.get(&entities.key(&entity)).unwrap().unwrap();
+    entities.assert_same(&entity, &stored);
+
+    // Updating with nothing changed still finds the entity.
+    assert_eq!(repo.update(&stored).unwrap(), Some(entities.key(&entity)));
+}
+
+pub fn update_rejects_stale_versions<K, V, R, E>(repo: &mut R, entities: &mut E) where
+    K: PartialEq + Debug,
+    V: Versioned,
+    R: Repository<K, V>,
+    E: Entities<K, V>,
+{
+    let entity = entities.entity();
+    repo.insert(&entity).unwrap();
+    let mut ours = repo.get(&entities.key(&entity)).unwrap().unwrap();
+    let mut theirs = repo.get(&entities.key(&entity)).unwrap().unwrap();
+
+    entities.modify(&mut theirs);
+    repo.update(&theirs).unwrap();
+    let stored = repo.get(&entities.key(&entity)).unwrap().unwrap();
+    assert!(stored.version() > ours.version());
+
+    entities.modify(&mut ours);
+    let e = repo.update(&ours).expect_err("stale update was accepted");
+    assert!(StaleVersion::find(&e).is_some(), "expected a stale version, got {}", e);
+    // Their changes must have survived.
+    entities.assert_same(&stored, &repo.get(&entities.key(&entity)).unwrap().unwrap());
+}
+
+pub fn update_returns_none_when_missing<K, V, R, E>(repo: &mut R, entities: &mut E) where
+    K: PartialEq + Debug,
+    R: Repository<K, V>,
+    E: Entities<K, V>,
+{
+    let entity = entities.entity();
+
+    assert_eq!(repo.update(&entity).unwrap(), None);
+    // Update never inserts.
+    assert!(repo.get(&entities.key(&entity)).unwrap().is_none());
+}
+
+pub fn remove_returns_the_key_once<K, V, R, E>(repo: &mut R, entities: &mut E) where
+    K: PartialEq + Debug,
+    R: Repository<K, V>,
+    E: Entities<K, V>,
+{
+    let entity = entities.entity();
+    let key = entities.key(&entity);
+    repo.insert(&entity).unwrap();
+    assert!(repo.contains(&key).unwrap());
+
+    assert_eq!(repo.remove(&key).unwrap(), Some(entities.key(&entity)));
+    assert!(!repo.contains(&key).unwrap());
+    assert_eq!(repo.remove(&key).unwrap(), None);
+
+    // Once removed, the key is free to be inserted again.
+    assert_eq!(repo.insert(&entity).unwrap(), Some(entities.key(&entity)));
+}
+
+pub fn entities_are_independent<K, V, R, E>(repo: &mut R, entities: &mut E) where
+    K: PartialEq + Debug,
+    R: Repository<K, V>,
+    E: Entities<K, V>,
+{
+    let first = entities.entity();
+    let second = entities.entity();
+    repo.insert(&first).unwrap();
+    repo.insert(&second).unwrap();
+
+    let mut second = repo.get(&entities.key(&second)).unwrap().unwrap();
+    entities.modify(&mut second);
+    repo.update(&second).unwrap();
+    entities.assert_same(&first, &repo.get(&entities.key(&first)).unwrap().unwrap());
+
+    repo.remove(&entities.key(&second)).unwrap();
+    entities.assert_same(&first, &repo.get(&entities.key(&first)).unwrap().unwrap());
+}
+
+// Keys the suite hands out. They start high to stay clear of anything a test database already
+// has in it.
+static NEXT_ID: AtomicU32 = AtomicU32::new(3_000_000_000);
+
+fn next_id() -> u32 {
+    NEXT_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+// UserEntities makes users with playlists, songs and linked identities.
+pub struct UserEntities;
+
+impl UserEntities {
+    pub fn new() -> UserEntities {
+        UserEntities
+    }
+}
+
+impl Default for UserEntities {
+    fn default() -> Self {
+        UserEntities::new()
+    }
+}
+
+impl Entities<UserID, User> for UserEntities {
+    fn entity(&mut self) -> User {
+        let user_id = next_id();
+        let mut user = User::new(
+            user_id,
+            format!("O'Brien {}", user_id),
+            "https://i1.sndcdn.com/avatar.jpg".to_string(),
+            format!("https://soundcloud.com/user-{}", user_id),
+        );
+
+        let mut playlist = Playlist::new("Favourites".to_string());
+        for _ in 0..2 {
+            playlist.add_song(conformance_song(user_id));
+        }
+        user.set_active_playlist(&playlist.id());
+        user.add_playlist(playlist);
+        user.link_identity(LinkedIdentity {
+            provider: "vimeo".to_string(),
+            subject: user_id.to_string(),
+            username: format!("vimeo {}", user_id),
+        });
+
+        user
+    }
+
+    fn key(&self, user: &User) -> UserID {
+        user.id()
+    }
+
+    fn modify(&mut self, user: &mut User) {
+        let mut playlist = Playlist::new("Another one".to_string());
+        playlist.add_song(conformance_song(user.id()));
+        user.set_active_playlist(&playlist.id());
+        user.add_playlist(playlist);
+        user.unlink_identity("vimeo");
+    }
+
+    // Versions are the repository's business, so they're left out.
+    fn assert_same(&self, want: &User, got: &User) {
+        let mut got = got.clone();
+        got.set_version(want.version());
+        assert_eq!(want, &got);
+    }
+}
+
+fn conformance_song(user_id: UserID) -> Song {
+    let song_id = next_id();
+    Song::new(
+        song_id,
+        user_id,
+        180_000,
+        format!("O'Brien {}", user_id),
+        format!("song {}", song_id),
+        "public".to_string(),
+        format!("song-{}", song_id),
+        format!("https://soundcloud.com/user-{}/song-{}", user_id, song_id),
+        None,
+        format!("https://api.soundcloud.com/tracks/{}/stream", song_id),
+    )
+}
+
+// ChatroomEntities makes chatrooms with people in them and on the waitlist. Nobody is playing, so
+// the user repository chatrooms are built with can be empty.
+pub struct ChatroomEntities<U> {
+    users: U,
+}
+
+impl<U> ChatroomEntities<U> where
+    U: Repository<u32, User> + Clone,
+{
+    pub fn new(users: U) -> ChatroomEntities<U> {
+        ChatroomEntities {
+            users,
+        }
+    }
+}
+
+impl<U> Entities<Ulid, Chatroom<U>> for ChatroomEntities<U> where
+    U: Repository<u32, User> + Clone,
+{
+    fn entity(&mut self) -> Chatroom<U> {
+        let moderator = next_id();
+        let mut chatroom = Chatroom::new(self.users.clone(), moderator, format!("O'Brien's room {}", moderator));
+        for user_id in &[moderator, next_id(), next_id()] {
+            chatroom.join(ChatUser(*user_id, format!("user {}", user_id)));
+            chatroom.join_waitlist(*user_id);
+        }
+
+        chatroom
+    }
+
+    fn key(&self, chatroom: &Chatroom<U>) -> Ulid {
+        chatroom.id()
+    }
+
+    fn modify(&mut self, chatroom: &mut Chatroom<U>) {
+        let newcomer = next_id();
+        chatroom.join(ChatUser(newcomer, format!("user {}", newcomer)));
+        chatroom.change_moderator(newcomer);
+
+        let first = chatroom.current_users()[0].0;
+        chatroom.leave_waitlist(first);
+        chatroom.leave(first);
+    }
+
+    fn assert_same(&self, want: &Chatroom<U>, got: &Chatroom<U>) {
+        assert_eq!(want.id(), got.id());
+        assert_eq!(want.name(), got.name());
+        assert_eq!(want.moderator(), got.moderator());
+        assert_eq!(want.current_users(), got.current_users());
+        assert_eq!(want.waitlist().id(), got.waitlist().id());
+        assert_eq!(want.waitlist_djs(), got.waitlist_djs());
+        assert_eq!(want.waitlist().current_dj(), got.waitlist().current_dj());
+        assert_eq!(want.waitlist().current_playlist(), got.waitlist().current_playlist());
+    }
+}
+
+// TokenEntities makes sealed tokens, for users nobody else has.
+pub struct TokenEntities {
+    cipher: TokenCipher,
+}
+
+impl TokenEntities {
+    pub fn new() -> TokenEntities {
+        TokenEntities {
+            cipher: TokenCipher::new(&[7; 32]),
+        }
+    }
+
+    fn seal(&self, user_id: UserID, access_token: String) -> StoredToken {
+        let token = OauthToken {
+            access_token,
+            refresh_token: Some(format!("refresh {}", user_id)),
+            expires_at: Some(1_600_000_000),
+        };
+        self.cipher.seal(user_id, "vimeo", &token).unwrap()
+    }
+}
+
+impl Default for TokenEntities {
+    fn default() -> Self {
+        TokenEntities::new()
+    }
+}
+
+impl Entities<TokenKey, StoredToken> for TokenEntities {
+    fn entity(&mut self) -> StoredToken {
+        let user_id = next_id();
+        self.seal(user_id, format!("access {}", user_id))
+    }
+
+    fn key(&self, token: &StoredToken) -> TokenKey {
+        token.key()
+    }
+
+    fn modify(&mut self, token: &mut StoredToken) {
+        *token = self.seal(token.user_id, format!("refreshed {}", token.user_id));
+    }
+
+    fn assert_same(&self, want: &StoredToken, got: &StoredToken) {
+        assert_eq!(want, got);
+    }
+}
+
+/// Generates a test for every check in the conformance suite.
+///
+/// `$repo` and `$entities` are evaluated afresh for every test, so each one starts with its own
+/// repository. A backend that isn't always available can `return` from `$repo` to skip the test.
+///
+/// Entities that aren't [`Versioned`] skip the stale version check, with `unversioned:` in front.
+///
+/// [`Versioned`]: repositories/abstractions/trait.Versioned.html
+#[macro_export]
+macro_rules! repository_conformance_tests {
+    (unversioned: $repo:expr, $entities:expr) => {
+        $crate::repository_conformance_tests!(@tests $repo, $entities;
+            insert_returns_the_key,
+            insert_returns_none_for_duplicates,
+            get_returns_none_when_missing,
+            update_replaces_the_entity,
+            update_returns_none_when_missing,
+            remove_returns_the_key_once,
+            entities_are_independent,
+        );
+    };
+    ($repo:expr, $entities:expr) => {
+        $crate::repository_conformance_tests!(@tests $repo, $entities;
+            insert_returns_the_key,
+            insert_returns_none_for_duplicates,
+            get_returns_none_when_missing,
+            update_replaces_the_entity,
+            update_rejects_stale_versions,
+            update_returns_none_when_missing,
+            remove_returns_the_key_once,
+            entities_are_independent,
+        );
+    };
+    (@tests $repo:expr, $entities:expr; $($check:ident,)*) => {
+        $(
+            #[test]
+            #[allow(unused)]
+            fn $check() {
+                let mut repo = $repo;
+                let mut entities = $entities;
+                $crate::test_tools::conformance::$check(&mut repo, &mut entities);
+            }
+        )*
+    };
+}
@@ -159,7 +159,7 @@ pub(crate) fn new_test_chatroom(spec: TestChatroomSpec) -> Chatroom<MockUserRepo
     for (i, user) in users.iter().enumerate() {
         chatroom.join(ChatUser(user.id(), user.username()));
         if waitlist_set.contains(&(i as u32 + 1)) {
-            chatroom.join_waitlist(user.id())
+            chatroom.join_waitlist(user.id());
         }
     }
 
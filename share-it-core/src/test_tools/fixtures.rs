@@ -0,0 +1,6 @@
+// Raw SoundCloud API responses, captured from the public API docs. These back the
+// MockSoundcloudClient, and are shared with the integration tests so the stub server
+// serves the exact same payloads.
+pub const TRACK_JSON: &str = include_str!("fixtures/track.json");
+pub const USER_JSON: &str = include_str!("fixtures/user.json");
+pub const PLAYLIST_JSON: &str = include_str!("fixtures/playlist.json");
@@ -1,24 +1,42 @@
 use std::fmt;
 use std::error;
 use std::collections::HashMap;
-use crate::user::User;
-use crate::repositories::abstractions::Repository;
+use crate::user::{User, UserID, PlaylistID};
+use crate::playlist::Playlist;
+use crate::repositories::abstractions::{Query, Repository, StaleVersion, Versioned};
+use crate::repositories::query::{paginate, Page, PageRequest, PlaylistFilter, UserFilter};
 use rusty_ulid::Ulid;
 use crate::waitlist::Waitlist;
+use crate::soundcloud_api::{SoundcloudClient, SoundcloudTrack, SoundcloudUser, SoundcloudPlaylist, SoundcloudResource};
+use crate::test_tools::fixtures::{TRACK_JSON, USER_JSON, PLAYLIST_JSON};
+use crate::clock::Clock;
+use crate::tokens::{StoredToken, TokenGrant, TokenKey, TokenRefresher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Debug, Clone)]
-pub struct MockError;
+pub enum MockError {
+    Failed,
+    // The mock user repository turns away stale updates, like the real ones.
+    Stale(StaleVersion),
+}
 
 impl fmt::Display for MockError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "this is a mock error")
+        match self {
+            MockError::Failed => write!(f, "this is a mock error"),
+            MockError::Stale(e) => write!(f, "this is a mock stale update: {}", e),
+        }
     }
 }
 
 impl error::Error for MockError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        // Generic error, underlying cause isn't tracked.
-        None
+        match self {
+            // Generic error, underlying cause isn't tracked.
+            MockError::Failed => None,
+            MockError::Stale(e) => Some(e),
+        }
     }
 }
 
@@ -61,14 +79,18 @@ impl Repository<u32, User> for MockUserRepository {
     }
 
     fn update(&mut self, entity: &User) -> Result<Option<u32>, Self::Error> {
-        let result = if self.contains(&entity.id()).unwrap() {
-            self.data.insert(entity.id(), entity.clone());
-            Some(entity.id())
-        } else {
-            None
+        let stored = match self.data.get(&entity.id()) {
+            Some(stored) => stored.version(),
+            None => return Ok(None),
         };
+        if stored != entity.version() {
+            return Err(MockError::Stale(StaleVersion { loaded: entity.version(), stored }));
+        }
 
-        Ok(result)
+        let mut user = entity.clone();
+        user.set_version(stored + 1);
+        self.data.insert(entity.id(), user);
+        Ok(Some(entity.id()))
     }
 
     fn remove(&mut self, key: &u32) -> Result<Option<u32>, Self::Error> {
@@ -81,48 +103,320 @@ impl Repository<u32, User> for MockUserRepository {
     }
 }
 
-impl Repository<u32, User> for &mut MockUserRepository {
-    // For ease of use in testing. Use real error type in production.
+impl Query<UserFilter, User> for MockUserRepository {
+    type Cursor = UserID;
     type Error = MockError;
 
-    fn insert(&mut self, entity: &User) -> Result<Option<u32>, Self::Error> {
-        let result = if self.contains(&entity.id()).unwrap() {
-            None
-        } else {
-            self.data.insert(entity.id(), entity.clone());
-            Some(entity.id())
-        };
+    fn query(&mut self, filter: &UserFilter, page: &PageRequest<UserID>) -> Result<Page<User, UserID>, MockError> {
+        let users = self.data.values()
+            .filter(|user| filter.matches(user))
+            .map(|user| (user.id(), user.clone()))
+            .collect();
 
-        Ok(result)
+        Ok(paginate(users, page))
     }
+}
 
-    fn get(&mut self, key: &u32) -> Result<Option<User>, Self::Error> {
-        let result = if let Some(user) = self.data.get(&key) {
-            Some(user.clone())
-        } else {
-            None
+impl Query<PlaylistFilter, Playlist> for MockUserRepository {
+    type Cursor = PlaylistID;
+    type Error = MockError;
+
+    fn query(&mut self, filter: &PlaylistFilter, page: &PageRequest<PlaylistID>) -> Result<Page<Playlist, PlaylistID>, MockError> {
+        let playlists = match self.data.get(&filter.user_id) {
+            Some(user) => user.playlists().map(|playlist| (playlist.id(), playlist.clone())).collect(),
+            None => Vec::new(),
         };
 
-        Ok(result)
+        Ok(paginate(playlists, page))
     }
+}
 
-    fn update(&mut self, entity: &User) -> Result<Option<u32>, Self::Error> {
-        let result = if self.contains(&entity.id()).unwrap() {
-            self.data.insert(entity.id(), entity.clone());
-            Some(entity.id())
-        } else {
-            None
-        };
+#[derive(Clone)]
+pub struct MockTokenRepository {
+    data: HashMap<TokenKey, StoredToken>
+}
 
-        Ok(result)
+impl MockTokenRepository {
+    pub fn new() -> MockTokenRepository {
+        MockTokenRepository {
+            data: HashMap::new(),
+        }
     }
+}
 
-    fn remove(&mut self, key: &u32) -> Result<Option<u32>, Self::Error> {
-        let result = self.data.remove(&key);
-        if let Some(user) = result {
-            Ok(Some(user.id()))
-        } else {
-            Ok(None)
+impl Default for MockTokenRepository {
+    fn default() -> Self {
+        MockTokenRepository::new()
+    }
+}
+
+impl Repository<TokenKey, StoredToken> for MockTokenRepository {
+    type Error = MockError;
+
+    fn insert(&mut self, entity: &StoredToken) -> Result<Option<TokenKey>, Self::Error> {
+        if self.data.contains_key(&entity.key()) {
+            return Ok(None);
+        }
+        self.data.insert(entity.key(), entity.clone());
+        Ok(Some(entity.key()))
+    }
+
+    fn get(&mut self, key: &TokenKey) -> Result<Option<StoredToken>, Self::Error> {
+        Ok(self.data.get(key).cloned())
+    }
+
+    fn update(&mut self, entity: &StoredToken) -> Result<Option<TokenKey>, Self::Error> {
+        if !self.data.contains_key(&entity.key()) {
+            return Ok(None);
+        }
+        self.data.insert(entity.key(), entity.clone());
+        Ok(Some(entity.key()))
+    }
+
+    fn remove(&mut self, key: &TokenKey) -> Result<Option<TokenKey>, Self::Error> {
+        Ok(self.data.remove(key).map(|stored| stored.key()))
+    }
+}
+
+// MockTokenRefresher hands out queued grants in order, and fails once it runs out. It records the
+// provider and refresh token of every refresh. Clones share the same state.
+#[derive(Clone)]
+pub struct MockTokenRefresher {
+    grants: Arc<Mutex<Vec<TokenGrant>>>,
+    refreshed: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl MockTokenRefresher {
+    pub fn new() -> MockTokenRefresher {
+        MockTokenRefresher {
+            grants: Arc::new(Mutex::new(Vec::new())),
+            refreshed: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn grant(&self, grant: TokenGrant) {
+        self.grants.lock().unwrap().push(grant);
+    }
+
+    pub fn refreshed(&self) -> Vec<(String, String)> {
+        self.refreshed.lock().unwrap().clone()
+    }
+}
+
+impl Default for MockTokenRefresher {
+    fn default() -> Self {
+        MockTokenRefresher::new()
+    }
+}
+
+impl TokenRefresher for MockTokenRefresher {
+    type Error = MockError;
+
+    fn refresh(&self, provider: &str, refresh_token: &str) -> Result<TokenGrant, Self::Error> {
+        self.refreshed.lock().unwrap().push((provider.to_string(), refresh_token.to_string()));
+
+        let mut grants = self.grants.lock().unwrap();
+        if grants.is_empty() {
+            return Err(MockError::Failed);
         }
+        Ok(grants.remove(0))
+    }
+}
+
+// MockSoundcloudClient is an in-process stand in for SoundCloud. It serves whatever tracks, users
+// and playlists it has been handed, and never touches the network.
+#[derive(Clone)]
+pub struct MockSoundcloudClient {
+    tracks: HashMap<u32, SoundcloudTrack>,
+    users: HashMap<u32, SoundcloudUser>,
+    playlists: HashMap<u32, SoundcloudPlaylist>,
+    // Which user each OAuth access token was issued to.
+    tokens: HashMap<String, u32>,
+}
+
+impl MockSoundcloudClient {
+    pub fn new() -> MockSoundcloudClient {
+        MockSoundcloudClient {
+            tracks: HashMap::new(),
+            users: HashMap::new(),
+            playlists: HashMap::new(),
+            tokens: HashMap::new(),
+        }
+    }
+
+    // with_fixtures returns a client preloaded with the JSON fixtures in test_tools/fixtures.
+    pub fn with_fixtures() -> MockSoundcloudClient {
+        let mut client = MockSoundcloudClient::new();
+        client.add_track(serde_json::from_str(TRACK_JSON).unwrap());
+        client.add_user(serde_json::from_str(USER_JSON).unwrap());
+        client.add_playlist(serde_json::from_str(PLAYLIST_JSON).unwrap());
+        client
+    }
+
+    pub fn add_track(&mut self, track: SoundcloudTrack) {
+        self.tracks.insert(track.id, track);
+    }
+
+    pub fn add_user(&mut self, user: SoundcloudUser) {
+        self.users.insert(user.id, user);
+    }
+
+    pub fn add_playlist(&mut self, playlist: SoundcloudPlaylist) {
+        self.playlists.insert(playlist.id, playlist);
+    }
+
+    pub fn remove_track(&mut self, track_id: u32) {
+        self.tracks.remove(&track_id);
+    }
+
+    // add_token issues an access token for the given user, so `me` recognises it.
+    pub fn add_token(&mut self, access_token: &str, user_id: u32) {
+        self.tokens.insert(access_token.to_string(), user_id);
+    }
+}
+
+impl Default for MockSoundcloudClient {
+    fn default() -> Self {
+        MockSoundcloudClient::new()
+    }
+}
+
+impl SoundcloudClient for MockSoundcloudClient {
+    // For ease of use in testing. Use real error type in production.
+    type Error = MockError;
+
+    fn resolve(&mut self, permalink_url: &str) -> Result<Option<SoundcloudResource>, Self::Error> {
+        if let Some(track) = self.tracks.values().find(|t| t.permalink_url == permalink_url) {
+            return Ok(Some(SoundcloudResource::Track(track.clone())));
+        }
+        if let Some(playlist) = self.playlists.values().find(|p| p.permalink_url == permalink_url) {
+            return Ok(Some(SoundcloudResource::Playlist(playlist.clone())));
+        }
+        if let Some(user) = self.users.values().find(|u| u.permalink_url == permalink_url) {
+            return Ok(Some(SoundcloudResource::User(user.clone())));
+        }
+
+        Ok(None)
+    }
+
+    fn track(&mut self, track_id: u32) -> Result<Option<SoundcloudTrack>, Self::Error> {
+        Ok(self.tracks.get(&track_id).cloned())
+    }
+
+    fn user(&mut self, user_id: u32) -> Result<Option<SoundcloudUser>, Self::Error> {
+        Ok(self.users.get(&user_id).cloned())
+    }
+
+    fn playlist(&mut self, playlist_id: u32) -> Result<Option<SoundcloudPlaylist>, Self::Error> {
+        Ok(self.playlists.get(&playlist_id).cloned())
+    }
+
+    fn search_tracks(&mut self, query: &str) -> Result<Vec<SoundcloudTrack>, Self::Error> {
+        let query = query.to_lowercase();
+        Ok(self.tracks.values()
+            .filter(|t| t.title.to_lowercase().contains(&query))
+            .cloned()
+            .collect())
+    }
+
+    fn me(&mut self, access_token: &str) -> Result<SoundcloudUser, Self::Error> {
+        self.tokens.get(access_token)
+            .and_then(|user_id| self.users.get(user_id))
+            .cloned()
+            .ok_or(MockError::Failed)
+    }
+}
+
+// MockClock is a fake clock that only moves when told to. Sleeping returns immediately, advances
+// the clock and records how long the caller asked to sleep. Clones share the same time.
+#[derive(Clone)]
+pub struct MockClock {
+    start: Instant,
+    start_system: SystemTime,
+    elapsed: Arc<Mutex<Duration>>,
+    sleeps: Arc<Mutex<Vec<Duration>>>,
+}
+
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock {
+            start: Instant::now(),
+            start_system: SystemTime::now(),
+            elapsed: Arc::new(Mutex::new(Duration::from_secs(0))),
+            sleeps: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.sleeps.lock().unwrap().clone()
+    }
+
+    pub fn total_slept(&self) -> Duration {
+        self.sleeps().iter().sum()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start_system + *self.elapsed.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.sleeps.lock().unwrap().push(duration);
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MockSoundcloudClient;
+    use crate::soundcloud_api::{SoundcloudClient, SoundcloudResource};
+
+    #[test]
+    fn mock_soundcloud_client_serves_fixtures() {
+        let mut client = MockSoundcloudClient::with_fixtures();
+
+        assert_eq!(client.track(13158665).unwrap().unwrap().id, 13158665);
+        assert_eq!(client.user(3207).unwrap().unwrap().id, 3207);
+        assert_eq!(client.playlist(405726).unwrap().unwrap().id, 405726);
+        assert!(client.track(1).unwrap().is_none());
+    }
+
+    #[test]
+    fn mock_soundcloud_client_resolves_and_searches() {
+        let mut client = MockSoundcloudClient::with_fixtures();
+
+        let resolved = client.resolve("https://soundcloud.com/jwagener").unwrap();
+        match resolved {
+            Some(SoundcloudResource::User(u)) => assert_eq!(u.id, 3207),
+            _ => panic!("expected the fixture user to resolve"),
+        }
+        assert!(client.resolve("https://soundcloud.com/nobody").unwrap().is_none());
+
+        assert_eq!(client.search_tracks("munching").unwrap().len(), 1);
+        assert!(client.search_tracks("nothing like this").unwrap().is_empty());
+    }
+
+    #[test]
+    fn mock_soundcloud_client_knows_token_owners() {
+        let mut client = MockSoundcloudClient::with_fixtures();
+        client.add_token("test_token", 3207);
+
+        assert_eq!(client.me("test_token").unwrap().id, 3207);
+        assert!(client.me("someone_elses_token").is_err());
     }
 }
@@ -2,3 +2,10 @@ pub mod mocks;
 pub use mocks::*;
 
 pub mod factories;
+
+pub mod fixtures;
+
+pub mod conformance;
+
+pub mod stub_server;
+pub use stub_server::*;
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// StubResponse is a canned HTTP response handed back by the StubServer.
+#[derive(Clone)]
+pub struct StubResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl StubResponse {
+    pub fn json(status: u16, body: &str) -> StubResponse {
+        StubResponse {
+            status,
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body: body.to_string(),
+        }
+    }
+
+    pub fn status(status: u16) -> StubResponse {
+        StubResponse {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    pub fn redirect(location: &str) -> StubResponse {
+        StubResponse::status(302).with_header("Location", location)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> StubResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+// StubRequest is what the StubServer saw come in, so tests can assert on it afterwards.
+#[derive(Clone, Debug)]
+pub struct StubRequest {
+    pub method: String,
+    // The full request target, query string included.
+    pub target: String,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl StubRequest {
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or("")
+    }
+}
+
+// StubServer is a tiny HTTP/1.1 server bound to a random local port, so tests can exercise real
+// HTTP clients with no network access.
+//
+// Responses are queued per path. Each request to a path pops the next response off its queue,
+// and the last response keeps being served once the queue runs dry. Unknown paths get a 404.
+pub struct StubServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<StubRequest>>>,
+}
+
+pub struct StubServerBuilder {
+    routes: HashMap<String, Vec<StubResponse>>,
+}
+
+impl StubServerBuilder {
+    pub fn route(mut self, path: &str, response: StubResponse) -> StubServerBuilder {
+        self.routes.entry(path.to_string()).or_default().push(response);
+        self
+    }
+
+    pub fn start(self) -> StubServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind stub server");
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let routes = Arc::new(Mutex::new(self.routes));
+
+        let seen = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                handle_connection(stream, &routes, &seen);
+            }
+        });
+
+        StubServer { addr, requests }
+    }
+}
+
+impl StubServer {
+    pub fn builder() -> StubServerBuilder {
+        StubServerBuilder {
+            routes: HashMap::new(),
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<StubRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn request_count(&self, path: &str) -> usize {
+        self.requests().iter().filter(|r| r.path() == path).count()
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    routes: &Mutex<HashMap<String, Vec<StubResponse>>>,
+    seen: &Mutex<Vec<StubRequest>>,
+) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let target = parts.next().unwrap_or("").to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).is_err() {
+            return;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(i) = line.find(':') {
+            headers.insert(line[..i].trim().to_lowercase(), line[i + 1..].trim().to_string());
+        }
+    }
+
+    let content_length = headers.get("content-length")
+        .and_then(|l| l.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+
+    let request = StubRequest {
+        method,
+        target,
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+    };
+
+    let response = {
+        let mut routes = routes.lock().unwrap();
+        match routes.get_mut(request.path()) {
+            Some(queue) if queue.len() > 1 => queue.remove(0),
+            Some(queue) if queue.len() == 1 => queue[0].clone(),
+            _ => StubResponse::status(404),
+        }
+    };
+    seen.lock().unwrap().push(request);
+
+    let mut out = format!("HTTP/1.1 {} Stub\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, response.body.len());
+    for (name, value) in &response.headers {
+        out.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    out.push_str("\r\n");
+    out.push_str(&response.body);
+
+    let mut stream = stream;
+    let _ = stream.write_all(out.as_bytes());
+    let _ = stream.flush();
+}
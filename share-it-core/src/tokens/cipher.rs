@@ -0,0 +1,132 @@
+use std::convert::TryInto;
+use aes_gcm::Aes256Gcm;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use crate::tokens::{OauthToken, StoredToken, TokenError};
+use crate::user::UserID;
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+// TokenCipher encrypts tokens at rest with AES-256-GCM. Every token gets a fresh random nonce, and
+// is bound to its user and provider, so a stored token copied onto another user won't decrypt.
+pub struct TokenCipher {
+    cipher: Aes256Gcm,
+}
+
+impl TokenCipher {
+    pub fn new(key: &[u8; KEY_LEN]) -> TokenCipher {
+        TokenCipher {
+            cipher: Aes256Gcm::new(&(*key).into()),
+        }
+    }
+
+    // from_base64 reads a key in the form we keep it in config, e.g. TOKEN_ENCRYPTION_KEY.
+    pub fn from_base64(key: &str) -> Result<TokenCipher, TokenError> {
+        let bytes = base64::decode(key.trim()).map_err(|_| TokenError::InvalidKey)?;
+        if bytes.len() != KEY_LEN {
+            return Err(TokenError::InvalidKey);
+        }
+
+        let mut key = [0; KEY_LEN];
+        key.copy_from_slice(&bytes);
+        Ok(TokenCipher::new(&key))
+    }
+
+    pub fn seal(&self, user_id: UserID, provider: &str, token: &OauthToken) -> Result<StoredToken, TokenError> {
+        let plaintext = serde_json::to_vec(token).map_err(|_| TokenError::Encrypt)?;
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let aad = associated_data(user_id, provider);
+
+        let ciphertext = self.cipher
+            .encrypt(&nonce.into(), Payload { msg: &plaintext, aad: &aad })
+            .map_err(|_| TokenError::Encrypt)?;
+
+        Ok(StoredToken {
+            user_id,
+            provider: provider.to_string(),
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    pub fn open(&self, stored: &StoredToken) -> Result<OauthToken, TokenError> {
+        let nonce: [u8; NONCE_LEN] = stored.nonce.as_slice().try_into().map_err(|_| TokenError::Decrypt)?;
+        let aad = associated_data(stored.user_id, &stored.provider);
+
+        let plaintext = self.cipher
+            .decrypt(&nonce.into(), Payload { msg: &stored.ciphertext, aad: &aad })
+            .map_err(|_| TokenError::Decrypt)?;
+
+        serde_json::from_slice(&plaintext).map_err(|_| TokenError::Decrypt)
+    }
+}
+
+fn associated_data(user_id: UserID, provider: &str) -> Vec<u8> {
+    format!("{}:{}", user_id, provider).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenCipher;
+    use crate::tokens::{OauthToken, TokenError};
+
+    fn token() -> OauthToken {
+        OauthToken {
+            access_token: "secret_access_token".to_string(),
+            refresh_token: Some("secret_refresh_token".to_string()),
+            expires_at: Some(1_600_000_000),
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn sealed_token_opens_to_original() {
+        let cipher = TokenCipher::new(&[7; 32]);
+
+        let stored = cipher.seal(1, "vimeo", &token()).unwrap();
+
+        assert_eq!(cipher.open(&stored).unwrap(), token());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn sealed_token_is_not_plaintext() {
+        let cipher = TokenCipher::new(&[7; 32]);
+
+        let stored = cipher.seal(1, "vimeo", &token()).unwrap();
+
+        let raw = String::from_utf8_lossy(&stored.ciphertext);
+        assert!(!raw.contains("secret_access_token"));
+        assert!(!raw.contains("secret_refresh_token"));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn token_moved_to_another_user_does_not_open() {
+        let cipher = TokenCipher::new(&[7; 32]);
+
+        let mut stored = cipher.seal(1, "vimeo", &token()).unwrap();
+        stored.user_id = 2;
+
+        match cipher.open(&stored) {
+            Err(TokenError::Decrypt) => {},
+            _ => panic!("expected a decrypt error"),
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn token_does_not_open_with_another_key() {
+        let stored = TokenCipher::new(&[7; 32]).seal(1, "vimeo", &token()).unwrap();
+
+        assert!(TokenCipher::new(&[8; 32]).open(&stored).is_err());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn key_must_be_32_bytes() {
+        assert!(TokenCipher::from_base64(&base64::encode([1; 32])).is_ok());
+        assert!(TokenCipher::from_base64(&base64::encode([1; 16])).is_err());
+        assert!(TokenCipher::from_base64("not base64!").is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use std::error;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::user::UserID;
+
+pub mod cipher;
+pub use cipher::*;
+
+pub mod vault;
+pub use vault::*;
+
+// Tokens are stored per user, per provider.
+pub type TokenKey = (UserID, String);
+
+// TokenGrant is what a provider hands back when we exchange a code or a refresh token.
+#[derive(Clone, PartialEq)]
+pub struct TokenGrant {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<Duration>,
+}
+
+// OauthToken is a token we hold on to for a user, so we can call a provider on their behalf.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct OauthToken {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    // Seconds since the unix epoch. Tokens without an expiry (e.g. SoundCloud's non-expiring
+    // scope) never need refreshing.
+    pub expires_at: Option<u64>,
+}
+
+impl OauthToken {
+    pub fn from_grant(grant: TokenGrant, now: SystemTime) -> OauthToken {
+        OauthToken {
+            access_token: grant.access_token,
+            refresh_token: grant.refresh_token,
+            expires_at: grant.expires_in.map(|expires_in| unix_secs(now + expires_in)),
+        }
+    }
+
+    // expires_within is true if the token will have expired `margin` from now.
+    pub fn expires_within(&self, margin: Duration, now: SystemTime) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= unix_secs(now + margin),
+            None => false,
+        }
+    }
+}
+
+// Tokens are secrets, so keep them out of logs and panics.
+impl fmt::Debug for OauthToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OauthToken")
+            .field("access_token", &"<redacted>")
+            .field("refresh_token", &self.refresh_token.as_ref().map(|_| "<redacted>"))
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+// StoredToken is an OauthToken as it sits in storage: encrypted, and only readable with the key
+// it was sealed with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredToken {
+    pub user_id: UserID,
+    pub provider: String,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl StoredToken {
+    pub fn key(&self) -> TokenKey {
+        (self.user_id, self.provider.clone())
+    }
+}
+
+/// TokenRefresher trades a refresh token for a new access token with the provider that issued it.
+pub trait TokenRefresher {
+    type Error: std::error::Error;
+
+    /// Asks the named provider for a new token.
+    ///
+    /// # Failure case
+    ///
+    /// If we don't know the provider, fail to reach it, or it refuses the refresh token, then an
+    /// error is returned.
+    fn refresh(&self, provider: &str, refresh_token: &str) -> Result<TokenGrant, Self::Error>;
+}
+
+#[derive(Debug)]
+pub enum TokenError {
+    // The encryption key isn't 32 bytes of base64.
+    InvalidKey,
+    Encrypt,
+    // The stored token was tampered with, belongs to another user or provider, or was sealed with
+    // a different key.
+    Decrypt,
+    Storage(String),
+    Refresh(String),
+    // The token has expired, and there's no refresh token to get a new one with. The user has to
+    // go through the provider's login again.
+    Expired,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenError::InvalidKey => write!(f, "token encryption key must be 32 bytes of base64"),
+            TokenError::Encrypt => write!(f, "failed to encrypt token"),
+            TokenError::Decrypt => write!(f, "failed to decrypt token"),
+            TokenError::Storage(e) => write!(f, "token storage error: {}", e),
+            TokenError::Refresh(e) => write!(f, "failed to refresh token: {}", e),
+            TokenError::Expired => write!(f, "token expired and can't be refreshed"),
+        }
+    }
+}
+
+impl error::Error for TokenError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{OauthToken, TokenGrant};
+    use std::time::{Duration, SystemTime};
+
+    fn grant(expires_in: Option<Duration>) -> TokenGrant {
+        TokenGrant {
+            access_token: "access".to_string(),
+            refresh_token: Some("refresh".to_string()),
+            expires_in,
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn token_expires_after_grant_lifetime() {
+        let now = SystemTime::now();
+        let token = OauthToken::from_grant(grant(Some(Duration::from_secs(3600))), now);
+
+        assert!(!token.expires_within(Duration::from_secs(60), now));
+        assert!(token.expires_within(Duration::from_secs(3600), now));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn token_without_expiry_never_expires() {
+        let now = SystemTime::now();
+        let token = OauthToken::from_grant(grant(None), now);
+
+        assert!(!token.expires_within(Duration::from_secs(100 * 365 * 24 * 3600), now));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn token_debug_hides_secrets() {
+        let token = OauthToken::from_grant(grant(None), SystemTime::now());
+
+        let debugged = format!("{:?}", token);
+        assert!(!debugged.contains("access\""));
+        assert!(!debugged.contains("refresh\""));
+    }
+}
@@ -0,0 +1,216 @@
+use std::time::Duration;
+use crate::clock::{Clock, SystemClock};
+use crate::repositories::abstractions::Repository;
+use crate::tokens::{OauthToken, StoredToken, TokenCipher, TokenError, TokenGrant, TokenKey, TokenRefresher};
+use crate::user::UserID;
+
+// Refresh tokens this long before they expire, so they can't expire mid request.
+const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+// TokenVault keeps every user's provider tokens, encrypted, in a token repository. Asking it for
+// an access token refreshes the token first if it's about to expire.
+pub struct TokenVault<R, F, C = SystemClock> {
+    tokens: R,
+    cipher: TokenCipher,
+    refresher: F,
+    clock: C,
+    refresh_margin: Duration,
+}
+
+impl<R, F> TokenVault<R, F, SystemClock> where
+    R: Repository<TokenKey, StoredToken>,
+    F: TokenRefresher,
+{
+    pub fn new(tokens: R, cipher: TokenCipher, refresher: F) -> TokenVault<R, F, SystemClock> {
+        TokenVault::with_clock(tokens, cipher, refresher, SystemClock)
+    }
+}
+
+impl<R, F, C> TokenVault<R, F, C> where
+    R: Repository<TokenKey, StoredToken>,
+    F: TokenRefresher,
+    C: Clock,
+{
+    pub fn with_clock(tokens: R, cipher: TokenCipher, refresher: F, clock: C) -> TokenVault<R, F, C> {
+        TokenVault {
+            tokens,
+            cipher,
+            refresher,
+            clock,
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+        }
+    }
+
+    pub fn set_refresh_margin(&mut self, margin: Duration) {
+        self.refresh_margin = margin;
+    }
+
+    // store saves the grant as the user's token for the provider, replacing any token they had.
+    pub fn store(&mut self, user_id: UserID, provider: &str, grant: TokenGrant) -> Result<(), TokenError> {
+        let token = OauthToken::from_grant(grant, self.clock.system_time());
+        self.save(user_id, provider, &token)
+    }
+
+    // access_token returns the user's access token for the provider, or None if they haven't
+    // logged in with it. Tokens about to expire are refreshed, and the new token stored, first.
+    pub fn access_token(&mut self, user_id: UserID, provider: &str) -> Result<Option<String>, TokenError> {
+        let key = (user_id, provider.to_string());
+        let stored = match self.tokens.get(&key).map_err(storage_error)? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        let token = self.cipher.open(&stored)?;
+
+        let now = self.clock.system_time();
+        if !token.expires_within(self.refresh_margin, now) {
+            return Ok(Some(token.access_token));
+        }
+
+        let refresh_token = match token.refresh_token {
+            Some(refresh_token) => refresh_token,
+            // Nothing to refresh with, but it's still good for now.
+            None if !token.expires_within(Duration::from_secs(0), now) => return Ok(Some(token.access_token)),
+            None => return Err(TokenError::Expired),
+        };
+
+        let grant = self.refresher.refresh(provider, &refresh_token)
+            .map_err(|e| TokenError::Refresh(e.to_string()))?;
+        let mut refreshed = OauthToken::from_grant(grant, now);
+        // Providers don't always hand out a new refresh token, in which case the old one still works.
+        if refreshed.refresh_token.is_none() {
+            refreshed.refresh_token = Some(refresh_token);
+        }
+        self.save(user_id, provider, &refreshed)?;
+
+        Ok(Some(refreshed.access_token))
+    }
+
+    // remove forgets the user's token for the provider, returning whether they had one.
+    pub fn remove(&mut self, user_id: UserID, provider: &str) -> Result<bool, TokenError> {
+        let removed = self.tokens.remove(&(user_id, provider.to_string())).map_err(storage_error)?;
+        Ok(removed.is_some())
+    }
+
+    fn save(&mut self, user_id: UserID, provider: &str, token: &OauthToken) -> Result<(), TokenError> {
+        let stored = self.cipher.seal(user_id, provider, token)?;
+        if self.tokens.update(&stored).map_err(storage_error)?.is_none() {
+            self.tokens.insert(&stored).map_err(storage_error)?;
+        }
+        Ok(())
+    }
+}
+
+fn storage_error<E: std::error::Error>(e: E) -> TokenError {
+    TokenError::Storage(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenVault;
+    use crate::test_tools::{MockClock, MockTokenRefresher, MockTokenRepository};
+    use crate::tokens::{TokenCipher, TokenError, TokenGrant};
+    use std::time::Duration;
+
+    fn grant(access_token: &str, refresh_token: Option<&str>, expires_in: Option<u64>) -> TokenGrant {
+        TokenGrant {
+            access_token: access_token.to_string(),
+            refresh_token: refresh_token.map(|t| t.to_string()),
+            expires_in: expires_in.map(Duration::from_secs),
+        }
+    }
+
+    fn new_test_vault(refresher: MockTokenRefresher, clock: MockClock)
+        -> TokenVault<MockTokenRepository, MockTokenRefresher, MockClock>
+    {
+        TokenVault::with_clock(MockTokenRepository::new(), TokenCipher::new(&[7; 32]), refresher, clock)
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn fresh_token_is_returned_as_is() {
+        let refresher = MockTokenRefresher::new();
+        let mut vault = new_test_vault(refresher.clone(), MockClock::new());
+        vault.store(1, "vimeo", grant("access", Some("refresh"), Some(3600))).unwrap();
+
+        assert_eq!(vault.access_token(1, "vimeo").unwrap(), Some("access".to_string()));
+        assert!(refresher.refreshed().is_empty());
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn missing_token_is_none() {
+        let mut vault = new_test_vault(MockTokenRefresher::new(), MockClock::new());
+
+        assert_eq!(vault.access_token(1, "vimeo").unwrap(), None);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn expiring_token_is_refreshed_and_stored() {
+        let clock = MockClock::new();
+        let refresher = MockTokenRefresher::new();
+        refresher.grant(grant("new_access", None, Some(3600)));
+        refresher.grant(grant("newer_access", None, Some(3600)));
+        let mut vault = new_test_vault(refresher.clone(), clock.clone());
+        vault.store(1, "vimeo", grant("access", Some("refresh"), Some(3600))).unwrap();
+
+        clock.advance(Duration::from_secs(3590));
+
+        assert_eq!(vault.access_token(1, "vimeo").unwrap(), Some("new_access".to_string()));
+        assert_eq!(refresher.refreshed(), vec![("vimeo".to_string(), "refresh".to_string())]);
+
+        // The refreshed token is what's stored now, and it kept the old refresh token.
+        assert_eq!(vault.access_token(1, "vimeo").unwrap(), Some("new_access".to_string()));
+        assert_eq!(refresher.refreshed().len(), 1);
+        clock.advance(Duration::from_secs(3590));
+        assert_eq!(vault.access_token(1, "vimeo").unwrap(), Some("newer_access".to_string()));
+        assert_eq!(refresher.refreshed()[1], ("vimeo".to_string(), "refresh".to_string()));
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn expired_token_without_refresh_token_is_an_error() {
+        let clock = MockClock::new();
+        let mut vault = new_test_vault(MockTokenRefresher::new(), clock.clone());
+        vault.store(1, "vimeo", grant("access", None, Some(3600))).unwrap();
+
+        clock.advance(Duration::from_secs(3590));
+        assert_eq!(vault.access_token(1, "vimeo").unwrap(), Some("access".to_string()));
+
+        clock.advance(Duration::from_secs(20));
+        match vault.access_token(1, "vimeo") {
+            Err(TokenError::Expired) => {},
+            _ => panic!("expected an expired token error"),
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn failed_refresh_is_an_error() {
+        let clock = MockClock::new();
+        let mut vault = new_test_vault(MockTokenRefresher::new(), clock.clone());
+        vault.store(1, "vimeo", grant("access", Some("refresh"), Some(3600))).unwrap();
+
+        clock.advance(Duration::from_secs(3600));
+
+        match vault.access_token(1, "vimeo") {
+            Err(TokenError::Refresh(_)) => {},
+            _ => panic!("expected a refresh error"),
+        }
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn store_replaces_existing_token() {
+        let mut vault = new_test_vault(MockTokenRefresher::new(), MockClock::new());
+        vault.store(1, "vimeo", grant("first", None, None)).unwrap();
+        vault.store(1, "vimeo", grant("second", None, None)).unwrap();
+        vault.store(1, "soundcloud", grant("soundcloud", None, None)).unwrap();
+
+        assert_eq!(vault.access_token(1, "vimeo").unwrap(), Some("second".to_string()));
+        assert_eq!(vault.access_token(1, "soundcloud").unwrap(), Some("soundcloud".to_string()));
+
+        assert!(vault.remove(1, "vimeo").unwrap());
+        assert_eq!(vault.access_token(1, "vimeo").unwrap(), None);
+    }
+}
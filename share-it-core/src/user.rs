@@ -2,20 +2,44 @@ use crate::SoundcloudUser;
 use std::collections::HashMap;
 use rusty_ulid::Ulid;
 use crate::playlist::Playlist;
+use crate::song::{Song, Availability};
+use crate::repositories::abstractions::Versioned;
 
-pub(crate) type PlaylistID = Ulid;
-pub(crate) type UserID = u32;
-pub(crate) type Username = String;
+pub type PlaylistID = Ulid;
+pub type UserID = u32;
+pub type Username = String;
 
-#[derive(Debug, Clone, PartialEq)]
-pub(crate) struct User {
+// LinkedIdentity is an account on an OAuth provider (SoundCloud, Vimeo etc.) that a user has
+// linked to their share-it user.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkedIdentity {
+    pub provider: String,
+    // The provider's own id for the account.
+    pub subject: String,
+    pub username: String,
+}
+
+impl LinkedIdentity {
+    pub fn soundcloud(s_user: &SoundcloudUser) -> LinkedIdentity {
+        LinkedIdentity {
+            provider: "soundcloud".to_string(),
+            subject: s_user.id.to_string(),
+            username: s_user.username.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct User {
     id: UserID,
     username: Username,
     avatar_url: String,
     permalink_url: String,
     active_playlist: Option<PlaylistID>,
-    // TODO: Add users playlists here once we have a playlist model.
-    playlists: HashMap<PlaylistID, Playlist>
+    playlists: HashMap<PlaylistID, Playlist>,
+    identities: Vec<LinkedIdentity>,
+    // Set by the repository the user was loaded from.
+    version: u64,
 }
 
 impl User {
@@ -30,6 +54,8 @@ impl User {
             permalink_url,
             active_playlist: None,
             playlists: HashMap::new(),
+            identities: Vec::new(),
+            version: 0,
         }
     }
 
@@ -49,6 +75,34 @@ impl User {
         self.permalink_url.clone()
     }
 
+    // refresh_profile copies over the profile details a user may have changed on SoundCloud since
+    // we last saw them. Playlists are ours, so they are left alone.
+    pub fn refresh_profile(&mut self, s_user: SoundcloudUser) {
+        self.link_identity(LinkedIdentity::soundcloud(&s_user));
+        self.username = s_user.username;
+        self.avatar_url = s_user.avatar_url;
+        self.permalink_url = s_user.permalink_url;
+    }
+
+    pub fn identities(&self) -> &[LinkedIdentity] {
+        &self.identities
+    }
+
+    pub fn identity(&self, provider: &str) -> Option<&LinkedIdentity> {
+        self.identities.iter().find(|i| i.provider == provider)
+    }
+
+    // link_identity links the account to this user. A user has at most one account per provider,
+    // so this replaces any account they had linked for the same provider.
+    pub fn link_identity(&mut self, identity: LinkedIdentity) {
+        self.unlink_identity(&identity.provider);
+        self.identities.push(identity);
+    }
+
+    pub fn unlink_identity(&mut self, provider: &str) {
+        self.identities.retain(|i| i.provider != provider);
+    }
+
     pub fn active_playlist(&self) -> Option<&Ulid> {
         if let Some(playlist_id) = &self.active_playlist {
             Some(playlist_id)
@@ -88,28 +142,75 @@ impl User {
         }
     }
 
+    pub fn playlists(&self) -> impl Iterator<Item = &Playlist> {
+        self.playlists.values()
+    }
+
     pub fn playlist_count(&self) -> usize {
         self.playlists.len()
     }
+
+    // song_ids returns the id of every song across all of this users playlists, without
+    // duplicates.
+    pub fn song_ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.playlists.values()
+            .flat_map(|p| p.songs().map(|s| s.id()))
+            .collect();
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+
+    // set_song_availability marks the song in every playlist it appears in, returning true if
+    // anything changed.
+    pub fn set_song_availability(&mut self, song_id: u32, availability: Availability) -> bool {
+        let mut changed = false;
+        for playlist in self.playlists.values_mut() {
+            changed |= playlist.set_song_availability(song_id, availability);
+        }
+        changed
+    }
+
+    pub fn skip_unavailable_songs(&mut self, playlist_id: &PlaylistID) -> Vec<Song> {
+        if let Some(playlist) = self.playlists.get_mut(playlist_id) {
+            playlist.skip_unavailable()
+        } else {
+            Vec::new()
+        }
+    }
 }
 
 impl From<SoundcloudUser> for User {
     fn from(s_user: SoundcloudUser) -> Self {
         User {
+            identities: vec![LinkedIdentity::soundcloud(&s_user)],
             id: s_user.id,
             username: s_user.username,
             avatar_url: s_user.avatar_url,
             permalink_url: s_user.permalink_url,
             active_playlist: None,
             playlists: HashMap::new(),
+            version: 0,
         }
     }
 }
 
+impl Versioned for User {
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::User;
+    use super::{User, LinkedIdentity};
     use crate::SoundcloudUser;
+    use crate::song::Availability;
+    use crate::test_tools::factories::{new_test_user, new_test_playlist};
 
     #[test]
     fn mapping_from_soundcloud_user_works() {
@@ -145,5 +246,37 @@ mod tests {
         let u = User::from(s_user.clone());
 
         assert_eq!(u.id, s_user.id);
+        assert_eq!(u.identity("soundcloud").unwrap().subject, "3207");
+    }
+
+    #[test]
+    fn song_availability_is_set_across_playlists() {
+        let mut u = new_test_user(0);
+        u.add_playlist(new_test_playlist(0, 2));
+        u.add_playlist(new_test_playlist(0, 3));
+
+        assert_eq!(u.song_ids(), vec![0, 1, 2]);
+        assert!(u.set_song_availability(1, Availability::Deleted));
+        assert!(!u.set_song_availability(1, Availability::Deleted));
+    }
+
+    #[test]
+    fn linking_identities() {
+        let mut u = new_test_user(0);
+        let vimeo = LinkedIdentity {
+            provider: "vimeo".to_string(),
+            subject: "12345".to_string(),
+            username: "vimeo user".to_string(),
+        };
+
+        u.link_identity(vimeo.clone());
+        assert_eq!(u.identity("vimeo"), Some(&vimeo));
+
+        let relinked = LinkedIdentity { subject: "67890".to_string(), ..vimeo };
+        u.link_identity(relinked.clone());
+        assert_eq!(u.identities(), &[relinked][..]);
+
+        u.unlink_identity("vimeo");
+        assert!(u.identities().is_empty());
     }
 }
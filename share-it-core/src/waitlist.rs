@@ -1,22 +1,34 @@
 use crate::Song;
 use std::collections::VecDeque;
 use std::fmt::Display;
-use serde::export::Formatter;
+use std::fmt::Formatter;
 use crate::user::{UserID, Username, User, PlaylistID};
 use crate::repositories::abstractions::Repository;
 use rusty_ulid::Ulid;
 
 pub type DJ = (UserID, Username);
 
+// WaitlistNotification is something a DJ should be told about that happened while the waitlist
+// was picking the next song.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WaitlistNotification {
+    // The song is no longer available on SoundCloud and was skipped.
+    SongUnavailable { user_id: UserID, song_id: u32, title: String },
+    // The DJ has nothing to play, so their turn was skipped. Either none of the songs in their
+    // active playlist are available, or the playlist is empty, or they haven't picked one.
+    NothingPlayable { user_id: UserID },
+}
+
 #[derive(Debug, PartialEq)]
-pub(crate) struct Waitlist<T> where
+pub struct Waitlist<T> where
     T: Repository<u32, User>,
 {
     id: Ulid,
     users: T,
     current_dj: Option<User>,
     current_playlist: Option<PlaylistID>,
-    queue: VecDeque<DJ>
+    queue: VecDeque<DJ>,
+    notifications: Vec<WaitlistNotification>,
 }
 
 impl<T> Waitlist<T> where
@@ -29,11 +41,37 @@ impl<T> Waitlist<T> where
             current_dj: None,
             current_playlist: None,
             queue: VecDeque::new(),
+            notifications: Vec::new(),
+        }
+    }
+
+    // restore rebuilds a waitlist from storage. Notifications aren't kept, as they're only
+    // interesting right after play_next.
+    pub fn restore(id: Ulid,
+                   user_repository: T,
+                   current_dj: Option<User>,
+                   current_playlist: Option<PlaylistID>,
+                   queue: VecDeque<DJ>) -> Waitlist<T> {
+        Waitlist {
+            id,
+            users: user_repository,
+            current_dj,
+            current_playlist,
+            queue,
+            notifications: Vec::new(),
         }
     }
 
     pub fn id(&self) -> Ulid {
-        self.id.clone()
+        self.id
+    }
+
+    pub fn current_dj(&self) -> Option<&User> {
+        self.current_dj.as_ref()
+    }
+
+    pub fn current_playlist(&self) -> Option<&PlaylistID> {
+        self.current_playlist.as_ref()
     }
 
     pub fn join(&mut self, dj: DJ) -> bool {
@@ -56,6 +94,32 @@ impl<T> Waitlist<T> where
         }
     }
 
+    // replay_turn puts the waitlist back the way play_next left it when it gave the DJ their turn,
+    // playing from playlist_id. The DJs play_next skipped are skipped again, but nobody's songs are
+    // looked at or cycled, and nobody is loaded from the user repository, as that already happened
+    // the first time.
+    pub fn replay_turn(&mut self, dj: &User, playlist_id: &PlaylistID) {
+        if self.current_dj.is_some() {
+            self.queue.pop_front();
+        }
+        while let Some((u_id, _)) = self.queue.front() {
+            if *u_id == dj.id() {
+                break;
+            }
+            self.queue.pop_front();
+        }
+
+        self.current_dj = Some(dj.clone());
+        self.current_playlist = Some(*playlist_id);
+    }
+
+    // stop empties the waitlist, the way play_next leaves it once nobody has anything to play.
+    pub fn stop(&mut self) {
+        self.queue.clear();
+        self.current_dj = None;
+        self.current_playlist = None;
+    }
+
     fn contains_user(&self, user_id: u32) -> bool {
         self.queue.iter().any(|(u_id, _)| { user_id == *u_id })
     }
@@ -65,38 +129,42 @@ impl<T> Waitlist<T> where
     }
 
     // play_next will return a song if the waitlist is non-empty, and the next DJ has
-    // at least one song in their active playlist. Otherwise, we skip that DJ, and keep going.
+    // at least one available song in their active playlist. Otherwise, we skip that DJ, notify them,
+    // and keep going.
+    // Unavailable songs (deleted or made private on SoundCloud) at the top of the active playlist are
+    // cycled to the bottom, and the DJ is notified about each of them.
     // If we find no valid songs in active playlists from any of the DJs in the queue, then we
     // return None.
     // If there was a problem communicating with underlying DB, then we return the error type
     // defined by the repository implementation.
     pub fn play_next(&mut self) -> Result<Option<Song>, T::Error> {
+        // First we remove the current user from the top of the queue if we have a current_dj.
+        // Otherwise they are the first to ever show up so they should be played.
+        if self.current_dj.is_some() {
+            self.queue.pop_front();
+        }
+
+        // Now let's make sure we cycle their playlist for them before moving to the next DJ.
+        if let (Some(dj), Some(current_playlist)) = (&self.current_dj, &self.current_playlist) {
+            // The DJ may have changed since their turn started, so we cycle a fresh copy of them
+            // rather than overwrite their changes with the one we've held on to.
+            if let Some(mut user) = self.users.get(&dj.id())? {
+                user.cycle_playlist(current_playlist);
+                // Must persist dj back now that we cycled their playlist.
+                // TODO: If we get an underlying database error of some kind, we will
+                // bail here, which means we fail to play next. Is this really what we want?
+                self.users.update(&user)?;
+            }
+        }
+
         loop {
             // Base case.
             if self.queue.is_empty() {
+                self.current_dj = None;
+                self.current_playlist = None;
                 return Ok(None)
             }
 
-            // First we remove the current user from the top of the queue if we have a current_dj.
-            // Otherwise they are the first to ever show up so they should be played.
-            if let Some(_) = &self.current_dj {
-                self.queue.pop_front();
-            }
-
-            // Now let's make sure we cycle their playlist for them before moving to the next DJ.
-            if let Some(user) = &mut self.current_dj {
-                if let Some(current_playlist) = &self.current_playlist {
-                    user.cycle_playlist(current_playlist);
-                    // Must persist dj back now that we cycled their playlist.
-                    // TODO: If we get an underlying database error of some kind, we will
-                    // bail here, which means we fail to play next. Is this really what we want?
-                    self.users.update(user)?;
-                }
-            }
-
-            // Check if queue is now empty and bail if so.
-            if self.queue.is_empty() { return Ok(None); }
-
             // Now we fetch the full user from the top of the queue, based on the given user_id.
             let (u_id, _) = self.queue.front().unwrap();
             let maybe_user = self.users.get(u_id)?;
@@ -104,39 +172,72 @@ impl<T> Waitlist<T> where
                 // TODO: This is very odd, somehow we got a user_id for a user that doesn't exist in our system.
                 // This seems like a very big mess up and we might want to do something other than skip them,
                 // Like a re-fetch from SC to our DB.
+                self.queue.pop_front();
                 continue
             }
-            let user = maybe_user.unwrap();
+            let mut user = maybe_user.unwrap();
 
             // Found a valid user, let's see if they have an active playlist, and if that playlist is non-empty.
             // If so, we have a match and should return the top song for playback.
             // If not, we must remove them from the wait-list and skip them.
-            let active_playlist_id = user.active_playlist();
-            if active_playlist_id.is_none() {
-                // No active playlist set, so let's skip this DJ.
-                // TODO: Emit a skip event here.
-                continue;
-            }
-            let maybe_playlist = user.get_playlist(active_playlist_id.unwrap());
-            if maybe_playlist.is_none() {
+            let active_playlist_id = match user.active_playlist() {
+                Some(playlist_id) => *playlist_id,
+                None => {
+                    // No active playlist set, so let's skip this DJ.
+                    self.notifications.push(WaitlistNotification::NothingPlayable { user_id: user.id() });
+                    self.queue.pop_front();
+                    continue;
+                }
+            };
+            if user.get_playlist(&active_playlist_id).is_none() {
                 // Didn't find the active playlist in the users playlists.
                 // This is very odd and we should never hit this. Let's skip for now.
-                // TODO: Emit a skip event here.
+                self.notifications.push(WaitlistNotification::NothingPlayable { user_id: user.id() });
+                self.queue.pop_front();
                 continue;
             }
+
+            // Move any songs that can no longer be streamed out of the way, and let the DJ know.
+            let skipped = user.skip_unavailable_songs(&active_playlist_id);
+            if !skipped.is_empty() {
+                self.users.update(&user)?;
+                for song in skipped {
+                    self.notifications.push(WaitlistNotification::SongUnavailable {
+                        user_id: user.id(),
+                        song_id: song.id(),
+                        title: song.title(),
+                    });
+                }
+            }
+
             // Found the playlist!
-            let playlist = maybe_playlist.unwrap();
+            let top_song = match user.get_playlist(&active_playlist_id).unwrap().top_song() {
+                Some(song) if song.is_available() => song,
+                // Nothing in this playlist can be played, or there's nothing in it at all, so this
+                // DJ has to sit this turn out.
+                _ => {
+                    self.notifications.push(WaitlistNotification::NothingPlayable { user_id: user.id() });
+                    self.queue.pop_front();
+                    continue;
+                }
+            };
 
             // Let's set the current dj and return the top song.
             // We need to store their playlist id as well in case they change their active playlist
             // during the middle of their turn, so we always cycle the correct playlist next time
             // play_next() gets called.
-            self.current_dj = Some(user.clone());
-            self.current_playlist = Some(playlist.id());
-            return Ok(playlist.top_song());
+            self.current_dj = Some(user);
+            self.current_playlist = Some(active_playlist_id);
+            return Ok(Some(top_song));
         }
     }
 
+    // take_notifications drains everything the waitlist wants to tell DJs about since it was last
+    // called.
+    pub fn take_notifications(&mut self) -> Vec<WaitlistNotification> {
+        std::mem::take(&mut self.notifications)
+    }
+
     pub fn djs(&self) -> &VecDeque<DJ> {
         &self.queue
     }
@@ -165,11 +266,12 @@ impl<T> Clone for Waitlist<T>
 {
     fn clone(&self) -> Self {
         Waitlist {
-            id: self.id.clone(),
+            id: self.id,
             users: self.users.clone(),
             current_dj: self.current_dj.clone(),
-            current_playlist: self.current_playlist.clone(),
+            current_playlist: self.current_playlist,
             queue: self.queue.clone(),
+            notifications: self.notifications.clone(),
         }
     }
 }
@@ -179,6 +281,10 @@ mod tests {
     use crate::MockUserRepository;
     use crate::repositories::abstractions::Repository;
     use crate::test_tools::factories::{new_test_waitlist_with_repo, TestWaitlistSpec, new_test_waitlist};
+    use crate::test_tools::factories::{new_test_user, new_test_playlist};
+    use crate::waitlist::{Waitlist, WaitlistNotification};
+    use crate::song::Availability;
+    use crate::user::User;
 
     #[test]
     #[allow(unused)]
@@ -276,4 +382,79 @@ mod tests {
         let song = waitlist.play_next().unwrap();
         assert_eq!(song, None);
     }
+
+    fn new_test_dj(user_id: u32, song_count: u32) -> User {
+        let mut user = new_test_user(user_id);
+        let playlist = new_test_playlist(user_id, song_count);
+        user.set_active_playlist(&playlist.id());
+        user.add_playlist(playlist);
+        user
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn unavailable_songs_are_skipped_and_dj_notified() {
+        let mut repo = MockUserRepository::new();
+        let mut dj = new_test_dj(0, 3);
+        dj.set_song_availability(0, Availability::Deleted);
+        repo.insert(&dj);
+
+        let mut waitlist = Waitlist::new(&mut repo);
+        waitlist.join((dj.id(), dj.username()));
+        let song = waitlist.play_next().unwrap().unwrap();
+
+        assert_eq!(song.id(), 1);
+        assert_eq!(waitlist.take_notifications(), vec![WaitlistNotification::SongUnavailable {
+            user_id: 0,
+            song_id: 0,
+            title: "test song".to_string(),
+        }]);
+        assert!(waitlist.take_notifications().is_empty());
+
+        // The skipped song was cycled to the bottom, and that was persisted.
+        let saved = repo.get(&0).unwrap().unwrap();
+        let playlist = saved.get_playlist(saved.active_playlist().unwrap()).unwrap();
+        assert_eq!(playlist.top_song().unwrap().id(), 1);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn dj_with_nothing_playable_is_skipped() {
+        let mut repo = MockUserRepository::new();
+        let mut first = new_test_dj(0, 1);
+        first.set_song_availability(0, Availability::Private);
+        repo.insert(&first);
+        let second = new_test_dj(1, 1);
+        repo.insert(&second);
+
+        let mut waitlist = Waitlist::new(&mut repo);
+        waitlist.join((first.id(), first.username()));
+        waitlist.join((second.id(), second.username()));
+        let song = waitlist.play_next().unwrap().unwrap();
+
+        // The first DJ had nothing playable, so the second DJ is up.
+        assert_eq!(song.id(), 0);
+        assert_eq!(waitlist.len(), 1);
+        assert_eq!(waitlist.take_notifications(), vec![WaitlistNotification::NothingPlayable { user_id: 0 }]);
+    }
+
+    #[test]
+    #[allow(unused)]
+    fn dj_with_an_empty_playlist_is_skipped() {
+        let mut repo = MockUserRepository::new();
+        let first = new_test_dj(0, 0);
+        repo.insert(&first);
+        let second = new_test_dj(1, 1);
+        repo.insert(&second);
+
+        let mut waitlist = Waitlist::new(&mut repo);
+        waitlist.join((first.id(), first.username()));
+        waitlist.join((second.id(), second.username()));
+        let song = waitlist.play_next().unwrap().unwrap();
+
+        // The second DJ is up, rather than the first getting a turn with nothing to play.
+        assert_eq!(song.id(), 0);
+        assert_eq!(waitlist.current_dj().map(|dj| dj.id()), Some(1));
+        assert_eq!(waitlist.take_notifications(), vec![WaitlistNotification::NothingPlayable { user_id: 0 }]);
+    }
 }
@@ -1,2 +1,5 @@
+// Every integration test file pulls in the whole common module, but none of them use all of it.
+#![allow(dead_code)]
+
 pub mod error;
 pub use error::*;
@@ -0,0 +1,263 @@
+mod common;
+use common::*;
+
+use share_it_core::soundcloud_api::{HttpSoundcloudClient, SoundcloudClient, SoundcloudClientConfig, SoundcloudError, SoundcloudResource, RetryPolicy};
+use share_it_core::test_tools::fixtures::{TRACK_JSON, USER_JSON, PLAYLIST_JSON};
+use share_it_core::test_tools::stub_server::{StubResponse, StubServer};
+use share_it_core::test_tools::MockClock;
+use std::time::Duration;
+
+fn client_for(server: &StubServer) -> HttpSoundcloudClient<MockClock> {
+    client_with_config(server, SoundcloudClientConfig::default(), MockClock::new())
+}
+
+fn client_with_config(server: &StubServer, config: SoundcloudClientConfig, clock: MockClock) -> HttpSoundcloudClient<MockClock> {
+    HttpSoundcloudClient::with_config(server.url(), "test_client_id".to_string(), config, clock)
+}
+
+fn retrying_config(max_retries: u32) -> SoundcloudClientConfig {
+    SoundcloudClientConfig {
+        retry: RetryPolicy::new(max_retries, Duration::from_millis(100), Duration::from_secs(10)),
+        ..SoundcloudClientConfig::default()
+    }
+}
+
+#[test]
+fn fetches_track_user_and_playlist() {
+    let server = StubServer::builder()
+        .route("/tracks/13158665", StubResponse::json(200, TRACK_JSON))
+        .route("/users/3207", StubResponse::json(200, USER_JSON))
+        .route("/playlists/405726", StubResponse::json(200, PLAYLIST_JSON))
+        .start();
+    let mut client = client_for(&server);
+
+    assert_eq!(client.track(13158665).unwrap().unwrap().id, 13158665);
+    assert_eq!(client.user(3207).unwrap().unwrap().id, 3207);
+    assert_eq!(client.playlist(405726).unwrap().unwrap().tracks.len(), 1);
+
+    // Every call must identify us to SoundCloud.
+    assert!(server.requests().iter().all(|r| r.target.contains("client_id=test_client_id")));
+}
+
+#[test]
+fn missing_resources_are_none() {
+    let server = StubServer::builder().start();
+    let mut client = client_for(&server);
+
+    assert!(client.track(1).unwrap().is_none());
+    assert!(client.user(1).unwrap().is_none());
+    assert!(client.playlist(1).unwrap().is_none());
+    assert!(client.resolve("https://soundcloud.com/nobody").unwrap().is_none());
+}
+
+#[test]
+fn resolve_follows_redirect_to_resource() {
+    let server = StubServer::builder()
+        .route("/resolve", StubResponse::redirect("/tracks/13158665"))
+        .route("/tracks/13158665", StubResponse::json(200, TRACK_JSON))
+        .start();
+    let mut client = client_for(&server);
+
+    let resolved = client.resolve("https://soundcloud.com/user2835985/munching-at-tiannas-house").unwrap();
+    match resolved {
+        Some(SoundcloudResource::Track(t)) => assert_eq!(t.id, 13158665),
+        _ => panic!("expected permalink to resolve to a track"),
+    }
+
+    let resolve_request = &server.requests()[0];
+    assert!(resolve_request.target.contains("url=https%3A%2F%2Fsoundcloud.com%2Fuser2835985%2Fmunching-at-tiannas-house"));
+}
+
+#[test]
+fn search_tracks_returns_matches() {
+    let search_response = format!("[{}]", TRACK_JSON);
+    let server = StubServer::builder()
+        .route("/tracks", StubResponse::json(200, &search_response))
+        .start();
+    let mut client = client_for(&server);
+
+    let tracks = client.search_tracks("munching").unwrap();
+    assert_eq!(tracks.len(), 1);
+    assert!(server.requests()[0].target.contains("q=munching"));
+}
+
+#[test]
+fn unexpected_status_is_an_error() {
+    let server = StubServer::builder()
+        .route("/tracks/13158665", StubResponse::status(418))
+        .start();
+    let mut client = client_for(&server);
+
+    match client.track(13158665) {
+        Err(SoundcloudError::Status(418)) => (),
+        _ => panic!("expected an unexpected status error"),
+    }
+}
+
+#[test]
+fn malformed_body_is_an_error() {
+    let server = StubServer::builder()
+        .route("/tracks/13158665", StubResponse::json(200, "{ not json"))
+        .start();
+    let mut client = client_for(&server);
+
+    match client.track(13158665) {
+        Err(SoundcloudError::Deserialize(_)) => (),
+        _ => panic!("expected a deserialize error"),
+    }
+}
+
+#[test]
+fn server_errors_are_retried_with_backoff() {
+    let server = StubServer::builder()
+        .route("/tracks/13158665", StubResponse::status(503))
+        .route("/tracks/13158665", StubResponse::status(502))
+        .route("/tracks/13158665", StubResponse::json(200, TRACK_JSON))
+        .start();
+    let clock = MockClock::new();
+    let mut client = client_with_config(&server, retrying_config(3), clock.clone());
+
+    assert_eq!(client.track(13158665).unwrap().unwrap().id, 13158665);
+    assert_eq!(server.request_count("/tracks/13158665"), 3);
+
+    let sleeps = clock.sleeps();
+    assert_eq!(sleeps.len(), 2);
+    assert!(sleeps[0] >= Duration::from_millis(50) && sleeps[0] <= Duration::from_millis(100));
+    assert!(sleeps[1] >= Duration::from_millis(100) && sleeps[1] <= Duration::from_millis(200));
+}
+
+#[test]
+fn rate_limited_requests_honour_retry_after() {
+    let server = StubServer::builder()
+        .route("/tracks/13158665", StubResponse::status(429).with_header("Retry-After", "7"))
+        .route("/tracks/13158665", StubResponse::json(200, TRACK_JSON))
+        .start();
+    let clock = MockClock::new();
+    let mut client = client_with_config(&server, retrying_config(3), clock.clone());
+
+    assert!(client.track(13158665).unwrap().is_some());
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(7)]);
+}
+
+#[test]
+fn retry_after_beyond_the_max_delay_is_not_waited_out() {
+    let server = StubServer::builder()
+        .route("/tracks/13158665", StubResponse::status(429).with_header("Retry-After", "86400"))
+        .route("/tracks/13158665", StubResponse::json(200, TRACK_JSON))
+        .start();
+    let clock = MockClock::new();
+    let mut client = client_with_config(&server, retrying_config(3), clock.clone());
+
+    match client.track(13158665) {
+        Err(SoundcloudError::RateLimited { retry_after }) => assert_eq!(retry_after, Some(Duration::from_secs(86400))),
+        _ => panic!("expected a rate limited error"),
+    }
+    assert_eq!(server.request_count("/tracks/13158665"), 1);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[test]
+fn persistent_rate_limiting_is_a_typed_error() {
+    let server = StubServer::builder()
+        .route("/tracks/13158665", StubResponse::status(429).with_header("Retry-After", "2"))
+        .start();
+    let clock = MockClock::new();
+    let mut client = client_with_config(&server, retrying_config(2), clock.clone());
+
+    match client.track(13158665) {
+        Err(SoundcloudError::RateLimited { retry_after }) => assert_eq!(retry_after, Some(Duration::from_secs(2))),
+        _ => panic!("expected a rate limited error"),
+    }
+    // The first attempt, plus two retries.
+    assert_eq!(server.request_count("/tracks/13158665"), 3);
+    assert_eq!(clock.total_slept(), Duration::from_secs(4));
+}
+
+#[test]
+fn persistent_server_errors_are_a_typed_error() {
+    let server = StubServer::builder()
+        .route("/tracks/13158665", StubResponse::status(500))
+        .start();
+    let mut client = client_with_config(&server, retrying_config(1), MockClock::new());
+
+    match client.track(13158665) {
+        Err(SoundcloudError::Server(500)) => (),
+        _ => panic!("expected a server error"),
+    }
+    assert_eq!(server.request_count("/tracks/13158665"), 2);
+}
+
+#[test]
+fn forbidden_is_not_retried() {
+    let server = StubServer::builder()
+        .route("/tracks/13158665", StubResponse::status(403))
+        .start();
+    let clock = MockClock::new();
+    let mut client = client_with_config(&server, retrying_config(3), clock.clone());
+
+    match client.track(13158665) {
+        Err(SoundcloudError::Forbidden) => (),
+        _ => panic!("expected a forbidden error"),
+    }
+    assert_eq!(server.request_count("/tracks/13158665"), 1);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[test]
+fn not_found_is_not_retried() {
+    let server = StubServer::builder().start();
+    let clock = MockClock::new();
+    let mut client = client_with_config(&server, retrying_config(3), clock.clone());
+
+    assert!(client.track(13158665).unwrap().is_none());
+    assert_eq!(server.request_count("/tracks/13158665"), 1);
+    assert!(clock.sleeps().is_empty());
+}
+
+#[test]
+fn rate_limiter_paces_requests() {
+    let server = StubServer::builder()
+        .route("/tracks/13158665", StubResponse::json(200, TRACK_JSON))
+        .start();
+    let config = SoundcloudClientConfig {
+        burst: 1,
+        requests_per_second: 1.0,
+        retry: RetryPolicy::no_retries(),
+    };
+    let clock = MockClock::new();
+    let mut client = client_with_config(&server, config, clock.clone());
+
+    client.track(13158665).unwrap();
+    client.track(13158665).unwrap();
+    client.track(13158665).unwrap();
+
+    // The first request spends the burst, each one after waits a full second for a new token.
+    assert_eq!(clock.sleeps(), vec![Duration::from_secs(1), Duration::from_secs(1)]);
+}
+
+#[test]
+fn me_authenticates_with_access_token() {
+    let server = StubServer::builder()
+        .route("/me", StubResponse::json(200, USER_JSON))
+        .start();
+    let mut client = client_for(&server);
+
+    assert_eq!(client.me("test_access_token").unwrap().id, 3207);
+
+    let request = &server.requests()[0];
+    assert_eq!(request.headers.get("authorization"), Some(&"OAuth test_access_token".to_string()));
+    assert!(!request.target.contains("client_id"));
+}
+
+#[test]
+fn me_with_rejected_token_is_unauthorized() {
+    let server = StubServer::builder()
+        .route("/me", StubResponse::status(401))
+        .start();
+    let mut client = client_for(&server);
+
+    match client.me("expired_token") {
+        Err(SoundcloudError::Unauthorized) => (),
+        _ => panic!("expected an unauthorized error"),
+    }
+}
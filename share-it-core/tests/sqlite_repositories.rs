@@ -0,0 +1,168 @@
+use share_it_core::chatroom::{Chatroom, ChatUser};
+use share_it_core::playlist::Playlist;
+use share_it_core::repositories::abstractions::{Repository, Versioned};
+use share_it_core::repositories::event_log::EventSourcedChatrooms;
+use share_it_core::repositories::sql::{SqliteChatroomLog, SqliteChatrooms, SqliteTokens, SqliteUsers};
+use share_it_core::user::{LinkedIdentity, User};
+use share_it_core::test_tools::conformance::{ChatroomEntities, TokenEntities, UserEntities};
+use share_it_core::{repository_conformance_tests, MockUserRepository, Song};
+use std::path::PathBuf;
+
+// TempDb is a SQLite file that's deleted when the test is done with it.
+struct TempDb {
+    path: PathBuf,
+}
+
+impl TempDb {
+    fn new(name: &str) -> TempDb {
+        let path = std::env::temp_dir().join(format!("share-it-{}-{}.sqlite", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        TempDb { path }
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn user_with_playlist(user_id: u32, song_count: u32) -> User {
+    let mut user = User::new(
+        user_id,
+        format!("user {}", user_id),
+        "https://i1.sndcdn.com/avatar.jpg".to_string(),
+        format!("https://soundcloud.com/user-{}", user_id),
+    );
+
+    let mut playlist = Playlist::new("Favourites".to_string());
+    for song_id in 0..song_count {
+        playlist.add_song(Song::new(
+            user_id * 100 + song_id,
+            user_id,
+            180_000,
+            format!("user {}", user_id),
+            format!("song {}", song_id),
+            "public".to_string(),
+            format!("song-{}", song_id),
+            format!("https://soundcloud.com/user-{}/song-{}", user_id, song_id),
+            None,
+            format!("https://api.soundcloud.com/tracks/{}/stream", user_id * 100 + song_id),
+        ));
+    }
+    user.set_active_playlist(&playlist.id());
+    user.add_playlist(playlist);
+    user
+}
+
+#[test]
+fn users_persist_across_connections() {
+    let db = TempDb::new("users");
+    let mut user = user_with_playlist(1, 3);
+    user.link_identity(LinkedIdentity {
+        provider: "vimeo".to_string(),
+        subject: "42".to_string(),
+        username: "Robert'); DROP TABLE users;--".to_string(),
+    });
+
+    {
+        let mut users = SqliteUsers::open(&db.path).unwrap();
+        assert_eq!(users.insert(&user).unwrap(), Some(1));
+    }
+
+    // A second connection to the same file sees what the first one wrote.
+    let mut users = SqliteUsers::open(&db.path).unwrap();
+    assert_eq!(users.get(&1).unwrap(), Some(user.clone()));
+
+    let playlist_id = *user.active_playlist().unwrap();
+    user.cycle_playlist(&playlist_id);
+    assert_eq!(users.update(&user).unwrap(), Some(1));
+    // Updating moved the stored user on to the next version.
+    user.set_version(1);
+    assert_eq!(SqliteUsers::open(&db.path).unwrap().get(&1).unwrap(), Some(user));
+
+    assert_eq!(users.remove(&1).unwrap(), Some(1));
+    assert!(SqliteUsers::open(&db.path).unwrap().get(&1).unwrap().is_none());
+}
+
+#[test]
+fn in_memory_users_have_the_same_semantics() {
+    let mut users = SqliteUsers::in_memory();
+    let user = user_with_playlist(7, 2);
+
+    assert!(users.get(&7).unwrap().is_none());
+    assert_eq!(users.update(&user).unwrap(), None);
+    assert_eq!(users.insert(&user).unwrap(), Some(7));
+    assert_eq!(users.insert(&user).unwrap(), None);
+    assert_eq!(users.get(&7).unwrap(), Some(user));
+    assert_eq!(users.remove(&7).unwrap(), Some(7));
+    assert_eq!(users.remove(&7).unwrap(), None);
+}
+
+#[test]
+fn chatrooms_persist_and_keep_playing() {
+    let db = TempDb::new("chatrooms");
+    let mut user_repo = MockUserRepository::new();
+    for user_id in 1..=3 {
+        user_repo.insert(&user_with_playlist(user_id, 2)).unwrap();
+    }
+
+    let mut chatroom = Chatroom::new(user_repo.clone(), 1, "Late night".to_string());
+    for user_id in 1..=3 {
+        chatroom.join(ChatUser(user_id, format!("user {}", user_id)));
+        chatroom.join_waitlist(user_id);
+    }
+    let first = chatroom.play_next().unwrap().unwrap();
+    assert_eq!(first.user_id(), 1);
+
+    {
+        let mut chatrooms = SqliteChatrooms::open(&db.path, user_repo.clone()).unwrap();
+        assert_eq!(chatrooms.insert(&chatroom).unwrap(), Some(chatroom.id()));
+    }
+
+    let mut chatrooms = SqliteChatrooms::open(&db.path, user_repo.clone()).unwrap();
+    let mut loaded = chatrooms.get(&chatroom.id()).unwrap().unwrap();
+    assert_eq!(loaded.name(), "Late night");
+    assert_eq!(loaded.moderator(), 1);
+    assert_eq!(loaded.current_users(), chatroom.current_users());
+    assert_eq!(loaded.waitlist_djs(), chatroom.waitlist_djs());
+
+    // The rebuilt room picks up with the next DJ in line.
+    assert_eq!(loaded.play_next().unwrap().unwrap().user_id(), 2);
+    assert_eq!(chatrooms.update(&loaded).unwrap(), Some(chatroom.id()));
+    let reloaded = chatrooms.get(&chatroom.id()).unwrap().unwrap();
+    assert_eq!(reloaded.waitlist().current_dj().map(|u| u.id()), Some(2));
+
+    assert_eq!(chatrooms.remove(&chatroom.id()).unwrap(), Some(chatroom.id()));
+    assert!(chatrooms.get(&chatroom.id()).unwrap().is_none());
+}
+
+mod user_conformance {
+    use super::*;
+
+    repository_conformance_tests!(SqliteUsers::in_memory(), UserEntities::new());
+}
+
+mod chatroom_conformance {
+    use super::*;
+
+    repository_conformance_tests!(
+        SqliteChatrooms::in_memory(MockUserRepository::new()),
+        ChatroomEntities::new(MockUserRepository::new())
+    );
+}
+
+mod event_sourced_chatroom_conformance {
+    use super::*;
+
+    repository_conformance_tests!(
+        EventSourcedChatrooms::new(SqliteChatroomLog::in_memory(MockUserRepository::new())),
+        ChatroomEntities::new(MockUserRepository::new())
+    );
+}
+
+mod token_conformance {
+    use super::*;
+
+    repository_conformance_tests!(unversioned: SqliteTokens::in_memory(), TokenEntities::new());
+}